@@ -0,0 +1,27 @@
+#![no_main]
+
+// The scanner has no dependencies on the host bindings, so it is built
+// straight from the actor's source rather than through the cdylib.
+#[path = "../../src/tags.rs"]
+#[allow(dead_code)]
+mod tags;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(content) = std::str::from_utf8(data) else {
+        return;
+    };
+    let marker = "<fs-command name=\"default\">";
+    let blocks = tags::blocks(content, marker);
+    assert!(blocks.len() <= tags::MAX_COMMANDS);
+    for block in &blocks {
+        assert!(block.fields.len() <= tags::MAX_FIELDS);
+        for (name, value) in &block.fields {
+            // Every field borrows from the input
+            let range = content.as_bytes().as_ptr_range();
+            assert!(range.contains(&name.as_ptr()));
+            assert!(value.is_empty() || range.contains(&value.as_ptr()));
+        }
+    }
+});
@@ -0,0 +1,106 @@
+//! The response budget: room for result text shared across a batch, so one
+//! verbose command cannot crowd out the rest or push the response past
+//! what the parent takes in.
+//!
+//! Each result is given an equal share, and what short results leave unused
+//! is shared again among the longer ones. A result over its share keeps its
+//! first lines, which hold the start of a read or a listing, and ends with
+//! a note of how much was cut.
+
+use serde::Serialize;
+
+/// Least room any result is given, however small the budget
+pub const MIN_SHARE: usize = 200;
+
+/// Operations whose results are one entry per line
+const LISTINGS: [&str; 3] = ["list-files", "manifest", "queue"];
+
+/// Operations whose results are a file's content
+const READS: [&str; 3] = ["read-file", "read-around", "read-base64"];
+
+/// A result cut to its share.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trimmed {
+    /// Position of the result in the batch, from 0
+    pub index: usize,
+    pub operation: String,
+    pub path: String,
+    /// Bytes of the result text kept, and how many there were
+    pub kept: usize,
+    pub bytes: usize,
+    /// Lines left out
+    pub lines: usize,
+}
+
+/// The bytes each of `sizes` may take so that together they fit `budget`.
+/// Sizes that fit an even share keep it all; the rest split what is left.
+pub fn shares(sizes: &[usize], budget: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| sizes[i]);
+    let mut shares = vec![0; sizes.len()];
+    let mut left = budget;
+    for (n, &i) in order.iter().enumerate() {
+        let share = (left / (sizes.len() - n)).max(MIN_SHARE);
+        shares[i] = sizes[i].min(share);
+        left = left.saturating_sub(shares[i]);
+    }
+    shares
+}
+
+/// `text` cut to at most `share` bytes, note included: whole lines from the
+/// start, or the start of the first line when it alone is too long. Returns
+/// the text and the lines left out, counting one cut short.
+pub fn trim(operation: &str, text: &str, share: usize) -> (String, usize) {
+    let total = text.lines().count();
+    // Room for the note, which is longest with the largest counts
+    let room = share.saturating_sub(note(operation, text.len(), total).len() + 1);
+    let mut kept = 0;
+    let mut lines = 0;
+    for line in text.split_inclusive('\n') {
+        if kept + line.len() > room {
+            break;
+        }
+        kept += line.len();
+        lines += 1;
+    }
+    if lines == 0 {
+        kept = (0..=room.min(text.len())).rev().find(|i| text.is_char_boundary(*i)).unwrap_or(0);
+    }
+    let dropped = total - lines;
+    (format!("{}\n{}", text[..kept].trim_end_matches('\n'), note(operation, text.len() - kept, dropped)), dropped)
+}
+
+fn note(operation: &str, bytes: usize, lines: usize) -> String {
+    if LISTINGS.contains(&operation) {
+        format!("[… {} more entries trimmed to fit the response budget]", lines)
+    } else if READS.contains(&operation) {
+        format!("[… {} more bytes ({} lines) trimmed to fit the response budget; read on with offset]", bytes, lines)
+    } else {
+        format!("[… {} more bytes ({} lines) trimmed to fit the response budget]", bytes, lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_what_short_results_leave() {
+        assert_eq!(shares(&[100, 5000, 50, 3000], 2000), vec![100, 925, 50, 925]);
+        assert_eq!(shares(&[100, 150], 2000), vec![100, 150]);
+        assert_eq!(shares(&[1000, 1000], 10), vec![200, 200]);
+    }
+
+    #[test]
+    fn keeps_whole_lines_from_the_start() {
+        let listing: String = (0..100).map(|i| format!("- file{}.rs\n", i)).collect();
+        let (text, dropped) = trim("list-files", &listing, 200);
+        assert!(text.len() <= 200);
+        assert!(text.starts_with("- file0.rs\n- file1.rs\n"));
+        assert!(text.ends_with(&format!("[… {} more entries trimmed to fit the response budget]", dropped)));
+
+        let (text, dropped) = trim("read-file", &"x".repeat(500), 200);
+        assert_eq!(dropped, 1);
+        assert!(text.len() <= 200 && text.starts_with("xxx"));
+    }
+}
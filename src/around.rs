@@ -0,0 +1,88 @@
+//! The lines around matches in a file, for `read-around`: what is needed
+//! to write an edit's anchor without reading the whole file.
+//!
+//! Matches close enough for their context to touch are shown as one
+//! region. Lines are numbered from 1 and marked the way `grep -n -C` marks
+//! them: `:` after the number of a matching line, `-` after a context line.
+
+use serde::Serialize;
+
+/// Context lines on each side when the command gives no `lines`
+pub const DEFAULT_LINES: usize = 3;
+
+/// Most context lines on each side
+pub const MAX_LINES: usize = 100;
+
+/// Most matches shown; the rest are only counted
+pub const MAX_MATCHES: usize = 50;
+
+/// Consecutive lines of the file around one or more matches.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Region {
+    /// First and last line shown, from 1
+    pub start: usize,
+    pub end: usize,
+    /// Lines in the region holding part of a match
+    pub matches: Vec<usize>,
+}
+
+/// The regions around the byte ranges `matches` in `text`, with `context`
+/// lines either side.
+pub fn regions(text: &str, matches: &[(usize, usize)], context: usize) -> Vec<Region> {
+    let starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1).filter(|i| *i < text.len()))
+        .collect();
+    let line_of = |offset: usize| starts.partition_point(|start| *start <= offset);
+    let last_line = starts.len();
+
+    let mut regions: Vec<Region> = Vec::new();
+    for &(start, end) in matches {
+        let first = line_of(start);
+        let last = line_of(end.saturating_sub(1).max(start));
+        let shown = (first.saturating_sub(context).max(1), (last + context).min(last_line));
+        match regions.last_mut() {
+            Some(region) if shown.0 <= region.end + 1 => {
+                region.end = region.end.max(shown.1);
+                // Matches come in order, so only the last line can repeat
+                let after = region.matches.last().map_or(first, |line| (line + 1).max(first));
+                region.matches.extend(after..=last);
+            }
+            _ => regions.push(Region { start: shown.0, end: shown.1, matches: (first..=last).collect() }),
+        }
+    }
+    regions
+}
+
+/// The regions as numbered lines, separated by `--`.
+pub fn render(text: &str, regions: &[Region]) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let width = regions.last().map_or(1, |region| region.end.to_string().len());
+    regions
+        .iter()
+        .map(|region| {
+            (region.start..=region.end)
+                .map(|number| {
+                    let mark = if region.matches.contains(&number) { ':' } else { '-' };
+                    format!("{:>width$}{} {}", number, mark, lines.get(number - 1).unwrap_or(&""), width = width)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n--\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_nearby_matches() {
+        let text = "a\nb\nfoo\nc\nd\nfoo\ne\nf\ng\nh\ni\nfoo\n";
+        let matches: Vec<(usize, usize)> = text.match_indices("foo").map(|(i, m)| (i, i + m.len())).collect();
+        let found = regions(text, &matches, 1);
+        assert_eq!(found, vec![Region { start: 2, end: 7, matches: vec![3, 6] }, Region { start: 11, end: 12, matches: vec![12] }]);
+        assert_eq!(render(text, &found[1..]), "11- i\n12: foo");
+        assert_eq!(render(text, &regions(text, &matches[..1], 0)), "3: foo");
+    }
+}
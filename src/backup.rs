@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Prior content of a file, archived in the store before it was overwritten.
+/// Only the hash and the store's id are kept locally.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub hash: String,
+    /// Id the store assigned to the archived content
+    pub id: String,
+    pub bytes: usize,
+    /// Head-update count when the backup was taken
+    pub turn: u64,
+}
+
+/// Pick a backup by content hash or store id, or the most recent one whose
+/// content differs from `current`, the hash of what the file holds now. With
+/// backups taken before every write, the latest record is usually the file
+/// as it already is.
+pub fn find<'a>(records: &'a [BackupRecord], selector: Option<&str>, current: Option<&str>) -> Option<&'a BackupRecord> {
+    match selector {
+        Some(selector) => records
+            .iter()
+            .rev()
+            .find(|record| record.hash == selector || record.id == selector),
+        None => records.iter().rev().find(|record| Some(record.hash.as_str()) != current),
+    }
+}
+
+/// A backup given up by `gc-backups`: no longer restorable, and left for
+/// the store's owner to collect.
+#[derive(Debug, Clone, Serialize)]
+pub struct Released {
+    pub path: String,
+    pub id: String,
+    pub hash: String,
+    pub bytes: usize,
+}
+
+/// The backups of files at or beneath `path`, or of every file when it is
+/// empty, beyond the `keep` most recent of each.
+pub fn expired(backups: &BTreeMap<String, Vec<BackupRecord>>, path: &str, keep: usize) -> Vec<Released> {
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+    backups
+        .iter()
+        .filter(|(file, _)| path.is_empty() || *file == path || file.starts_with(&format!("{}/", path)))
+        .flat_map(|(file, records)| {
+            records[..records.len().saturating_sub(keep)].iter().map(|record| Released {
+                path: file.clone(),
+                id: record.id.clone(),
+                hash: record.hash.clone(),
+                bytes: record.bytes,
+            })
+        })
+        .collect()
+}
+
+/// The entry left in the backup store naming what was released. The store
+/// protocol has no delete, so this is how its owner learns which entries
+/// nothing refers to any more.
+pub fn marker(child_id: &str, turn: u64, released: &[Released]) -> Vec<u8> {
+    serde_json::to_vec(&json!({"fs_child_gc": {"child_id": child_id, "turn": turn, "released": released}})).unwrap_or_default()
+}
@@ -0,0 +1,69 @@
+//! Per-command limits on work that grows with the size of the tree, so a
+//! command over a huge directory returns what it has so far, with a way to
+//! continue, instead of running without end.
+
+/// Work left for one command: directory entries it may still visit (each
+/// costs a host call) and bytes it may still read.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    entries: usize,
+    bytes: usize,
+    exhausted: bool,
+}
+
+impl Budget {
+    pub fn new(entries: usize, bytes: usize) -> Self {
+        Self {
+            entries,
+            bytes,
+            exhausted: false,
+        }
+    }
+
+    /// Spend one entry visit; `false` once none are left.
+    pub fn visit(&mut self) -> bool {
+        if self.entries == 0 {
+            self.exhausted = true;
+            return false;
+        }
+        self.entries -= 1;
+        true
+    }
+
+    /// Charge bytes already read. A file's size is only known once it has
+    /// been read, so the read that crosses the limit completes and the next
+    /// one is refused.
+    pub fn spend_bytes(&mut self, bytes: usize) {
+        if bytes >= self.bytes {
+            self.bytes = 0;
+            self.exhausted = true;
+        } else {
+            self.bytes -= bytes;
+        }
+    }
+
+    /// Whether some work was refused or the byte allowance is used up.
+    pub fn exhausted(&self) -> bool {
+        self.exhausted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_at_either_limit() {
+        let mut budget = Budget::new(2, 10);
+        assert!(budget.visit() && budget.visit());
+        assert!(!budget.exhausted());
+        assert!(!budget.visit());
+        assert!(budget.exhausted());
+
+        let mut budget = Budget::new(5, 10);
+        budget.spend_bytes(6);
+        assert!(!budget.exhausted());
+        budget.spend_bytes(6);
+        assert!(budget.exhausted());
+    }
+}
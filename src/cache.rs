@@ -0,0 +1,56 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Small bounded least-recently-used cache. Entries are evicted oldest-use
+/// first once `capacity` is reached.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, V>,
+    order: VecDeque<K>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let value = self.entries.get(key)?.clone();
+        self.touch(key);
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key.clone(), value).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Drop every entry whose key fails `keep`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        self.order.retain(|k| keep(k));
+        self.entries.retain(|k, _| keep(k));
+    }
+
+    fn touch(&mut self, key: &K) {
+        if let Some(position) = self.order.iter().position(|k| k == key) {
+            if let Some(k) = self.order.remove(position) {
+                self.order.push_back(k);
+            }
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Structural edits to the dependency tables of a Cargo.toml.
+//!
+//! The manifest is parsed with `toml_edit`, so comments, key order and
+//! formatting outside the entry being changed survive the edit, and the
+//! result is always a manifest Cargo can parse.
+
+use toml_edit::{DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+/// The kinds of dependency table a manifest or target section can hold.
+const KINDS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// A dependency to add, or to merge into an existing entry.
+#[derive(Debug, Default)]
+pub struct Dependency<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub features: Vec<&'a str>,
+    pub optional: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Updated,
+    Unchanged,
+}
+
+/// `text` with `dependency` added to `table`, or merged into the entry
+/// already there: its version replaced, features added to the list, and
+/// `optional` set when asked for. A table kept in alphabetical order stays
+/// that way.
+pub fn add_dependency(text: &str, table: &str, dependency: &Dependency) -> Result<(String, Change), String> {
+    check_name(dependency.name)?;
+    let mut doc = parse(text)?;
+    let deps = table_mut(doc.as_table_mut(), &table_path(table)?)?;
+    let added = match deps.get_mut(dependency.name) {
+        Some(entry) => {
+            merge(entry, dependency)?;
+            false
+        }
+        None => {
+            let sorted = is_sorted(deps);
+            deps.insert(dependency.name, new_entry(dependency));
+            if sorted {
+                deps.sort_values();
+            }
+            true
+        }
+    };
+    let updated = doc.to_string();
+    let change = match (added, updated == text) {
+        (true, _) => Change::Added,
+        (false, true) => Change::Unchanged,
+        (false, false) => Change::Updated,
+    };
+    Ok((updated, change))
+}
+
+/// `text` with a new version set, and the tables that changed. Without a
+/// `dependency` this is the package's own version; with one it is that
+/// dependency's version in every table listing it, or only in `table`.
+pub fn set_version(
+    text: &str,
+    dependency: Option<&str>,
+    table: Option<&str>,
+    version: &str,
+) -> Result<(String, Vec<String>), String> {
+    let mut doc = parse(text)?;
+    let mut changed = Vec::new();
+    match dependency {
+        None => {
+            let path = if doc.get("package").is_some() { vec!["package"] } else { vec!["workspace", "package"] };
+            let package = existing_mut(doc.as_table_mut(), &path)
+                .ok_or("the manifest has no [package] or [workspace.package] table")?;
+            match package.get_mut("version") {
+                Some(item) if item.is_table_like() => {
+                    return Err("the package version is inherited from the workspace; set it in the workspace manifest".to_string())
+                }
+                Some(item) => set_string(item, version),
+                None => {
+                    package.insert("version", toml_edit::value(version));
+                }
+            }
+            changed.push(path.join("."));
+        }
+        Some(name) => {
+            check_name(name)?;
+            let platforms = platforms(&doc);
+            let paths = match table {
+                Some(table) => vec![table_path(table)?],
+                None => dependency_tables(&platforms),
+            };
+            let dependency = Dependency { name, version, ..Default::default() };
+            for path in paths {
+                let Some(entry) = existing_mut(doc.as_table_mut(), &path).and_then(|deps| deps.get_mut(name)) else {
+                    continue;
+                };
+                merge(entry, &dependency)?;
+                changed.push(path.join("."));
+            }
+        }
+    }
+    Ok((doc.to_string(), changed))
+}
+
+fn parse(text: &str) -> Result<DocumentMut, String> {
+    text.parse::<DocumentMut>()
+        .map_err(|e| format!("not a valid TOML file: {}", e.to_string().trim_end()))
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a valid crate name", name))
+    }
+}
+
+/// The keys leading to a table named like `dev-dependencies`,
+/// `workspace.dependencies` or `target.'cfg(unix)'.dependencies`.
+fn table_path(table: &str) -> Result<Vec<&str>, String> {
+    let table = table.trim();
+    if KINDS.contains(&table) {
+        return Ok(vec![table]);
+    }
+    if table == "workspace.dependencies" {
+        return Ok(vec!["workspace", "dependencies"]);
+    }
+    if let Some((platform, kind)) = table.strip_prefix("target.").and_then(|rest| rest.rsplit_once('.')) {
+        let platform = platform.trim_matches(['\'', '"']);
+        if KINDS.contains(&kind) && !platform.is_empty() {
+            return Ok(vec!["target", platform, kind]);
+        }
+    }
+    Err(format!(
+        "'{}' is not a dependency table; expected {}, workspace.dependencies or target.<platform>.<kind>",
+        table,
+        KINDS.join(", ")
+    ))
+}
+
+/// The platforms the manifest has `[target.<platform>]` sections for.
+fn platforms(doc: &DocumentMut) -> Vec<String> {
+    doc.get("target")
+        .and_then(Item::as_table_like)
+        .map(|targets| targets.iter().map(|(platform, _)| platform.to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Every dependency table a manifest with these platforms can have.
+fn dependency_tables(platforms: &[String]) -> Vec<Vec<&str>> {
+    let mut paths: Vec<Vec<&str>> = KINDS.iter().map(|kind| vec![*kind]).collect();
+    paths.push(vec!["workspace", "dependencies"]);
+    for platform in platforms {
+        paths.extend(KINDS.iter().map(|kind| vec!["target", platform.as_str(), kind]));
+    }
+    paths
+}
+
+fn existing_mut<'d>(root: &'d mut Table, path: &[&str]) -> Option<&'d mut dyn TableLike> {
+    let mut current: &mut dyn TableLike = root;
+    for key in path {
+        current = current.get_mut(key)?.as_table_like_mut()?;
+    }
+    Some(current)
+}
+
+/// The table at `path`, created if missing.
+fn table_mut<'d>(root: &'d mut Table, path: &[&str]) -> Result<&'d mut dyn TableLike, String> {
+    let mut current: &mut dyn TableLike = root;
+    for (i, key) in path.iter().enumerate() {
+        if !current.contains_key(key) {
+            let mut table = Table::new();
+            // Only the innermost table needs a header of its own
+            table.set_implicit(i + 1 < path.len());
+            current.insert(key, Item::Table(table));
+        }
+        current = current
+            .get_mut(key)
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| format!("'{}' in the manifest is not a table", path[..=i].join(".")))?;
+    }
+    Ok(current)
+}
+
+fn is_sorted(table: &dyn TableLike) -> bool {
+    let keys: Vec<&str> = table.iter().map(|(key, _)| key).collect();
+    keys.windows(2).all(|pair| pair[0] <= pair[1])
+}
+
+/// A plain `name = "version"` when that says everything, else an inline
+/// table.
+fn new_entry(dependency: &Dependency) -> Item {
+    if dependency.features.is_empty() && !dependency.optional {
+        return toml_edit::value(dependency.version);
+    }
+    let mut entry = InlineTable::new();
+    entry.insert("version", dependency.version.into());
+    if !dependency.features.is_empty() {
+        entry.insert("features", Value::Array(dependency.features.iter().copied().collect()));
+    }
+    if dependency.optional {
+        entry.insert("optional", true.into());
+    }
+    Item::Value(Value::InlineTable(entry))
+}
+
+fn merge(entry: &mut Item, dependency: &Dependency) -> Result<(), String> {
+    if let Some(old) = entry.as_str().map(str::to_string) {
+        if dependency.features.is_empty() && !dependency.optional {
+            set_string(entry, dependency.version);
+            return Ok(());
+        }
+        let mut table = InlineTable::new();
+        table.insert("version", old.into());
+        let decor = entry.as_value().map(|value| value.decor().clone()).unwrap_or_default();
+        *table.decor_mut() = decor;
+        *entry = Item::Value(Value::InlineTable(table));
+    }
+    let name = dependency.name;
+    let entry = entry
+        .as_table_like_mut()
+        .ok_or_else(|| format!("the entry for '{}' is neither a version nor a table", name))?;
+    if entry.get("workspace").and_then(Item::as_bool) == Some(true) {
+        return Err(format!(
+            "'{}' is inherited from the workspace; change it in [workspace.dependencies] instead",
+            name
+        ));
+    }
+
+    match entry.get_mut("version") {
+        Some(version) => set_string(version, dependency.version),
+        None => {
+            entry.insert("version", toml_edit::value(dependency.version));
+        }
+    }
+    if !dependency.features.is_empty() {
+        match entry.get_mut("features").and_then(Item::as_array_mut) {
+            Some(features) => {
+                for feature in &dependency.features {
+                    if !features.iter().any(|f| f.as_str() == Some(feature)) {
+                        features.push(*feature);
+                    }
+                }
+            }
+            None => {
+                let features = dependency.features.iter().copied().collect();
+                entry.insert("features", Item::Value(Value::Array(features)));
+            }
+        }
+    }
+    if dependency.optional {
+        entry.insert("optional", toml_edit::value(true));
+    }
+    Ok(())
+}
+
+/// Replace a string value, keeping the spacing and comments around it.
+fn set_string(item: &mut Item, text: &str) {
+    match item.as_value_mut() {
+        Some(value) => {
+            let decor = value.decor().clone();
+            *value = text.into();
+            *value.decor_mut() = decor;
+        }
+        None => *item = toml_edit::value(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "[package]\nname = \"demo\"\nversion = \"0.1.0\" # bumped by release\n\n[dependencies]\n# logging\nlog = \"0.4\"\nserde = { version = \"1.0\", features = [\"derive\"] }\n\n[target.'cfg(unix)'.dependencies]\nlibc = \"0.2\"\n";
+
+    #[test]
+    fn adds_and_merges_dependencies() {
+        let regex = Dependency { name: "regex", version: "1", ..Default::default() };
+        let (text, change) = add_dependency(MANIFEST, "dependencies", &regex).unwrap();
+        assert_eq!(change, Change::Added);
+        assert!(text.contains("# logging\nlog = \"0.4\"\nregex = \"1\"\nserde = {"));
+
+        let serde = Dependency { name: "serde", version: "1.0.200", features: vec!["derive", "rc"], optional: false };
+        let (text, change) = add_dependency(MANIFEST, "dependencies", &serde).unwrap();
+        assert_eq!(change, Change::Updated);
+        assert!(text.contains("serde = { version = \"1.0.200\", features = [\"derive\", \"rc\"] }\n"));
+
+        let log = Dependency { name: "log", version: "0.4", features: vec!["std"], optional: true };
+        let (text, _) = add_dependency(MANIFEST, "dependencies", &log).unwrap();
+        assert!(text.contains("log = { version = \"0.4\", features = [\"std\"], optional = true }\n"));
+
+        let (text, change) = add_dependency(MANIFEST, "dev-dependencies", &regex).unwrap();
+        assert_eq!(change, Change::Added);
+        assert!(text.ends_with("\n[dev-dependencies]\nregex = \"1\"\n"));
+        assert!(add_dependency(MANIFEST, "dependencies = 1", &regex).is_err());
+    }
+
+    #[test]
+    fn sets_versions() {
+        let (text, changed) = set_version(MANIFEST, None, None, "0.2.0").unwrap();
+        assert!(text.contains("version = \"0.2.0\" # bumped by release\n"));
+        assert_eq!(changed, ["package"]);
+
+        let (text, changed) = set_version(MANIFEST, Some("libc"), None, "0.2.150").unwrap();
+        assert!(text.contains("libc = \"0.2.150\"\n"));
+        assert_eq!(changed, ["target.cfg(unix).dependencies"]);
+
+        let (_, changed) = set_version(MANIFEST, Some("tokio"), None, "1").unwrap();
+        assert!(changed.is_empty());
+
+        let inherited = "[package]\nname = \"a\"\nversion.workspace = true\n";
+        assert!(set_version(inherited, None, None, "1.0.0").is_err());
+    }
+}
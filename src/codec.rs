@@ -0,0 +1,50 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// How the actor state blob handed back to the runtime is encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateEncoding {
+    /// Readable, and what every existing state blob uses
+    #[default]
+    Json,
+    /// Compact binary, much cheaper once history and manifests grow
+    Cbor,
+}
+
+pub fn encode<T: Serialize>(value: &T, encoding: StateEncoding) -> Result<Vec<u8>, String> {
+    match encoding {
+        StateEncoding::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+        StateEncoding::Cbor => {
+            let mut bytes = Vec::new();
+            ciborium::into_writer(value, &mut bytes).map_err(|e| e.to_string())?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Decode a state blob in either encoding. A JSON state always starts with
+/// `{`, which as a CBOR initial byte would be a text string, never a map, so
+/// the first byte tells the two apart.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    if bytes.first() == Some(&b'{') {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    } else {
+        ciborium::from_reader(bytes).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn round_trips_in_both_encodings() {
+        let value = json!({"name": "default", "turn": 3, "history": [{"seq": 1}], "html": null});
+        for encoding in [StateEncoding::Json, StateEncoding::Cbor] {
+            let bytes = encode(&value, encoding).unwrap();
+            assert_eq!(decode::<Value>(&bytes).unwrap(), value);
+        }
+    }
+}
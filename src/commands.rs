@@ -0,0 +1,3740 @@
+//! Commands: what one asks for, running a batch of them against the
+//! workspace, and the result of each.
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "regex")]
+use regex::Regex;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+use crate::backup::BackupRecord;
+use crate::budget::Budget;
+use crate::cache::LruCache;
+use crate::encoding::Bom;
+use crate::error::{ErrorCode, FsChildError};
+use crate::fs::Filesystem;
+use crate::history::HistoryEntry;
+use crate::host::Capability;
+use crate::macros::Macro;
+use crate::mounts::Mount;
+use crate::operations::{operation_spec, OperationSpec};
+use crate::quota::Limit;
+use crate::state::{ChildMessage, State};
+use crate::transaction::Journal;
+use crate::{around, backup, compact, cursors, conflict, csv, diff, edit, editorconfig, encoding, fuzzy, handlers, history, logger, macros, mounts, operations, overview, path, policy, pretty, queue, quota, report, render, schema, staging, toc, vcs, watch};
+#[cfg(feature = "structured")]
+use crate::{cargo_toml, frontmatter};
+#[cfg(feature = "templates")]
+use crate::template;
+#[cfg(feature = "archive")]
+use crate::tar;
+
+/// Directory listings kept in memory between requests
+const LIST_CACHE_CAPACITY: usize = 32;
+
+/// Compiled regular expressions kept in memory between requests
+#[cfg(feature = "regex")]
+const REGEX_CACHE_CAPACITY: usize = 32;
+
+thread_local! {
+    /// Listings by resolved path, with the turn they were taken on. Dropped
+    /// when this actor changes anything under the path, and otherwise after
+    /// `list_cache_turns` head-updates.
+    static LIST_CACHE: RefCell<LruCache<String, (u64, Vec<String>)>> =
+        RefCell::new(LruCache::new(LIST_CACHE_CAPACITY));
+
+    /// Sessions reuse a handful of patterns turn after turn, and compiling
+    /// is far costlier than matching.
+    #[cfg(feature = "regex")]
+    static REGEX_CACHE: RefCell<LruCache<String, Regex>> =
+        RefCell::new(LruCache::new(REGEX_CACHE_CAPACITY));
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FsCommand {
+    pub(crate) operation: String,
+    #[serde(default)]
+    pub(crate) path: String,
+    pub(crate) content: Option<String>,
+    pub(crate) old_text: Option<String>,
+    pub(crate) new_text: Option<String>,
+    /// Commands carrying a key that already succeeded are not run again
+    pub(crate) idempotency_key: Option<String>,
+    /// Id of a value in a store, for store transfer operations
+    pub(crate) id: Option<String>,
+    /// Role of the store to use for store transfer operations
+    pub(crate) store: Option<String>,
+    /// Most recent backups to keep per file, for `gc-backups`
+    pub(crate) keep: Option<String>,
+    /// Operation to describe, for `help` and `why-denied`
+    pub(crate) topic: Option<String>,
+    /// First and last turn (head-update count) to include, for `export-history`
+    pub(crate) since: Option<String>,
+    pub(crate) until: Option<String>,
+    /// Byte offset to start reading at, or `next` to resume, for `read-file`
+    pub(crate) offset: Option<String>,
+    /// Most bytes to return, for `read-file`
+    pub(crate) length: Option<String>,
+    /// SHA-256, or a prefix of it, of the content last read; `read-file`
+    /// answers "unchanged" while the file still has it
+    pub(crate) if_changed_since: Option<String>,
+    /// `true` to treat `old_text` as a regular expression, for `edit-file`
+    pub(crate) regex: Option<String>,
+    /// Which match of `old_text` to replace, counting from 1, for `edit-file`
+    pub(crate) occurrence: Option<String>,
+    /// `true` to replace every match of `old_text`, for `edit-file`
+    pub(crate) replace_all: Option<String>,
+    /// Text to find, or with `regex` a regular expression, for `read-around`
+    pub(crate) pattern: Option<String>,
+    /// Lines of context either side of each match, for `read-around`
+    pub(crate) lines: Option<String>,
+    /// `true` to let any run of whitespace in `old_text` match any run in
+    /// the file, for `edit-file`
+    pub(crate) ignore_whitespace: Option<String>,
+    /// `true` to let `old_text` match text that is nearly the same, for
+    /// `edit-file`
+    pub(crate) fuzzy: Option<String>,
+    /// Least confidence, from 0 to 1, a fuzzy match needs
+    pub(crate) min_confidence: Option<String>,
+    /// `true` to list every file beneath `path`, for `list-files`
+    pub(crate) recursive: Option<String>,
+    /// Directory levels to descend, at most `max_walk_depth`; for
+    /// `generate-toc`, the deepest heading level listed
+    pub(crate) depth: Option<String>,
+    /// `next_cursor` of an earlier recursive listing, to continue it
+    pub(crate) cursor: Option<String>,
+    /// How the whole batch runs; see [`BatchMode`]
+    pub(crate) mode: Option<String>,
+    /// `true` or a number of head-updates to hold the command back for;
+    /// see the `queue` module
+    pub(crate) defer: Option<String>,
+    /// `true` to pretty-print JSON, TOML or YAML content, for `write-file`
+    pub(crate) format: Option<String>,
+    /// `true` to sort JSON object keys when formatting
+    pub(crate) sort_keys: Option<String>,
+    /// `false` to refuse to replace a file that exists, for `write-file`
+    pub(crate) overwrite: Option<String>,
+    /// `false` to refuse to create a file that does not exist, for
+    /// `write-file`
+    pub(crate) create: Option<String>,
+    /// `true` to replace a file with content far smaller than it, for
+    /// `write-file`
+    pub(crate) confirm_truncation: Option<String>,
+    /// `true` to show what a `write-file` would change, as a diff, without
+    /// writing
+    pub(crate) dry_run: Option<String>,
+    /// Schema the written JSON must satisfy: a path to a schema file, or
+    /// the schema itself, for `write-file` and `edit-file`
+    pub(crate) schema: Option<String>,
+    /// Front matter key, for `frontmatter-get` and `frontmatter-set`
+    pub(crate) key: Option<String>,
+    /// Value to set, as JSON or else as plain text, for `frontmatter-set`
+    pub(crate) value: Option<String>,
+    /// Crate name, for `cargo-add-dep` and `cargo-set-version`
+    pub(crate) dependency: Option<String>,
+    /// Version requirement, or the package version, for the `cargo-*`
+    /// operations
+    pub(crate) version: Option<String>,
+    /// Comma-separated features to enable, for `cargo-add-dep`
+    pub(crate) features: Option<String>,
+    /// Dependency table such as `dev-dependencies`, for the `cargo-*`
+    /// operations
+    pub(crate) table: Option<String>,
+    /// `true` to make the dependency optional, for `cargo-add-dep`
+    pub(crate) optional: Option<String>,
+    /// File a conversion writes; see [`FsCommand::target`]
+    pub(crate) destination: Option<String>,
+    /// `false` when a CSV file has no header row, for the conversions
+    pub(crate) header: Option<String>,
+    /// CSV field separator, for the conversions
+    pub(crate) delimiter: Option<String>,
+    /// Macro name, for `define-macro` and `run-macro`
+    #[serde(rename = "macro")]
+    pub(crate) macro_name: Option<String>,
+    /// `name=value` arguments, for `run-macro`
+    pub(crate) args: Option<String>,
+    /// Commit message, for `commit`
+    pub(crate) message: Option<String>,
+    /// `true` to discard uncommitted changes, for `checkout`, or to replace
+    /// a file someone else changed, for the operations that overwrite
+    pub(crate) force: Option<String>,
+    /// JSON object the template is filled from, for `render`
+    pub(crate) context: Option<String>,
+    /// Token from a result that stopped early, for `continue`
+    pub(crate) token: Option<String>,
+}
+
+impl FsCommand {
+    /// Value of a named field, treating an empty path as absent.
+    fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "path" => Some(self.path.as_str()).filter(|path| !path.is_empty()),
+            "content" => self.content.as_deref(),
+            // An empty anchor would match everywhere, so counts as missing
+            "old_text" => self.old_text.as_deref().filter(|text| !text.is_empty()),
+            "new_text" => self.new_text.as_deref(),
+            "idempotency_key" => self.idempotency_key.as_deref(),
+            "id" => self.id.as_deref(),
+            "store" => self.store.as_deref(),
+            "keep" => self.keep.as_deref(),
+            "topic" => self.topic.as_deref(),
+            "since" => self.since.as_deref(),
+            "until" => self.until.as_deref(),
+            "offset" => self.offset.as_deref(),
+            "length" => self.length.as_deref(),
+            "if_changed_since" => self.if_changed_since.as_deref(),
+            "regex" => self.regex.as_deref(),
+            "occurrence" => self.occurrence.as_deref(),
+            "replace_all" => self.replace_all.as_deref(),
+            "pattern" => self.pattern.as_deref(),
+            "lines" => self.lines.as_deref(),
+            "ignore_whitespace" => self.ignore_whitespace.as_deref(),
+            "fuzzy" => self.fuzzy.as_deref(),
+            "min_confidence" => self.min_confidence.as_deref(),
+            "recursive" => self.recursive.as_deref(),
+            "depth" => self.depth.as_deref(),
+            "cursor" => self.cursor.as_deref(),
+            "mode" => self.mode.as_deref(),
+            "defer" => self.defer.as_deref(),
+            "format" => self.format.as_deref(),
+            "sort_keys" => self.sort_keys.as_deref(),
+            "overwrite" => self.overwrite.as_deref(),
+            "create" => self.create.as_deref(),
+            "confirm_truncation" => self.confirm_truncation.as_deref(),
+            "dry_run" => self.dry_run.as_deref(),
+            "schema" => self.schema.as_deref(),
+            "key" => self.key.as_deref(),
+            "value" => self.value.as_deref(),
+            "dependency" => self.dependency.as_deref(),
+            "version" => self.version.as_deref(),
+            "features" => self.features.as_deref(),
+            "table" => self.table.as_deref(),
+            "optional" => self.optional.as_deref(),
+            "destination" => self.destination.as_deref(),
+            "header" => self.header.as_deref(),
+            "delimiter" => self.delimiter.as_deref(),
+            "macro" => self.macro_name.as_deref(),
+            "args" => self.args.as_deref(),
+            "message" => self.message.as_deref(),
+            "force" => self.force.as_deref(),
+            "context" => self.context.as_deref(),
+            "token" => self.token.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Bytes of content the command carries, as counted in the metrics.
+    fn request_bytes(&self) -> usize {
+        [&self.content, &self.old_text, &self.new_text, &self.value]
+            .iter()
+            .filter_map(|field| field.as_ref())
+            .map(String::len)
+            .sum()
+    }
+
+    /// The file a command writes. Conversions read `path` and write
+    /// `destination`, by default `path` with the new format's extension; a
+    /// manifest of `path`, or the file a template renders, is written to
+    /// `destination` if one is given.
+    fn target(&self) -> String {
+        let extension = match self.operation.as_str() {
+            "csv-to-json" => Some("json"),
+            "json-to-csv" => Some("csv"),
+            "manifest" | "render" => None,
+            _ => return self.path.clone(),
+        };
+        match (self.destination.as_deref().map(str::trim).filter(|d| !d.is_empty()), extension) {
+            (Some(destination), _) => path::normalize(destination).unwrap_or_else(|_| destination.to_string()),
+            (None, None) => self.path.clone(),
+            (None, Some(extension)) => {
+                let name_start = self.path.rfind('/').map_or(0, |i| i + 1);
+                match self.path[name_start..].rfind('.').filter(|dot| *dot > 0) {
+                    Some(dot) => format!("{}.{}", &self.path[..name_start + dot], extension),
+                    None => format!("{}.{}", self.path, extension),
+                }
+            }
+        }
+    }
+
+    /// Head-updates the command is held back for, if it is deferred.
+    fn deferral(&self) -> Result<Option<u64>, FsChildError> {
+        self.defer.as_deref().map_or(Ok(None), queue::delay).map_err(FsChildError::Parse)
+    }
+
+    /// Whether the command can change the filesystem: a write operation,
+    /// or a manifest saved to a file.
+    fn writes(&self) -> bool {
+        if self.previews() {
+            return false;
+        }
+        operation_spec(&self.operation).is_some_and(|spec| spec.permission == Some("write"))
+            || (matches!(self.operation.as_str(), "manifest" | "render") && self.destination.is_some())
+    }
+
+    /// Whether running the command again answers the same and changes
+    /// nothing, as a read does until something writes. Reads that send to
+    /// the store or change the subscriptions are not.
+    fn repeatable(&self) -> bool {
+        !self.writes()
+            && operation_spec(&self.operation).is_some_and(|spec| spec.permission == Some("read"))
+            && !matches!(self.operation.as_str(), "push-to-store" | "export-dir" | "subscribe" | "unsubscribe")
+    }
+
+    /// Whether the command is a `write-file` only showing what it would do.
+    pub(crate) fn previews(&self) -> bool {
+        self.operation == "write-file" && flag(self.dry_run.as_deref())
+    }
+}
+
+/// How the commands of one message are run. A batch takes the `mode` of the
+/// first command that names one, or the configured `batch_mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BatchMode {
+    /// Run each command in turn; a failure does not stop the rest
+    #[default]
+    Sequential,
+    /// Check every command first and run none unless all would succeed
+    ValidateFirst,
+    /// Validate first, then undo every change if a command still fails
+    Transactional,
+}
+
+/// Operations changing more than their target, such as the whole
+/// workspace or the history, which a transactional batch cannot undo and
+/// so refuses.
+const UNJOURNALED: &[&str] = &["checkout", "commit", "commit-staged", "compact"];
+
+/// What a write counted against quotas found before it ran.
+struct QuotaPrior {
+    prefixes: Vec<String>,
+    /// Usage under each prefix, in the same order
+    usage: Vec<quota::Usage>,
+    /// Size of the target, if it was a file
+    size: Option<usize>,
+    /// The target as it was, to put back if the write is refused
+    journal: Journal,
+}
+
+/// Result of a bounded recursive walk.
+#[derive(Debug, Default)]
+struct Walk {
+    files: Vec<String>,
+    /// Last path the walk finished with, to resume after when it stopped
+    /// early
+    next_cursor: Option<String>,
+    /// Directories not entered because they sit below the depth limit
+    pruned: Vec<String>,
+    /// Whether it stopped because the work budget ran out
+    budget_exhausted: bool,
+    /// Last path finished, file or whole directory
+    done: Option<String>,
+}
+
+/// How many idempotency keys are remembered before the oldest are forgotten
+const IDEMPOTENCY_KEY_LIMIT: usize = 256;
+
+/// Hex digits of a file's SHA-256 shown in read results
+const HASH_SHOWN: usize = 16;
+
+/// Shortest `if_changed_since` prefix compared against a file's SHA-256
+const MIN_HASH_PREFIX: usize = 8;
+
+/// Smallest file the truncation guard protects; below it, little is lost
+const TRUNCATION_MIN_BYTES: usize = 128;
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct CommandResult {
+    /// Position of the command in its batch; results are always in this order
+    pub(crate) index: usize,
+    pub(crate) operation: String,
+    pub(crate) path: String,
+    #[serde(skip)]
+    pub(crate) message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<ErrorCode>,
+    /// Why the command was not executed even though it did not fail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) skipped: Option<&'static str>,
+    /// Operation-specific structured output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) data: Option<Value>,
+}
+
+impl CommandResult {
+    pub(crate) fn ok(cmd: &FsCommand, message: String) -> Self {
+        Self {
+            index: 0,
+            operation: cmd.operation.clone(),
+            path: cmd.path.clone(),
+            message,
+            error: None,
+            skipped: None,
+            data: None,
+        }
+    }
+
+    pub(crate) fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub(crate) fn skipped(cmd: &FsCommand, reason: &'static str, message: String) -> Self {
+        Self {
+            skipped: Some(reason),
+            ..Self::ok(cmd, message)
+        }
+    }
+
+    pub(crate) fn error(cmd: &FsCommand, error: FsChildError) -> Self {
+        Self {
+            index: 0,
+            operation: cmd.operation.clone(),
+            path: cmd.path.clone(),
+            message: error.to_string(),
+            error: Some(error.code()),
+            skipped: None,
+            data: None,
+        }
+    }
+
+    pub(crate) fn host_err(cmd: &FsCommand, host_error: &str, message: String) -> Self {
+        Self::error(cmd, FsChildError::from_host(host_error, message))
+    }
+
+    /// A failure described by `context`, followed by the error itself.
+    pub(crate) fn failed(cmd: &FsCommand, error: FsChildError, context: String) -> Self {
+        Self {
+            message: format!("{}: {}", context, error),
+            ..Self::error(cmd, error)
+        }
+    }
+}
+
+impl State {
+    /// Bytes and files under `prefix`, found by reading every file. A tree
+    /// too large for the walk limits cannot be measured, and counting only
+    /// the part they reach would let writes past the quota.
+    fn measure(&self, prefix: &str) -> Result<quota::Usage, FsChildError> {
+        let mut budget = self.budget();
+        let walk = self.walk(prefix, self.max_walk_depth, None, self.max_walk_entries, &mut budget);
+        if walk.next_cursor.is_some() || !walk.pruned.is_empty() {
+            return Err(FsChildError::Quota(format!(
+                "Cannot check quota '{}': it holds more than the walk limits reach, so its usage is unknown. The parent can raise `max_walk_entries`, `max_walk_depth` or `max_work_entries`",
+                prefix
+            )));
+        }
+        let mut usage = quota::Usage::default();
+        for file in walk.files.iter().filter(|file| !vcs::is_internal(file)) {
+            if let Ok(content) = self.fs.read_file(&self.resolve_path(file)) {
+                usage.apply(None, Some(content.len()));
+            }
+        }
+        Ok(usage)
+    }
+
+    pub(crate) fn usage_of(&mut self, prefix: &str) -> Result<quota::Usage, FsChildError> {
+        if let Some(usage) = self.usage.get(prefix) {
+            return Ok(*usage);
+        }
+        let usage = self.measure(prefix)?;
+        self.usage.insert(prefix.to_string(), usage);
+        Ok(usage)
+    }
+
+    /// The quotas a command writing `target` counts against: those covering
+    /// it, or all of them for a checkout, which can change any file. The
+    /// history and mounts are not counted, nor are staged changes applied
+    /// or dropped, since they were counted as they were staged, nor
+    /// compaction, which leaves every file reading as it did, nor releasing
+    /// backups, which touches no file.
+    fn quotas_for(&self, cmd: &FsCommand, target: &str) -> Vec<String> {
+        let whole_workspace =
+            matches!(cmd.operation.as_str(), "commit" | "stage" | "commit-staged" | "discard-staged" | "compact" | "gc-backups");
+        // Prefixes are compared with where the target lands, not how it was written
+        let Some(target) = self.canonical(target).filter(|_| cmd.writes() && !whole_workspace) else {
+            return Vec::new();
+        };
+        if self.mount_at(&target).is_some() || vcs::is_internal(&target) {
+            return Vec::new();
+        }
+        self.quotas
+            .keys()
+            .filter(|prefix| cmd.operation == "checkout" || quota::covers(prefix, &target))
+            .cloned()
+            .collect()
+    }
+
+    /// Usage under each quota `cmd` counts against, before it runs. A
+    /// quota whose usage cannot be measured refuses the command.
+    fn quota_prior(&mut self, cmd: &FsCommand, target: &str, target_path: &str) -> Result<Option<QuotaPrior>, FsChildError> {
+        let prefixes = self.quotas_for(cmd, target);
+        if prefixes.is_empty() {
+            return Ok(None);
+        }
+        let usage = prefixes.iter().map(|prefix| self.usage_of(prefix)).collect::<Result<_, _>>()?;
+        let mut journal = Journal::default();
+        journal.record(&*self.fs, target_path);
+        let size = self.fs.read_file(target_path).ok().map(|content| content.len());
+        Ok(Some(QuotaPrior { prefixes, usage, size, journal }))
+    }
+
+    /// Count a successful write against its quotas. One taking a hard quota
+    /// over its limit is undone and fails; going over a soft quota, or any
+    /// quota by a checkout, adds a warning.
+    fn enforce_quotas(&mut self, cmd: &FsCommand, target: &str, target_path: &str, prior: QuotaPrior, result: CommandResult) -> CommandResult {
+        // These write more than their target, so are measured again
+        let remeasure = matches!(cmd.operation.as_str(), "import-dir" | "checkout");
+        let after = self.fs.read_file(target_path).ok().map(|content| content.len());
+        let mut over = Vec::new();
+        let mut unmeasured = None;
+        for (prefix, before) in prior.prefixes.iter().zip(&prior.usage) {
+            let now = match remeasure {
+                true => match self.measure(prefix) {
+                    Ok(now) => now,
+                    Err(e) => {
+                        self.usage.remove(prefix);
+                        unmeasured = Some(e);
+                        continue;
+                    }
+                },
+                false => {
+                    let mut now = *before;
+                    now.apply(prior.size, after);
+                    now
+                }
+            };
+            self.usage.insert(prefix.clone(), now);
+            let quota = &self.quotas[prefix];
+            if let Some(excess) = quota.excess(&now).filter(|_| now.grew_from(before)) {
+                over.push(json!({"quota": prefix, "excess": excess, "limit": quota.limit}));
+            }
+        }
+        if let Some(e) = unmeasured.filter(|_| cmd.operation != "checkout") {
+            let problems = prior.journal.rollback(&*self.fs);
+            self.usage.clear();
+            return CommandResult::failed(cmd, e, match problems.is_empty() {
+                true => format!("Undid the write to '{}'", target),
+                false => format!("Could not undo the write to '{}' ({})", target, problems.join("; ")),
+            });
+        }
+        let refused = over.iter().find(|o| o["limit"] == json!(Limit::Hard)).filter(|_| cmd.operation != "checkout");
+        if let Some(refused) = refused {
+            let problems = prior.journal.rollback(&*self.fs);
+            for (prefix, before) in prior.prefixes.iter().zip(prior.usage) {
+                self.usage.insert(prefix.clone(), before);
+            }
+            let (prefix, excess) = (refused["quota"].as_str().unwrap_or_default(), refused["excess"].as_str().unwrap_or_default());
+            let message = match problems.is_empty() {
+                true => format!("Writing '{}' would put '{}' over its quota ({}); the change was undone", target, prefix, excess),
+                false => {
+                    self.usage.clear();
+                    format!("Writing '{}' put '{}' over its quota ({}) and could not be undone: {}", target, prefix, excess, problems.join("; "))
+                }
+            };
+            return CommandResult::error(cmd, FsChildError::Quota(message)).with_data(json!({"quota": prefix, "excess": excess}));
+        }
+        if over.is_empty() {
+            return result;
+        }
+        let warnings: Vec<String> = over
+            .iter()
+            .map(|o| format!("'{}' holds {}", o["quota"].as_str().unwrap_or_default(), o["excess"].as_str().unwrap_or_default()))
+            .collect();
+        let mut result = result;
+        result.message.push_str(&format!(" (over quota: {})", warnings.join("; ")));
+        let mut data = result.data.take().unwrap_or_else(|| json!({}));
+        data["quota_warnings"] = json!(over);
+        result.with_data(data)
+    }
+
+    fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    /// Whether `permission` is granted where `path` is: by its mount, or
+    /// by the instance for paths under `base_path`.
+    fn allowed_at(&self, permission: &str, path: &str) -> bool {
+        match self.mount_at(path) {
+            Some((_, mount)) => mount.allows(permission),
+            None => self.has_permission(permission),
+        }
+    }
+
+    /// The mount a `name:path` command path is under, with its name and the
+    /// path inside it.
+    fn mount_of<'a>(&'a self, path: &'a str) -> Option<(&'a str, &'a Mount, &'a str)> {
+        let (name, rest) = mounts::split(path)?;
+        self.mounts.get(name).map(|mount| (name, mount, rest))
+    }
+
+    /// Where a command path lands once resolved on the host, however it
+    /// was written: in the innermost mount holding it, as `name:/rest`, or
+    /// under `base_path`, relative to it. `None` for a path outside both.
+    fn canonical(&self, path: &str) -> Option<String> {
+        let host = self.resolve_path(path);
+        let mount = self
+            .mounts
+            .iter()
+            .filter_map(|(name, mount)| Some((path::depth(&mount.path), name, path::within(&mount.path, &host)?)))
+            .max_by_key(|(depth, _, _)| *depth);
+        let base = path::within(&self.base_path, &host);
+        match (mount, base) {
+            (Some((depth, name, rest)), base) if base.is_none() || depth > path::depth(&self.base_path) => {
+                Some(format!("{}:{}", name, path::normalize(&format!("/{}", rest)).ok()?))
+            }
+            (_, base) => base,
+        }
+    }
+
+    /// The mount a command path lands in once resolved, with its name, so
+    /// `../docs/x` is in a mount at the host's `docs` directory just as
+    /// `docs:/x` is.
+    fn mount_at(&self, path: &str) -> Option<(&str, &Mount)> {
+        let canonical = self.canonical(path)?;
+        let (name, _) = mounts::split(&canonical)?;
+        self.mounts.get_key_value(name).map(|(name, mount)| (name.as_str(), mount))
+    }
+
+    /// `path::normalize` for command paths, which may name a mount. A path
+    /// inside a mount is kept absolute within it, as `name:/rest`.
+    fn normalize_path(&self, path: &str) -> Result<String, FsChildError> {
+        if let Some((name, _, rest)) = self.mount_of(path) {
+            return Ok(format!("{}:{}", name, path::normalize(&format!("/{}", rest))?));
+        }
+        match mounts::split(path) {
+            Some((name, rest)) if rest.starts_with('/') && !self.mounts.is_empty() => {
+                Err(FsChildError::InvalidPath(format!(
+                    "No mount named '{}' in '{}'; mounts: {}",
+                    name,
+                    path,
+                    self.mounts.keys().cloned().collect::<Vec<_>>().join(", ")
+                )))
+            }
+            _ => path::normalize(path),
+        }
+    }
+
+    pub(crate) fn spec_allowed(&self, spec: &OperationSpec) -> bool {
+        spec.permission
+            .map(|permission| self.has_permission(permission))
+            .unwrap_or(true)
+    }
+
+    /// Host path for a command path. A path that cannot be normalized is
+    /// refused by `check_command` before it gets here; internal callers
+    /// only build well-formed paths.
+    pub(crate) fn resolve_path(&self, relative_path: &str) -> String {
+        if let Some((_, mount, rest)) = self.mount_of(relative_path) {
+            let inside = path::normalize(&format!("/{}", rest)).unwrap_or_else(|_| rest.to_string());
+            return path::join(&mount.path, inside.trim_start_matches('/'))
+                .unwrap_or_else(|_| format!("{}/{}", mount.path, inside));
+        }
+        path::join(&self.base_path, relative_path)
+            .unwrap_or_else(|_| format!("{}/{}", self.base_path, relative_path))
+    }
+
+    /// The store actor serving a role, falling back to the chat store for
+    /// roles that were never registered.
+    pub(crate) fn store_for(&self, role: &str) -> Option<&str> {
+        self.stores
+            .get(role)
+            .or(self.store_id.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Turn a file's bytes into a read-file result. Files within
+    /// `read_chunk_bytes` are returned whole; larger ones (or any read with an
+    /// `offset`/`length`) return one window, with a cursor to continue from.
+    /// Only the window is decoded and kept; the rest is dropped right away.
+    /// Every result names the file's SHA-256; a read whose
+    /// `if_changed_since` still matches it returns no content at all.
+    pub(crate) fn read_window(&mut self, cmd: &FsCommand, content: Vec<u8>) -> CommandResult {
+        let sha256 = vcs::Entry::of(&content).hash;
+        let short = &sha256[..HASH_SHOWN];
+        if let Some(since) = cmd.if_changed_since.as_deref().map(str::trim) {
+            if since.len() >= MIN_HASH_PREFIX && sha256.starts_with(&since.to_ascii_lowercase()) {
+                return CommandResult::ok(cmd, format!("'{}' is unchanged (sha256 {})", cmd.path, short))
+                    .with_data(json!({"sha256": sha256, "unchanged": true}));
+            }
+        }
+        // Offsets count bytes of the decoded text, after the mark
+        let bom = Bom::detect(&content);
+        let content = match bom {
+            Some(bom) => encoding::strip_bom(content, bom),
+            None => content,
+        };
+        let total = content.len();
+        let start = match cmd.offset.as_deref().map(str::trim) {
+            None => 0,
+            Some("next") => self.read_cursors.get(&cmd.path).copied().unwrap_or(0),
+            Some(offset) => match offset.parse::<usize>() {
+                Ok(offset) => offset,
+                Err(_) => return CommandResult::error(cmd, FsChildError::Parse(format!("Invalid offset '{}'", offset))),
+            },
+        };
+        let limit = match cmd.length.as_deref().map(str::trim) {
+            None => self.read_chunk_bytes,
+            Some(length) => match length.parse::<usize>() {
+                Ok(length) if length > 0 => length,
+                _ => return CommandResult::error(cmd, FsChildError::Parse(format!("Invalid length '{}'", length))),
+            },
+        };
+
+        let whole = cmd.offset.is_none() && cmd.length.is_none() && total <= limit;
+        let (start, end) = if whole {
+            (0, total)
+        } else {
+            utf8_window(&content, start, limit)
+        };
+        let (window, encoding) = match std::str::from_utf8(&content[start..end]) {
+            Ok(window) => (window.to_string(), None),
+            Err(_) => match encoding::render(&content[start..end], self.non_utf8) {
+                Some(rendered) => (rendered, Some(self.non_utf8)),
+                None => return CommandResult::error(cmd, FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path))),
+            },
+        };
+        drop(content);
+        let shown_as = match (bom, encoding) {
+            (_, Some(mode)) => format!(" [not UTF-8, shown as {}]", mode.name()),
+            (Some(Bom::Utf8), None) => " [UTF-8 with BOM; BOM not shown]".to_string(),
+            (Some(bom), None) => format!(" [{}, shown as UTF-8]", bom.name()),
+            (None, None) => String::new(),
+        };
+
+        if whole {
+            self.read_cursors.remove(&cmd.path);
+            let result = CommandResult::ok(cmd, format!("Contents of '{}' (sha256 {}){}: {}", cmd.path, short, shown_as, window));
+            return match (bom, encoding) {
+                (None, None) => result.with_data(json!({"sha256": sha256})),
+                _ => result.with_data(json!({"sha256": sha256, "encoding": encoding, "bom": bom})),
+            };
+        }
+
+        let next_offset = (end < total).then_some(end);
+        let mut message = format!(
+            "Contents of '{}' (bytes {}-{} of {}, sha256 {}){}: {}",
+            cmd.path, start, end, total, short, shown_as, window
+        );
+        match next_offset {
+            Some(next) => {
+                self.read_cursors.insert(cmd.path.clone(), next);
+                message.push_str(&format!("\n[{} more bytes; continue with <offset>{}</offset> or <offset>next</offset>]", total - end, next));
+            }
+            None => {
+                self.read_cursors.remove(&cmd.path);
+            }
+        }
+        CommandResult::ok(cmd, message).with_data(json!({
+            "offset": start,
+            "end": end,
+            "total_bytes": total,
+            "next_offset": next_offset,
+            "sha256": sha256,
+            "encoding": encoding,
+            "bom": bom,
+        }))
+    }
+
+    /// Fetch a value by id from the store serving `role`.
+    pub(crate) fn store_get(
+        &self,
+        role: &str,
+        id: &str,
+        max_bytes: usize,
+    ) -> Result<Vec<u8>, FsChildError> {
+        let store_id = self
+            .store_for(role)
+            .ok_or_else(|| FsChildError::Store("Store ID not set".to_string()))?;
+        self.store_client.get(store_id, id, max_bytes)
+    }
+
+    /// Store a value in the store serving `role`, returning its assigned id.
+    pub(crate) fn store_put(&self, role: &str, content: Vec<u8>) -> Result<String, FsChildError> {
+        let store_id = self
+            .store_for(role)
+            .ok_or_else(|| FsChildError::Store("Store ID not set".to_string()))?;
+        self.store_client.put(store_id, content)
+    }
+
+    /// `list-files` with `recursive`: one page of the files beneath `path`.
+    pub(crate) fn list_recursive(&self, cmd: &FsCommand) -> CommandResult {
+        let depth = match cmd.depth.as_deref().map(str::trim) {
+            None => self.max_walk_depth,
+            Some(depth) => match depth.parse::<usize>() {
+                Ok(depth) => depth.min(self.max_walk_depth),
+                Err(_) => return CommandResult::error(cmd, FsChildError::Parse(format!("Invalid depth '{}'", depth))),
+            },
+        };
+        let after = cmd.cursor.as_deref().map(str::trim).filter(|cursor| !cursor.is_empty());
+        if self.fs.list_files(&self.resolve_path(&cmd.path)).is_err() {
+            return CommandResult::error(cmd, FsChildError::NotFound(format!("Failed to list files in '{}': not a directory", cmd.path)));
+        }
+
+        let mut budget = self.budget();
+        let walk = self.walk(&cmd.path, depth, after, self.max_walk_entries, &mut budget);
+        let mut message = format!(
+            "Files under '{}': {}",
+            cmd.path,
+            walk.files.iter().map(|f| format!(" {}", path::display(f))).collect::<Vec<_>>().join("\n")
+        );
+        if let Some(next) = &walk.next_cursor {
+            let reason = if walk.budget_exhausted { "the work budget ran out" } else { "the file limit" };
+            message.push_str(&format!(
+                "\n[Stopped after {} files at {}; continue with <cursor>{}</cursor>]",
+                walk.files.len(),
+                reason,
+                next
+            ));
+        }
+        if !walk.pruned.is_empty() {
+            message.push_str(&format!(
+                "\n[Not descended, deeper than {} levels: {}]",
+                depth,
+                walk.pruned.join(", ")
+            ));
+        }
+        CommandResult::ok(cmd, message).with_data(json!({
+            "files": walk.files,
+            "next_cursor": walk.next_cursor,
+            "pruned": walk.pruned,
+            "budget_exhausted": walk.budget_exhausted,
+        }))
+    }
+
+    /// `list_files`, reusing a listing taken within the last
+    /// `list_cache_turns` head-updates.
+    pub(crate) fn list_files_cached(&self, path: &str) -> Result<Vec<String>, String> {
+        if self.list_cache_turns == 0 {
+            return self.fs.list_files(path);
+        }
+        let cached = LIST_CACHE.with(|cache| cache.borrow_mut().get(&path.to_string()));
+        if let Some((turn, files)) = cached {
+            if self.turn < turn + self.list_cache_turns {
+                logger::trace("cache", "Listing served from cache", &[("path", &path)]);
+                return Ok(files);
+            }
+        }
+        let files = self.fs.list_files(path)?;
+        LIST_CACHE.with(|cache| {
+            cache
+                .borrow_mut()
+                .insert(path.to_string(), (self.turn, files.clone()))
+        });
+        Ok(files)
+    }
+
+    /// Permission and required-field checks every command must pass before
+    /// it runs. Unknown operations pass, and are reported when executed.
+    pub(crate) fn check_command(&self, cmd: &FsCommand) -> Result<(), FsChildError> {
+        let Some(spec) = operation_spec(&cmd.operation) else {
+            return Ok(());
+        };
+        self.authorize(cmd, spec)?;
+        if let Some(capability) = self.host.lacks_for(&cmd.operation) {
+            return Err(unsupported(&cmd.operation, capability));
+        }
+        if let Some(feature) = spec.feature().filter(|feature| !operations::built(feature)) {
+            return Err(not_built(&format!("Operation '{}' is", cmd.operation), feature));
+        }
+        if flag(cmd.regex.as_deref()) && !operations::built("regex") {
+            return Err(not_built("Regular-expression edits are", "regex"));
+        }
+        if !cmd.path.is_empty() {
+            self.normalize_path(&cmd.path)?;
+        }
+        if let Some(destination) = &cmd.destination {
+            self.normalize_path(destination)?;
+        }
+        let missing: Vec<&str> = spec
+            .required
+            .iter()
+            .copied()
+            .filter(|field| cmd.field(field).is_none())
+            .collect();
+        if !missing.is_empty() {
+            return Err(FsChildError::MissingField(format!(
+                "Missing required field(s) for '{}': {}",
+                cmd.operation,
+                missing.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether the permissions, mounts and policy let a command run.
+    fn authorize(&self, cmd: &FsCommand, spec: &OperationSpec) -> Result<(), FsChildError> {
+        match self.refusal(cmd, spec) {
+            Some(refusal) => Err(refusal.error),
+            None => Ok(()),
+        }
+    }
+
+    /// Why the permissions, mounts or policy refuse a command, if they do,
+    /// and what the parent would change in the configuration to allow it.
+    fn refusal(&self, cmd: &FsCommand, spec: &OperationSpec) -> Option<Refusal> {
+        let destination = cmd.destination.clone().filter(|d| !d.trim().is_empty());
+        if let Some(permission) = spec.permission {
+            let mut paths = vec![cmd.path.clone()];
+            if cmd.writes() {
+                paths.push(cmd.target());
+                paths.extend(destination.clone());
+            }
+            // Malformed paths are left for `check_command` to report
+            let outside = paths.iter().find(|p| !p.is_empty() && self.normalize_path(p).is_ok() && self.canonical(p).is_none());
+            if let Some(outside) = outside {
+                let message = format!("Operation '{}' refused: '{}' resolves outside `base_path` and every mount", cmd.operation, outside);
+                return Some(Refusal::outside(message, permission));
+            }
+        }
+        if let Some(permission) = spec.permission.filter(|permission| !self.allowed_at(permission, &cmd.path)) {
+            let (subject, key) = self.permissions_key(&cmd.path);
+            let message = match self.mount_at(&cmd.path) {
+                Some((name, _)) => format!("Operation '{}' not permitted in mount '{}'", cmd.operation, name),
+                None => format!("Operation '{}' not permitted", cmd.operation),
+            };
+            return Some(Refusal::lacking(format!("{}: it needs the '{}' permission, which {} lacks", message, permission, subject), permission, key));
+        }
+        if cmd.writes() {
+            if let Some(target) = [Some(cmd.target()), destination].into_iter().flatten().find(|t| !self.allowed_at("write", t)) {
+                let message = format!("Operation '{}' writes '{}', which needs the 'write' permission", cmd.operation, target);
+                return Some(Refusal::lacking(message, "write", self.permissions_key(&target).1));
+            }
+        }
+        self.policy_paths(cmd, spec).iter().find_map(|path| self.policy_refusal(cmd, spec, path))
+    }
+
+    /// The policy rule refusing `cmd` on `path`, if one does.
+    fn policy_refusal(&self, cmd: &FsCommand, spec: &OperationSpec, path: &str) -> Option<Refusal> {
+        let decision = policy::evaluate(&self.policy, &action(cmd, spec), path);
+        let (rule, denial) = (decision.rule?, decision.denied?);
+        let key = format!("policy[{}]", rule - 1);
+        let message = format!(
+            "Operation '{}' on '{}' refused by policy rule {} ({}): {}",
+            cmd.operation,
+            path,
+            rule,
+            self.policy[rule - 1].describe(),
+            denial.reason
+        );
+        // Less content may pass a size limit, so only a refused action is
+        // worth warning off retrying
+        let advice = match denial.too_large {
+            true => "Write less, or the parent",
+            false => "Retrying will not help; the parent",
+        };
+        let message = format!(
+            "{}. {} can allow it by changing `{}` in the configuration, {}",
+            message, advice, key, denial.grant
+        );
+        let error = match denial.too_large {
+            true => FsChildError::TooLarge(message),
+            false => FsChildError::PermissionDenied(message),
+        };
+        Some(Refusal {
+            error,
+            requires: action(cmd, spec).operation.to_string(),
+            key,
+            grant: denial.grant,
+            rule: Some(rule),
+        })
+    }
+
+    /// The refusal for an operation that changes files besides those it
+    /// names, such as a checkout: the first of `paths` it could not write
+    /// had it named it, outside every directory it was given, without the
+    /// 'write' permission, or against a policy rule. Checked before it
+    /// changes anything, so a refused operation changes nothing.
+    pub(crate) fn refuse_writes<'a>(&self, cmd: &FsCommand, paths: impl IntoIterator<Item = &'a String>) -> Option<CommandResult> {
+        let spec = operation_spec(&cmd.operation)?;
+        let refusal = paths.into_iter().find_map(|path| match self.canonical(path) {
+            None => {
+                let message = format!("Operation '{}' refused: '{}' resolves outside `base_path` and every mount", cmd.operation, path);
+                Some(Refusal::outside(message, "write"))
+            }
+            Some(_) if !self.allowed_at("write", path) => {
+                let message = format!("Operation '{}' writes '{}', which needs the 'write' permission", cmd.operation, path);
+                Some(Refusal::lacking(message, "write", self.permissions_key(path).1))
+            }
+            Some(canonical) => self.policy_refusal(cmd, spec, &canonical),
+        })?;
+        let data = refusal.data();
+        Some(CommandResult::error(cmd, refusal.error).with_data(json!({"denied": data})))
+    }
+
+    /// Who grants the permissions at `path`, as the refusal names them, and
+    /// the configuration key they are listed under.
+    fn permissions_key(&self, path: &str) -> (String, String) {
+        match self.mount_at(path) {
+            Some((name, _)) => (format!("mount '{}'", name), format!("mounts.{}.permissions", name)),
+            None => ("this instance".to_string(), "permissions".to_string()),
+        }
+    }
+
+    /// The error for a command the permissions, mounts or policy refuse,
+    /// with what would allow it in `data.denied`.
+    fn refused(&self, cmd: &FsCommand, e: FsChildError) -> CommandResult {
+        match operation_spec(&cmd.operation).and_then(|spec| self.refusal(cmd, spec)) {
+            Some(refusal) => CommandResult::error(cmd, e).with_data(json!({"denied": refusal.data()})),
+            None => CommandResult::error(cmd, e),
+        }
+    }
+
+    /// Every path a command touches, for the policy to decide on. Commands
+    /// needing no permission touch no files and are left to run.
+    fn policy_paths(&self, cmd: &FsCommand, spec: &OperationSpec) -> Vec<String> {
+        if self.policy.is_empty() || spec.permission.is_none() {
+            return Vec::new();
+        }
+        let mut paths = vec![cmd.path.clone()];
+        if cmd.writes() {
+            paths.push(cmd.target());
+            paths.extend(cmd.destination.clone().filter(|d| !d.trim().is_empty()));
+        }
+        // Rules match where a path lands, not how it was written
+        paths.retain(|p| !p.is_empty());
+        let mut paths: Vec<String> = paths.iter().map(|p| self.canonical(p).unwrap_or_else(|| p.clone())).collect();
+        paths.dedup();
+        paths
+    }
+
+    /// Answer to `why-denied`: how the permissions, mounts and policy weigh
+    /// the operation named in `topic` on `path`.
+    pub(crate) fn why_denied(&self, cmd: &FsCommand) -> CommandResult {
+        let topic = cmd.topic.as_deref().unwrap_or_default().trim();
+        let Some(spec) = operation_spec(topic) else {
+            return CommandResult::error(cmd, unknown_operation(topic, format!("Unknown operation: {}", topic)));
+        };
+        let probe = FsCommand { operation: topic.to_string(), ..cmd.clone() };
+        let refusal = self.refusal(&probe, spec);
+        let granted = match self.mount_at(&cmd.path) {
+            Some((name, mount)) => format!("mount '{}' grants {}", name, mount.permissions.join(", ")),
+            None => format!("instance grants {}", self.permissions.join(", ")),
+        };
+        let needs = spec.permission.map_or("no permission".to_string(), |p| format!("'{}'", p));
+        let mut lines = vec![format!("Permissions: '{}' needs {}; the {}", topic, needs, granted)];
+        let mut rules = Vec::new();
+        for path in self.policy_paths(&probe, spec) {
+            lines.push(format!("Policy for '{}':", path));
+            lines.extend(policy::explain(&self.policy, &action(&probe, spec), &path).into_iter().map(|l| format!("  {}", l)));
+            let decision = policy::evaluate(&self.policy, &action(&probe, spec), &path);
+            rules.push(json!({"path": path, "rule": decision.rule, "denied": decision.denied.map(|d| d.reason)}));
+        }
+        let headline = match &refusal {
+            None => format!("'{}' on '{}' would be allowed", topic, cmd.path),
+            Some(refusal) => format!("'{}' on '{}' would be refused: {}", topic, cmd.path, refusal.error),
+        };
+        CommandResult::ok(cmd, format!("{}\n{}", headline, lines.join("\n"))).with_data(json!({
+            "allowed": refusal.is_none(),
+            "error": refusal.as_ref().map(|refusal| refusal.error.code()),
+            "denied": refusal.as_ref().map(Refusal::data),
+            "policy": rules,
+        }))
+    }
+
+    /// Hold `cmd` back for `delay` head-updates; it runs in
+    /// [`Self::run_deferred`] once due.
+    fn defer(&mut self, head: &str, mut cmd: FsCommand, delay: u64) -> CommandResult {
+        if self.deferred.len() >= queue::MAX_TASKS {
+            return CommandResult::error(&cmd, FsChildError::TooLarge(format!(
+                "Cannot defer '{}': {} commands are already queued",
+                cmd.operation,
+                queue::MAX_TASKS
+            )));
+        }
+        self.deferred_seq += 1;
+        cmd.defer = None;
+        let task = queue::Task {
+            id: self.deferred_seq,
+            command: cmd,
+            head: head.to_string(),
+            queued: self.turn,
+            due: self.turn + delay,
+        };
+        let result = CommandResult::skipped(&task.command, "deferred", format!("Deferred {}", task.describe(self.turn)))
+            .with_data(json!({"task": task.id, "due": task.due}));
+        self.deferred.push(task);
+        result
+    }
+
+    /// Answer to `queue`: the deferred commands still waiting.
+    pub(crate) fn list_queue(&self, cmd: &FsCommand) -> CommandResult {
+        let text = if self.deferred.is_empty() {
+            "No deferred commands".to_string()
+        } else {
+            let lines: Vec<String> = self.deferred.iter().map(|task| format!("  {}", task.describe(self.turn))).collect();
+            format!("{} deferred commands:\n{}", self.deferred.len(), lines.join("\n"))
+        };
+        let tasks: Vec<Value> = self
+            .deferred
+            .iter()
+            .map(|task| json!({
+                "id": task.id,
+                "operation": task.command.operation,
+                "path": task.command.path,
+                "head": task.head,
+                "queued": task.queued,
+                "due": task.due,
+            }))
+            .collect();
+        CommandResult::ok(cmd, text).with_data(json!({"turn": self.turn, "tasks": tasks}))
+    }
+
+    /// Run the deferred commands that came due, as one batch, and add their
+    /// results to `response`.
+    pub(crate) fn run_deferred(&mut self, head: &str, due: Vec<queue::Task>, response: &mut ChildMessage) {
+        if due.is_empty() {
+            return;
+        }
+        logger::info("batch", "Running deferred commands", &[("head", &head), ("commands", &due.len())]);
+        let tasks: Vec<u64> = due.iter().map(|task| task.id).collect();
+        let results = self.process_fs_commands(head, due.into_iter().map(|task| task.command).collect());
+        self.mirror_audit(head, &results);
+        let section = format!("Deferred commands that came due:\n{}", render::results_text(&results));
+        if self.render_html {
+            if let Some(html) = render::html!(render::results_html(&results)) {
+                response.html = Some(response.html.take().unwrap_or_default() + &html);
+            }
+        }
+        response.text = if response.text.is_empty() { section } else { format!("{}\n\n{}", response.text, section) };
+        response.data["deferred"] = json!({"tasks": tasks, "results": results});
+    }
+
+    /// Why a command replacing `target_path` would clobber a change someone
+    /// else made since this actor last read or wrote it, with the details
+    /// for the result data. Files the actor never saw, and commands with
+    /// `force`, pass.
+    fn conflict(&self, cmd: &FsCommand, target_path: &str) -> Option<(FsChildError, Value)> {
+        if flag(cmd.force.as_deref()) || !operation_spec(&cmd.operation).is_some_and(|spec| spec.overwrites) {
+            return None;
+        }
+        let seen = self.seen.get(target_path)?;
+        let found = self.fs.read_file(target_path).ok().map(|content| watch::content_hash(&content));
+        if found.as_ref() == Some(&seen.hash) {
+            return None;
+        }
+        let target = cmd.target();
+        let what = match found {
+            Some(_) => "changed",
+            None => "been deleted",
+        };
+        let message = format!(
+            "'{}' has {} since this actor last read or wrote it (turn {}); read it again, or set force to replace it anyway",
+            target, what, seen.turn
+        );
+        Some((FsChildError::Conflict(message), json!({"path": target, "seen": seen.hash, "found": found, "seen_turn": seen.turn})))
+    }
+
+    /// Remember the content a successful command left behind in the file it
+    /// replaced, or found in the file it read.
+    fn note_seen(&mut self, cmd: &FsCommand, path: &str, target_path: &str) {
+        let Some(spec) = operation_spec(&cmd.operation) else {
+            return;
+        };
+        let file = match spec.permission {
+            _ if spec.overwrites && !cmd.previews() => target_path,
+            Some("read") if !cmd.path.is_empty() => path,
+            _ => return,
+        };
+        match self.fs.read_file(file) {
+            Ok(content) => conflict::remember(&mut self.seen, file, watch::content_hash(&content), self.turn),
+            Err(_) => {
+                self.seen.remove(file);
+            }
+        }
+    }
+
+    /// Put an overlay holding `stage` between the commands and the
+    /// filesystem, so what they change is staged.
+    fn open_overlay(&mut self, stage: staging::Stage) {
+        let overlay = Rc::new(staging::Overlay::new(self.fs.clone(), &self.resolve_path("."), stage));
+        self.fs = overlay.clone();
+        self.overlay = Some(overlay);
+    }
+
+    /// Take the overlay away again, keeping what it staged for the next
+    /// batch.
+    fn close_overlay(&mut self) {
+        if let Some(overlay) = self.overlay.take() {
+            self.fs = overlay.base();
+            self.staging = Some(overlay.take_stage());
+        }
+    }
+
+    /// The command path naming a host path: `name:/rest` inside a mount,
+    /// or relative to `base_path`.
+    fn workspace_path(&self, host_path: &str) -> String {
+        for (name, mount) in &self.mounts {
+            if let Some(rest) = host_path.strip_prefix(mount.path.trim_end_matches('/')).filter(|rest| rest.starts_with('/')) {
+                return format!("{}:{}", name, rest);
+            }
+        }
+        match host_path.strip_prefix(&self.resolve_path(".")) {
+            Some(rest) if rest.starts_with('/') => rest[1..].to_string(),
+            _ => host_path.to_string(),
+        }
+    }
+
+    /// Staged paths by what became of them, as command paths.
+    pub(crate) fn staged_paths(&self, stage: &staging::Stage) -> Value {
+        let paths = |wanted: staging::Change| -> Vec<String> {
+            stage.changes.iter().filter(|(_, change)| **change == wanted).map(|(path, _)| self.workspace_path(path)).collect()
+        };
+        json!({
+            "written": paths(staging::Change::Written),
+            "deleted": paths(staging::Change::Deleted),
+            "created": paths(staging::Change::Created),
+        })
+    }
+
+    /// `stage`: send the writes, edits and deletes that follow to an
+    /// overlay, until `commit-staged` or `discard-staged`.
+    pub(crate) fn stage(&mut self, cmd: &FsCommand) -> CommandResult {
+        if let Some(overlay) = &self.overlay {
+            let stage = overlay.stage();
+            return CommandResult::ok(cmd, format!("Already staging: {} changes staged since turn {}", stage.changes.len(), stage.started))
+                .with_data(json!({"since": stage.started, "staged": stage.changes.len(), "already": true}));
+        }
+        self.open_overlay(staging::Stage { started: self.turn, changes: BTreeMap::new() });
+        CommandResult::ok(cmd, "Staging: writes, edits and deletes now go to an overlay until commit-staged or discard-staged".to_string())
+            .with_data(json!({"since": self.turn, "staged": 0, "already": false}))
+    }
+
+    /// `preview-staged`: a diff of every staged change against the
+    /// workspace.
+    pub(crate) fn preview_staged(&self, cmd: &FsCommand) -> CommandResult {
+        let Some(overlay) = &self.overlay else {
+            return CommandResult::ok(cmd, "Nothing is staged".to_string()).with_data(json!({"changes": []}));
+        };
+        let base = overlay.base();
+        let stage = overlay.stage();
+        let mut sections = Vec::new();
+        let mut changes = Vec::new();
+        let (mut added, mut removed) = (0, 0);
+        for (host_path, change) in &stage.changes {
+            let path = self.workspace_path(host_path);
+            let mut entry = json!({"path": path, "change": change});
+            if *change == staging::Change::Created {
+                sections.push(format!("new directory {}\n", path));
+                changes.push(entry);
+                continue;
+            }
+            let old = base.read_file(host_path).ok();
+            let new = match change {
+                staging::Change::Written => overlay.read_file(host_path).ok(),
+                _ => None,
+            };
+            fn text(content: &Option<Vec<u8>>) -> Option<Option<&str>> {
+                content.as_deref().map(|bytes| std::str::from_utf8(bytes).ok())
+            }
+            match (text(&old), text(&new)) {
+                (Some(None), _) | (_, Some(None)) => sections.push(format!("Binary file {} {}\n", path, if new.is_some() { "changed" } else { "deleted" })),
+                (old_text, new_text) => {
+                    let old_name = old_text.map_or("/dev/null".to_string(), |_| format!("a/{}", path));
+                    let new_name = new_text.map_or("/dev/null".to_string(), |_| format!("b/{}", path));
+                    let diff = diff::unified(&old_name, &new_name, old_text.flatten().unwrap_or_default(), new_text.flatten().unwrap_or_default());
+                    (added, removed) = (added + diff.added, removed + diff.removed);
+                    entry["added"] = json!(diff.added);
+                    entry["removed"] = json!(diff.removed);
+                    sections.push(match diff.is_empty() {
+                        true => format!("{} rewritten unchanged\n", path),
+                        false => diff.text,
+                    });
+                }
+            }
+            changes.push(entry);
+        }
+        let headline = format!(
+            "{} staged changes since turn {} (+{} -{} lines); apply them with commit-staged or drop them with discard-staged",
+            stage.changes.len(),
+            stage.started,
+            added,
+            removed
+        );
+        let text = match sections.is_empty() {
+            true => headline,
+            false => format!("{}\n\n{}", headline, sections.concat().trim_end()),
+        };
+        CommandResult::ok(cmd, text).with_data(json!({"since": stage.started, "changes": changes, "added": added, "removed": removed}))
+    }
+
+    /// `commit-staged`: apply every staged change to the workspace, or
+    /// none if one fails, and stop staging.
+    pub(crate) fn commit_staged(&mut self, cmd: &FsCommand) -> CommandResult {
+        let Some(overlay) = self.overlay.take() else {
+            return CommandResult::error(cmd, FsChildError::NotFound("Nothing is staged; start staging with stage".to_string()));
+        };
+        self.fs = overlay.base();
+        let stage = overlay.take_stage();
+        let root = self.resolve_path(".");
+        if let Err(e) = staging::commit(&*self.fs, &root, &stage) {
+            let paths = self.staged_paths(&stage);
+            self.open_overlay(stage);
+            return CommandResult::error(cmd, FsChildError::from_host(&e, format!("Nothing committed, and the changes are still staged: {}", e)))
+                .with_data(paths);
+        }
+        staging::clear(&*self.fs, &root, &stage);
+        for path in stage.changes.keys() {
+            invalidate_listings(path);
+        }
+        CommandResult::ok(cmd, format!("Committed {} staged changes; staging is off", stage.changes.len())).with_data(self.staged_paths(&stage))
+    }
+
+    /// `discard-staged`: drop every staged change and stop staging.
+    pub(crate) fn discard_staged(&mut self, cmd: &FsCommand) -> CommandResult {
+        let Some(overlay) = self.overlay.take() else {
+            return CommandResult::error(cmd, FsChildError::NotFound("Nothing is staged".to_string()));
+        };
+        self.fs = overlay.base();
+        let stage = overlay.take_stage();
+        staging::clear(&*self.fs, &self.resolve_path("."), &stage);
+        for path in stage.changes.keys() {
+            invalidate_listings(path);
+        }
+        // Usage was measured with the overlay on
+        self.usage.clear();
+        CommandResult::ok(cmd, format!("Discarded {} staged changes; staging is off", stage.changes.len())).with_data(self.staged_paths(&stage))
+    }
+
+    /// Whether a command would fail against the current filesystem, found
+    /// without changing anything. Paths an earlier command in the batch
+    /// writes are not inspected, since their content is not known yet.
+    fn preflight(&self, cmd: &FsCommand, written: &BTreeSet<String>) -> Result<(), FsChildError> {
+        let Some(spec) = operation_spec(&cmd.operation) else {
+            return Err(unknown_operation(&cmd.operation, format!("Unknown operation: {}", cmd.operation)));
+        };
+        if written.contains(&cmd.path) {
+            return Ok(());
+        }
+        if let Some((e, _)) = self.conflict(cmd, &self.resolve_path(&cmd.target())) {
+            return Err(e);
+        }
+        let path = self.resolve_path(&cmd.path);
+        let read = || {
+            self.fs.read_file(&path).map_err(|e| {
+                type_mismatch(&*self.fs, cmd, &path)
+                    .unwrap_or_else(|| FsChildError::from_host(&e, format!("Cannot read '{}': {}", cmd.path, e)))
+            })
+        };
+
+        match spec.name {
+            "read-file" | "read-around" | "delete-file" | "frontmatter-get" | "file-type" | "render" => {
+                read()?;
+            }
+            "write-file" => {
+                // A preview shows the diff even when the write would shrink the file too far
+                let ratio = if cmd.previews() { 0.0 } else { self.truncation_ratio };
+                write_precondition(&*self.fs, cmd, &path, ratio)?;
+                pretty_print(&mut cmd.clone())?;
+                if let Some(schema) = self.load_schema(cmd)? {
+                    schema_violations(cmd, &schema, cmd.content.as_deref().unwrap_or_default()).map_err(|(e, _)| e)?;
+                }
+            }
+            #[cfg(feature = "structured")]
+            "frontmatter-set" => {
+                let text = String::from_utf8(read()?)
+                    .map_err(|_| FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)))?;
+                let value = frontmatter_value(cmd.value.as_deref().unwrap_or_default());
+                frontmatter::set(&text, cmd.key.as_deref().unwrap_or_default().trim(), &value)
+                    .map_err(|e| FsChildError::Parse(format!("Not changing '{}': {}", cmd.path, e)))?;
+            }
+            #[cfg(feature = "structured")]
+            "cargo-add-dep" => {
+                let text = String::from_utf8(read()?)
+                    .map_err(|_| FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)))?;
+                let table = cmd.table.as_deref().unwrap_or("dependencies");
+                cargo_toml::add_dependency(&text, table, &cargo_dependency(cmd)?)
+                    .map_err(|e| FsChildError::Parse(format!("Not changing '{}': {}", cmd.path, e)))?;
+            }
+            #[cfg(feature = "structured")]
+            "cargo-set-version" => {
+                let text = String::from_utf8(read()?)
+                    .map_err(|_| FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)))?;
+                let dependency = cmd.dependency.as_deref().map(str::trim);
+                let version = cmd.version.as_deref().unwrap_or_default().trim();
+                let (_, tables) = cargo_toml::set_version(&text, dependency, cmd.table.as_deref(), version)
+                    .map_err(|e| FsChildError::Parse(format!("Not changing '{}': {}", cmd.path, e)))?;
+                if let (Some(name), true) = (dependency, tables.is_empty()) {
+                    return Err(FsChildError::NotFound(format!("'{}' has no dependency on {}", cmd.path, name)));
+                }
+            }
+            "append-ndjson" => {
+                ndjson_record(cmd)?;
+            }
+            "csv-to-json" | "json-to-csv" => {
+                let text = String::from_utf8(read()?)
+                    .map_err(|_| FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)))?;
+                conversion(cmd, &text, &cmd.target())?;
+            }
+            "generate-toc" => {
+                let text = String::from_utf8(read()?)
+                    .map_err(|_| FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)))?;
+                toc_for(cmd, &text)?;
+            }
+            "edit-file" => {
+                let content = read()?;
+                refuse_utf16(cmd, &content)?;
+                let old_text = cmd.old_text.as_deref().unwrap_or_default();
+                let positions = if flag(cmd.ignore_whitespace.as_deref()) || flag(cmd.fuzzy.as_deref()) {
+                    let text = std::str::from_utf8(&content).map_err(|_| {
+                        FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path))
+                    })?;
+                    loose_ranges(cmd, text, old_text)?.0.into_iter().map(|(start, _)| start).collect()
+                } else if flag(cmd.regex.as_deref()) {
+                    let text = std::str::from_utf8(&content).map_err(|_| {
+                        FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path))
+                    })?;
+                    regex_starts(old_text, text)?
+                } else {
+                    edit::find_all(&content, old_text.as_bytes())
+                };
+                edit::select(&cmd.path, &content, &positions, edit_target(cmd)?)?;
+            }
+            "read-base64" => {
+                let size = read()?.len();
+                if size > self.max_inline_bytes {
+                    return Err(FsChildError::TooLarge(format!(
+                        "File '{}' is {} bytes, over the {} byte inline limit",
+                        cmd.path, size, self.max_inline_bytes
+                    )));
+                }
+            }
+            "push-to-store" => {
+                let size = read()?.len();
+                if size > self.max_transfer_bytes {
+                    return Err(FsChildError::TooLarge(format!(
+                        "File '{}' is {} bytes, over the {} byte transfer limit",
+                        cmd.path, size, self.max_transfer_bytes
+                    )));
+                }
+            }
+            "fetch-from-store" => {
+                let role = cmd.store.as_deref().unwrap_or("artifact");
+                if self.store_for(role).is_none() {
+                    return Err(FsChildError::Store(format!("No store registered for role '{}'", role)));
+                }
+            }
+            "export-dir" if self.fs.list_files(&path).is_err() => {
+                return Err(FsChildError::NotFound(format!("'{}' is not a directory", cmd.path)));
+            }
+            "import-dir" => {
+                let role = cmd.store.as_deref().unwrap_or("artifact");
+                if self.store_for(role).is_none() {
+                    return Err(FsChildError::Store(format!("No store registered for role '{}'", role)));
+                }
+                if self.fs.path_exists(&path).unwrap_or(false) {
+                    return Err(already_exists(cmd));
+                }
+            }
+            "checkout" => {
+                self.find_commit(cmd.id.as_deref().unwrap_or_default())?;
+            }
+            "define-macro" => {
+                define_macro(cmd)?;
+            }
+            "run-macro" => {
+                return Err(self.macro_error(cmd));
+            }
+            "restore-from-store" => {
+                let known = self
+                    .backups
+                    .get(&cmd.path)
+                    .and_then(|records| backup::find(records, cmd.id.as_deref(), self.current_hash(&path).as_deref()))
+                    .is_some();
+                if !known {
+                    return Err(FsChildError::NotFound(format!("No backup of '{}' matches", cmd.path)));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The batch with each `run-macro` replaced by the macro's steps. A
+    /// macro defined earlier in the same batch can be run; a run that cannot
+    /// be expanded stays in place and fails when its turn comes. Steps take
+    /// the run's `mode`, and its idempotency key with the step number.
+    fn expand_macros(&self, commands: Vec<FsCommand>) -> Vec<FsCommand> {
+        if !commands.iter().any(|cmd| cmd.operation.ends_with("-macro")) {
+            return commands;
+        }
+        let mut defined = self.macros.clone();
+        let mut expanded = Vec::new();
+        for cmd in commands {
+            match cmd.operation.as_str() {
+                "define-macro" => {
+                    if let Ok((name, m)) = define_macro(&cmd) {
+                        defined.insert(name, m);
+                    }
+                    expanded.push(cmd);
+                }
+                "run-macro" => match expand_run(&cmd, &defined) {
+                    Ok(steps) => {
+                        for (i, mut step) in steps.into_iter().enumerate() {
+                            step.mode = step.mode.or_else(|| cmd.mode.clone());
+                            step.defer = step.defer.or_else(|| cmd.defer.clone());
+                            if let Some(key) = &cmd.idempotency_key {
+                                step.idempotency_key = Some(format!("{}#{}", key, i + 1));
+                            }
+                            expanded.push(step);
+                        }
+                    }
+                    Err(_) => expanded.push(cmd),
+                },
+                _ => expanded.push(cmd),
+            }
+        }
+        expanded
+    }
+
+    /// Why a `run-macro` left in the batch could not be expanded.
+    pub(crate) fn macro_error(&self, cmd: &FsCommand) -> FsChildError {
+        expand_run(cmd, &self.macros).err().unwrap_or_else(|| {
+            FsChildError::Parse(format!("Macro '{}' was defined after it was run", cmd.macro_name.as_deref().unwrap_or_default()))
+        })
+    }
+
+    /// Check a whole batch up front. Returns the failures, by index, or an
+    /// empty list when every command would run.
+    fn validate_batch(&self, commands: &[FsCommand], mode: BatchMode) -> Vec<(usize, FsChildError)> {
+        let mut failures = Vec::new();
+        let mut written = BTreeSet::new();
+        for (index, cmd) in commands.iter().enumerate() {
+            // A redelivered command that already ran is skipped, not re-checked
+            if cmd.idempotency_key.as_ref().is_some_and(|key| self.idempotency_keys.contains(key)) {
+                continue;
+            }
+            if mode == BatchMode::Transactional && UNJOURNALED.contains(&cmd.operation.as_str()) {
+                failures.push((index, FsChildError::Unsupported(format!(
+                    "'{}' cannot run in a transactional batch: the files it changes are not journaled, so it could not be rolled back. Send it in a batch of its own",
+                    cmd.operation
+                ))));
+                continue;
+            }
+            // A deferred command meets the filesystem as it is when it runs
+            let checked = self.check_command(cmd).and_then(|_| match cmd.deferral()? {
+                Some(_) => Ok(()),
+                None => self.preflight(cmd, &written),
+            });
+            match checked {
+                Ok(()) => {
+                    if cmd.writes() {
+                        written.insert(cmd.target());
+                    }
+                }
+                Err(e) => failures.push((index, e)),
+            }
+        }
+        failures
+    }
+
+    pub(crate) fn process_fs_commands(&mut self, head: &str, commands: Vec<FsCommand>) -> Vec<CommandResult> {
+        self.load_cold_state();
+        // Commands read compacted files as their content
+        let base = self.fs.clone();
+        self.fs = Rc::new(compact::Resolver::new(base.clone(), &self.resolve_path(".")));
+        if let Some(stage) = self.staging.take() {
+            self.open_overlay(stage);
+        }
+        let results = self.run_commands(head, commands);
+        self.close_overlay();
+        self.fs = base;
+        results
+    }
+
+    fn run_commands(&mut self, head: &str, commands: Vec<FsCommand>) -> Vec<CommandResult> {
+        let mut results = Vec::new();
+        let mut commands = self.expand_macros(commands);
+        cursors::expire(&mut self.cursors, self.turn, self.cursor_turns);
+        let request_bytes: Vec<usize> = commands.iter().map(FsCommand::request_bytes).collect();
+
+        // Results echo the normalized path, and duplicate detection and
+        // preflight compare paths in one form. Paths that fail to normalize
+        // are left as written for `check_command` to refuse.
+        for cmd in commands.iter_mut().filter(|cmd| !cmd.path.is_empty()) {
+            if let Ok(normalized) = self.normalize_path(&cmd.path) {
+                cmd.path = normalized;
+            }
+        }
+
+        let mode = commands
+            .iter()
+            .find_map(|cmd| cmd.mode.as_deref())
+            .and_then(|mode| serde_json::from_value::<BatchMode>(json!(mode.trim())).ok())
+            .unwrap_or(self.batch_mode);
+        if mode != BatchMode::Sequential {
+            let failures = self.validate_batch(&commands, mode);
+            if !failures.is_empty() {
+                logger::info("batch", "Failed validation", &[("failures", &failures.len()), ("commands", &commands.len())]);
+                return self.reject_batch(head, commands, failures);
+            }
+        }
+        // Commands since the last one that could change the filesystem; an
+        // exact repeat of one of these would do the same thing again
+        let mut since_last_change: Vec<(usize, FsCommand)> = Vec::new();
+        let mut journal = (mode == BatchMode::Transactional).then(Journal::default);
+        // What was seen before the batch, for when it is rolled back
+        let mut seen_before = journal.as_ref().map(|_| self.seen.clone());
+        // Index of the command whose failure rolled the batch back
+        let mut rolled_back_at: Option<usize> = None;
+        let mut transaction_keys: Vec<String> = Vec::new();
+
+        for mut cmd in commands {
+            // Tokens handed out earlier in this batch count too
+            if cmd.operation == "continue" {
+                cmd = self.resume_cursor(cmd);
+            }
+            let path = self.resolve_path(&cmd.path);
+            let target = cmd.target();
+            let target_path = self.resolve_path(&target);
+
+            if let Some(failed) = rolled_back_at {
+                results.push(CommandResult::skipped(
+                    &cmd,
+                    "not-executed",
+                    format!("Not executed: command {} failed and the batch was rolled back", failed + 1),
+                ));
+                continue;
+            }
+
+            // Only a repeat of a read that succeeded would answer the same
+            let duplicate = since_last_change
+                .iter()
+                .find(|(index, seen)| *seen == cmd && results[*index].error.is_none() && results[*index].skipped.is_none());
+            if let Some((original, _)) = duplicate {
+                results.push(CommandResult::skipped(
+                    &cmd,
+                    "deduplicated",
+                    format!("Skipped '{}' on '{}': duplicate of command {} in this batch", cmd.operation, cmd.path, original + 1),
+                ).with_data(json!({"duplicate_of": original})));
+                continue;
+            }
+            if operation_spec(&cmd.operation).is_none() || cmd.writes() {
+                since_last_change.clear();
+            }
+            if cmd.repeatable() {
+                since_last_change.push((results.len(), cmd.clone()));
+            }
+
+            if let Some(key) = &cmd.idempotency_key {
+                if self.idempotency_keys.contains(key) {
+                    results.push(CommandResult::skipped(
+                        &cmd,
+                        "already-executed",
+                        format!("Skipped '{}' on '{}': already executed (idempotency key '{}')", cmd.operation, cmd.path, key),
+                    ));
+                    continue;
+                }
+            }
+
+            // From here a failure rolls a transactional batch back
+            let result = match self.check_command(&cmd) {
+                Err(e) => self.refused(&cmd, e),
+                Ok(()) => match cmd.deferral() {
+                    Err(e) => CommandResult::error(&cmd, e),
+                    Ok(Some(delay)) if operation_spec(&cmd.operation).is_some() => {
+                        results.push(self.defer(head, cmd, delay));
+                        continue;
+                    }
+                    Ok(_) => self.execute(&mut cmd, journal.as_mut(), &path, &target, &target_path),
+                },
+            };
+            if cmd.writes() {
+                invalidate_listings(&target_path);
+            }
+            if result.error.is_some() {
+                if let Some(journal) = journal.take() {
+                    rolled_back_at = Some(results.len());
+                    results.push(result);
+                    self.roll_back(journal, &transaction_keys, &mut results);
+                    if let Some(seen) = seen_before.take() {
+                        self.seen = seen;
+                    }
+                    continue;
+                }
+            }
+            if result.error.is_none() {
+                self.note_seen(&cmd, &path, &target_path);
+                if let Some(key) = cmd.idempotency_key {
+                    if journal.is_some() {
+                        transaction_keys.push(key.clone());
+                    }
+                    self.remember_idempotency_key(key);
+                }
+            }
+            results.push(result);
+        }
+
+        // Every command yields exactly one result, in the order it was given
+        for (index, result) in results.iter_mut().enumerate() {
+            result.index = index;
+            if let Some(code) = result.error {
+                self.errors.count(code);
+            }
+            // Without a host clock commands are not timed
+            self.metrics.record(&result.operation, result.error, request_bytes[index], result.message.len(), None);
+        }
+        self.record_history(head, &results);
+        results
+    }
+
+    /// Run one command that passed its checks: the conflict check and
+    /// backup, formatting, the handler and the quotas. A failure at any
+    /// step is the result, so a transactional batch rolls back on it.
+    fn execute(&mut self, cmd: &mut FsCommand, journal: Option<&mut Journal>, path: &str, target: &str, target_path: &str) -> CommandResult {
+        if let Some((e, data)) = self.conflict(cmd, target_path) {
+            return CommandResult::error(cmd, e).with_data(data);
+        }
+
+        if self.backup_before_write
+            && operation_spec(&cmd.operation).is_some_and(|spec| spec.overwrites)
+            && !cmd.previews()
+        {
+            if let Err(e) = self.backup_file(target, target_path) {
+                return CommandResult::failed(
+                    cmd,
+                    e,
+                    format!("Not modifying '{}': backup to the store failed", target),
+                );
+            }
+        }
+
+        if let Some(journal) = journal {
+            if cmd.writes() {
+                journal.record(&*self.fs, target_path);
+            }
+        }
+        let quota_prior = match self.quota_prior(cmd, target, target_path) {
+            Ok(prior) => prior,
+            Err(e) => return CommandResult::error(cmd, e),
+        };
+
+        // Formatting comes first so editorconfig has the last word on
+        // indentation and line endings
+        let formatted = match pretty_print(cmd) {
+            Ok(formatted) => formatted,
+            Err(e) => return CommandResult::error(cmd, e),
+        };
+
+        let normalized = if self.editorconfig {
+            self.apply_editorconfig(cmd)
+        } else {
+            Vec::new()
+        };
+
+        let schema = match self.load_schema(cmd) {
+            Ok(schema) => schema,
+            Err(e) => return CommandResult::error(cmd, e),
+        };
+
+        let exec = handlers::Exec {
+            cmd,
+            path,
+            target,
+            target_path,
+            schema: schema.as_ref(),
+        };
+        let result = match handlers::handler(&cmd.operation) {
+            Some(handler) => handler(self, &exec),
+            None => CommandResult::error(cmd, unknown_operation(&cmd.operation, format!("Unknown operation: {}", cmd.operation))),
+        };
+        // Later commands needing what the host just refused fail fast
+        if result.error == Some(ErrorCode::Unsupported) {
+            if let Some(capability) = Capability::required_by(&cmd.operation) {
+                self.host.mark_missing(capability);
+            }
+        }
+        // Host errors for the wrong kind of path are vague; say which
+        // kind it was instead
+        let result = match result.error {
+            Some(ErrorCode::NotFound | ErrorCode::Io) => match type_mismatch(&*self.fs, cmd, path) {
+                Some(e) => CommandResult::error(cmd, e),
+                None => result,
+            },
+            _ => result,
+        };
+        let result = match (&result.error, formatted) {
+            (None, Some((note, formatted))) => {
+                let mut result = result;
+                result.message.push_str(&format!(" ({})", note));
+                let mut data = result.data.take().unwrap_or_else(|| json!({}));
+                data["formatted"] = formatted;
+                result.with_data(data)
+            }
+            _ => result,
+        };
+        let result = match (&result.error, normalized.is_empty()) {
+            (None, false) => {
+                let mut result = result;
+                result.message.push_str(&format!(" (normalized per .editorconfig: {})", normalized.join(", ")));
+                let mut data = result.data.take().unwrap_or_else(|| json!({}));
+                data["editorconfig"] = json!(normalized);
+                result.with_data(data)
+            }
+            _ => result,
+        };
+        let result = match quota_prior {
+            Some(prior) if result.error.is_none() => self.enforce_quotas(cmd, target, target_path, prior, result),
+            _ => result,
+        };
+        match result.error {
+            None => self.offer_continuation(cmd, result),
+            Some(_) => result,
+        }
+    }
+
+    /// The command a `continue` stands for, using its token up. One naming
+    /// no live token is left for its handler to refuse.
+    fn resume_cursor(&mut self, cmd: FsCommand) -> FsCommand {
+        match cmd.token.as_deref().and_then(|token| self.cursors.remove(token.trim())) {
+            Some(cursor) => cursor.command,
+            None => cmd,
+        }
+    }
+
+    /// Hand out a token for the rest of a result that stopped early.
+    fn offer_continuation(&mut self, cmd: &FsCommand, mut result: CommandResult) -> CommandResult {
+        let next = match &result.data {
+            Some(data) if self.cursor_turns > 0 => cursors::continuation(cmd, data),
+            _ => None,
+        };
+        let Some(command) = next else {
+            return result;
+        };
+        self.cursor_seq += 1;
+        let token = format!("c{}", self.cursor_seq);
+        cursors::remember(&mut self.cursors, token.clone(), cursors::Cursor { command, turn: self.turn });
+        result.message.push_str(&format!("\n[Token {} resumes this with <operation>continue</operation>]", token));
+        if let Some(data) = result.data.as_mut() {
+            data["continuation"] = json!(token);
+        }
+        result
+    }
+
+    /// `continue` with a token that is unknown, used or expired.
+    pub(crate) fn continue_unknown(&self, cmd: &FsCommand) -> CommandResult {
+        let token = cmd.token.as_deref().unwrap_or_default().trim();
+        CommandResult::error(
+            cmd,
+            FsChildError::NotFound(format!(
+                "No continuation token '{}': tokens are used once and expire after {} head-updates",
+                token, self.cursor_turns
+            )),
+        )
+    }
+
+    fn record_history(&mut self, head: &str, results: &[CommandResult]) {
+        for result in results {
+            // Informational operations are not part of the session's work
+            if operation_spec(&result.operation).is_some_and(|spec| spec.permission.is_none()) {
+                continue;
+            }
+            self.history_seq += 1;
+            self.history.push(HistoryEntry {
+                seq: self.history_seq,
+                turn: self.turn,
+                head: head.to_string(),
+                operation: result.operation.clone(),
+                path: result.path.clone(),
+                error: result.error,
+                skipped: result.skipped.map(String::from),
+            });
+        }
+        if self.history.len() > history::HISTORY_LIMIT {
+            let excess = self.history.len() - history::HISTORY_LIMIT;
+            self.history.drain(..excess);
+        }
+    }
+
+    /// Append an audit record for a batch to the audit store, returning the
+    /// id the store assigned. Audit failures are logged but never fail the
+    /// batch itself.
+    pub(crate) fn mirror_audit(&self, head: &str, results: &[CommandResult]) -> Option<String> {
+        if !self.audit_to_store || results.is_empty() || !self.host.supports(Capability::Store) {
+            return None;
+        }
+
+        let record = json!({
+            "instance": self.name,
+            "child_id": self.child_id,
+            "head": head,
+            "turn": self.turn,
+            "results": results,
+        });
+        let bytes = serde_json::to_vec(&record).ok()?;
+        match self.store_put("audit", bytes) {
+            Ok(id) => {
+                logger::debug("audit", "Record stored", &[("head", &head), ("id", &id)]);
+                Some(id)
+            }
+            Err(e) => {
+                logger::error("audit", "Failed to store record", &[("head", &head), ("error", &e)]);
+                None
+            }
+        }
+    }
+
+    /// Undo a transactional batch after a failure, marking the commands that
+    /// had already succeeded as rolled back.
+    fn roll_back(&mut self, journal: Journal, keys: &[String], results: &mut [CommandResult]) {
+        let problems = journal.rollback(&*self.fs);
+        // Measured again when next needed, rather than undone write by write
+        self.usage.clear();
+        // Undone commands must run again if the batch is redelivered
+        self.idempotency_keys.retain(|key| !keys.contains(key));
+        logger::warn("batch", "Rolled back", &[("unrestored", &problems.len())]);
+        // Any listing taken during the batch may show its undone changes
+        LIST_CACHE.with(|cache| cache.borrow_mut().retain(|_| false));
+        let Some((failed, done)) = results.split_last_mut() else {
+            return;
+        };
+        // Commands the batch deferred go with it
+        for result in done.iter_mut().filter(|r| r.skipped == Some("deferred")) {
+            let task = result.data.as_ref().and_then(|data| data["task"].as_u64());
+            self.deferred.retain(|t| Some(t.id) != task);
+            result.skipped = None;
+        }
+        for result in done.iter_mut().filter(|r| r.error.is_none() && r.skipped.is_none()) {
+            result.skipped = Some("rolled-back");
+            result.message = format!("{} (rolled back)", result.message);
+        }
+        failed.message = format!("{}\nThe batch was rolled back.", failed.message);
+        if !problems.is_empty() {
+            failed.message = format!(
+                "{}\nCould not restore: {}",
+                failed.message,
+                problems.join("; ")
+            );
+            failed.data = Some(json!({"rollback_failed": problems}));
+        }
+    }
+
+    /// Results for a batch that failed validation: the failures, and every
+    /// other command reported as not executed.
+    fn reject_batch(
+        &mut self,
+        head: &str,
+        commands: Vec<FsCommand>,
+        failures: Vec<(usize, FsChildError)>,
+    ) -> Vec<CommandResult> {
+        let failed = failures.len();
+        let mut failures = failures.into_iter().peekable();
+        let mut results = Vec::new();
+        for (index, cmd) in commands.iter().enumerate() {
+            let mut result = match failures.next_if(|(i, _)| *i == index) {
+                Some((_, e)) => self.refused(cmd, e),
+                None => CommandResult::skipped(
+                    cmd,
+                    "batch-invalid",
+                    format!(
+                        "Not executed: {} command(s) in this batch failed validation",
+                        failed
+                    ),
+                ),
+            };
+            result.index = index;
+            self.metrics.record(&result.operation, result.error, cmd.request_bytes(), result.message.len(), None);
+            results.push(result);
+        }
+        self.record_history(head, &results);
+        results
+    }
+
+    /// Archive the current content of a file in the backup store. Missing
+    /// files need no backup, and unchanged content is not archived twice.
+    fn backup_file(&mut self, relative_path: &str, path: &str) -> Result<(), FsChildError> {
+        if !self.host.supports(Capability::Store) {
+            logger::debug("backup", "Skipped, the host provides no store", &[("path", &relative_path)]);
+            return Ok(());
+        }
+        let Ok(content) = self.fs.read_file(path) else {
+            return Ok(());
+        };
+        let hash = watch::content_hash(&content);
+        let records = self.backups.entry(relative_path.to_string()).or_default();
+        if records.last().is_some_and(|record| record.hash == hash) {
+            return Ok(());
+        }
+
+        let bytes = content.len();
+        let id = self.store_put("backup", content)?;
+        logger::debug("backup", "Backed up", &[("path", &relative_path), ("hash", &hash), ("id", &id)]);
+        self.backups
+            .entry(relative_path.to_string())
+            .or_default()
+            .push(BackupRecord {
+                hash,
+                id,
+                bytes,
+                turn: self.turn,
+            });
+        Ok(())
+    }
+
+    /// Forget the backups of files at or beneath `path` beyond the `keep`
+    /// most recent of each, once an entry naming them is in the backup
+    /// store. Returns them with that entry's id; nothing is stored when
+    /// there is nothing to release.
+    pub(crate) fn release_backups(&mut self, path: &str, keep: usize) -> Result<(Vec<backup::Released>, Option<String>), FsChildError> {
+        let released = backup::expired(&self.backups, path, keep);
+        if released.is_empty() {
+            return Ok((released, None));
+        }
+        let marker = backup::marker(self.child_id.as_deref().unwrap_or_default(), self.turn, &released);
+        let id = self.store_put("backup", marker)?;
+        for gone in &released {
+            if let Some(records) = self.backups.get_mut(&gone.path) {
+                records.retain(|record| record.id != gone.id);
+            }
+        }
+        self.backups.retain(|_, records| !records.is_empty());
+        logger::info("backup", "Released", &[("backups", &released.len()), ("marker", &id)]);
+        Ok((released, Some(id)))
+    }
+
+    /// Content hash of the file at `path`, if it can be read.
+    pub(crate) fn current_hash(&self, path: &str) -> Option<String> {
+        self.fs.read_file(path).ok().map(|content| watch::content_hash(&content))
+    }
+
+    fn remember_idempotency_key(&mut self, key: String) {
+        self.idempotency_keys.push_back(key);
+        while self.idempotency_keys.len() > IDEMPOTENCY_KEY_LIMIT {
+            self.idempotency_keys.pop_front();
+        }
+    }
+
+    /// Rewrite the text a write or edit is about to put on disk so it
+    /// follows the `.editorconfig` files above it, returning the properties
+    /// that changed something. An edit's new text is a fragment, so only
+    /// the whole-file write gains a final newline.
+    fn apply_editorconfig(&self, cmd: &mut FsCommand) -> Vec<&'static str> {
+        let (text, fragment) = match cmd.operation.as_str() {
+            "write-file" => (&mut cmd.content, false),
+            "edit-file" => (&mut cmd.new_text, true),
+            _ => return Vec::new(),
+        };
+        let Some(original) = text.as_deref() else {
+            return Vec::new();
+        };
+        let settings = self.editorconfig_for(&cmd.path);
+        if settings.is_empty() {
+            return Vec::new();
+        }
+        let (normalized, changed) = settings.normalize(original, fragment);
+        *text = Some(normalized);
+        changed
+    }
+
+    /// Settings for `path` from every `.editorconfig` between its directory
+    /// and the first one marked `root`, nearer files taking precedence.
+    fn editorconfig_for(&self, path: &str) -> editorconfig::Settings {
+        let mut found = Vec::new();
+        let mut dir = path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            dir = parent;
+            let location = if dir.is_empty() { "/" } else { dir };
+            let file = self.read_editorconfig(&format!("{}/.editorconfig", dir));
+            let root = file.as_ref().is_some_and(|file| file.root);
+            found.extend(file.map(|file| (location, file)));
+            if root {
+                break;
+            }
+        }
+        let below_base = !path.starts_with('/') && !path.starts_with("../") && self.mount_of(path).is_none();
+        if below_base && !found.iter().any(|(_, file)| file.root) {
+            found.extend(self.read_editorconfig(".editorconfig").map(|file| (".", file)));
+        }
+
+        let mut settings = editorconfig::Settings::default();
+        for (location, file) in found.iter().rev() {
+            let relative = match *location {
+                "." => path,
+                "/" => path.trim_start_matches('/'),
+                location => path.strip_prefix(location).map_or(path, |rest| rest.trim_start_matches('/')),
+            };
+            file.apply(relative, &mut settings);
+        }
+        settings
+    }
+
+    fn read_editorconfig(&self, path: &str) -> Option<editorconfig::File> {
+        let bytes = self.fs.read_file(&self.resolve_path(path)).ok()?;
+        Some(editorconfig::parse(&String::from_utf8_lossy(&bytes)))
+    }
+
+    /// The schema a write or edit names, read from a file unless given
+    /// inline.
+    fn load_schema(&self, cmd: &FsCommand) -> Result<Option<Value>, FsChildError> {
+        let Some(schema) = cmd.schema.as_deref().map(str::trim).filter(|s| !s.is_empty()) else {
+            return Ok(None);
+        };
+        if !matches!(cmd.operation.as_str(), "write-file" | "edit-file") {
+            return Ok(None);
+        }
+        let text = if schema.starts_with('{') {
+            schema.to_string()
+        } else {
+            let bytes = self.fs.read_file(&self.resolve_path(schema))
+                .map_err(|e| FsChildError::from_host(&e, format!("Cannot read schema '{}': {}", schema, e)))?;
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| FsChildError::Parse(format!("Schema '{}' is not valid JSON: {}", schema, e)))
+    }
+
+    /// A fresh work budget for one command.
+    pub(crate) fn budget(&self) -> Budget {
+        Budget::new(self.max_work_entries, self.max_work_bytes)
+    }
+
+    /// The top-level entries of the workspace with the files under each,
+    /// and the projects whose manifests sit at the top or one level down.
+    /// The walk limits apply to all of it together.
+    pub(crate) fn workspace_overview(&self) -> overview::Overview {
+        let mut overview = overview::Overview { complete: true, ..Default::default() };
+        let Ok(mut names) = self.fs.list_files(&self.resolve_path(".")) else {
+            return overview;
+        };
+        names.sort();
+        let mut budget = self.budget();
+        let mut manifests = Vec::new();
+        for name in names {
+            let dir = self.fs.list_files(&self.resolve_path(&name)).is_ok();
+            let files = match dir && !overview::NOT_COUNTED.contains(&name.as_str()) {
+                true => {
+                    let walk = self.walk(&name, self.max_walk_depth, None, self.max_walk_entries, &mut budget);
+                    overview.complete &= walk.next_cursor.is_none() && walk.pruned.is_empty();
+                    overview.files += walk.files.len();
+                    for file in &walk.files {
+                        if file.matches('/').count() == 1 {
+                            manifests.push(file.clone());
+                        }
+                    }
+                    Some(walk.files.len())
+                }
+                false => {
+                    if !dir {
+                        overview.files += 1;
+                        manifests.push(name.clone());
+                    }
+                    None
+                }
+            };
+            match overview.entries.len() < overview::MAX_ENTRIES {
+                true => overview.entries.push(overview::Entry { name, dir, files }),
+                false => overview.more += 1,
+            }
+        }
+        for manifest in manifests {
+            let file_name = manifest.rsplit('/').next().unwrap_or_default();
+            let Some((_, kind)) = overview::MARKERS.iter().find(|(marker, _)| *marker == file_name) else {
+                continue;
+            };
+            let content = self.fs.read_file(&self.resolve_path(&manifest)).unwrap_or_default();
+            overview.projects.push(overview::project(&manifest, kind, &String::from_utf8_lossy(&content)));
+        }
+        overview
+    }
+
+    /// The project whose manifest is at the top of the workspace, first by
+    /// the order of [`overview::MARKERS`], and the example paths its files
+    /// suggest. Only the likely source directories are walked.
+    pub(crate) fn detected_project(&self) -> Option<(overview::Project, overview::ExamplePaths)> {
+        let names = self.fs.list_files(&self.resolve_path(".")).ok()?;
+        let (manifest, kind) = overview::MARKERS.iter().find(|(marker, _)| names.iter().any(|name| name == marker))?;
+        let content = self.fs.read_file(&self.resolve_path(manifest)).unwrap_or_default();
+        let project = overview::project(manifest, kind, &String::from_utf8_lossy(&content));
+
+        let package = project.name.as_deref().map(|name| name.replace('-', "_")).unwrap_or_default();
+        let mut files: Vec<String> = names.iter().filter(|name| name.contains('.')).cloned().collect();
+        let mut budget = self.budget();
+        for dir in ["src", "lib", package.as_str()] {
+            if !dir.is_empty() && names.iter().any(|name| name == dir) {
+                files.extend(self.walk_files(dir, self.max_walk_entries, &mut budget));
+            }
+        }
+        let paths = overview::example_paths(&project, &files);
+        Some((project, paths))
+    }
+
+    /// Recursively list files under a directory, relative to `base_path`.
+    fn walk_files(&self, dir: &str, limit: usize, budget: &mut Budget) -> Vec<String> {
+        self.walk(dir, self.max_walk_depth, None, limit, budget).files
+    }
+
+    /// Walk of the files under `dir`, visiting each directory's entries in
+    /// sorted order, descending at most `max_depth` directories and
+    /// returning at most `limit` files that come after the path `after`.
+    /// Everything up to `after` is passed over without asking the host
+    /// about it, so a resumed walk costs no more than a fresh one.
+    fn walk(&self, dir: &str, max_depth: usize, after: Option<&str>, limit: usize, budget: &mut Budget) -> Walk {
+        let mut walk = Walk::default();
+        if !self.walk_dir(dir, 0, max_depth, after, limit, budget, &mut walk) {
+            walk.budget_exhausted = budget.exhausted();
+            walk.next_cursor = walk.done.clone().or_else(|| after.map(str::to_string));
+        }
+        walk
+    }
+
+    /// One directory of [`walk`](Self::walk); `false` once it had to stop.
+    #[allow(clippy::too_many_arguments)]
+    fn walk_dir(
+        &self,
+        current: &str,
+        depth: usize,
+        max_depth: usize,
+        after: Option<&str>,
+        limit: usize,
+        budget: &mut Budget,
+        walk: &mut Walk,
+    ) -> bool {
+        if !budget.visit() {
+            return false;
+        }
+        let Ok(mut entries) = self.fs.list_files(&self.resolve_path(current)) else {
+            return true;
+        };
+        entries.sort();
+        for entry in entries {
+            let relative = if current == "." {
+                entry
+            } else {
+                format!("{}/{}", current.trim_end_matches('/'), entry)
+            };
+            let resume_inside = match after.map(|after| path::walk_order(&relative, after)) {
+                Some(path::WalkOrder::Before | path::WalkOrder::Same) => continue,
+                Some(path::WalkOrder::Ancestor) => after,
+                Some(path::WalkOrder::After) | None => None,
+            };
+            if self.cold_state_dir.as_ref() == Some(&relative) || [vcs::DIR, staging::DIR, compact::DIR, report::DIR].contains(&relative.as_str()) {
+                continue;
+            }
+            if walk.files.len() >= limit || !budget.visit() {
+                return false;
+            }
+            if self.fs.list_files(&self.resolve_path(&relative)).is_ok() {
+                if depth < max_depth {
+                    if !self.walk_dir(&relative, depth + 1, max_depth, resume_inside, limit, budget, walk) {
+                        return false;
+                    }
+                } else {
+                    walk.pruned.push(relative.clone());
+                }
+            } else {
+                walk.files.push(relative.clone());
+            }
+            walk.done = Some(relative);
+        }
+        true
+    }
+
+    /// `manifest`: the path, size and SHA-256 of every file under `path`,
+    /// returned in the result data or written to `destination`. A returned
+    /// manifest that hits the walk limits comes with a cursor; a written
+    /// one must be complete.
+    pub(crate) fn manifest(&self, cmd: &FsCommand, target: &str, target_path: &str) -> CommandResult {
+        if self.fs.list_files(&self.resolve_path(&cmd.path)).is_err() {
+            return CommandResult::error(cmd, FsChildError::NotFound(format!("Failed to list files in '{}': not a directory", cmd.path)));
+        }
+        let writing = cmd.destination.is_some();
+        let after = cmd.cursor.as_deref().map(str::trim).filter(|cursor| !cursor.is_empty() && !writing);
+        let mut budget = self.budget();
+        let walk = self.walk(&cmd.path, self.max_walk_depth, after, self.max_walk_entries, &mut budget);
+
+        let prefix = if cmd.path == "." { String::new() } else { format!("{}/", cmd.path.trim_end_matches('/')) };
+        // Reading gets a budget of its own, so the first file is always
+        // read however much the walk used
+        let mut reads = self.budget();
+        let mut files = Vec::new();
+        let mut total_bytes = 0;
+        let mut next_cursor = walk.next_cursor.clone();
+        for (i, file) in walk.files.iter().enumerate() {
+            if file == target {
+                continue;
+            }
+            if reads.exhausted() {
+                next_cursor = Some(walk.files[i - 1].clone());
+                break;
+            }
+            let content = match self.fs.read_file(&self.resolve_path(file)) {
+                Ok(content) => content,
+                Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", file, e)),
+            };
+            reads.spend_bytes(content.len());
+            total_bytes += content.len();
+            files.push(json!({
+                "path": file.strip_prefix(&prefix).unwrap_or(file),
+                "size": content.len(),
+                "sha256": format!("{:x}", Sha256::digest(&content)),
+            }));
+        }
+
+        let complete = next_cursor.is_none() && walk.pruned.is_empty();
+        if writing && !complete {
+            return CommandResult::error(cmd, FsChildError::TooLarge(format!(
+                "'{}' is too large to describe in one manifest within the walk limits; leave out the destination to page through it",
+                cmd.path
+            )));
+        }
+        let manifest = json!({
+            "root": cmd.path,
+            "files": files,
+            "total_bytes": total_bytes,
+            "complete": complete,
+        });
+        if !writing {
+            let mut message = format!("Manifest of '{}': {} files, {} bytes", cmd.path, files.len(), total_bytes);
+            if let Some(next) = &next_cursor {
+                message.push_str(&format!("; stopped early, continue with <cursor>{}</cursor>", next));
+            }
+            return CommandResult::ok(cmd, message)
+                .with_data(json!({"manifest": manifest, "next_cursor": next_cursor, "pruned": walk.pruned}));
+        }
+        let mut text = serde_json::to_string_pretty(&manifest).unwrap_or_default();
+        text.push('\n');
+        match self.fs.write_file(target_path, &text) {
+            Ok(_) => CommandResult::ok(cmd, format!("Wrote a manifest of '{}' ({} files, {} bytes) to '{}'", cmd.path, files.len(), total_bytes, target))
+                .with_data(json!({"destination": target, "files": files.len(), "total_bytes": total_bytes})),
+            Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", target, e)),
+        }
+    }
+
+    /// `compact`: replace the files under `path` that share their content
+    /// with another file there by stubs naming one stored copy. Files the
+    /// walk limits leave out are left for a later `compact` from the cursor.
+    pub(crate) fn compact(&self, cmd: &FsCommand) -> CommandResult {
+        if self.fs.list_files(&self.resolve_path(&cmd.path)).is_err() {
+            return CommandResult::error(cmd, FsChildError::NotFound(format!("Failed to list files in '{}': not a directory", cmd.path)));
+        }
+        let after = cmd.cursor.as_deref().map(str::trim).filter(|cursor| !cursor.is_empty());
+        let mut budget = self.budget();
+        let walk = self.walk(&cmd.path, self.max_walk_depth, after, self.max_walk_entries, &mut budget);
+        let mut reads = self.budget();
+        let mut next_cursor = walk.next_cursor.clone();
+        let mut groups: BTreeMap<String, (vcs::Entry, Vec<String>)> = BTreeMap::new();
+        for (i, file) in walk.files.iter().enumerate().filter(|(_, file)| !vcs::is_internal(file)) {
+            if reads.exhausted() {
+                next_cursor = Some(walk.files[i - 1].clone());
+                break;
+            }
+            let content = match self.fs.read_file(&self.resolve_path(file)) {
+                Ok(content) => content,
+                Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", file, e)),
+            };
+            reads.spend_bytes(content.len());
+            if content.len() < compact::MIN_BYTES {
+                continue;
+            }
+            let entry = vcs::Entry::of(&content);
+            groups.entry(entry.hash.clone()).or_insert_with(|| (entry, Vec::new())).1.push(file.clone());
+        }
+        groups.retain(|_, (_, files)| files.len() > 1);
+        if let Some(refused) = self.refuse_writes(cmd, groups.values().flat_map(|(_, files)| files)) {
+            return refused;
+        }
+
+        let root = self.resolve_path(".");
+        let mut stubbed = 0;
+        let mut saved = 0;
+        for (hash, (entry, files)) in &groups {
+            let blob = compact::blob_path(&root, hash);
+            if !self.fs.path_exists(&blob).unwrap_or(false) {
+                let content = match self.fs.read_file(&self.resolve_path(&files[0])) {
+                    Ok(content) => content,
+                    Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", files[0], e)),
+                };
+                let written = self
+                    .create_parents(&format!("{}/{}", compact::DIR, hash))
+                    .and_then(|_| self.fs.write_file(&blob, &vcs::encode_blob(&content)));
+                if let Err(e) = written {
+                    return CommandResult::host_err(cmd, &e, format!("Failed to store the shared copy of '{}': {}", files[0], e));
+                }
+            }
+            for file in files {
+                if let Err(e) = self.fs.write_file(&self.resolve_path(file), &compact::stub(entry)) {
+                    return CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", file, e));
+                }
+                invalidate_listings(&self.resolve_path(file));
+            }
+            stubbed += files.len();
+            saved += entry.size * (files.len() - 1);
+        }
+
+        let mut message = match groups.is_empty() {
+            true => format!("No duplicate files of {} bytes or more under '{}'", compact::MIN_BYTES, cmd.path),
+            false => format!(
+                "Compacted '{}': {} files now share {} stored copies, saving {} bytes",
+                cmd.path,
+                stubbed,
+                groups.len(),
+                saved
+            ),
+        };
+        if let Some(next) = &next_cursor {
+            message.push_str(&format!("; stopped early, continue with <cursor>{}</cursor>", next));
+        }
+        let groups: Vec<Value> = groups
+            .into_values()
+            .map(|(entry, files)| json!({"sha256": entry.hash, "size": entry.size, "files": files}))
+            .collect();
+        CommandResult::ok(cmd, message).with_data(json!({
+            "groups": groups,
+            "files": stubbed,
+            "saved_bytes": saved,
+            "next_cursor": next_cursor,
+            "pruned": walk.pruned,
+        }))
+    }
+
+    /// `render`: the template at `path` filled from `context`, returned or
+    /// written to `destination`. Templates it includes are read from the
+    /// workspace under the same permissions as `path`.
+    #[cfg(feature = "templates")]
+    pub(crate) fn render_template(&self, cmd: &FsCommand, path: &str, target: &str, target_path: &str) -> CommandResult {
+        let context = match template::context(cmd.context.as_deref()) {
+            Ok(context) => context,
+            Err(e) => return CommandResult::error(cmd, FsChildError::Parse(format!("Cannot render '{}': {}", cmd.path, e))),
+        };
+        let source = match read_text(&*self.fs, cmd, path) {
+            Ok(source) => source,
+            Err(e) => return CommandResult::error(cmd, e),
+        };
+        // The first error reading an included template, to report as is
+        let mut refused = None;
+        let load = |name: &str| {
+            self.load_template(name).map_err(|e| {
+                let message = e.to_string();
+                refused.get_or_insert(e);
+                message
+            })
+        };
+        let rendered = template::render(&cmd.path, source, &context, load);
+        let text = match (rendered, refused) {
+            (Ok(text), _) => text,
+            (Err(_), Some(e)) => return CommandResult::error(cmd, e),
+            (Err(failure), None) => {
+                let message = format!("Cannot render '{}': {}", cmd.path, failure.message);
+                return CommandResult::error(cmd, match failure.missing {
+                    true => FsChildError::NotFound(message),
+                    false => FsChildError::Parse(message),
+                });
+            }
+        };
+        if cmd.destination.is_none() {
+            return CommandResult::ok(cmd, format!("Rendered '{}' ({} bytes):\n{}", cmd.path, text.len(), text))
+                .with_data(json!({"bytes": text.len()}));
+        }
+        match self.fs.write_file(target_path, &text) {
+            Ok(_) => CommandResult::ok(cmd, format!("Rendered '{}' to '{}' ({} bytes)", cmd.path, target, text.len()))
+                .with_data(json!({"destination": target, "bytes": text.len()})),
+            Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", target, e)),
+        }
+    }
+
+    /// A template included by another, or `None` if there is no such file.
+    #[cfg(feature = "templates")]
+    fn load_template(&self, name: &str) -> Result<Option<String>, FsChildError> {
+        let relative = self.normalize_path(name)?;
+        if !self.allowed_at("read", &relative) {
+            return Err(FsChildError::PermissionDenied(format!("Template '{}' cannot be read: 'read' is not permitted there", name)));
+        }
+        match self.fs.read_file(&self.resolve_path(&relative)) {
+            Ok(content) => String::from_utf8(content)
+                .map(Some)
+                .map_err(|_| FsChildError::NotUtf8(format!("Template '{}' is not valid UTF-8", name))),
+            Err(e) => match FsChildError::from_host(&e, format!("Failed to read template '{}': {}", name, e)) {
+                FsChildError::NotFound(_) => Ok(None),
+                e => Err(e),
+            },
+        }
+    }
+
+    /// `export-dir`: every file under `path` packed into a tar archive and
+    /// pushed to the store. Only a complete archive is stored.
+    #[cfg(feature = "archive")]
+    pub(crate) fn export_dir(&self, cmd: &FsCommand) -> CommandResult {
+        if self.fs.list_files(&self.resolve_path(&cmd.path)).is_err() {
+            return CommandResult::error(cmd, FsChildError::NotFound(format!("Failed to list files in '{}': not a directory", cmd.path)));
+        }
+        let mut budget = self.budget();
+        let walk = self.walk(&cmd.path, self.max_walk_depth, None, self.max_walk_entries, &mut budget);
+        if walk.next_cursor.is_some() || !walk.pruned.is_empty() {
+            return CommandResult::error(cmd, FsChildError::TooLarge(format!(
+                "'{}' is too large to export within the walk limits",
+                cmd.path
+            )));
+        }
+
+        let prefix = if cmd.path == "." { String::new() } else { format!("{}/", cmd.path.trim_end_matches('/')) };
+        let mut files = Vec::new();
+        let mut total_bytes = 0;
+        for file in &walk.files {
+            let content = match self.fs.read_file(&self.resolve_path(file)) {
+                Ok(content) => content,
+                Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", file, e)),
+            };
+            total_bytes += content.len();
+            // Checked as we go, so a huge tree stops reading early
+            if total_bytes > self.max_transfer_bytes {
+                return CommandResult::error(cmd, FsChildError::TooLarge(format!(
+                    "'{}' holds more than the {} byte transfer limit",
+                    cmd.path, self.max_transfer_bytes
+                )));
+            }
+            files.push((file.strip_prefix(&prefix).unwrap_or(file).to_string(), content));
+        }
+        let archive = match tar::write(&files) {
+            Ok(archive) => archive,
+            Err(e) => return CommandResult::error(cmd, FsChildError::InvalidPath(format!("Cannot archive '{}': {}", cmd.path, e))),
+        };
+        if archive.len() > self.max_transfer_bytes {
+            return CommandResult::error(cmd, FsChildError::TooLarge(format!(
+                "The archive of '{}' is {} bytes, over the {} byte transfer limit",
+                cmd.path, archive.len(), self.max_transfer_bytes
+            )));
+        }
+
+        let role = cmd.store.as_deref().unwrap_or("artifact");
+        let size = archive.len();
+        match self.store_put(role, archive) {
+            Ok(id) => CommandResult::ok(cmd, format!(
+                "Exported '{}' ({} files, {} bytes) to the {} store as '{}'",
+                cmd.path, files.len(), size, role, id
+            ))
+            .with_data(json!({"id": id, "store": role, "files": files.len(), "bytes": size})),
+            Err(e) => CommandResult::failed(cmd, e, format!("Failed to store the archive of '{}' in the {} store", cmd.path, role)),
+        }
+    }
+
+    /// `import-dir`: a tar archive fetched from the store and unpacked into
+    /// the new directory `path`. Every entry is checked before anything is
+    /// written, so a bad archive leaves no partial tree behind.
+    #[cfg(feature = "archive")]
+    pub(crate) fn import_dir(&self, cmd: &FsCommand, path: &str) -> CommandResult {
+        let Some(id) = &cmd.id else {
+            return CommandResult::error(cmd, FsChildError::MissingField("No id provided for import-dir operation".to_string()));
+        };
+        if self.fs.path_exists(path).unwrap_or(false) {
+            return CommandResult::error(cmd, already_exists(cmd));
+        }
+        let role = cmd.store.as_deref().unwrap_or("artifact");
+        let archive = match self.store_get(role, id, self.max_transfer_bytes) {
+            Ok(archive) => archive,
+            Err(e) => return CommandResult::failed(cmd, e, format!("Failed to fetch '{}' from the {} store", id, role)),
+        };
+        let entries = match tar::read(&archive) {
+            Ok(entries) => entries,
+            Err(e) => return CommandResult::error(cmd, FsChildError::Parse(format!("Store value '{}' is not a usable archive: {}", id, e))),
+        };
+
+        let mut dirs = BTreeSet::new();
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+        let mut binary = Vec::new();
+        for entry in entries {
+            let relative = match tar::relative_path(&entry.path) {
+                Ok(relative) => relative,
+                Err(e) => return CommandResult::error(cmd, FsChildError::InvalidPath(format!("Not unpacking '{}': {}", id, e))),
+            };
+            if relative.is_empty() {
+                continue;
+            }
+            match entry.kind {
+                tar::Kind::Directory => {
+                    dirs.insert(relative);
+                }
+                tar::Kind::File => {
+                    let mut parent = relative.as_str();
+                    while let Some((dir, _)) = parent.rsplit_once('/') {
+                        dirs.insert(dir.to_string());
+                        parent = dir;
+                    }
+                    // The host filesystem interface only writes text
+                    match String::from_utf8(entry.content) {
+                        Ok(text) => files.push((relative, text)),
+                        Err(_) => binary.push(relative),
+                    }
+                }
+                tar::Kind::Other(_) => skipped.push(relative),
+            }
+        }
+        if !binary.is_empty() {
+            return CommandResult::error(cmd, FsChildError::NotUtf8(format!(
+                "Not unpacking '{}': {} file(s) are not valid UTF-8 and cannot be written: {}",
+                id,
+                binary.len(),
+                binary.join(", ")
+            )));
+        }
+
+        let root = cmd.path.trim_end_matches('/');
+        let unpacked: Vec<String> = dirs.iter().chain(files.iter().map(|(file, _)| file)).map(|path| format!("{}/{}", root, path)).collect();
+        if let Some(refused) = self.refuse_writes(cmd, &unpacked) {
+            return refused;
+        }
+        // Sorted, so every directory is created after its parent
+        for dir in std::iter::once(String::new()).chain(dirs.iter().cloned()) {
+            let relative = if dir.is_empty() { root.to_string() } else { format!("{}/{}", root, dir) };
+            let host_path = self.resolve_path(&relative);
+            if self.fs.path_exists(&host_path).unwrap_or(false) {
+                continue;
+            }
+            if let Err(e) = self.fs.create_dir(&host_path) {
+                return CommandResult::host_err(cmd, &e, format!("Failed to create directory '{}': {}", relative, e));
+            }
+        }
+        let mut total_bytes = 0;
+        for (file, text) in &files {
+            let relative = format!("{}/{}", root, file);
+            if let Err(e) = self.fs.write_file(&self.resolve_path(&relative), text) {
+                return CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", relative, e));
+            }
+            total_bytes += text.len();
+        }
+
+        let mut message = format!("Unpacked '{}' into '{}' ({} files, {} bytes)", id, cmd.path, files.len(), total_bytes);
+        if !skipped.is_empty() {
+            message.push_str(&format!("; skipped {} link or special entries", skipped.len()));
+        }
+        CommandResult::ok(cmd, message).with_data(json!({
+            "id": id,
+            "store": role,
+            "files": files.len(),
+            "bytes": total_bytes,
+            "skipped": skipped,
+        }))
+    }
+
+    /// Every file in the workspace, leaving out the actor's own data. A
+    /// tree too large to walk in full is refused rather than recorded in
+    /// part.
+    fn snapshot(&self) -> Result<BTreeMap<String, vcs::Entry>, FsChildError> {
+        let too_large = || {
+            FsChildError::TooLarge(format!(
+                "The workspace is too large to snapshot within max_walk_entries ({}), max_walk_depth ({}) and the work budget",
+                self.max_walk_entries, self.max_walk_depth
+            ))
+        };
+        let mut budget = self.budget();
+        let walk = self.walk(".", self.max_walk_depth, None, self.max_walk_entries, &mut budget);
+        if walk.next_cursor.is_some() || !walk.pruned.is_empty() {
+            return Err(too_large());
+        }
+        let mut files = BTreeMap::new();
+        for file in walk.files.into_iter().filter(|file| !vcs::is_internal(file)) {
+            if budget.exhausted() {
+                return Err(too_large());
+            }
+            let content = self
+                .fs
+                .read_file(&self.resolve_path(&file))
+                .map_err(|e| FsChildError::from_host(&e, format!("Cannot read '{}': {}", file, e)))?;
+            budget.spend_bytes(content.len());
+            files.insert(file, vcs::Entry::of(&content));
+        }
+        Ok(files)
+    }
+
+    fn head_commit(&self) -> Option<vcs::Commit> {
+        let head = self.fs.read_file(&self.resolve_path(&vcs::head_path())).ok()?;
+        self.load_commit(String::from_utf8_lossy(&head).trim()).ok()
+    }
+
+    fn load_commit(&self, id: &str) -> Result<vcs::Commit, FsChildError> {
+        let bytes = self
+            .fs
+            .read_file(&self.resolve_path(&vcs::commit_path(id)))
+            .map_err(|e| FsChildError::from_host(&e, format!("Cannot read commit '{}': {}", id, e)))?;
+        serde_json::from_slice(&bytes).map_err(|e| FsChildError::Parse(format!("Commit '{}' is unreadable: {}", id, e)))
+    }
+
+    /// The commit an id or id prefix names.
+    fn find_commit(&self, selector: &str) -> Result<vcs::Commit, FsChildError> {
+        let ids: Vec<String> = self
+            .fs
+            .list_files(&self.resolve_path(&vcs::commits_dir()))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|name| name.strip_suffix(".json").map(str::to_string))
+            .collect();
+        let id = vcs::find(&ids, selector).map_err(FsChildError::NotFound)?;
+        self.load_commit(id)
+    }
+
+    /// Create every missing directory above the workspace path `relative`.
+    pub(crate) fn create_parents(&self, relative: &str) -> Result<(), String> {
+        let mut end = 0;
+        while let Some(slash) = relative[end..].find('/') {
+            end += slash;
+            let dir = self.resolve_path(&relative[..end]);
+            if !self.fs.path_exists(&dir).unwrap_or(false) {
+                self.fs.create_dir(&dir)?;
+            }
+            end += 1;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn commit(&self, cmd: &FsCommand) -> CommandResult {
+        let message = cmd.message.as_deref().unwrap_or_default().trim();
+        let files = match self.snapshot() {
+            Ok(files) => files,
+            Err(e) => return CommandResult::failed(cmd, e, "Nothing committed".to_string()),
+        };
+        let parent = self.head_commit();
+        let diff = vcs::diff(&parent.as_ref().map(|p| p.files.clone()).unwrap_or_default(), &files);
+        if let Some(parent) = parent.as_ref().filter(|_| diff.is_empty()) {
+            return CommandResult::ok(cmd, format!("Nothing changed since commit {}", parent.id))
+                .with_data(json!({"id": parent.id, "unchanged": true}));
+        }
+
+        let commit = vcs::Commit::new(parent.map(|p| p.id), message, self.turn, files);
+        let history: Vec<String> = commit
+            .files
+            .values()
+            .map(|entry| vcs::blob_path(&entry.hash))
+            .chain([vcs::commit_path(&commit.id), vcs::head_path()])
+            .collect();
+        if let Some(refused) = self.refuse_writes(cmd, &history) {
+            return refused;
+        }
+        let write = |relative: &str, text: &str| {
+            self.create_parents(relative)
+                .and_then(|_| self.fs.write_file(&self.resolve_path(relative), text))
+                .map_err(|e| (relative.to_string(), e))
+        };
+        let mut written = BTreeSet::new();
+        for (file, entry) in &commit.files {
+            let blob = vcs::blob_path(&entry.hash);
+            if !written.insert(blob.clone()) || self.fs.path_exists(&self.resolve_path(&blob)).unwrap_or(false) {
+                continue;
+            }
+            let content = match self.fs.read_file(&self.resolve_path(file)) {
+                Ok(content) => content,
+                Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", file, e)),
+            };
+            if let Err((path, e)) = write(&blob, &vcs::encode_blob(&content)) {
+                return CommandResult::host_err(cmd, &e, format!("Failed to write '{}': {}", path, e));
+            }
+        }
+        let manifest = match serde_json::to_string_pretty(&commit) {
+            Ok(manifest) => manifest,
+            Err(e) => return CommandResult::error(cmd, e.into()),
+        };
+        if let Err((path, e)) = write(&vcs::commit_path(&commit.id), &manifest).and_then(|_| write(&vcs::head_path(), &commit.id)) {
+            return CommandResult::host_err(cmd, &e, format!("Failed to write '{}': {}", path, e));
+        }
+
+        CommandResult::ok(cmd, format!("Committed {}: {} files ({})", commit.id, commit.files.len(), diff.summary())).with_data(json!({
+            "id": commit.id,
+            "parent": commit.parent,
+            "files": commit.files.len(),
+            "added": diff.added,
+            "changed": diff.changed,
+            "removed": diff.removed,
+        }))
+    }
+
+    pub(crate) fn checkout(&self, cmd: &FsCommand) -> CommandResult {
+        let commit = match self.find_commit(cmd.id.as_deref().unwrap_or_default()) {
+            Ok(commit) => commit,
+            Err(e) => return CommandResult::error(cmd, e),
+        };
+        let current = match self.snapshot() {
+            Ok(files) => files,
+            Err(e) => return CommandResult::failed(cmd, e, "Nothing checked out".to_string()),
+        };
+        if !flag(cmd.force.as_deref()) {
+            let head_files = self.head_commit().map(|head| head.files).unwrap_or_default();
+            let uncommitted = vcs::diff(&head_files, &current);
+            if !uncommitted.is_empty() {
+                return CommandResult::error(cmd, FsChildError::Conflict(format!(
+                    "Not checking out {}: the workspace has uncommitted changes ({}); commit them first, or set force to discard them",
+                    commit.id,
+                    uncommitted.summary()
+                )))
+                .with_data(json!({"uncommitted": uncommitted}));
+            }
+        }
+
+        let diff = vcs::diff(&current, &commit.files);
+        if let Some(refused) = self.refuse_writes(cmd, diff.added.iter().chain(&diff.changed).chain(&diff.removed)) {
+            return refused;
+        }
+        if let Some(e) = self.checkout_quota(&commit.id, &current, &commit.files, &diff) {
+            return CommandResult::error(cmd, e);
+        }
+        // Every file is read from the history before any is replaced, so a
+        // missing one leaves the tree as it was
+        let mut texts = Vec::new();
+        let mut skipped = Vec::new();
+        for file in diff.added.iter().chain(&diff.changed) {
+            let entry = &commit.files[file];
+            let blob = self.fs.read_file(&self.resolve_path(&vcs::blob_path(&entry.hash)));
+            let content = match blob.map_err(|e| e.to_string()).and_then(|blob| vcs::decode_blob(&blob, entry)) {
+                Ok(content) => content,
+                Err(e) => return CommandResult::error(cmd, FsChildError::NotFound(format!("Content of '{}' is missing from the history: {}", file, e))),
+            };
+            // The host filesystem interface only writes text
+            match String::from_utf8(content) {
+                Ok(text) => texts.push((file, text)),
+                Err(_) => skipped.push(file.clone()),
+            }
+        }
+        for (file, text) in texts {
+            if let Err(e) = self.create_parents(file).and_then(|_| self.fs.write_file(&self.resolve_path(file), &text)) {
+                return CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", file, e));
+            }
+        }
+        for file in &diff.removed {
+            if let Err(e) = self.fs.delete_file(&self.resolve_path(file)) {
+                return CommandResult::host_err(cmd, &e, format!("Failed to delete file '{}': {}", file, e));
+            }
+        }
+        if let Err(e) = self.fs.write_file(&self.resolve_path(&vcs::head_path()), &commit.id) {
+            return CommandResult::host_err(cmd, &e, format!("Failed to write '{}': {}", vcs::head_path(), e));
+        }
+
+        let mut message = format!("Checked out {} ({}): {}", commit.id, commit.message, diff.summary());
+        if !skipped.is_empty() {
+            message.push_str(&format!("; {} binary file(s) could not be written back: {}", skipped.len(), skipped.join(", ")));
+        }
+        CommandResult::ok(cmd, message).with_data(json!({
+            "id": commit.id,
+            "added": diff.added,
+            "changed": diff.changed,
+            "removed": diff.removed,
+            "skipped": skipped,
+        }))
+    }
+
+    /// The error for a checkout of `id` that would take a hard quota over
+    /// its limit, judged from the sizes the history records before any file
+    /// changes. Usage was measured as the command began.
+    fn checkout_quota(
+        &self,
+        id: &str,
+        current: &BTreeMap<String, vcs::Entry>,
+        files: &BTreeMap<String, vcs::Entry>,
+        diff: &vcs::Diff,
+    ) -> Option<FsChildError> {
+        for (prefix, quota) in self.quotas.iter().filter(|(_, quota)| quota.limit == Limit::Hard) {
+            let Some(before) = self.usage.get(prefix) else {
+                continue;
+            };
+            let mut after = *before;
+            for file in diff.added.iter().chain(&diff.changed).chain(&diff.removed).filter(|file| quota::covers(prefix, file)) {
+                after.apply(current.get(file).map(|entry| entry.size), files.get(file).map(|entry| entry.size));
+            }
+            if let Some(excess) = quota.excess(&after).filter(|_| after.grew_from(before)) {
+                return Some(FsChildError::Quota(format!("Not checking out {}: it would put '{}' over its quota ({})", id, prefix, excess)));
+            }
+        }
+        None
+    }
+
+    pub(crate) fn matching_files(&self, pattern: &str, budget: &mut Budget) -> Vec<String> {
+        if !watch::is_glob(pattern) {
+            if self.fs.list_files(&self.resolve_path(pattern)).is_ok() {
+                return self.walk_files(pattern, watch::WATCH_SCAN_LIMIT, budget);
+            }
+            return vec![pattern.to_string()];
+        }
+        self.walk_files(&watch::glob_base(pattern), watch::WATCH_SCAN_LIMIT, budget)
+            .into_iter()
+            .filter(|path| watch::glob_match(pattern, path))
+            .collect()
+    }
+}
+
+/// A command the permissions, mounts or policy refuse, and the change to
+/// the configuration that would let it run.
+struct Refusal {
+    error: FsChildError,
+    /// The permission or policy action missing
+    requires: String,
+    /// Configuration key to change, as in `permissions` or `policy[0]`
+    key: String,
+    grant: String,
+    /// Deciding policy rule, from 1
+    rule: Option<usize>,
+}
+
+impl Refusal {
+    /// A permission missing from `key`.
+    fn lacking(message: String, permission: &str, key: String) -> Self {
+        let grant = format!("adding '{}' to `{}`", permission, key);
+        Refusal {
+            error: FsChildError::PermissionDenied(format!("{}. Retrying will not help; the parent can allow it by {} in the configuration", message, grant)),
+            requires: permission.to_string(),
+            key,
+            grant,
+            rule: None,
+        }
+    }
+
+    /// A path outside every directory the instance was given.
+    fn outside(message: String, permission: &str) -> Self {
+        let grant = "adding a mount holding it to `mounts`".to_string();
+        Refusal {
+            error: FsChildError::PermissionDenied(format!("{}. Retrying will not help; the parent can allow it by {} in the configuration", message, grant)),
+            requires: permission.to_string(),
+            key: "mounts".to_string(),
+            grant,
+            rule: None,
+        }
+    }
+
+    fn data(&self) -> Value {
+        json!({"requires": self.requires, "config_key": self.key, "grant": self.grant, "rule": self.rule})
+    }
+}
+
+/// A command as the policy rules see it.
+fn action<'a>(cmd: &'a FsCommand, spec: &'a OperationSpec) -> policy::Action<'a> {
+    policy::Action {
+        operation: &cmd.operation,
+        permission: spec.permission,
+        writes: cmd.writes().then(|| cmd.request_bytes()),
+    }
+}
+
+/// An unknown-operation error with `message`, pointing at the operation
+/// `name` was probably meant to be.
+pub(crate) fn unknown_operation(name: &str, message: String) -> FsChildError {
+    match operations::suggest(name) {
+        Some(suggestion) => FsChildError::UnknownOperation(format!("{}; did you mean '{}'?", message, suggestion)),
+        None => FsChildError::UnknownOperation(message),
+    }
+}
+
+/// Whether an optional boolean field is set to true.
+pub(crate) fn flag(value: Option<&str>) -> bool {
+    value.is_some_and(|v| matches!(v.trim(), "true" | "yes" | "1"))
+}
+
+/// Whether a `write-file` may go ahead given whether its file exists: with
+/// `overwrite` false it may only create the file, with `create` false only
+/// replace it.
+pub(crate) fn write_precondition(fs: &dyn Filesystem, cmd: &FsCommand, path: &str, truncation_ratio: f64) -> Result<(), FsChildError> {
+    let overwrite = cmd.overwrite.as_deref().is_none_or(|v| flag(Some(v)));
+    let create = cmd.create.as_deref().is_none_or(|v| flag(Some(v)));
+    if !overwrite && !create {
+        return Err(FsChildError::Parse("overwrite and create cannot both be false".to_string()));
+    }
+    match fs.path_exists(path) {
+        Ok(true) if !overwrite => Err(FsChildError::AlreadyExists(format!(
+            "'{}' already exists; overwrite is false, so it was left as it is",
+            cmd.path
+        ))),
+        Ok(false) if !create => Err(FsChildError::NotFound(format!(
+            "'{}' does not exist; create is false, so only an existing file is written",
+            cmd.path
+        ))),
+        Ok(true) => truncation_guard(fs, cmd, path, truncation_ratio),
+        _ => Ok(()),
+    }
+}
+
+/// `write-file` with `dry_run`: what the write would do, shown for a file
+/// that exists as a diff against its content, and nothing written.
+pub(crate) fn write_preview(fs: &dyn Filesystem, cmd: &FsCommand, path: &str, content: &str, truncation_ratio: f64) -> CommandResult {
+    let mut data = json!({"dry_run": true, "bytes": content.len()});
+    let mut text = match fs.read_file(path) {
+        Err(_) => format!("Would create '{}' ({} bytes, {} lines)", cmd.path, content.len(), content.lines().count()),
+        Ok(current) => match String::from_utf8(current) {
+            Err(_) => format!("Would replace binary file '{}' with {} bytes of text", cmd.path, content.len()),
+            Ok(current) => {
+                let diff = diff::unified(&format!("a/{}", cmd.path), &format!("b/{}", cmd.path), &current, content);
+                data["added"] = json!(diff.added);
+                data["removed"] = json!(diff.removed);
+                match diff.is_empty() {
+                    true => format!("Would leave '{}' unchanged: the content is the same", cmd.path),
+                    false => format!("Would change '{}' (+{} -{} lines):\n{}", cmd.path, diff.added, diff.removed, diff.text.trim_end()),
+                }
+            }
+        },
+    };
+    if let Err(e) = truncation_guard(fs, cmd, path, truncation_ratio) {
+        text = format!("{}\nThe write itself would fail with {:?}: {}", text, e.code(), e);
+        data["would_fail"] = json!(e.code());
+    }
+    CommandResult::ok(cmd, text).with_data(data)
+}
+
+/// Refuse to replace a file with content under `ratio` of its size, which
+/// is more often a summary written over it than a rewrite, unless
+/// `confirm_truncation` says it is meant.
+fn truncation_guard(fs: &dyn Filesystem, cmd: &FsCommand, path: &str, ratio: f64) -> Result<(), FsChildError> {
+    if ratio <= 0.0 || flag(cmd.confirm_truncation.as_deref()) {
+        return Ok(());
+    }
+    let Ok(existing) = fs.read_file(path) else {
+        return Ok(());
+    };
+    let content = cmd.content.as_deref().unwrap_or_default();
+    if existing.len() < TRUNCATION_MIN_BYTES || content.len() as f64 >= existing.len() as f64 * ratio {
+        return Ok(());
+    }
+    let lines = |bytes: &[u8]| bytes.split(|b| *b == b'\n').filter(|line| !line.is_empty()).count();
+    Err(FsChildError::Truncation(format!(
+        "Not writing '{}': {} bytes ({} lines) would replace {} bytes ({} lines), less than {}% of it. \
+         Set confirm_truncation to true if the file is meant to shrink, or change part of it with edit-file",
+        cmd.path,
+        content.len(),
+        lines(content.as_bytes()),
+        existing.len(),
+        lines(&existing),
+        (ratio * 100.0).round()
+    )))
+}
+
+/// With `format`, pretty-print the content of a write to a `.json`,
+/// `.toml`, `.yaml` or `.yml` file. Returns a note for the result and the
+/// value of `data.formatted`: the format, or `null` when it was skipped.
+fn pretty_print(cmd: &mut FsCommand) -> Result<Option<(String, Value)>, FsChildError> {
+    if cmd.operation != "write-file" || !flag(cmd.format.as_deref()) {
+        return Ok(None);
+    }
+    let (Some(kind), Some(content)) = (pretty::Kind::from_path(&cmd.path), cmd.content.as_deref()) else {
+        return Ok(Some(("not formatted: unrecognized file type".to_string(), Value::Null)));
+    };
+    match pretty::format(kind, content, flag(cmd.sort_keys.as_deref())) {
+        Ok(pretty::Outcome::Formatted(text)) => {
+            cmd.content = Some(text);
+            Ok(Some((format!("formatted as {}", kind.name()), json!(kind.name()))))
+        }
+        Ok(pretty::Outcome::Skipped(reason)) => Ok(Some((format!("not formatted: {}", reason), Value::Null))),
+        Err(e) => Err(FsChildError::Parse(format!("Not writing '{}': the content is {}", cmd.path, e))),
+    }
+}
+
+/// The result refusing text that is not JSON, or breaks `schema`, before
+/// it is written. It lists every violation in `data.violations`.
+pub(crate) fn schema_failure(cmd: &FsCommand, schema: Option<&Value>, text: &str) -> Option<CommandResult> {
+    let (e, violations) = schema_violations(cmd, schema?, text).err()?;
+    let result = CommandResult::error(cmd, e);
+    Some(match violations {
+        Some(violations) => result.with_data(json!({"violations": violations})),
+        None => result,
+    })
+}
+
+fn schema_violations(
+    cmd: &FsCommand,
+    schema: &Value,
+    text: &str,
+) -> Result<(), (FsChildError, Option<Vec<schema::Violation>>)> {
+    let document = serde_json::from_str::<Value>(text).map_err(|e| {
+        let message = format!("Not writing '{}': the content is not valid JSON: {}", cmd.path, e);
+        (FsChildError::SchemaViolation(message), None)
+    })?;
+    let violations = schema::validate(schema, &document);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let listed = violations
+        .iter()
+        .take(10)
+        .map(|v| format!("\n  {}: {}", if v.path.is_empty() { "(document)" } else { &v.path }, v.message))
+        .collect::<String>();
+    let more = violations.len().saturating_sub(10);
+    let message = format!(
+        "Not writing '{}': {} schema violation(s){}{}",
+        cmd.path,
+        violations.len(),
+        listed,
+        if more > 0 { format!("\n  ... and {} more", more) } else { String::new() }
+    );
+    Err((FsChildError::SchemaViolation(message), Some(violations)))
+}
+
+/// Read a file as text for an operation that parses it.
+fn read_text(fs: &dyn Filesystem, cmd: &FsCommand, path: &str) -> Result<String, FsChildError> {
+    let content = fs.read_file(path)
+        .map_err(|e| FsChildError::from_host(&e, format!("Failed to read file '{}': {}", cmd.path, e)))?;
+    String::from_utf8(content)
+        .map_err(|_| FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)))
+}
+
+#[cfg(feature = "structured")]
+pub(crate) fn frontmatter_get(fs: &dyn Filesystem, cmd: &FsCommand, path: &str) -> CommandResult {
+    let matter = match read_text(fs, cmd, path).and_then(|text| {
+        frontmatter::get(&text).map_err(|e| FsChildError::Parse(format!("In '{}': {}", cmd.path, e)))
+    }) {
+        Ok(Some(matter)) => matter,
+        Ok(None) => return CommandResult::ok(cmd, format!("'{}' has no front matter", cmd.path))
+            .with_data(json!({"frontmatter": null})),
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    match cmd.key.as_deref().map(str::trim) {
+        None => CommandResult::ok(cmd, format!("Front matter of '{}': {}", cmd.path, matter))
+            .with_data(json!({"frontmatter": matter})),
+        Some(key) => match matter.get(key) {
+            Some(value) => CommandResult::ok(cmd, format!("{} in '{}': {}", key, cmd.path, value))
+                .with_data(json!({"key": key, "value": value})),
+            None => CommandResult::error(cmd, FsChildError::NotFound(format!("Front matter of '{}' has no key '{}'", cmd.path, key))),
+        },
+    }
+}
+
+/// A `value` field as JSON: `true`, `3` or `["a"]` are typed values, and
+/// anything that does not parse is text.
+#[cfg(feature = "structured")]
+fn frontmatter_value(raw: &str) -> Value {
+    serde_json::from_str::<Value>(raw.trim()).unwrap_or_else(|_| json!(raw))
+}
+
+#[cfg(feature = "structured")]
+pub(crate) fn frontmatter_set(fs: &dyn Filesystem, cmd: &FsCommand, path: &str) -> CommandResult {
+    let (Some(key), Some(raw)) = (cmd.key.as_deref().map(str::trim), cmd.value.as_deref()) else {
+        return CommandResult::error(cmd, FsChildError::MissingField("Both key and value must be provided for frontmatter-set".to_string()));
+    };
+    let value = frontmatter_value(raw);
+    let text = match read_text(fs, cmd, path) {
+        Ok(text) => text,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    let updated = match frontmatter::set(&text, key, &value) {
+        Ok(updated) => updated,
+        Err(e) => return CommandResult::error(cmd, FsChildError::Parse(format!("Not changing '{}': {}", cmd.path, e))),
+    };
+    match fs.write_file(path, &updated) {
+        Ok(_) => CommandResult::ok(cmd, format!("Set {} in the front matter of '{}' to {}", key, cmd.path, value))
+            .with_data(json!({"key": key, "value": value})),
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", cmd.path, e)),
+    }
+}
+
+/// The `dependency` field as a [`cargo_toml::Dependency`] to add.
+#[cfg(feature = "structured")]
+fn cargo_dependency(cmd: &FsCommand) -> Result<cargo_toml::Dependency<'_>, FsChildError> {
+    let (Some(name), Some(version)) = (cmd.dependency.as_deref(), cmd.version.as_deref()) else {
+        return Err(FsChildError::MissingField("Both dependency and version must be provided for cargo-add-dep".to_string()));
+    };
+    Ok(cargo_toml::Dependency {
+        name: name.trim(),
+        version: version.trim(),
+        features: cmd
+            .features
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+        optional: flag(cmd.optional.as_deref()),
+    })
+}
+
+#[cfg(feature = "structured")]
+pub(crate) fn cargo_add_dep(fs: &dyn Filesystem, cmd: &FsCommand, path: &str) -> CommandResult {
+    let dependency = match cargo_dependency(cmd) {
+        Ok(dependency) => dependency,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    let table = cmd.table.as_deref().unwrap_or("dependencies").trim();
+    let (updated, change) = match read_text(fs, cmd, path).and_then(|text| {
+        cargo_toml::add_dependency(&text, table, &dependency)
+            .map_err(|e| FsChildError::Parse(format!("Not changing '{}': {}", cmd.path, e)))
+    }) {
+        Ok(edited) => edited,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    let (verb, action) = match change {
+        cargo_toml::Change::Added => ("Added", "added"),
+        cargo_toml::Change::Updated => ("Updated", "updated"),
+        cargo_toml::Change::Unchanged => {
+            return CommandResult::ok(cmd, format!("'{}' already lists {} as asked in [{}]", cmd.path, dependency.name, table))
+                .with_data(json!({"dependency": dependency.name, "table": table, "action": "unchanged"}))
+        }
+    };
+    match fs.write_file(path, &updated) {
+        Ok(_) => CommandResult::ok(cmd, format!("{} {} {} in [{}] of '{}'", verb, dependency.name, dependency.version, table, cmd.path))
+            .with_data(json!({"dependency": dependency.name, "version": dependency.version, "table": table, "action": action})),
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", cmd.path, e)),
+    }
+}
+
+#[cfg(feature = "structured")]
+pub(crate) fn cargo_set_version(fs: &dyn Filesystem, cmd: &FsCommand, path: &str) -> CommandResult {
+    let Some(version) = cmd.version.as_deref().map(str::trim) else {
+        return CommandResult::error(cmd, FsChildError::MissingField("No version provided for cargo-set-version".to_string()));
+    };
+    let dependency = cmd.dependency.as_deref().map(str::trim);
+    let (updated, tables) = match read_text(fs, cmd, path).and_then(|text| {
+        cargo_toml::set_version(&text, dependency, cmd.table.as_deref(), version)
+            .map_err(|e| FsChildError::Parse(format!("Not changing '{}': {}", cmd.path, e)))
+    }) {
+        Ok(edited) => edited,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    let subject = match dependency {
+        Some(name) if tables.is_empty() => {
+            return CommandResult::error(cmd, FsChildError::NotFound(format!("'{}' has no dependency on {}", cmd.path, name)))
+        }
+        Some(name) => name.to_string(),
+        None => "the package version".to_string(),
+    };
+    match fs.write_file(path, &updated) {
+        Ok(_) => CommandResult::ok(cmd, format!("Set {} to {} in [{}] of '{}'", subject, version, tables.join("], ["), cmd.path))
+            .with_data(json!({"dependency": dependency, "version": version, "tables": tables})),
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", cmd.path, e)),
+    }
+}
+
+/// The table of contents `generate-toc` would leave in the file.
+fn toc_for(cmd: &FsCommand, text: &str) -> Result<toc::Toc, FsChildError> {
+    let depth = match cmd.depth.as_deref().map(str::trim) {
+        None => toc::DEFAULT_DEPTH,
+        Some(depth) => match depth.parse::<usize>() {
+            Ok(depth @ 1..=6) => depth,
+            _ => return Err(FsChildError::Parse(format!("Invalid depth '{}'; heading levels run from 1 to 6", depth))),
+        },
+    };
+    toc::generate(text, depth).map_err(|e| FsChildError::Parse(format!("Not changing '{}': {}", cmd.path, e)))
+}
+
+pub(crate) fn generate_toc(fs: &dyn Filesystem, cmd: &FsCommand, path: &str) -> CommandResult {
+    let (text, toc) = match read_text(fs, cmd, path).and_then(|text| toc_for(cmd, &text).map(|toc| (text, toc))) {
+        Ok(generated) => generated,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    let data = json!({"entries": toc.entries, "inserted": toc.inserted});
+    if toc.text == text {
+        return CommandResult::ok(cmd, format!("The table of contents of '{}' is already up to date", cmd.path)).with_data(data);
+    }
+    match fs.write_file(path, &toc.text) {
+        Ok(_) => CommandResult::ok(cmd, format!(
+            "{} the table of contents of '{}' ({} entries)",
+            if toc.inserted { "Inserted" } else { "Updated" },
+            cmd.path,
+            toc.entries
+        ))
+        .with_data(data),
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", cmd.path, e)),
+    }
+}
+
+/// The record of an `append-ndjson` as one line of JSON.
+fn ndjson_record(cmd: &FsCommand) -> Result<String, FsChildError> {
+    let Some(content) = cmd.content.as_deref() else {
+        return Err(FsChildError::MissingField("No content provided for append-ndjson".to_string()));
+    };
+    pretty::compact_json(content)
+        .map_err(|e| FsChildError::Parse(format!("Not appending to '{}': the record is {}", cmd.path, e)))
+}
+
+pub(crate) fn append_ndjson(fs: &dyn Filesystem, cmd: &FsCommand, path: &str) -> CommandResult {
+    let record = match ndjson_record(cmd) {
+        Ok(record) => record,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    // The host can only write whole files, so the log is read and written
+    // back with the record on the end
+    let mut text = match fs.read_file(path) {
+        Ok(content) => match String::from_utf8(content) {
+            Ok(text) => text,
+            Err(_) => return CommandResult::error(cmd, FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path))),
+        },
+        Err(_) if !fs.path_exists(path).unwrap_or(false) => String::new(),
+        Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", cmd.path, e)),
+    };
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(&record);
+    text.push('\n');
+    let lines = text.lines().count();
+    match fs.write_file(path, &text) {
+        Ok(_) => CommandResult::ok(cmd, format!("Appended a record to '{}' (now {} lines)", cmd.path, lines))
+            .with_data(json!({"bytes": record.len() + 1, "lines": lines})),
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", cmd.path, e)),
+    }
+}
+
+/// The converted text of `text` and how many records it holds.
+fn conversion(cmd: &FsCommand, text: &str, target: &str) -> Result<(String, usize), FsChildError> {
+    if target == cmd.path {
+        return Err(FsChildError::InvalidPath(format!("'{}' would be converted onto itself; give a destination", cmd.path)));
+    }
+    let csv_path = if cmd.operation == "csv-to-json" { &cmd.path } else { target };
+    let delimiter = match cmd.delimiter.as_deref() {
+        None if csv_path.to_ascii_lowercase().ends_with(".tsv") => '\t',
+        None => ',',
+        Some("\\t" | "tab") => '\t',
+        Some(delimiter) => {
+            let mut chars = delimiter.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !matches!(c, '"' | '\n' | '\r') => c,
+                _ => return Err(FsChildError::Parse(format!("Invalid delimiter '{}'; it must be one character", delimiter))),
+            }
+        }
+    };
+    let header = cmd.header.as_deref().is_none_or(|header| flag(Some(header)));
+    let converted = match cmd.operation.as_str() {
+        "csv-to-json" => csv::to_json(text, delimiter, header),
+        _ => csv::from_json(text, delimiter, header),
+    };
+    converted.map_err(|e| FsChildError::Parse(format!("Cannot convert '{}': {}", cmd.path, e)))
+}
+
+pub(crate) fn convert(fs: &dyn Filesystem, cmd: &FsCommand, path: &str, target: &str, target_path: &str) -> CommandResult {
+    let (converted, records) = match read_text(fs, cmd, path).and_then(|text| conversion(cmd, &text, target)) {
+        Ok(converted) => converted,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    match fs.write_file(target_path, &converted) {
+        Ok(_) => CommandResult::ok(cmd, format!("Converted '{}' to '{}' ({} records)", cmd.path, target, records))
+            .with_data(json!({"destination": target, "records": records})),
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", target, e)),
+    }
+}
+
+/// Edits search and rewrite UTF-8 bytes, so a UTF-16 file would only ever
+/// report its anchor missing; say why instead.
+pub(crate) fn refuse_utf16(cmd: &FsCommand, content: &[u8]) -> Result<(), FsChildError> {
+    match Bom::detect(content) {
+        Some(bom @ (Bom::Utf16le | Bom::Utf16be)) => Err(FsChildError::NotUtf8(format!(
+            "'{}' is {}; edit-file only changes UTF-8 files (rewrite it with write-file instead)",
+            cmd.path,
+            bom.name()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Which matches an edit replaces, from its `occurrence` and `replace_all`
+/// fields.
+pub(crate) fn edit_target(cmd: &FsCommand) -> Result<edit::Target, FsChildError> {
+    match cmd.occurrence.as_deref().map(str::trim) {
+        Some(n) => n
+            .parse()
+            .ok()
+            .filter(|n| *n > 0)
+            .map(edit::Target::Occurrence)
+            .ok_or_else(|| FsChildError::Parse(format!("Invalid occurrence '{}': expected a number from 1", n))),
+        None if flag(cmd.replace_all.as_deref()) => Ok(edit::Target::All),
+        None => Ok(edit::Target::Only),
+    }
+}
+
+/// Compile `pattern`, reusing an earlier compilation of the same pattern.
+#[cfg(feature = "regex")]
+fn cached_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let key = pattern.to_string();
+    if let Some(regex) = REGEX_CACHE.with(|cache| cache.borrow_mut().get(&key)) {
+        return Ok(regex);
+    }
+    let regex = Regex::new(pattern)?;
+    REGEX_CACHE.with(|cache| cache.borrow_mut().insert(key, regex.clone()));
+    Ok(regex)
+}
+
+/// Where each match of the regular expression `pattern` in `text` starts.
+fn regex_starts(pattern: &str, text: &str) -> Result<Vec<usize>, FsChildError> {
+    Ok(regex_ranges(pattern, text)?.into_iter().map(|(start, _)| start).collect())
+}
+
+/// The byte range of each match of the regular expression `pattern` in
+/// `text`.
+#[cfg(feature = "regex")]
+fn regex_ranges(pattern: &str, text: &str) -> Result<Vec<(usize, usize)>, FsChildError> {
+    let regex = cached_regex(pattern)
+        .map_err(|e| FsChildError::Parse(format!("Invalid regular expression '{}': {}", pattern, e)))?;
+    Ok(regex.find_iter(text).map(|m| (m.start(), m.end())).collect())
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex_ranges(_: &str, _: &str) -> Result<Vec<(usize, usize)>, FsChildError> {
+    Err(not_built("Regular-expression searches are", "regex"))
+}
+
+/// `read-around`: the lines around each match of `pattern` in a file,
+/// numbered, with the file's SHA-256 as `read-file` gives it.
+pub(crate) fn read_around(fs: &dyn Filesystem, cmd: &FsCommand, path: &str) -> CommandResult {
+    let pattern = cmd.pattern.as_deref().unwrap_or_default();
+    if pattern.is_empty() {
+        return CommandResult::error(cmd, FsChildError::MissingField("No pattern provided for read-around".to_string()));
+    }
+    let context = match cmd.lines.as_deref().map(str::trim) {
+        None => around::DEFAULT_LINES,
+        Some(lines) => match lines.parse::<usize>() {
+            Ok(lines) if lines <= around::MAX_LINES => lines,
+            _ => {
+                return CommandResult::error(
+                    cmd,
+                    FsChildError::Parse(format!("`lines` must be a number of lines up to {}, not '{}'", around::MAX_LINES, lines)),
+                )
+            }
+        },
+    };
+    let content = match fs.read_file(path) {
+        Ok(content) => content,
+        Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", cmd.path, e)),
+    };
+    let sha256 = vcs::Entry::of(&content).hash;
+    if let Err(e) = refuse_utf16(cmd, &content) {
+        return CommandResult::error(cmd, e);
+    }
+    let Ok(text) = String::from_utf8(content) else {
+        return CommandResult::error(cmd, FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)));
+    };
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+
+    let matches = match flag(cmd.regex.as_deref()) {
+        // `^` and `$` match at every line, as a line search expects
+        true => match regex_ranges(&format!("(?m){}", pattern), text) {
+            Ok(matches) => matches,
+            Err(e) => return CommandResult::error(cmd, e),
+        },
+        false => text.match_indices(pattern).map(|(start, m)| (start, start + m.len())).collect(),
+    };
+    let short = &sha256[..HASH_SHOWN];
+    if matches.is_empty() {
+        return CommandResult::ok(cmd, format!("No matches of '{}' in '{}' (sha256 {})", pattern, cmd.path, short))
+            .with_data(json!({"matches": 0, "regions": [], "sha256": sha256}));
+    }
+    let shown = matches.len().min(around::MAX_MATCHES);
+    let regions = around::regions(text, &matches[..shown], context);
+    let mut message = format!(
+        "{} {} of '{}' in '{}' (sha256 {}):\n{}",
+        matches.len(),
+        if matches.len() == 1 { "match" } else { "matches" },
+        pattern,
+        cmd.path,
+        short,
+        around::render(text, &regions)
+    );
+    if shown < matches.len() {
+        message.push_str(&format!("\n[Only the first {} matches shown; narrow the pattern to see the rest]", shown));
+    }
+    CommandResult::ok(cmd, message).with_data(json!({
+        "matches": matches.len(),
+        "regions": regions,
+        "sha256": sha256,
+    }))
+}
+
+/// `edit-file` with `old_text` as a regular expression; `new_text` may
+/// refer to capture groups as `$1` or `${name}`.
+#[cfg(feature = "regex")]
+pub(crate) fn edit_with_regex(fs: &dyn Filesystem, cmd: &FsCommand, path: &str, pattern: &str, replacement: &str, schema: Option<&Value>) -> CommandResult {
+    let regex = match cached_regex(pattern) {
+        Ok(regex) => regex,
+        Err(e) => return CommandResult::error(cmd, FsChildError::Parse(format!("Invalid regular expression '{}': {}", pattern, e))),
+    };
+    let content = match fs.read_file(path) {
+        Ok(content) => content,
+        Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", cmd.path, e)),
+    };
+    if let Err(e) = refuse_utf16(cmd, &content) {
+        return CommandResult::error(cmd, e);
+    }
+    let Ok(content_str) = String::from_utf8(content) else {
+        return CommandResult::error(cmd, FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)));
+    };
+
+    let starts: Vec<usize> = regex.find_iter(&content_str).map(|m| m.start()).collect();
+    let selected = match edit_target(cmd).and_then(|target| edit::select(&cmd.path, content_str.as_bytes(), &starts, target)) {
+        Ok(selected) => selected,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    let matches = selected.len();
+    let mut edited = String::with_capacity(content_str.len());
+    let mut last = 0;
+    for caps in regex.captures_iter(&content_str) {
+        let Some(m) = caps.get(0) else { continue };
+        if selected.binary_search(&m.start()).is_ok() {
+            edited.push_str(&content_str[last..m.start()]);
+            caps.expand(replacement, &mut edited);
+            last = m.end();
+        }
+    }
+    edited.push_str(&content_str[last..]);
+    if let Some(failure) = schema_failure(cmd, schema, &edited) {
+        return failure;
+    }
+    match fs.write_file(path, &edited) {
+        Ok(_) => CommandResult::ok(cmd, format!("Successfully edited file '{}' ({} matches)", cmd.path, matches))
+            .with_data(json!({"matches": matches})),
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write edited content to '{}': {}", cmd.path, e)),
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+pub(crate) fn edit_with_regex(_: &dyn Filesystem, cmd: &FsCommand, _: &str, _: &str, _: &str, _: Option<&Value>) -> CommandResult {
+    CommandResult::error(cmd, not_built("Regular-expression edits are", "regex"))
+}
+
+fn loose_edit_check(cmd: &FsCommand) -> Result<(), FsChildError> {
+    if flag(cmd.regex.as_deref()) {
+        let option = if flag(cmd.fuzzy.as_deref()) { "fuzzy" } else { "ignore_whitespace" };
+        return Err(FsChildError::Parse(format!("regex and {} cannot be combined", option)));
+    }
+    Ok(())
+}
+
+/// The least confidence a fuzzy match of `cmd` needs.
+fn min_confidence(cmd: &FsCommand) -> Result<f64, FsChildError> {
+    match cmd.min_confidence.as_deref().map(str::trim) {
+        None => Ok(fuzzy::DEFAULT_MIN_CONFIDENCE),
+        Some(value) => value
+            .parse::<f64>()
+            .ok()
+            .filter(|min| (0.0..=1.0).contains(min))
+            .ok_or_else(|| FsChildError::Parse(format!("Invalid min_confidence '{}': expected a number from 0 to 1", value))),
+    }
+}
+
+/// Byte ranges of a loose anchor's matches, and a fuzzy match's confidence
+type LooseMatches = (Vec<(usize, usize)>, Option<f64>);
+
+/// The byte ranges `old_text` matches with `ignore_whitespace` or `fuzzy`,
+/// and for `fuzzy` the confidence of the match. A fuzzy anchor is searched
+/// for by edit distance only when ignoring whitespace finds nothing, and a
+/// closest match below `min_confidence` is refused, naming its line.
+fn loose_ranges(cmd: &FsCommand, text: &str, old_text: &str) -> Result<LooseMatches, FsChildError> {
+    loose_edit_check(cmd)?;
+    let ranges = edit::find_loose(text, old_text);
+    if !flag(cmd.fuzzy.as_deref()) {
+        return Ok((ranges, None));
+    }
+    let min = min_confidence(cmd)?;
+    if !ranges.is_empty() {
+        return Ok((ranges, Some(1.0)));
+    }
+    if fuzzy::cells(text, old_text) > fuzzy::MAX_CELLS {
+        return Err(FsChildError::TooLarge(format!(
+            "'{}' is too large to search for a fuzzy match of old_text; quote it exactly, or find it with read-around",
+            cmd.path
+        )));
+    }
+    let found = fuzzy::closest(text, old_text);
+    let Some(distance) = found.first().map(|m| m.distance) else {
+        return Ok((Vec::new(), None));
+    };
+    let confidence = fuzzy::confidence(old_text, distance);
+    if confidence < min {
+        let line = edit::line_numbers(text.as_bytes(), &[found[0].start])[0];
+        return Err(FsChildError::AnchorNotFound(format!(
+            "Text to replace not found in '{}': the closest match, on line {}, has confidence {:.2}, below the {:.2} needed",
+            cmd.path, line, confidence, min
+        )));
+    }
+    Ok((found.into_iter().map(|m| (m.start, m.end)).collect(), Some(confidence)))
+}
+
+/// `edit-file` with `ignore_whitespace` or `fuzzy`: `old_text` matched with
+/// each run of whitespace standing for any run, or with `fuzzy` the closest
+/// text to it, and `new_text` reindented to the indentation found in the
+/// file. An edit that only changes whitespace is reported as unchanged and
+/// nothing is written.
+pub(crate) fn edit_ignoring_whitespace(fs: &dyn Filesystem, cmd: &FsCommand, path: &str, old_text: &str, new_text: &str, schema: Option<&Value>) -> CommandResult {
+    let content = match fs.read_file(path) {
+        Ok(content) => content,
+        Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", cmd.path, e)),
+    };
+    if let Err(e) = refuse_utf16(cmd, &content) {
+        return CommandResult::error(cmd, e);
+    }
+    let Ok(content_str) = String::from_utf8(content) else {
+        return CommandResult::error(cmd, FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path)));
+    };
+
+    let (ranges, confidence) = match loose_ranges(cmd, &content_str, old_text) {
+        Ok(found) => found,
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    // Reported to three places, which is as much as the threshold means
+    let confidence = confidence.map(|c| (c * 1000.0).round() / 1000.0);
+    let starts: Vec<usize> = ranges.iter().map(|(start, _)| *start).collect();
+    let selected = match edit_target(cmd).and_then(|target| edit::select(&cmd.path, content_str.as_bytes(), &starts, target)) {
+        Ok(selected) => selected,
+        Err(e @ FsChildError::AmbiguousAnchor(_)) => {
+            return CommandResult::error(cmd, e).with_data(json!({"lines": edit::line_numbers(content_str.as_bytes(), &starts)}))
+        }
+        Err(e) => return CommandResult::error(cmd, e),
+    };
+    let matches = selected.len();
+    if edit::same_ignoring_whitespace(old_text, new_text) {
+        return CommandResult::ok(cmd, format!(
+            "Left '{}' unchanged: new_text differs from old_text only in whitespace",
+            cmd.path
+        ))
+        .with_data(loose_data(json!({"matches": matches, "unchanged": true}), confidence));
+    }
+
+    let mut edited = String::with_capacity(content_str.len() + new_text.len());
+    let mut last = 0;
+    for (start, end) in ranges.into_iter().filter(|(start, _)| selected.binary_search(start).is_ok()) {
+        edited.push_str(&content_str[last..start]);
+        let indent = edit::indent_at(&content_str, start);
+        edited.push_str(&edit::reindent(new_text, old_text, &content_str[start..end], indent));
+        last = end;
+    }
+    edited.push_str(&content_str[last..]);
+    if let Some(failure) = schema_failure(cmd, schema, &edited) {
+        return failure;
+    }
+    match fs.write_file(path, &edited) {
+        Ok(_) => {
+            let how = match confidence {
+                Some(confidence) if confidence < 1.0 => format!("fuzzy, confidence {:.2}", confidence),
+                _ => "whitespace ignored".to_string(),
+            };
+            CommandResult::ok(cmd, format!("Successfully edited file '{}' ({} matches, {})", cmd.path, matches, how))
+                .with_data(loose_data(json!({"matches": matches}), confidence))
+        }
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write edited content to '{}': {}", cmd.path, e)),
+    }
+}
+
+/// `data` of a loose edit, with the confidence of a fuzzy one.
+fn loose_data(mut data: Value, confidence: Option<f64>) -> Value {
+    if let Some(confidence) = confidence {
+        data["confidence"] = json!(confidence);
+    }
+    data
+}
+
+/// Whether a failed command was pointed at a directory where it needs a
+/// file, or the reverse. Only consulted after the host has refused, so a
+/// successful command costs no extra host calls.
+fn type_mismatch(fs: &dyn Filesystem, cmd: &FsCommand, host_path: &str) -> Option<FsChildError> {
+    let is_dir = || fs.list_files(host_path).is_ok();
+    match cmd.operation.as_str() {
+        "read-file" | "read-around" | "read-base64" | "file-type" | "write-file" | "edit-file" | "frontmatter-get" | "frontmatter-set" | "delete-file"
+        | "cargo-add-dep" | "cargo-set-version" | "generate-toc" | "csv-to-json" | "json-to-csv" | "append-ndjson"
+        | "push-to-store" | "fetch-from-store"
+        | "restore-from-store"
+            if is_dir() =>
+        {
+            Some(FsChildError::IsDirectory(format!(
+                "'{}' is a directory; {} needs a file",
+                cmd.path, cmd.operation
+            )))
+        }
+        "list-files" | "create-dir" | "manifest" | "export-dir" if fs.path_exists(host_path).unwrap_or(false) && !is_dir() => {
+            Some(FsChildError::NotADirectory(format!(
+                "'{}' is a file; {} needs a directory",
+                cmd.path, cmd.operation
+            )))
+        }
+        _ => None,
+    }
+}
+
+fn unsupported(operation: &str, capability: Capability) -> FsChildError {
+    FsChildError::Unsupported(format!(
+        "Operation '{}' is unsupported by host: this runtime provides no {} interface",
+        operation,
+        capability.name()
+    ))
+}
+
+/// The error for something this build was compiled without; `what` reads
+/// as the subject of "unsupported", as in "Operation 'x' is".
+fn not_built(what: &str, feature: &str) -> FsChildError {
+    FsChildError::Unsupported(format!(
+        "{} unsupported by this build: it was compiled without the '{}' feature",
+        what, feature
+    ))
+}
+
+/// The name and macro a `define-macro` command describes.
+pub(crate) fn define_macro(cmd: &FsCommand) -> Result<(String, Macro), FsChildError> {
+    let name = cmd.macro_name.as_deref().unwrap_or_default().trim();
+    macros::check_name(name).map_err(FsChildError::Parse)?;
+    let m = macros::define(cmd.content.as_deref().unwrap_or_default())
+        .map_err(|e| FsChildError::Parse(format!("Cannot define '{}': {}", name, e)))?;
+    Ok((name.to_string(), m))
+}
+
+/// The steps a `run-macro` command runs, given the macros defined so far.
+fn expand_run(cmd: &FsCommand, defined: &BTreeMap<String, Macro>) -> Result<Vec<FsCommand>, FsChildError> {
+    let name = cmd.macro_name.as_deref().unwrap_or_default().trim();
+    let Some(m) = defined.get(name) else {
+        let known = if defined.is_empty() { "none".to_string() } else { defined.keys().cloned().collect::<Vec<_>>().join(", ") };
+        return Err(FsChildError::NotFound(format!("No macro named '{}'; defined macros: {}", name, known)));
+    };
+    let args = macros::parse_args(cmd.args.as_deref().unwrap_or_default())
+        .map_err(|e| FsChildError::Parse(format!("Cannot read the args of '{}': {}", name, e)))?;
+    macros::expand(name, m, &args).map_err(FsChildError::Parse)
+}
+
+fn already_exists(cmd: &FsCommand) -> FsChildError {
+    FsChildError::AlreadyExists(format!(
+        "'{}' already exists; import-dir unpacks into a new directory",
+        cmd.path
+    ))
+}
+
+/// Forget cached listings that a change at `path` could have affected: the
+/// path itself, the directories above it, and anything beneath it.
+pub(crate) fn invalidate_listings(path: &str) {
+    let path = path.trim_end_matches('/');
+    LIST_CACHE.with(|cache| {
+        cache.borrow_mut().retain(|listed| {
+            let listed = listed.trim_end_matches('/');
+            let related = listed == path
+                || listed == "."
+                || path.starts_with(&format!("{}/", listed))
+                || listed.starts_with(&format!("{}/", path));
+            !related
+        })
+    });
+}
+
+/// Byte range of at most `limit` bytes starting at `offset`, with both ends
+/// moved back onto UTF-8 character boundaries. Always makes progress unless
+/// `offset` is at or past the end.
+fn utf8_window(bytes: &[u8], offset: usize, limit: usize) -> (usize, usize) {
+    let is_boundary = |i: usize| i >= bytes.len() || (bytes[i] & 0xC0) != 0x80;
+    let mut start = offset.min(bytes.len());
+    // Binary content may have no boundary before the offset at all
+    while start > 0 && !is_boundary(start) {
+        start -= 1;
+    }
+    let mut end = start.saturating_add(limit).min(bytes.len());
+    while end > start && !is_boundary(end) {
+        end -= 1;
+    }
+    if end == start && start < bytes.len() {
+        // A single character wider than the limit
+        end = start + 1;
+        while end < bytes.len() && !is_boundary(end) {
+            end += 1;
+        }
+    }
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_keep_to_character_boundaries() {
+        let text = "aé€b".as_bytes();
+        // Inside 'é' and inside '€'
+        assert_eq!(utf8_window(text, 2, 3), (1, 3));
+        assert_eq!(utf8_window(text, 4, 2), (3, 6));
+        assert_eq!(utf8_window(text, 0, 2), (0, 1));
+        assert_eq!(utf8_window(text, 9, 4), (7, 7));
+    }
+
+    #[test]
+    fn windows_over_binary_content_stay_in_bounds() {
+        let bytes = [0x89, 0x80, 0x80, 0x50, 0x4E, 0x47, 0xBF, 0xBF];
+        assert_eq!(utf8_window(&bytes, 0, 4), (0, 4));
+        assert_eq!(utf8_window(&bytes, 2, 1), (0, 3));
+        assert_eq!(utf8_window(&bytes, 7, 4), (5, 8));
+        assert_eq!(utf8_window(&[0x80, 0x80], 1, 1), (0, 2));
+    }
+}
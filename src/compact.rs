@@ -0,0 +1,131 @@
+//! Deduplication of identical files, for generated trees that hold many
+//! copies of the same content.
+//!
+//! `compact` keeps one copy of each duplicated content under [`DIR`], named
+//! by its SHA-256, and replaces every file holding it with a one-line stub
+//! naming that copy. The host interface has no links, so stubs are the only
+//! way to share content. Commands read through [`Resolver`], which hands
+//! back the content in place of a stub, so a compacted file reads as it did
+//! before; writing one replaces its stub with the new content.
+
+use sha2::{Digest, Sha256};
+use std::rc::Rc;
+
+use crate::fs::Filesystem;
+use crate::vcs;
+
+/// Directory of the shared copies, relative to `base_path`
+pub const DIR: &str = ".fs-child/compact";
+
+/// Files smaller than this are left alone; a stub would save little
+pub const MIN_BYTES: usize = 256;
+
+const STUB_PREFIX: &str = "#fs-child compacted ";
+
+/// Where the shared copy of content with SHA-256 `hash` is kept.
+pub fn blob_path(root: &str, hash: &str) -> String {
+    format!("{}/{}/{}", root, DIR, hash)
+}
+
+/// The stub standing in for a file whose content is `entry`.
+pub fn stub(entry: &vcs::Entry) -> String {
+    let encoding = if entry.binary { "base64" } else { "text" };
+    format!("{}{} {} {}\n", STUB_PREFIX, entry.hash, entry.size, encoding)
+}
+
+/// What a stub names, if `content` is one.
+fn parse_stub(content: &[u8]) -> Option<vcs::Entry> {
+    let line = std::str::from_utf8(content).ok()?.strip_prefix(STUB_PREFIX)?.strip_suffix('\n')?;
+    match line.split(' ').collect::<Vec<_>>().as_slice() {
+        [hash, size, encoding] if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => Some(vcs::Entry {
+            hash: hash.to_string(),
+            size: size.parse().ok()?,
+            binary: match *encoding {
+                "base64" => true,
+                "text" => false,
+                _ => return None,
+            },
+        }),
+        _ => None,
+    }
+}
+
+/// The content a file stands for: what its stub names, or the file itself.
+/// A stub whose copy is missing or does not match is returned as it is.
+pub fn resolve(fs: &dyn Filesystem, root: &str, content: Vec<u8>) -> Vec<u8> {
+    let Some(entry) = parse_stub(&content) else {
+        return content;
+    };
+    let shared = fs
+        .read_file(&blob_path(root, &entry.hash))
+        .and_then(|blob| vcs::decode_blob(&blob, &entry))
+        .ok()
+        .filter(|shared| format!("{:x}", Sha256::digest(shared)) == entry.hash);
+    shared.unwrap_or(content)
+}
+
+/// The filesystem with stubs read as the content they stand for.
+#[derive(Debug)]
+pub struct Resolver {
+    base: Rc<dyn Filesystem>,
+    /// Host path of `base_path`, which [`DIR`] is under
+    root: String,
+}
+
+impl Resolver {
+    pub fn new(base: Rc<dyn Filesystem>, root: &str) -> Resolver {
+        Resolver { base, root: root.to_string() }
+    }
+}
+
+impl Filesystem for Resolver {
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, String> {
+        self.base.read_file(path).map(|content| resolve(&*self.base, &self.root, content))
+    }
+
+    fn write_file(&self, path: &str, content: &str) -> Result<(), String> {
+        self.base.write_file(path, content)
+    }
+
+    fn list_files(&self, path: &str) -> Result<Vec<String>, String> {
+        self.base.list_files(path)
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), String> {
+        self.base.delete_file(path)
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), String> {
+        self.base.create_dir(path)
+    }
+
+    fn delete_dir(&self, path: &str) -> Result<(), String> {
+        self.base.delete_dir(path)
+    }
+
+    fn path_exists(&self, path: &str) -> Result<bool, String> {
+        self.base.path_exists(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFilesystem;
+
+    #[test]
+    fn reads_stubs_as_their_content() {
+        let content = "x".repeat(300);
+        let entry = vcs::Entry::of(content.as_bytes());
+        let fs = MemoryFilesystem::new("/work")
+            .with_file(&blob_path("/work", &entry.hash), &content)
+            .with_file("/work/a.txt", stub(&entry))
+            .with_file("/work/b.txt", stub(&vcs::Entry { hash: "0".repeat(64), ..entry.clone() }));
+        let resolver = Resolver::new(Rc::new(fs), "/work");
+        assert_eq!(resolver.read_file("/work/a.txt").unwrap(), content.as_bytes());
+        // A stub naming a missing copy is left as it is
+        assert!(resolver.read_file("/work/b.txt").unwrap().starts_with(STUB_PREFIX.as_bytes()));
+        assert_eq!(parse_stub(stub(&entry).as_bytes()), Some(entry));
+        assert_eq!(parse_stub(b"#fs-child compacted abc 3 text\n"), None);
+    }
+}
@@ -0,0 +1,310 @@
+//! The configuration the actor is started with, from its `init.json`.
+//!
+//! Every key is checked: an unknown one, a value of the wrong type or one
+//! out of range fails `init` with a message naming it, rather than leaving
+//! the actor running on defaults nobody asked for.
+
+use crate::codec::StateEncoding;
+use crate::delegate::{self, Delegate};
+use crate::encoding::NonUtf8Mode;
+use crate::host::Host;
+use crate::logger::Level;
+use crate::mounts::{self, Mount};
+use crate::policy::{self, Rule};
+use crate::quota::{self, Quota};
+use crate::commands::BatchMode;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Permissions an instance can be granted.
+pub const PERMISSIONS: [&str; 2] = ["read", "write"];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default = "default_base_path")]
+    pub base_path: String,
+    #[serde(default = "default_permissions")]
+    pub permissions: Vec<String>,
+    pub max_response_bytes: Option<usize>,
+    pub response_budget_bytes: Option<usize>,
+    #[serde(default = "crate::state::default_max_transfer_bytes")]
+    pub max_transfer_bytes: usize,
+    #[serde(default = "crate::state::default_max_inline_bytes")]
+    pub max_inline_bytes: usize,
+    #[serde(default = "crate::state::default_max_message_bytes")]
+    pub max_message_bytes: usize,
+    #[serde(default = "crate::state::default_read_chunk_bytes")]
+    pub read_chunk_bytes: usize,
+    #[serde(default = "crate::state::default_max_walk_depth")]
+    pub max_walk_depth: usize,
+    #[serde(default = "crate::state::default_max_walk_entries")]
+    pub max_walk_entries: usize,
+    #[serde(default = "crate::state::default_max_work_entries")]
+    pub max_work_entries: usize,
+    #[serde(default = "crate::state::default_max_work_bytes")]
+    pub max_work_bytes: usize,
+    #[serde(default)]
+    pub list_cache_turns: u64,
+    #[serde(default = "crate::state::default_cursor_turns")]
+    pub cursor_turns: u64,
+    #[serde(default)]
+    pub non_utf8: NonUtf8Mode,
+    #[serde(default)]
+    pub batch_mode: BatchMode,
+    #[serde(default)]
+    pub state_encoding: StateEncoding,
+    pub cold_state_dir: Option<String>,
+    pub heartbeat_interval: Option<u64>,
+    #[serde(default)]
+    pub backup_before_write: bool,
+    #[serde(default = "crate::state::default_truncation_ratio")]
+    pub truncation_ratio: f64,
+    #[serde(default)]
+    pub audit_to_store: bool,
+    #[serde(default)]
+    pub workspace_overview: bool,
+    #[serde(default)]
+    pub batch_reports: bool,
+    #[serde(default)]
+    pub detect_project: bool,
+    #[serde(default)]
+    pub editorconfig: bool,
+    #[serde(default)]
+    pub missing_capabilities: Vec<String>,
+    #[serde(default)]
+    pub log_level: Level,
+    pub metrics_interval: Option<u64>,
+    #[serde(default)]
+    pub mounts: BTreeMap<String, MountConfig>,
+    #[serde(default)]
+    pub delegates: BTreeMap<String, DelegateConfig>,
+    /// Path rules, checked in order for every command
+    #[serde(default)]
+    pub policy: Vec<Rule>,
+    #[serde(default)]
+    pub quotas: BTreeMap<String, Quota>,
+    /// Add a usage report to the response every this many head-updates
+    pub usage_report_interval: Option<u64>,
+}
+
+/// A root besides `base_path`, addressed as `name:/path`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountConfig {
+    pub path: String,
+    /// The instance's `permissions` when left out
+    pub permissions: Option<Vec<String>>,
+}
+
+/// A child instance for a subtree, addressed by commands with its name.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DelegateConfig {
+    pub path: String,
+    /// The instance's `permissions` when left out
+    pub permissions: Option<Vec<String>>,
+    pub manifest: Option<String>,
+    pub actor_id: Option<String>,
+}
+
+fn default_name() -> String {
+    "default".to_string()
+}
+
+fn default_base_path() -> String {
+    ".".to_string()
+}
+
+fn default_permissions() -> Vec<String> {
+    PERMISSIONS.iter().map(|p| p.to_string()).collect()
+}
+
+impl Config {
+    /// Parse and check the init data; none at all means every default.
+    pub fn parse(data: Option<&[u8]>) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_slice(data.unwrap_or(b"{}"))
+            .map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.validate().map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.cold_state_dir = config
+            .cold_state_dir
+            .map(|dir| dir.trim_end_matches('/').to_string())
+            .filter(|dir| !dir.is_empty());
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("`name` must not be empty".to_string());
+        }
+        // The name is written into the opening tag of commands
+        if self.name.contains(['"', '<', '>']) {
+            return Err(format!("`name` '{}' cannot contain '\"', '<' or '>'", self.name));
+        }
+        for (name, delegate) in &self.delegates {
+            let key = format!("delegates.{}", name);
+            if name.trim().is_empty() || name.contains(['"', '<', '>']) || *name == self.name {
+                return Err(format!("`{}` needs a name other than this instance's, without '\"', '<' or '>'", key));
+            }
+            delegate::check_path(&delegate.path).map_err(|e| format!("`{}.path`: {}", key, e))?;
+            if delegate.manifest.is_none() && delegate.actor_id.is_none() {
+                return Err(format!("`{}` needs a `manifest` to spawn or the `actor_id` of a running child", key));
+            }
+            if let Some(permissions) = &delegate.permissions {
+                check_permissions(&format!("{}.permissions", key), permissions)?;
+            }
+        }
+        if self.base_path.is_empty() {
+            return Err("`base_path` must not be empty".to_string());
+        }
+        check_permissions("permissions", &self.permissions)?;
+        for (name, mount) in &self.mounts {
+            mounts::check_name(name).map_err(|e| format!("in `mounts`: {}", e))?;
+            if mount.path.is_empty() {
+                return Err(format!("`mounts.{}.path` must not be empty", name));
+            }
+            if let Some(permissions) = &mount.permissions {
+                check_permissions(&format!("mounts.{}.permissions", name), permissions)?;
+            }
+        }
+        for (i, rule) in self.policy.iter().enumerate() {
+            policy::check(rule).map_err(|e| format!("`policy[{}]`: {}", i, e))?;
+        }
+        for (prefix, q) in &self.quotas {
+            quota::check(prefix, q).map_err(|e| format!("`quotas.{}`: {}", prefix, e))?;
+        }
+        if !(0.0..=1.0).contains(&self.truncation_ratio) {
+            return Err("`truncation_ratio` must be from 0 to 1".to_string());
+        }
+        let limits = [
+            ("max_walk_entries", self.max_walk_entries),
+            ("max_work_entries", self.max_work_entries),
+            ("max_work_bytes", self.max_work_bytes),
+            ("read_chunk_bytes", self.read_chunk_bytes),
+        ];
+        if let Some((key, _)) = limits.iter().find(|(_, value)| *value == 0) {
+            return Err(format!("`{}` must be greater than 0", key));
+        }
+        self.host().map(|_| ())
+    }
+
+    /// Every mount, with permissions filled in.
+    pub fn mounts(&self) -> BTreeMap<String, Mount> {
+        self.mounts
+            .iter()
+            .map(|(name, mount)| {
+                let permissions = mount.permissions.clone().unwrap_or_else(|| self.permissions.clone());
+                (name.clone(), Mount { path: mount.path.clone(), permissions })
+            })
+            .collect()
+    }
+
+    /// Every delegate, with permissions filled in and its subtree
+    /// normalized.
+    pub fn delegates(&self) -> BTreeMap<String, Delegate> {
+        self.delegates
+            .iter()
+            .map(|(name, delegate)| {
+                let delegate = Delegate {
+                    path: delegate::check_path(&delegate.path).unwrap_or_else(|_| delegate.path.clone()),
+                    permissions: delegate.permissions.clone().unwrap_or_else(|| self.permissions.clone()),
+                    manifest: delegate.manifest.clone(),
+                    actor_id: delegate.actor_id.clone(),
+                };
+                (name.clone(), delegate)
+            })
+            .collect()
+    }
+
+    /// Every quota, by its prefix normalized.
+    pub fn quotas(&self) -> BTreeMap<String, Quota> {
+        self.quotas
+            .iter()
+            .map(|(prefix, q)| (quota::check(prefix, q).unwrap_or_else(|_| prefix.clone()), q.clone()))
+            .collect()
+    }
+
+    /// The host lacking the capabilities in `missing_capabilities`.
+    pub fn host(&self) -> Result<Host, String> {
+        let (host, unknown) = Host::with_missing(self.missing_capabilities.iter().map(String::as_str));
+        match unknown.first() {
+            Some(name) => Err(format!("unknown host capability '{}' in `missing_capabilities`", name)),
+            None => Ok(host),
+        }
+    }
+}
+
+fn check_permissions(key: &str, permissions: &[String]) -> Result<(), String> {
+    match permissions.iter().find(|p| !PERMISSIONS.contains(&p.as_str())) {
+        Some(unknown) => Err(format!(
+            "unknown permission '{}' in `{}`, expected {}",
+            unknown,
+            key,
+            PERMISSIONS.join(" or ")
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(json: &str) -> String {
+        Config::parse(Some(json.as_bytes())).unwrap_err()
+    }
+
+    #[test]
+    fn parses_with_defaults() {
+        let config = Config::parse(None).unwrap();
+        assert_eq!(config.name, "default");
+        assert_eq!(config.permissions, ["read", "write"]);
+        assert_eq!(config.max_walk_depth, 16);
+
+        let json = br#"{"name": "docs", "cold_state_dir": ".fs-child/", "batch_mode": "transactional"}"#;
+        let config = Config::parse(Some(json)).unwrap();
+        assert_eq!(config.cold_state_dir.as_deref(), Some(".fs-child"));
+        assert_eq!(config.batch_mode, BatchMode::Transactional);
+
+        let json = br#"{"permissions": ["read"], "mounts": {"docs": {"path": "/work/docs", "permissions": ["read", "write"]}, "src": {"path": "/work/src"}}}"#;
+        let mounts = Config::parse(Some(json)).unwrap().mounts();
+        assert_eq!(mounts["docs"].permissions, ["read", "write"]);
+        assert_eq!(mounts["src"].permissions, ["read"]);
+
+        let json = br#"{"delegates": {"src-writer": {"path": "./src/", "manifest": "fs-child.toml"}}}"#;
+        let delegates = Config::parse(Some(json)).unwrap().delegates();
+        assert_eq!(delegates["src-writer"].path, "src");
+
+        let json = br#"{"policy": [{"match": "src/**/*.rs", "allow": ["read", "edit"], "max_bytes": 100000}]}"#;
+        let policy = Config::parse(Some(json)).unwrap().policy;
+        assert_eq!(policy[0].max_bytes, Some(100000));
+
+        let json = br#"{"quotas": {"./src/": {"max_bytes": 1000, "limit": "soft"}, ".": {"max_files": 50}}}"#;
+        let quotas = Config::parse(Some(json)).unwrap().quotas();
+        assert_eq!(quotas["src"].limit, crate::quota::Limit::Soft);
+        assert_eq!(quotas["."].max_files, Some(50));
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_use() {
+        assert!(error(r#"{"permisions": ["read"]}"#).contains("unknown field `permisions`"));
+        assert!(error(r#"{"max_walk_depth": "8"}"#).contains("invalid type"));
+        assert!(error(r#"{"batch_mode": "parallel"}"#).contains("unknown variant `parallel`"));
+        assert!(error(r#"{"permissions": ["read", "exec"]}"#).contains("'exec'"));
+        assert!(error(r#"{"read_chunk_bytes": 0}"#).contains("`read_chunk_bytes` must be greater than 0"));
+        assert!(error(r#"{"missing_capabilities": ["gpu"]}"#).contains("'gpu'"));
+        assert!(error(r#"{"name": "a\"b"}"#).contains("cannot contain"));
+        assert!(error(r#"{"mounts": {"my docs": {"path": "/docs"}}}"#).contains("not a mount name"));
+        assert!(error(r#"{"mounts": {"docs": {"path": "/docs", "permissions": ["exec"]}}}"#).contains("`mounts.docs.permissions`"));
+        assert!(error(r#"{"delegates": {"w": {"path": "../up", "actor_id": "a"}}}"#).contains("`delegates.w.path`"));
+        assert!(error(r#"{"delegates": {"w": {"path": "src"}}}"#).contains("needs a `manifest`"));
+        assert!(error(r#"{"delegates": {"default": {"path": "src", "actor_id": "a"}}}"#).contains("other than"));
+        assert!(error(r#"{"policy": [{"match": "**", "deny": ["remove"]}]}"#).contains("`policy[0]`: unknown action 'remove'"));
+        assert!(error(r#"{"policy": [{"path": "**"}]}"#).contains("unknown field `path`"));
+        assert!(error(r#"{"quotas": {"src": {"limit": "hard"}}}"#).contains("`quotas.src`: needs"));
+        assert!(error(r#"{"quotas": {"src": {"max_bytes": 1, "limit": "strict"}}}"#).contains("unknown variant `strict`"));
+        assert!(error("not json").starts_with("Invalid configuration"));
+    }
+}
@@ -0,0 +1,49 @@
+//! Edits that would clobber someone else's work.
+//!
+//! Other children and people share the tree, so a file can change between
+//! the moment this actor reads it and the moment it writes it back. The
+//! content hash of every file the actor last read or wrote is remembered;
+//! a command that would replace a file whose hash has since changed is
+//! refused with `Conflict` unless it carries `force`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Files remembered at once; the longest unseen are forgotten first
+pub const MAX_SEEN: usize = 1024;
+
+/// A file's content as this actor last saw it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Seen {
+    pub hash: String,
+    /// Turn it was read or written on
+    pub turn: u64,
+}
+
+/// Remember `hash` for `path`, forgetting the longest unseen file when
+/// there are too many.
+pub fn remember(seen: &mut BTreeMap<String, Seen>, path: &str, hash: String, turn: u64) {
+    seen.insert(path.to_string(), Seen { hash, turn });
+    if seen.len() > MAX_SEEN {
+        if let Some(oldest) = seen.iter().min_by_key(|(_, s)| s.turn).map(|(path, _)| path.clone()) {
+            seen.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_the_longest_unseen_file() {
+        let mut seen = BTreeMap::new();
+        for i in 0..MAX_SEEN as u64 {
+            remember(&mut seen, &format!("/work/{}", i), "h".to_string(), i + 1);
+        }
+        remember(&mut seen, "/work/0", "h2".to_string(), 5000);
+        remember(&mut seen, "/work/new", "h".to_string(), 5001);
+        assert_eq!(seen.len(), MAX_SEEN);
+        assert!(seen.contains_key("/work/0") && !seen.contains_key("/work/1"));
+    }
+}
@@ -0,0 +1,252 @@
+//! Conversion between CSV and JSON arrays.
+//!
+//! CSV is read as RFC 4180 describes it: fields may be quoted, quotes are
+//! doubled inside quoted fields, and quoted fields may span lines. Cells are
+//! kept as strings; nothing is guessed about their types.
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use std::fmt;
+
+/// Rows of fields. Blank lines are skipped, and a byte-order mark at the
+/// start is ignored.
+pub fn parse(text: &str, delimiter: char) -> Result<Vec<Vec<String>>, String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    // Whether the current field has started, quoted or not
+    let mut started = false;
+    let mut quoted = false;
+    let mut line = 1;
+    let mut quote_line = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                '"' => quoted = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if !started => {
+                quoted = true;
+                started = true;
+                quote_line = line;
+            }
+            c if c == delimiter => {
+                row.push(std::mem::take(&mut field));
+                started = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                line += 1;
+                if started || !row.is_empty() {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                started = false;
+            }
+            c => {
+                field.push(c);
+                started = true;
+            }
+        }
+    }
+    if quoted {
+        return Err(format!("the quoted field opened on line {} is never closed", quote_line));
+    }
+    if started || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// CSV text as a JSON array, of objects keyed by the header row when
+/// `header`, else of arrays. Returns the JSON, one record per line, and
+/// the number of records.
+pub fn to_json(text: &str, delimiter: char, header: bool) -> Result<(String, usize), String> {
+    let mut rows = parse(text, delimiter)?.into_iter();
+    let columns = match header {
+        true => rows.next().unwrap_or_default(),
+        false => Vec::new(),
+    };
+    if let Some(duplicate) = columns.iter().enumerate().find_map(|(i, c)| columns[..i].contains(c).then_some(c)) {
+        return Err(format!("the header names the column '{}' twice", duplicate));
+    }
+
+    let mut records = Vec::new();
+    for (i, row) in rows.enumerate() {
+        let record = if header {
+            if row.len() > columns.len() {
+                // Counting the header as row 1
+                return Err(format!("row {} has {} fields but the header has {}", i + 2, row.len(), columns.len()));
+            }
+            // Short rows leave their last columns null
+            let fields = columns.iter().enumerate().map(|(i, column)| {
+                let value = row.get(i).map_or(Value::Null, |field| Value::String(field.clone()));
+                format!("{}: {}", Value::String(column.clone()), value)
+            });
+            format!("{{{}}}", fields.collect::<Vec<_>>().join(", "))
+        } else {
+            Value::Array(row.into_iter().map(Value::String).collect()).to_string()
+        };
+        records.push(record);
+    }
+
+    let json = match records.is_empty() {
+        true => "[]\n".to_string(),
+        false => format!("[\n  {}\n]\n", records.join(",\n  ")),
+    };
+    Ok((json, records.len()))
+}
+
+/// A JSON array of objects, or of arrays, as CSV. Objects become columns
+/// in the order their keys first appear, under a header row when
+/// `header`. Returns the CSV and the number of records.
+pub fn from_json(text: &str, delimiter: char, header: bool) -> Result<(String, usize), String> {
+    let records: Vec<Record> =
+        serde_json::from_str(text).map_err(|e| format!("not a JSON array of objects or arrays: {}", e))?;
+
+    let mut out = String::new();
+    if records.iter().all(|record| matches!(record, Record::Array(_))) {
+        for record in &records {
+            let Record::Array(values) = record else { unreachable!() };
+            write_row(&mut out, values.iter().map(cell), delimiter);
+        }
+        return Ok((out, records.len()));
+    }
+
+    let mut columns: Vec<&str> = Vec::new();
+    for record in &records {
+        let Record::Object(fields) = record else {
+            return Err("the array mixes objects and arrays".to_string());
+        };
+        for (key, _) in fields {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+    if header {
+        write_row(&mut out, columns.iter().map(|c| c.to_string()), delimiter);
+    }
+    for record in &records {
+        let Record::Object(fields) = record else { unreachable!() };
+        let row = columns.iter().map(|column| {
+            fields
+                .iter()
+                .find(|(key, _)| key == column)
+                .map_or_else(String::new, |(_, value)| cell(value))
+        });
+        write_row(&mut out, row, delimiter);
+    }
+    Ok((out, records.len()))
+}
+
+/// The text of one CSV cell: strings as they are, null as empty, and
+/// nested arrays or objects as compact JSON.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn write_row(out: &mut String, fields: impl Iterator<Item = String>, delimiter: char) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(delimiter);
+        }
+        if field.contains([delimiter, '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(&field);
+        }
+    }
+    out.push('\n');
+}
+
+/// One element of the input array, keeping object keys in the order they
+/// were written (`serde_json::Map` would sort them).
+enum Record {
+    Object(Vec<(String, Value)>),
+    Array(Vec<Value>),
+}
+
+impl<'de> Deserialize<'de> for Record {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RecordVisitor;
+
+        impl<'de> Visitor<'de> for RecordVisitor {
+            type Value = Record;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an object or an array")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Record, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(entry) = map.next_entry::<String, Value>()? {
+                    fields.push(entry);
+                }
+                Ok(Record::Object(fields))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Record, A::Error> {
+                let mut values = Vec::new();
+                while let Some(value) = seq.next_element()? {
+                    values.push(value);
+                }
+                Ok(Record::Array(values))
+            }
+        }
+
+        deserializer.deserialize_any(RecordVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\u{feff}name,note,qty\r\nWidget,\"says \"\"hi\"\", twice\",3\r\n\r\nGadget,\"two\nlines\"\r\nBolt,,\r\n";
+
+    #[test]
+    fn converts_csv_to_json() {
+        let (json, records) = to_json(CSV, ',', true).unwrap();
+        assert_eq!(records, 3);
+        assert_eq!(
+            json,
+            "[\n  {\"name\": \"Widget\", \"note\": \"says \\\"hi\\\", twice\", \"qty\": \"3\"},\n  {\"name\": \"Gadget\", \"note\": \"two\\nlines\", \"qty\": null},\n  {\"name\": \"Bolt\", \"note\": \"\", \"qty\": \"\"}\n]\n"
+        );
+        let (json, _) = to_json("a\tb\n", '\t', false).unwrap();
+        assert_eq!(json, "[\n  [\"a\",\"b\"]\n]\n");
+        assert!(to_json("a,a\n1,2\n", ',', true).is_err());
+        assert!(to_json("a\n\"open\n", ',', true).is_err());
+    }
+
+    #[test]
+    fn converts_json_to_csv_and_back() {
+        let json = r#"[{"zeta": "x,y", "alpha": 1}, {"alpha": null, "extra": [1, "q\"uote"]}]"#;
+        let (csv, records) = from_json(json, ',', true).unwrap();
+        assert_eq!(records, 2);
+        assert_eq!(csv, "zeta,alpha,extra\n\"x,y\",1,\n,,\"[1,\"\"q\\\"\"uote\"\"]\"\n");
+
+        let (back, _) = to_json(&csv, ',', true).unwrap();
+        assert!(back.starts_with("[\n  {\"zeta\": \"x,y\", \"alpha\": \"1\", \"extra\": \"\"},"));
+        assert_eq!(from_json("[[1, \"a b\"], []]", ';', true).unwrap().0, "1;a b\n\n");
+        assert!(from_json("[{\"a\": 1}, [2]]", ',', true).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! Continuation tokens for results too large for one response.
+//!
+//! A windowed read, a recursive listing, a manifest or a compaction that
+//! stops early comes back with a short token. `continue` with that token
+//! runs the same command again from where it stopped, so the caller need
+//! not rebuild it with the right `offset` or `cursor`. Tokens are kept in
+//! the actor's state and expire after `cursor_turns` head-updates.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::commands::FsCommand;
+
+/// Tokens kept at once; the oldest are dropped first
+pub const MAX_CURSORS: usize = 64;
+
+/// A command to run for the rest of a result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cursor {
+    pub command: FsCommand,
+    /// Turn the token was handed out on
+    pub turn: u64,
+}
+
+/// The command that picks up where `cmd` stopped, if its result `data`
+/// says there is more.
+pub fn continuation(cmd: &FsCommand, data: &Value) -> Option<FsCommand> {
+    let mut next = cmd.clone();
+    next.idempotency_key = None;
+    next.defer = None;
+    if let Some(offset) = data["next_offset"].as_u64() {
+        next.offset = Some(offset.to_string());
+    } else if let Some(cursor) = data["next_cursor"].as_str() {
+        next.cursor = Some(cursor.to_string());
+    } else {
+        return None;
+    }
+    Some(next)
+}
+
+/// Keep `cursor` as `token`, dropping the oldest when there are too many.
+pub fn remember(cursors: &mut BTreeMap<String, Cursor>, token: String, cursor: Cursor) {
+    cursors.insert(token, cursor);
+    while cursors.len() > MAX_CURSORS {
+        match cursors.iter().min_by_key(|(_, c)| c.turn).map(|(token, _)| token.clone()) {
+            Some(oldest) => cursors.remove(&oldest),
+            None => break,
+        };
+    }
+}
+
+/// Drop the tokens handed out `turns` or more head-updates before `turn`.
+pub fn expire(cursors: &mut BTreeMap<String, Cursor>, turn: u64, turns: u64) {
+    cursors.retain(|_, cursor| turn < cursor.turn + turns);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn continues_reads_and_walks() {
+        let cmd: FsCommand = serde_json::from_value(json!({"operation": "read-file", "path": "big.log", "length": "10"})).unwrap();
+        let next = continuation(&cmd, &json!({"next_offset": 10})).unwrap();
+        assert_eq!((next.offset.as_deref(), next.length.as_deref()), (Some("10"), Some("10")));
+        assert!(continuation(&cmd, &json!({"next_offset": null})).is_none());
+
+        let cmd: FsCommand = serde_json::from_value(json!({"operation": "list-files", "path": "src", "recursive": "true"})).unwrap();
+        assert_eq!(continuation(&cmd, &json!({"next_cursor": "src/b.rs"})).unwrap().cursor.as_deref(), Some("src/b.rs"));
+
+        let mut cursors = BTreeMap::new();
+        remember(&mut cursors, "c1".to_string(), Cursor { command: cmd.clone(), turn: 1 });
+        remember(&mut cursors, "c2".to_string(), Cursor { command: cmd, turn: 3 });
+        expire(&mut cursors, 6, 5);
+        assert_eq!(cursors.keys().collect::<Vec<_>>(), ["c2"]);
+    }
+}
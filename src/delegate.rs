@@ -0,0 +1,118 @@
+//! Child instances of this actor scoped to one subtree, for a supervisor
+//! pattern: a broad read-only instance hands writes under `src` to a child
+//! that can only see `src`.
+//!
+//! Commands in a chat message addressed to a delegate's name are checked to
+//! stay inside its subtree and forwarded to it as a `run-commands` request;
+//! its reply is folded into this instance's response. A delegate is either
+//! an actor already running, given by `actor_id`, or spawned from
+//! `manifest` the first time it has commands.
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::error::FsChildError;
+use crate::commands::FsCommand;
+use crate::path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delegate {
+    /// Subtree under `base_path` the child is rooted at
+    pub path: String,
+    pub permissions: Vec<String>,
+    /// Manifest the child is spawned from
+    pub manifest: Option<String>,
+    /// The running child, once known
+    pub actor_id: Option<String>,
+}
+
+impl Delegate {
+    /// The child's `init.json`: an instance named `name`, rooted at
+    /// `root`, the delegate's directory on the host.
+    pub fn init(&self, name: &str, root: &str) -> Vec<u8> {
+        json!({"name": name, "base_path": root, "permissions": self.permissions}).to_string().into_bytes()
+    }
+}
+
+/// Check a delegate's subtree: relative, and not climbing out of
+/// `base_path`.
+pub fn check_path(subtree: &str) -> Result<String, String> {
+    let normalized = path::normalize(subtree).map_err(|e| e.to_string())?;
+    if normalized == "." || normalized.starts_with('/') || normalized.starts_with("..") {
+        return Err(format!("'{}' is not a subdirectory of `base_path`", subtree));
+    }
+    Ok(normalized)
+}
+
+/// Why `cmd` cannot be run by a child rooted at a subtree: a path that
+/// is absolute or climbs out of it.
+pub fn escapes(cmd: &FsCommand) -> Option<String> {
+    [Some(cmd.path.as_str()), cmd.destination.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter(|p| !p.is_empty())
+        .find(|p| path::normalize(p).map_or(true, |n| n.starts_with('/') || n.starts_with("..")))
+        .map(str::to_string)
+}
+
+/// Spawning and talking to child actors. Delegation only goes through this
+/// trait, so tests can stand in for the runtime.
+pub trait Supervisor: std::fmt::Debug {
+    /// Start an actor from `manifest` with `init` as its configuration,
+    /// returning its id.
+    fn spawn(&self, manifest: &str, init: &[u8]) -> Result<String, FsChildError>;
+
+    /// Send `msg` to the actor `actor_id` and return its reply.
+    fn request(&self, actor_id: &str, msg: &[u8]) -> Result<Vec<u8>, FsChildError>;
+}
+
+/// The runtime's interfaces. Children are spawned through the supervisor
+/// interface and requests go through the message server.
+#[derive(Debug)]
+pub struct HostSupervisor;
+
+impl Supervisor for HostSupervisor {
+    fn spawn(&self, manifest: &str, init: &[u8]) -> Result<String, FsChildError> {
+        host_spawn(manifest, init).map_err(|e| match crate::host::is_unsupported(&e) {
+            true => FsChildError::Unsupported(format!("Spawning delegates is unsupported by host: {}", e)),
+            false => FsChildError::Delegate(format!("Spawning '{}' failed: {}", manifest, e)),
+        })
+    }
+
+    fn request(&self, actor_id: &str, msg: &[u8]) -> Result<Vec<u8>, FsChildError> {
+        crate::store::host_request(actor_id, &msg.to_vec()).map_err(|e| match crate::host::is_unsupported(&e) {
+            true => FsChildError::Unsupported(format!("Requests to delegates are unsupported by host: {}", e)),
+            false => FsChildError::Delegate(format!("Request to delegate '{}' failed: {}", actor_id, e)),
+        })
+    }
+}
+
+#[cfg(feature = "supervisor")]
+fn host_spawn(manifest: &str, init: &[u8]) -> Result<String, String> {
+    crate::bindings::ntwk::theater::supervisor::spawn(manifest, Some(init))
+}
+
+/// Without the `supervisor` feature the component does not import the
+/// interface.
+#[cfg(not(feature = "supervisor"))]
+fn host_spawn(_manifest: &str, _init: &[u8]) -> Result<String, String> {
+    Err("not supported: this build does not import the supervisor interface".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_commands_inside_the_subtree() {
+        assert_eq!(check_path("src/").unwrap(), "src");
+        assert!(check_path("../other").is_err());
+        assert!(check_path("/etc").is_err());
+        assert!(check_path(".").is_err());
+
+        let cmd = |path: &str| -> FsCommand { serde_json::from_value(json!({"operation": "read-file", "path": path})).unwrap() };
+        assert_eq!(escapes(&cmd("lib/mod.rs")), None);
+        assert_eq!(escapes(&cmd("a/../../x")).as_deref(), Some("a/../../x"));
+        assert_eq!(escapes(&cmd("/etc/passwd")).as_deref(), Some("/etc/passwd"));
+    }
+}
@@ -0,0 +1,174 @@
+//! Line diffs of text files, shown as unified diffs.
+//!
+//! Lines common to the start and end of both versions are set aside first,
+//! so the longest-common-subsequence table only covers the region that
+//! changed. A changed region too large for the table is shown as removed
+//! and added whole.
+
+/// Lines of context kept around each change
+pub const CONTEXT: usize = 3;
+
+/// Largest table of line pairs compared; beyond it the region is replaced whole
+const MAX_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Same,
+    Removed,
+    Added,
+}
+
+/// A unified diff and the number of lines it adds and removes.
+#[derive(Debug, Default, PartialEq)]
+pub struct LineDiff {
+    pub text: String,
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl LineDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.removed == 0
+    }
+}
+
+/// The diff from `old` to `new`, headed `--- old_name` and `+++ new_name`.
+/// Nothing is shown when the two are equal.
+pub fn unified(old_name: &str, new_name: &str, old: &str, new: &str) -> LineDiff {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = line_ops(&old_lines, &new_lines);
+    let added = ops.iter().filter(|op| **op == Op::Added).count();
+    let removed = ops.iter().filter(|op| **op == Op::Removed).count();
+    if added == 0 && removed == 0 {
+        return LineDiff::default();
+    }
+
+    let mut text = format!("--- {}\n+++ {}\n", old_name, new_name);
+    // Position of every op in both files, counted before it
+    let mut positions = Vec::with_capacity(ops.len());
+    let (mut o, mut n) = (0, 0);
+    for op in &ops {
+        positions.push((o, n));
+        match op {
+            Op::Same => (o, n) = (o + 1, n + 1),
+            Op::Removed => o += 1,
+            Op::Added => n += 1,
+        }
+    }
+    let changed: Vec<usize> = (0..ops.len()).filter(|i| ops[*i] != Op::Same).collect();
+    let mut i = 0;
+    while i < changed.len() {
+        let start = changed[i].saturating_sub(CONTEXT);
+        let mut end = changed[i];
+        while i < changed.len() && changed[i] <= end + 2 * CONTEXT {
+            end = changed[i];
+            i += 1;
+        }
+        let end = (end + CONTEXT + 1).min(ops.len());
+        let old_count = ops[start..end].iter().filter(|op| **op != Op::Added).count();
+        let new_count = ops[start..end].iter().filter(|op| **op != Op::Removed).count();
+        let (old_start, new_start) = positions[start];
+        text.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_count),
+            range(new_start, new_count)
+        ));
+        for (index, op) in ops.iter().enumerate().take(end).skip(start) {
+            let (o, n) = positions[index];
+            let (sign, line) = match op {
+                Op::Same => (' ', old_lines[o]),
+                Op::Removed => ('-', old_lines[o]),
+                Op::Added => ('+', new_lines[n]),
+            };
+            text.push(sign);
+            text.push_str(line);
+            if !line.ends_with('\n') {
+                text.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    LineDiff { text, added, removed }
+}
+
+/// A hunk range: the first line, 1-based, and the count when it is not 1.
+/// An empty range names the line before it.
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+fn line_ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut ops = vec![Op::Same; prefix];
+    if a.len().saturating_mul(b.len()) > MAX_CELLS {
+        ops.extend(std::iter::repeat_n(Op::Removed, a.len()));
+        ops.extend(std::iter::repeat_n(Op::Added, b.len()));
+    } else {
+        // lengths[i][j]: longest common subsequence of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lengths = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lengths[i * width + j] = match a[i] == b[j] {
+                    true => lengths[(i + 1) * width + j + 1] + 1,
+                    false => lengths[(i + 1) * width + j].max(lengths[i * width + j + 1]),
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i] == b[j] {
+                ops.push(Op::Same);
+                (i, j) = (i + 1, j + 1);
+            } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+                ops.push(Op::Removed);
+                i += 1;
+            } else {
+                ops.push(Op::Added);
+                j += 1;
+            }
+        }
+        ops.extend(std::iter::repeat_n(Op::Removed, a.len() - i));
+        ops.extend(std::iter::repeat_n(Op::Added, b.len() - j));
+    }
+    ops.extend(std::iter::repeat_n(Op::Same, suffix));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_changes_with_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nb\nc\nD\ne\nf\ng\nh\ni\nj\nk\n";
+        let diff = unified("a/x", "b/x", old, new);
+        assert_eq!((diff.added, diff.removed), (2, 1));
+        assert_eq!(
+            diff.text,
+            "--- a/x\n+++ b/x\n@@ -1,7 +1,7 @@\n a\n b\n c\n-d\n+D\n e\n f\n g\n@@ -8,3 +8,4 @@\n h\n i\n j\n+k\n"
+        );
+        assert!(unified("a", "b", old, old).is_empty());
+    }
+
+    #[test]
+    fn handles_empty_sides_and_missing_newlines() {
+        let created = unified("/dev/null", "b/new", "", "one\ntwo");
+        assert_eq!(created.text, "--- /dev/null\n+++ b/new\n@@ -0,0 +1,2 @@\n+one\n+two\n\\ No newline at end of file\n");
+        let deleted = unified("a/old", "/dev/null", "one\n", "");
+        assert_eq!(deleted.text, "--- a/old\n+++ /dev/null\n@@ -1 +0,0 @@\n-one\n");
+    }
+}
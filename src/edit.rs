@@ -0,0 +1,283 @@
+use memchr::memmem;
+
+use crate::error::FsChildError;
+
+/// Byte offsets of every non-overlapping occurrence of `needle`, left to
+/// right. Works on raw bytes, so no UTF-8 validation is needed to search.
+pub fn find_all(haystack: &[u8], needle: &[u8]) -> Vec<usize> {
+    if needle.is_empty() {
+        return Vec::new();
+    }
+    memmem::find_iter(haystack, needle).collect()
+}
+
+/// 1-based line number of each position, in one pass over the text.
+pub fn line_numbers(haystack: &[u8], positions: &[usize]) -> Vec<usize> {
+    let mut lines = Vec::with_capacity(positions.len());
+    let mut line = 1;
+    let mut scanned = 0;
+    for &pos in positions {
+        line += memchr::memchr_iter(b'\n', &haystack[scanned..pos]).count();
+        scanned = pos;
+        lines.push(line);
+    }
+    lines
+}
+
+/// Which of an anchor's matches an edit replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The anchor must match exactly once
+    Only,
+    /// The nth match, counting from 1
+    Occurrence(usize),
+    /// Every match
+    All,
+}
+
+/// Narrow `positions` to the matches `target` selects. By default an anchor
+/// that matches more than once is refused, naming the lines it matched on.
+pub fn select(path: &str, haystack: &[u8], positions: &[usize], target: Target) -> Result<Vec<usize>, FsChildError> {
+    match target {
+        Target::Occurrence(n) => match positions.get(n.wrapping_sub(1)) {
+            Some(&pos) => Ok(vec![pos]),
+            None => Err(FsChildError::AnchorNotFound(format!(
+                "Occurrence {} requested but the text to replace matches {} times in '{}'",
+                n,
+                positions.len(),
+                path
+            ))),
+        },
+        _ if positions.is_empty() => {
+            Err(FsChildError::AnchorNotFound(format!("Text to replace not found in '{}'", path)))
+        }
+        Target::All => Ok(positions.to_vec()),
+        Target::Only if positions.len() == 1 => Ok(positions.to_vec()),
+        Target::Only => {
+            let lines = line_numbers(haystack, positions)
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(FsChildError::AmbiguousAnchor(format!(
+                "Text to replace matches {} times in '{}' (lines {}); include more surrounding text, or set occurrence or replace_all",
+                positions.len(),
+                path,
+                lines
+            )))
+        }
+    }
+}
+
+/// Replace the occurrences of `old` at `positions` with `new`, rewriting
+/// `buf` in place. Peak memory is the larger of the old and new file rather
+/// than a decoded copy plus a replaced copy.
+pub fn replace_in_place(buf: &mut Vec<u8>, positions: &[usize], old: &[u8], new: &[u8]) {
+    if positions.is_empty() {
+        return;
+    }
+    let old_len = buf.len();
+
+    if new.len() <= old.len() {
+        // Shrinking: compact forwards
+        let mut write = positions[0];
+        for (n, &pos) in positions.iter().enumerate() {
+            buf[write..write + new.len()].copy_from_slice(new);
+            write += new.len();
+            let next = positions.get(n + 1).copied().unwrap_or(old_len);
+            let tail = pos + old.len();
+            buf.copy_within(tail..next, write);
+            write += next - tail;
+        }
+        buf.truncate(write);
+    } else {
+        // Growing: extend, then fill from the back so nothing unread is overwritten
+        let grow = (new.len() - old.len()) * positions.len();
+        buf.resize(old_len + grow, 0);
+        let mut read_end = old_len;
+        let mut write_end = old_len + grow;
+        for &pos in positions.iter().rev() {
+            let tail = pos + old.len();
+            let tail_len = read_end - tail;
+            buf.copy_within(tail..read_end, write_end - tail_len);
+            write_end -= tail_len;
+            buf[write_end - new.len()..write_end].copy_from_slice(new);
+            write_end -= new.len();
+            read_end = pos;
+        }
+    }
+}
+
+/// Byte ranges of every non-overlapping match of `old` in which each run of
+/// whitespace stands for any run of whitespace, so an anchor quoted with
+/// the wrong indentation or line wrapping still matches. Whitespace before
+/// and after `old` is ignored, so a match runs from its first to its last
+/// visible character.
+pub fn find_loose(text: &str, old: &str) -> Vec<(usize, usize)> {
+    let tokens: Vec<&str> = old.split_whitespace().collect();
+    let Some(first) = tokens.first() else {
+        return Vec::new();
+    };
+    let mut ranges = Vec::new();
+    let mut from = 0;
+    while let Some(i) = text[from..].find(first) {
+        let start = from + i;
+        match loose_match_end(text, start + first.len(), &tokens[1..]) {
+            Some(end) => {
+                ranges.push((start, end));
+                from = end;
+            }
+            // A later match may still start inside this one's first token
+            None => from = start + text[start..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    ranges
+}
+
+/// Where `tokens` end when each follows at least one whitespace character
+/// from `pos`, if they do. Tokens never start with whitespace, so taking
+/// the whole run before each is the only way to match.
+fn loose_match_end(text: &str, mut pos: usize, tokens: &[&str]) -> Option<usize> {
+    for token in tokens {
+        let rest = &text[pos..];
+        let gap = rest.len() - rest.trim_start().len();
+        if gap == 0 || !rest[gap..].starts_with(token) {
+            return None;
+        }
+        pos += gap + token.len();
+    }
+    Some(pos)
+}
+
+/// Whether `a` and `b` differ only in whitespace.
+pub fn same_ignoring_whitespace(a: &str, b: &str) -> bool {
+    a.split_whitespace().eq(b.split_whitespace())
+}
+
+/// The indentation of the line holding `pos`, when only whitespace comes
+/// before `pos` on it.
+pub fn indent_at(text: &str, pos: usize) -> &str {
+    let start = text[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let before = &text[start..pos];
+    if before.chars().all(|c| c == ' ' || c == '\t') {
+        before
+    } else {
+        ""
+    }
+}
+
+/// `new` laid out to replace `matched`, a loose match of `old` found at
+/// `indent`. Its first line starts where the match did. On later lines,
+/// each indentation `old` was quoted with becomes the indentation the file
+/// has on the corresponding line of the match, so deeper levels follow the
+/// file's style too; the quoted first line stands for `indent`.
+pub fn reindent(new: &str, old: &str, matched: &str, indent: &str) -> String {
+    fn indent_of(line: &str) -> &str {
+        &line[..line.len() - line.trim_start().len()]
+    }
+
+    let quoted: Vec<&str> = old.lines().skip_while(|line| line.trim().is_empty()).collect();
+    let quoted = &quoted[..quoted.iter().rposition(|line| !line.trim().is_empty()).map_or(0, |i| i + 1)];
+    // Quoted indentation and the file's, longest quoted first
+    let mut pairs: Vec<(&str, &str)> = Vec::new();
+    if let Some(first) = quoted.first() {
+        pairs.push((indent_of(first), indent));
+    }
+    let found: Vec<&str> = matched.lines().collect();
+    // Lines only pair up when the match kept the quoted line breaks
+    if found.len() == quoted.len() {
+        for (quoted, found) in quoted.iter().zip(&found).skip(1).filter(|(line, _)| !line.trim().is_empty()) {
+            pairs.push((indent_of(quoted), indent_of(found)));
+        }
+    }
+    pairs.sort_by_key(|(quoted, _)| std::cmp::Reverse(quoted.len()));
+
+    // The match stops at the last visible character, so trailing whitespace
+    // quoted with `old` is still in the file
+    let new = if old.ends_with(char::is_whitespace) { new.trim_end() } else { new };
+    let mut out = String::with_capacity(new.len());
+    for (i, line) in new.split('\n').enumerate() {
+        if i == 0 {
+            out.push_str(line.trim_start());
+            continue;
+        }
+        out.push('\n');
+        if line.trim().is_empty() {
+            out.push_str(line.trim_start_matches([' ', '\t']));
+            continue;
+        }
+        match pairs.iter().find_map(|(quoted, file)| Some((line.strip_prefix(quoted)?, file))) {
+            Some((rest, file)) => {
+                out.push_str(file);
+                out.push_str(rest);
+            }
+            None => out.push_str(line),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replace(text: &str, old: &str, new: &str) -> String {
+        let mut buf = text.as_bytes().to_vec();
+        let positions = find_all(&buf, old.as_bytes());
+        replace_in_place(&mut buf, &positions, old.as_bytes(), new.as_bytes());
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn numbers_lines_of_matches() {
+        let text = b"a\nb a\n\na";
+        let positions = find_all(text, b"a");
+        assert_eq!(line_numbers(text, &positions), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn selects_targeted_matches() {
+        let text = b"x a x a x";
+        let positions = find_all(text, b"x");
+        assert!(matches!(select("f", text, &positions, Target::Only), Err(FsChildError::AmbiguousAnchor(_))));
+        assert_eq!(select("f", text, &positions, Target::Occurrence(2)).unwrap(), vec![4]);
+        assert!(matches!(select("f", text, &positions, Target::Occurrence(4)), Err(FsChildError::AnchorNotFound(_))));
+        assert_eq!(select("f", text, &positions, Target::All).unwrap(), positions);
+        assert_eq!(select("f", text, &positions[..1], Target::Only).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn matches_loosely_and_keeps_file_indentation() {
+        let file = "fn main() {\n\tif ready {\n\t\tgo();\n\t}\n}\n";
+        let old = "    if ready {\n        go();\n    }\n";
+        let matches = find_loose(file, old);
+        assert_eq!(matches, vec![(13, 34)]);
+        assert_eq!(find_loose("a  b\nab", "a b"), vec![(0, 4)]);
+        assert!(find_loose("text", " \n").is_empty());
+        assert_eq!(find_loose("aaa b, é\u{2003}é", "aa b"), vec![(1, 5)]);
+        assert_eq!(find_loose("é\u{2003}é", "é é"), vec![(0, 7)]);
+
+        let indent = indent_at(file, matches[0].0);
+        assert_eq!(indent, "\t");
+        let new = "    if ready {\n        go();\n\n        done();\n            again();\n    }\n";
+        let replaced = format!("{}{}{}", &file[..13], reindent(new, old, &file[13..34], indent), &file[34..]);
+        assert_eq!(replaced, "fn main() {\n\tif ready {\n\t\tgo();\n\n\t\tdone();\n\t\t    again();\n\t}\n}\n");
+        assert!(same_ignoring_whitespace("a  b\n", " a\tb"));
+        assert!(!same_ignoring_whitespace("ab", "a b"));
+    }
+
+    #[test]
+    fn matches_str_replace() {
+        for (text, old, new) in [
+            ("a-b-c", "-", "+"),
+            ("a-b-c", "-", ""),
+            ("a-b-c", "-", "---"),
+            ("aaaa", "aa", "b"),
+            ("xaax", "a", "ééé"),
+            ("none here", "zz", "y"),
+            ("end", "end", "finish"),
+        ] {
+            assert_eq!(replace(text, old, new), text.replace(old, new), "{text} {old} {new}");
+        }
+    }
+}
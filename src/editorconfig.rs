@@ -0,0 +1,272 @@
+//! Just enough of EditorConfig (https://editorconfig.org) to make written
+//! text follow a project's whitespace conventions.
+
+use crate::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentStyle {
+    Tab,
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndOfLine {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl EndOfLine {
+    fn as_str(self) -> &'static str {
+        match self {
+            EndOfLine::Lf => "\n",
+            EndOfLine::Crlf => "\r\n",
+            EndOfLine::Cr => "\r",
+        }
+    }
+}
+
+/// The properties that apply to one file. Unset properties leave the text
+/// as it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub indent_style: Option<IndentStyle>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub end_of_line: Option<EndOfLine>,
+    pub insert_final_newline: Option<bool>,
+    pub trim_trailing_whitespace: Option<bool>,
+}
+
+/// One parsed `.editorconfig` file.
+#[derive(Debug, Default)]
+pub struct File {
+    /// Whether files in parent directories should be ignored
+    pub root: bool,
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+pub fn parse(text: &str) -> File {
+    let mut file = File::default();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            file.sections.push((glob.to_string(), Vec::new()));
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_lowercase();
+        match file.sections.last_mut() {
+            Some((_, properties)) => properties.push((key, value)),
+            None if key == "root" => file.root = value == "true",
+            None => {}
+        }
+    }
+    file
+}
+
+impl File {
+    /// Apply the sections matching `path` (relative to this file's
+    /// directory) on top of `settings`; later sections win.
+    pub fn apply(&self, path: &str, settings: &mut Settings) {
+        for (glob, properties) in &self.sections {
+            if !section_matches(glob, path) {
+                continue;
+            }
+            for (key, value) in properties {
+                settings.set(key, value);
+            }
+        }
+    }
+}
+
+impl Settings {
+    fn set(&mut self, key: &str, value: &str) {
+        let flag = match value {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+        match key {
+            "indent_style" => {
+                self.indent_style = match value {
+                    "tab" => Some(IndentStyle::Tab),
+                    "space" => Some(IndentStyle::Space),
+                    _ => None,
+                }
+            }
+            "indent_size" => self.indent_size = value.parse().ok().filter(|n| *n > 0),
+            "tab_width" => self.tab_width = value.parse().ok().filter(|n| *n > 0),
+            "end_of_line" => {
+                self.end_of_line = match value {
+                    "lf" => Some(EndOfLine::Lf),
+                    "crlf" => Some(EndOfLine::Crlf),
+                    "cr" => Some(EndOfLine::Cr),
+                    _ => None,
+                }
+            }
+            "insert_final_newline" => self.insert_final_newline = flag,
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = flag,
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Settings::default()
+    }
+
+    /// Columns one tab stands for.
+    fn tab_columns(&self) -> usize {
+        self.tab_width.or(self.indent_size).unwrap_or(4)
+    }
+
+    /// Rewrite `text` to follow these settings, returning it with the names
+    /// of the properties that changed something. A `fragment` (the new text
+    /// of an edit) is not the end of the file, so its final newline is left
+    /// alone.
+    pub fn normalize(&self, text: &str, fragment: bool) -> (String, Vec<&'static str>) {
+        let mut changed = Vec::new();
+        let eol = self.end_of_line.map(EndOfLine::as_str);
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while !rest.is_empty() {
+            let (line, ending, next) = split_line(rest);
+            rest = next;
+
+            let mut line = line.to_string();
+            if self.trim_trailing_whitespace == Some(true) {
+                let trimmed = line.trim_end_matches([' ', '\t']).len();
+                if trimmed < line.len() {
+                    line.truncate(trimmed);
+                    note(&mut changed, "trim_trailing_whitespace");
+                }
+            }
+            if let Some(style) = self.indent_style {
+                let reindented = self.reindent(&line, style);
+                if reindented != line {
+                    line = reindented;
+                    note(&mut changed, "indent_style");
+                }
+            }
+            out.push_str(&line);
+            match (ending, eol) {
+                ("", _) => {}
+                (ending, Some(eol)) if ending != eol => {
+                    out.push_str(eol);
+                    note(&mut changed, "end_of_line");
+                }
+                (ending, _) => out.push_str(ending),
+            }
+        }
+
+        if !fragment && self.insert_final_newline == Some(true) && !out.is_empty() && !out.ends_with(['\n', '\r']) {
+            out.push_str(eol.unwrap_or("\n"));
+            note(&mut changed, "insert_final_newline");
+        }
+        (out, changed)
+    }
+
+    /// `line` with its leading whitespace redone in `style`. Indentation
+    /// that does not divide evenly keeps its remainder as spaces.
+    fn reindent(&self, line: &str, style: IndentStyle) -> String {
+        let body = line.trim_start_matches([' ', '\t']);
+        let indent = &line[..line.len() - body.len()];
+        let tab = self.tab_columns();
+        let columns: usize = indent
+            .chars()
+            .fold(0, |col, c| if c == '\t' { col + tab - col % tab } else { col + 1 });
+        let indent = match style {
+            IndentStyle::Space => " ".repeat(columns),
+            IndentStyle::Tab => format!("{}{}", "\t".repeat(columns / tab), " ".repeat(columns % tab)),
+        };
+        format!("{}{}", indent, body)
+    }
+}
+
+fn note(changed: &mut Vec<&'static str>, property: &'static str) {
+    if !changed.contains(&property) {
+        changed.push(property);
+    }
+}
+
+/// The first line of `text`, its line ending (empty for the last line
+/// without one), and the rest.
+fn split_line(text: &str) -> (&str, &str, &str) {
+    match text.find(['\n', '\r']) {
+        None => (text, "", ""),
+        Some(i) if text[i..].starts_with("\r\n") => (&text[..i], "\r\n", &text[i + 2..]),
+        Some(i) => (&text[..i], &text[i..i + 1], &text[i + 1..]),
+    }
+}
+
+/// Whether a section glob covers `path`. A glob without a `/` matches the
+/// file name in any directory; braces list alternatives.
+fn section_matches(glob: &str, path: &str) -> bool {
+    expand_braces(glob).iter().any(|glob| {
+        if glob.contains('/') {
+            watch::glob_match(glob.trim_start_matches('/'), path)
+        } else {
+            watch::glob_match(&format!("**/{}", glob), path)
+        }
+    })
+}
+
+/// `*.{js,ts}` becomes `*.js` and `*.ts`. Nested braces are expanded one
+/// level at a time.
+fn expand_braces(glob: &str) -> Vec<String> {
+    let Some(open) = glob.find('{') else {
+        return vec![glob.to_string()];
+    };
+    let Some(close) = glob[open..].find('}').map(|i| open + i) else {
+        return vec![glob.to_string()];
+    };
+    let (head, tail) = (&glob[..open], &glob[close + 1..]);
+    glob[open + 1..close]
+        .split(',')
+        .flat_map(|choice| expand_braces(&format!("{}{}{}", head, choice, tail)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "root = true\n\n[*]\nend_of_line = lf\ninsert_final_newline = true\ntrim_trailing_whitespace = true\n\n[*.{rs,toml}]\nindent_style = space\nindent_size = 4\n\n[Makefile]\nindent_style = tab\n";
+
+    fn settings_for(path: &str) -> Settings {
+        let mut settings = Settings::default();
+        parse(CONFIG).apply(path, &mut settings);
+        settings
+    }
+
+    #[test]
+    fn matches_sections_by_name() {
+        let file = parse(CONFIG);
+        assert!(file.root);
+        assert_eq!(settings_for("src/lib.rs").indent_style, Some(IndentStyle::Space));
+        assert_eq!(settings_for("Cargo.toml").indent_size, Some(4));
+        assert_eq!(settings_for("sub/Makefile").indent_style, Some(IndentStyle::Tab));
+        assert_eq!(settings_for("notes.txt").indent_style, None);
+    }
+
+    #[test]
+    fn normalizes_whitespace() {
+        let (text, changed) = settings_for("src/lib.rs").normalize("fn f() {\r\n\tlet x = 1;  \r\n}", false);
+        assert_eq!(text, "fn f() {\n    let x = 1;\n}\n");
+        assert_eq!(changed, ["end_of_line", "trim_trailing_whitespace", "indent_style", "insert_final_newline"]);
+
+        let (text, changed) = settings_for("Makefile").normalize("all:\n        cc x.c\n", false);
+        assert_eq!(text, "all:\n\t\tcc x.c\n");
+        assert_eq!(changed, ["indent_style"]);
+
+        let (text, changed) = settings_for("src/lib.rs").normalize("\tlet y = 2;", true);
+        assert_eq!(text, "    let y = 2;");
+        assert_eq!(changed, ["indent_style"]);
+    }
+}
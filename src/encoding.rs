@@ -0,0 +1,147 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// How file content that is not valid UTF-8 is shown when read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NonUtf8Mode {
+    /// Refuse to read it
+    Error,
+    /// Decode it anyway, replacing invalid bytes with U+FFFD
+    #[default]
+    Lossy,
+    /// A hex dump of the start of the content
+    Hex,
+    /// The exact bytes, base64 encoded
+    Base64,
+}
+
+impl NonUtf8Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            NonUtf8Mode::Error => "error",
+            NonUtf8Mode::Lossy => "lossy",
+            NonUtf8Mode::Hex => "hex",
+            NonUtf8Mode::Base64 => "base64",
+        }
+    }
+}
+
+/// Byte-order mark at the start of a file, naming its encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Bom {
+    Utf8,
+    Utf16le,
+    Utf16be,
+}
+
+impl Bom {
+    pub fn detect(bytes: &[u8]) -> Option<Bom> {
+        match bytes {
+            [0xEF, 0xBB, 0xBF, ..] => Some(Bom::Utf8),
+            [0xFF, 0xFE, ..] => Some(Bom::Utf16le),
+            [0xFE, 0xFF, ..] => Some(Bom::Utf16be),
+            _ => None,
+        }
+    }
+
+    pub fn len(self) -> usize {
+        match self {
+            Bom::Utf8 => 3,
+            Bom::Utf16le | Bom::Utf16be => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Bom::Utf8 => "UTF-8 with BOM",
+            Bom::Utf16le => "UTF-16LE",
+            Bom::Utf16be => "UTF-16BE",
+        }
+    }
+}
+
+/// The text of a file that starts with `bom`, as UTF-8 bytes without the
+/// mark. UTF-16 is converted, with unpaired surrogates (and a trailing odd
+/// byte) replaced by U+FFFD; UTF-8 only loses its first three bytes.
+pub fn strip_bom(mut bytes: Vec<u8>, bom: Bom) -> Vec<u8> {
+    if bom == Bom::Utf8 {
+        bytes.drain(..bom.len());
+        return bytes;
+    }
+    let units = bytes[bom.len()..].chunks(2).map(|pair| match (bom, pair) {
+        (Bom::Utf16le, [lo, hi]) => u16::from_le_bytes([*lo, *hi]),
+        (_, [hi, lo]) => u16::from_be_bytes([*hi, *lo]),
+        _ => 0xFFFD,
+    });
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Most bytes shown in a hex preview
+pub const HEX_PREVIEW_BYTES: usize = 4096;
+
+/// Text standing in for non-UTF-8 `bytes`, or `None` when the mode refuses.
+pub fn render(bytes: &[u8], mode: NonUtf8Mode) -> Option<String> {
+    match mode {
+        NonUtf8Mode::Error => None,
+        NonUtf8Mode::Lossy => Some(String::from_utf8_lossy(bytes).into_owned()),
+        NonUtf8Mode::Hex => {
+            let mut dump = hex_dump(&bytes[..bytes.len().min(HEX_PREVIEW_BYTES)]);
+            if bytes.len() > HEX_PREVIEW_BYTES {
+                dump.push_str(&format!(
+                    "\n... {} more bytes",
+                    bytes.len() - HEX_PREVIEW_BYTES
+                ));
+            }
+            Some(dump)
+        }
+        NonUtf8Mode::Base64 => Some(BASE64.encode(bytes)),
+    }
+}
+
+/// Classic 16-bytes-per-line dump: offset, hex bytes, printable ASCII.
+fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let hex = chunk
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            format!("{:08x}  {:<47}  |{}|", line * 16, hex, ascii)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_text_behind_a_bom() {
+        let utf8 = b"\xEF\xBB\xBFhi".to_vec();
+        assert_eq!(Bom::detect(&utf8), Some(Bom::Utf8));
+        assert_eq!(strip_bom(utf8, Bom::Utf8), b"hi");
+
+        let le: Vec<u8> = [0xFF, 0xFE].into_iter().chain("hé😀".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+        assert_eq!(Bom::detect(&le), Some(Bom::Utf16le));
+        assert_eq!(strip_bom(le, Bom::Utf16le), "hé😀".as_bytes());
+
+        let be: Vec<u8> = [0xFE, 0xFF].into_iter().chain("ok".encode_utf16().flat_map(u16::to_be_bytes)).chain([0x00]).collect();
+        assert_eq!(strip_bom(be, Bom::Utf16be), "ok\u{FFFD}".as_bytes());
+
+        assert_eq!(Bom::detect(b"plain"), None);
+    }
+}
@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Machine-readable reason a command failed, carried in the response data
+/// so parents can branch on it instead of parsing the result text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    PermissionDenied,
+    NotFound,
+    NotUtf8,
+    AnchorNotFound,
+    AmbiguousAnchor,
+    InvalidPath,
+    SchemaViolation,
+    IsDirectory,
+    NotADirectory,
+    TooLarge,
+    MissingField,
+    UnknownOperation,
+    ParseError,
+    StoreError,
+    /// A delegate could not be started or did not answer
+    DelegateError,
+    /// The command would discard changes nobody saved
+    Conflict,
+    /// The command may only create its file, and the file is already there
+    AlreadyExists,
+    /// The command would replace a file with far less content, unconfirmed
+    Truncation,
+    /// The command would take part of the workspace over its quota
+    QuotaExceeded,
+    /// The runtime does not provide a host interface the command needs
+    Unsupported,
+    Io,
+    /// The actor itself failed while handling the request
+    Internal,
+}
+
+impl ErrorCode {
+    /// Classify an error string returned by the host filesystem interface.
+    pub fn from_host_error(error: &str) -> Self {
+        let lower = error.to_lowercase();
+        if crate::host::is_unsupported(error) {
+            ErrorCode::Unsupported
+        } else if lower.contains("no such file") || lower.contains("not found") {
+            ErrorCode::NotFound
+        } else if lower.contains("permission denied") {
+            ErrorCode::PermissionDenied
+        } else {
+            ErrorCode::Io
+        }
+    }
+}
+
+/// Failures seen this session, by kind, so a misbehaving session stands
+/// out in the actor's status.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ErrorCounts {
+    /// Malformed requests, commands or fields
+    pub parse: u64,
+    pub permission_denied: u64,
+    pub store: u64,
+    /// Errors reported by the host filesystem
+    pub filesystem: u64,
+    pub other: u64,
+}
+
+impl ErrorCounts {
+    pub fn count(&mut self, code: ErrorCode) {
+        let counter = match code {
+            ErrorCode::ParseError | ErrorCode::InvalidPath | ErrorCode::MissingField | ErrorCode::UnknownOperation => {
+                &mut self.parse
+            }
+            ErrorCode::PermissionDenied => &mut self.permission_denied,
+            ErrorCode::StoreError => &mut self.store,
+            ErrorCode::NotFound | ErrorCode::Io | ErrorCode::IsDirectory | ErrorCode::NotADirectory => {
+                &mut self.filesystem
+            }
+            _ => &mut self.other,
+        };
+        *counter += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.parse + self.permission_denied + self.store + self.filesystem + self.other
+    }
+}
+
+/// Errors raised inside the actor. Each variant maps to exactly one
+/// [`ErrorCode`], so the text, HTML and data of a failed result always agree.
+#[derive(Debug)]
+pub enum FsChildError {
+    PermissionDenied(String),
+    NotFound(String),
+    NotUtf8(String),
+    AnchorNotFound(String),
+    AmbiguousAnchor(String),
+    InvalidPath(String),
+    SchemaViolation(String),
+    IsDirectory(String),
+    NotADirectory(String),
+    TooLarge(String),
+    MissingField(String),
+    UnknownOperation(String),
+    Parse(String),
+    Store(String),
+    Delegate(String),
+    Conflict(String),
+    AlreadyExists(String),
+    Truncation(String),
+    Quota(String),
+    Unsupported(String),
+    Io(String),
+}
+
+impl FsChildError {
+    /// Classify an error string from the host filesystem interface,
+    /// described by `message`.
+    pub fn from_host(error: &str, message: String) -> Self {
+        match ErrorCode::from_host_error(error) {
+            ErrorCode::NotFound => FsChildError::NotFound(message),
+            ErrorCode::PermissionDenied => FsChildError::PermissionDenied(message),
+            ErrorCode::Unsupported => FsChildError::Unsupported(message),
+            _ => FsChildError::Io(message),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            FsChildError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+            FsChildError::NotFound(_) => ErrorCode::NotFound,
+            FsChildError::NotUtf8(_) => ErrorCode::NotUtf8,
+            FsChildError::AnchorNotFound(_) => ErrorCode::AnchorNotFound,
+            FsChildError::AmbiguousAnchor(_) => ErrorCode::AmbiguousAnchor,
+            FsChildError::InvalidPath(_) => ErrorCode::InvalidPath,
+            FsChildError::SchemaViolation(_) => ErrorCode::SchemaViolation,
+            FsChildError::IsDirectory(_) => ErrorCode::IsDirectory,
+            FsChildError::NotADirectory(_) => ErrorCode::NotADirectory,
+            FsChildError::TooLarge(_) => ErrorCode::TooLarge,
+            FsChildError::MissingField(_) => ErrorCode::MissingField,
+            FsChildError::UnknownOperation(_) => ErrorCode::UnknownOperation,
+            FsChildError::Parse(_) => ErrorCode::ParseError,
+            FsChildError::Store(_) => ErrorCode::StoreError,
+            FsChildError::Delegate(_) => ErrorCode::DelegateError,
+            FsChildError::Conflict(_) => ErrorCode::Conflict,
+            FsChildError::AlreadyExists(_) => ErrorCode::AlreadyExists,
+            FsChildError::Truncation(_) => ErrorCode::Truncation,
+            FsChildError::Quota(_) => ErrorCode::QuotaExceeded,
+            FsChildError::Unsupported(_) => ErrorCode::Unsupported,
+            FsChildError::Io(_) => ErrorCode::Io,
+        }
+    }
+}
+
+impl fmt::Display for FsChildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsChildError::PermissionDenied(message)
+            | FsChildError::NotFound(message)
+            | FsChildError::NotUtf8(message)
+            | FsChildError::AnchorNotFound(message)
+            | FsChildError::AmbiguousAnchor(message)
+            | FsChildError::InvalidPath(message)
+            | FsChildError::SchemaViolation(message)
+            | FsChildError::IsDirectory(message)
+            | FsChildError::NotADirectory(message)
+            | FsChildError::TooLarge(message)
+            | FsChildError::MissingField(message)
+            | FsChildError::UnknownOperation(message)
+            | FsChildError::Parse(message)
+            | FsChildError::Store(message)
+            | FsChildError::Delegate(message)
+            | FsChildError::Conflict(message)
+            | FsChildError::AlreadyExists(message)
+            | FsChildError::Truncation(message)
+            | FsChildError::Quota(message)
+            | FsChildError::Unsupported(message)
+            | FsChildError::Io(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for FsChildError {}
+
+impl From<serde_json::Error> for FsChildError {
+    fn from(error: serde_json::Error) -> Self {
+        FsChildError::Parse(error.to_string())
+    }
+}
@@ -0,0 +1,175 @@
+//! Guessing what a file holds from its leading bytes, falling back to its
+//! extension, so the right way to look at it can be chosen before reading
+//! it whole into the conversation.
+
+use crate::encoding::{self, Bom};
+
+/// What a file appears to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileType {
+    /// Short name such as `png` or `rust`
+    pub name: &'static str,
+    pub mime: &'static str,
+    /// Whether it reads as text
+    pub text: bool,
+    /// `content` when the bytes decided, `extension` when only the name did
+    pub detected_by: &'static str,
+}
+
+/// Signatures checked in order: offset, bytes, name, MIME type.
+const MAGIC: &[(usize, &[u8], &str, &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "png", "image/png"),
+    (0, b"\xff\xd8\xff", "jpeg", "image/jpeg"),
+    (0, b"GIF87a", "gif", "image/gif"),
+    (0, b"GIF89a", "gif", "image/gif"),
+    (0, b"BM", "bmp", "image/bmp"),
+    (0, b"\x00\x00\x01\x00", "ico", "image/vnd.microsoft.icon"),
+    (0, b"%PDF-", "pdf", "application/pdf"),
+    (0, b"\x00asm", "wasm", "application/wasm"),
+    (0, b"\x7fELF", "elf", "application/x-executable"),
+    (0, b"\xcf\xfa\xed\xfe", "mach-o", "application/x-mach-binary"),
+    (0, b"\xfe\xed\xfa\xcf", "mach-o", "application/x-mach-binary"),
+    (0, b"\xca\xfe\xba\xbe", "java-class", "application/java-vm"),
+    (0, b"MZ", "pe", "application/vnd.microsoft.portable-executable"),
+    (0, b"PK\x03\x04", "zip", "application/zip"),
+    (0, b"PK\x05\x06", "zip", "application/zip"),
+    (0, b"\x1f\x8b", "gzip", "application/gzip"),
+    (0, b"BZh", "bzip2", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\x00", "xz", "application/x-xz"),
+    (0, b"\x28\xb5\x2f\xfd", "zstd", "application/zstd"),
+    (0, b"7z\xbc\xaf\x27\x1c", "7z", "application/x-7z-compressed"),
+    (257, b"ustar", "tar", "application/x-tar"),
+    (0, b"SQLite format 3\x00", "sqlite", "application/vnd.sqlite3"),
+    (0, b"ID3", "mp3", "audio/mpeg"),
+    (0, b"OggS", "ogg", "audio/ogg"),
+    (0, b"fLaC", "flac", "audio/flac"),
+    (4, b"ftyp", "mp4", "video/mp4"),
+    (0, b"wOFF", "woff", "font/woff"),
+    (0, b"wOF2", "woff2", "font/woff2"),
+];
+
+/// Text formats by extension: extensions, name, MIME type.
+const TEXT_EXTENSIONS: &[(&[&str], &str, &str)] = &[
+    (&["json"], "json", "application/json"),
+    (&["ndjson", "jsonl"], "ndjson", "application/x-ndjson"),
+    (&["yaml", "yml"], "yaml", "application/yaml"),
+    (&["toml"], "toml", "application/toml"),
+    (&["md", "markdown"], "markdown", "text/markdown"),
+    (&["html", "htm"], "html", "text/html"),
+    (&["css"], "css", "text/css"),
+    (&["csv"], "csv", "text/csv"),
+    (&["tsv"], "tsv", "text/tab-separated-values"),
+    (&["xml"], "xml", "application/xml"),
+    (&["svg"], "svg", "image/svg+xml"),
+    (&["rs"], "rust", "text/x-rust"),
+    (&["py"], "python", "text/x-python"),
+    (&["js", "mjs", "cjs"], "javascript", "text/javascript"),
+    (&["ts", "tsx"], "typescript", "text/x-typescript"),
+    (&["go"], "go", "text/x-go"),
+    (&["c", "h"], "c", "text/x-c"),
+    (&["cpp", "cc", "hpp"], "cpp", "text/x-c++"),
+    (&["java"], "java", "text/x-java"),
+    (&["sh", "bash"], "shell", "text/x-shellscript"),
+    (&["wit"], "wit", "text/plain"),
+    (&["txt", "log"], "text", "text/plain"),
+];
+
+/// Binary formats whose signatures are too loose to sniff, by extension.
+const BINARY_EXTENSIONS: &[(&[&str], &str, &str)] = &[
+    (&["webp"], "webp", "image/webp"),
+    (&["wav"], "wav", "audio/wav"),
+    (&["tar"], "tar", "application/x-tar"),
+    (&["bin", "dat"], "binary", "application/octet-stream"),
+];
+
+pub fn detect(path: &str, bytes: &[u8]) -> FileType {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let extension = name
+        .rsplit_once('.')
+        .filter(|(stem, _)| !stem.is_empty())
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    let by_extension = |table: &[(&[&str], &'static str, &'static str)], text: bool| {
+        let extension = extension.as_deref()?;
+        let (_, name, mime) = table.iter().find(|(extensions, _, _)| extensions.contains(&extension))?;
+        Some(FileType { name, mime, text, detected_by: "extension" })
+    };
+    let from_content = |name, mime, text| FileType { name, mime, text, detected_by: "content" };
+
+    if bytes.len() >= 12 && bytes.starts_with(b"RIFF") {
+        match &bytes[8..12] {
+            b"WEBP" => return from_content("webp", "image/webp", false),
+            b"WAVE" => return from_content("wav", "audio/wav", false),
+            _ => {}
+        }
+    }
+    // Text first: a text file can begin with "BM" or "MZ" as easily as any
+    // other letters
+    if let Some(text) = looks_like_text(bytes) {
+        if let Some(known) = by_extension(TEXT_EXTENSIONS, true) {
+            return known;
+        }
+        let trimmed = text.trim_start();
+        return if trimmed.starts_with("<?xml") {
+            from_content("xml", "application/xml", true)
+        } else if trimmed.starts_with("<svg") {
+            from_content("svg", "image/svg+xml", true)
+        } else if trimmed.to_ascii_lowercase().starts_with("<!doctype html") {
+            from_content("html", "text/html", true)
+        } else if trimmed.starts_with("#!") {
+            from_content("script", "text/plain", true)
+        } else if trimmed.starts_with(['{', '[']) && serde_json::from_str::<serde::de::IgnoredAny>(trimmed).is_ok() {
+            from_content("json", "application/json", true)
+        } else {
+            FileType { name: "text", mime: "text/plain", text: true, detected_by: "content" }
+        };
+    }
+    if let Some((_, _, name, mime)) = MAGIC
+        .iter()
+        .find(|(offset, magic, _, _)| bytes.get(*offset..offset + magic.len()) == Some(*magic))
+    {
+        return from_content(name, mime, false);
+    }
+    by_extension(BINARY_EXTENSIONS, false)
+        .unwrap_or(FileType { name: "binary", mime: "application/octet-stream", text: false, detected_by: "content" })
+}
+
+/// The text of `bytes` when they are UTF-8, or UTF-16 with a byte-order
+/// mark, without NUL or other stray control characters (escapes, as in
+/// colored logs, are fine). An empty file counts as text.
+fn looks_like_text(bytes: &[u8]) -> Option<String> {
+    let bytes = match Bom::detect(bytes) {
+        Some(bom) => encoding::strip_bom(bytes.to_vec(), bom),
+        None => bytes.to_vec(),
+    };
+    let text = String::from_utf8(bytes).ok()?;
+    let stray = text.chars().any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c' | '\x1b'));
+    (!stray).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_signatures_before_extensions() {
+        let png = detect("logo.txt", b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR");
+        assert_eq!((png.name, png.text, png.detected_by), ("png", false, "content"));
+        assert_eq!(detect("pkg.wasm", b"\x00asm\x01\x00\x00\x00").mime, "application/wasm");
+
+        let mut tar = vec![0u8; 512];
+        tar[..8].copy_from_slice(b"file.txt");
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(detect("x", &tar).name, "tar");
+        assert_eq!(detect("data.bin", &[0, 1, 2, 200]).detected_by, "extension");
+    }
+
+    #[test]
+    fn classifies_text() {
+        assert_eq!(detect("src/lib.rs", b"fn main() {}\n").name, "rust");
+        assert_eq!(detect("notes", b"BMW parts list\n").name, "text");
+        assert_eq!(detect("out", b" {\"a\": [1]}").name, "json");
+        assert_eq!(detect("run", b"#!/bin/sh\necho hi\n").name, "script");
+        assert_eq!(detect("wide.txt", b"\xff\xfeh\x00i\x00").name, "text");
+        assert!(!detect("nul.txt", b"a\x00b").text);
+    }
+}
@@ -0,0 +1,146 @@
+//! YAML front matter at the top of Markdown files: a block opened by a
+//! `---` line and closed by a `---` or `...` line.
+//!
+//! Reading parses the block; writing replaces only the lines of the one
+//! top-level key being set, so comments, ordering and the body are kept.
+//! Both need the `structured` feature; finding the block does not.
+
+#[cfg(feature = "structured")]
+use serde_json::{json, Value};
+use std::ops::Range;
+
+/// Byte range of the YAML between the delimiter lines, if the text opens
+/// with front matter.
+pub fn block(text: &str) -> Option<Range<usize>> {
+    let first = text.find('\n')?;
+    if text[..first].trim_end_matches('\r') != "---" {
+        return None;
+    }
+    let start = first + 1;
+    let mut pos = start;
+    while pos < text.len() {
+        let end = text[pos..].find('\n').map_or(text.len(), |i| pos + i);
+        if matches!(text[pos..end].trim_end_matches('\r'), "---" | "...") {
+            return Some(start..pos);
+        }
+        pos = end + 1;
+    }
+    None
+}
+
+/// The front matter as a JSON object, or `None` when there is none.
+#[cfg(feature = "structured")]
+pub fn get(text: &str) -> Result<Option<Value>, String> {
+    let Some(range) = block(text) else {
+        return Ok(None);
+    };
+    let yaml = &text[range];
+    if yaml.trim().is_empty() {
+        return Ok(Some(json!({})));
+    }
+    match serde_yaml::from_str::<Value>(yaml) {
+        Ok(value @ Value::Object(_)) => Ok(Some(value)),
+        Ok(_) => Err("front matter is not a mapping of keys to values".to_string()),
+        Err(e) => Err(format!("front matter is not valid YAML: {}", e)),
+    }
+}
+
+/// `text` with the top-level `key` of its front matter set to `value`,
+/// added at the end of the block if missing. Text without front matter
+/// gains a block holding just this key.
+#[cfg(feature = "structured")]
+pub fn set(text: &str, key: &str, value: &Value) -> Result<String, String> {
+    let entry = serde_yaml::to_string(&json!({ key: value }))
+        .map_err(|e| format!("cannot write the value as YAML: {}", e))?;
+    let Some(range) = block(text) else {
+        return Ok(format!("---\n{}---\n{}", entry, text));
+    };
+    // Keep the file's line endings
+    let entry = if text[..range.start].ends_with("\r\n") {
+        entry.replace('\n', "\r\n")
+    } else {
+        entry
+    };
+
+    let yaml = &text[range.clone()];
+    let lines: Vec<Range<usize>> = line_ranges(yaml);
+    let updated = match lines.iter().position(|line| defines(&yaml[line.clone()], key)) {
+        Some(first) => {
+            // The entry continues over indented lines and list items, and
+            // over blank lines that are followed by more of them
+            let mut last = first;
+            for (i, line) in lines.iter().enumerate().skip(first + 1) {
+                let line = &yaml[line.clone()];
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if line.starts_with([' ', '\t', '-']) {
+                    last = i;
+                } else {
+                    break;
+                }
+            }
+            format!("{}{}{}", &yaml[..lines[first].start], entry, &yaml[lines[last].end..])
+        }
+        None if yaml.is_empty() || yaml.ends_with('\n') => format!("{}{}", yaml, entry),
+        None => format!("{}\n{}", yaml, entry),
+    };
+
+    serde_yaml::from_str::<serde_yaml::Value>(&updated)
+        .map_err(|e| format!("front matter would not be valid YAML after the change: {}", e))?;
+    Ok(format!("{}{}{}", &text[..range.start], updated, &text[range.end..]))
+}
+
+/// Byte ranges of each line, including its line ending.
+#[cfg(feature = "structured")]
+fn line_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let end = text[pos..].find('\n').map_or(text.len(), |i| pos + i + 1);
+        ranges.push(pos..end);
+        pos = end;
+    }
+    ranges
+}
+
+/// Whether `line` starts the top-level entry for `key`, in plain or quoted
+/// form.
+#[cfg(feature = "structured")]
+fn defines(line: &str, key: &str) -> bool {
+    [key.to_string(), format!("\"{}\"", key), format!("'{}'", key)]
+        .iter()
+        .any(|form| {
+            line.strip_prefix(form.as_str())
+                .and_then(|rest| rest.strip_prefix(':'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t', '\r', '\n']))
+        })
+}
+
+#[cfg(all(test, feature = "structured"))]
+mod tests {
+    use super::*;
+
+    const POST: &str = "---\ntitle: Hello\n# kept comment\ntags:\n  - a\n  - b\n\ndraft: true\n---\n# Body\n\ntags: not front matter\n";
+
+    #[test]
+    fn reads_front_matter() {
+        let matter = get(POST).unwrap().unwrap();
+        assert_eq!(matter["title"], "Hello");
+        assert_eq!(matter["tags"], json!(["a", "b"]));
+        assert_eq!(get("# No front matter\n").unwrap(), None);
+    }
+
+    #[test]
+    fn sets_one_key_in_place() {
+        let updated = set(POST, "tags", &json!(["x"])).unwrap();
+        assert_eq!(updated, "---\ntitle: Hello\n# kept comment\ntags:\n- x\n\ndraft: true\n---\n# Body\n\ntags: not front matter\n");
+
+        let updated = set(POST, "author", &json!("Ann: B")).unwrap();
+        assert!(updated.starts_with("---\ntitle: Hello\n"));
+        assert_eq!(get(&updated).unwrap().unwrap()["author"], "Ann: B");
+        assert!(updated.ends_with("draft: true\nauthor: 'Ann: B'\n---\n# Body\n\ntags: not front matter\n"));
+
+        assert_eq!(set("Body\n", "title", &json!("T")).unwrap(), "---\ntitle: T\n---\nBody\n");
+    }
+}
@@ -0,0 +1,267 @@
+//! The filesystem as the actor sees it.
+//!
+//! Command handling only touches files through [`Filesystem`], so it runs
+//! the same against the Theater host or an in-memory tree. [`HostFilesystem`]
+//! is the one place the filesystem interface of the WIT bindings is called.
+//! A build without the `filesystem` feature never calls it, so the component
+//! does not import the interface and every file operation is unsupported.
+
+#[cfg(feature = "filesystem")]
+use crate::bindings::ntwk::theater::filesystem;
+
+/// The host filesystem calls the actor relies on. Paths are as the host
+/// takes them, already resolved against `base_path`; errors are the host's
+/// own text, classified later by [`ErrorCode::from_host_error`].
+///
+/// [`ErrorCode::from_host_error`]: crate::error::ErrorCode::from_host_error
+pub trait Filesystem: std::fmt::Debug {
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, String>;
+
+    /// Replace the file's content, creating it if missing. The host only
+    /// writes text.
+    fn write_file(&self, path: &str, content: &str) -> Result<(), String>;
+
+    /// Names of the entries directly inside a directory; an error for a
+    /// file or a missing path.
+    fn list_files(&self, path: &str) -> Result<Vec<String>, String>;
+
+    fn delete_file(&self, path: &str) -> Result<(), String>;
+
+    fn create_dir(&self, path: &str) -> Result<(), String>;
+
+    fn delete_dir(&self, path: &str) -> Result<(), String>;
+
+    fn path_exists(&self, path: &str) -> Result<bool, String>;
+}
+
+/// The Theater host's filesystem interface.
+#[derive(Debug)]
+pub struct HostFilesystem;
+
+#[cfg(feature = "filesystem")]
+impl Filesystem for HostFilesystem {
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, String> {
+        filesystem::read_file(path)
+    }
+
+    fn write_file(&self, path: &str, content: &str) -> Result<(), String> {
+        filesystem::write_file(path, content)
+    }
+
+    fn list_files(&self, path: &str) -> Result<Vec<String>, String> {
+        filesystem::list_files(path)
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), String> {
+        filesystem::delete_file(path)
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), String> {
+        filesystem::create_dir(path)
+    }
+
+    fn delete_dir(&self, path: &str) -> Result<(), String> {
+        filesystem::delete_dir(path)
+    }
+
+    fn path_exists(&self, path: &str) -> Result<bool, String> {
+        filesystem::path_exists(path)
+    }
+}
+
+/// Refusal of every call by a build without the interface, worded so it
+/// classifies as unsupported.
+#[cfg(not(feature = "filesystem"))]
+fn not_imported<T>() -> Result<T, String> {
+    Err("Filesystem calls are unsupported: this build does not import the filesystem interface".to_string())
+}
+
+#[cfg(not(feature = "filesystem"))]
+impl Filesystem for HostFilesystem {
+    fn read_file(&self, _path: &str) -> Result<Vec<u8>, String> {
+        not_imported()
+    }
+
+    fn write_file(&self, _path: &str, _content: &str) -> Result<(), String> {
+        not_imported()
+    }
+
+    fn list_files(&self, _path: &str) -> Result<Vec<String>, String> {
+        not_imported()
+    }
+
+    fn delete_file(&self, _path: &str) -> Result<(), String> {
+        not_imported()
+    }
+
+    fn create_dir(&self, _path: &str) -> Result<(), String> {
+        not_imported()
+    }
+
+    fn delete_dir(&self, _path: &str) -> Result<(), String> {
+        not_imported()
+    }
+
+    fn path_exists(&self, _path: &str) -> Result<bool, String> {
+        not_imported()
+    }
+}
+
+/// In-memory filesystem for exercising command handling without a host.
+/// Clones share one tree, so a test can keep a handle on what the actor
+/// changed. Errors use the wording of the host's, so they classify alike.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MemoryFilesystem {
+    /// Files by absolute path; `None` marks a directory
+    entries: std::rc::Rc<std::cell::RefCell<std::collections::BTreeMap<String, Option<Vec<u8>>>>>,
+}
+
+#[cfg(test)]
+impl MemoryFilesystem {
+    /// A tree holding only the directory `root` and its ancestors.
+    pub fn new(root: &str) -> Self {
+        let fs = MemoryFilesystem { entries: Default::default() };
+        let mut dir = String::new();
+        for segment in root.split('/').filter(|s| !s.is_empty()) {
+            dir = format!("{}/{}", dir, segment);
+            fs.entries.borrow_mut().insert(dir.clone(), None);
+        }
+        fs
+    }
+
+    /// Add a file, creating the directories above it.
+    pub fn with_file(self, path: &str, content: impl AsRef<[u8]>) -> Self {
+        let mut entries = self.entries.borrow_mut();
+        let mut parent = path;
+        while let Some((dir, _)) = parent.rsplit_once('/').filter(|(dir, _)| !dir.is_empty()) {
+            entries.entry(dir.to_string()).or_insert(None);
+            parent = dir;
+        }
+        entries.insert(path.to_string(), Some(content.as_ref().to_vec()));
+        drop(entries);
+        self
+    }
+
+    /// Every entry under `root`, relative to it, directories ending in `/`.
+    pub fn tree(&self, root: &str) -> Vec<(String, Option<Vec<u8>>)> {
+        let prefix = format!("{}/", root.trim_end_matches('/'));
+        self.entries
+            .borrow()
+            .iter()
+            .filter_map(|(path, content)| {
+                let relative = path.strip_prefix(&prefix)?;
+                match content {
+                    Some(_) => Some((relative.to_string(), content.clone())),
+                    None => Some((format!("{}/", relative), None)),
+                }
+            })
+            .collect()
+    }
+
+    fn parent_is_dir(&self, path: &str) -> bool {
+        match path.rsplit_once('/') {
+            Some(("", _)) | None => true,
+            Some((dir, _)) => matches!(self.entries.borrow().get(dir), Some(None)),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Filesystem for MemoryFilesystem {
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, String> {
+        match self.entries.borrow().get(path) {
+            Some(Some(content)) => Ok(content.clone()),
+            Some(None) => Err(format!("Is a directory: {}", path)),
+            None => Err(format!("No such file or directory: {}", path)),
+        }
+    }
+
+    fn write_file(&self, path: &str, content: &str) -> Result<(), String> {
+        if matches!(self.entries.borrow().get(path), Some(None)) {
+            return Err(format!("Is a directory: {}", path));
+        }
+        if !self.parent_is_dir(path) {
+            return Err(format!("No such file or directory: {}", path));
+        }
+        self.entries.borrow_mut().insert(path.to_string(), Some(content.as_bytes().to_vec()));
+        Ok(())
+    }
+
+    fn list_files(&self, path: &str) -> Result<Vec<String>, String> {
+        let entries = self.entries.borrow();
+        match entries.get(path) {
+            Some(None) => {}
+            Some(Some(_)) => return Err(format!("Not a directory: {}", path)),
+            None => return Err(format!("No such file or directory: {}", path)),
+        }
+        let prefix = format!("{}/", path);
+        Ok(entries
+            .keys()
+            .filter_map(|entry| entry.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), String> {
+        let mut entries = self.entries.borrow_mut();
+        match entries.get(path) {
+            Some(Some(_)) => {
+                entries.remove(path);
+                Ok(())
+            }
+            Some(None) => Err(format!("Is a directory: {}", path)),
+            None => Err(format!("No such file or directory: {}", path)),
+        }
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), String> {
+        if self.entries.borrow().contains_key(path) {
+            return Err(format!("File exists: {}", path));
+        }
+        if !self.parent_is_dir(path) {
+            return Err(format!("No such file or directory: {}", path));
+        }
+        self.entries.borrow_mut().insert(path.to_string(), None);
+        Ok(())
+    }
+
+    fn delete_dir(&self, path: &str) -> Result<(), String> {
+        let mut entries = self.entries.borrow_mut();
+        match entries.get(path) {
+            Some(None) => {
+                let prefix = format!("{}/", path);
+                entries.retain(|entry, _| entry != path && !entry.starts_with(&prefix));
+                Ok(())
+            }
+            Some(Some(_)) => Err(format!("Not a directory: {}", path)),
+            None => Err(format!("No such file or directory: {}", path)),
+        }
+    }
+
+    fn path_exists(&self, path: &str) -> Result<bool, String> {
+        Ok(self.entries.borrow().contains_key(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_filesystem_behaves_like_a_tree() {
+        let fs = MemoryFilesystem::new("/work").with_file("/work/src/lib.rs", "fn main() {}");
+        assert_eq!(fs.list_files("/work").unwrap(), ["src"]);
+        assert!(fs.list_files("/work/src/lib.rs").unwrap_err().starts_with("Not a directory"));
+        assert!(fs.write_file("/work/missing/a.txt", "x").unwrap_err().starts_with("No such file"));
+        assert!(fs.read_file("/work/src").unwrap_err().starts_with("Is a directory"));
+
+        fs.create_dir("/work/out").unwrap();
+        fs.write_file("/work/out/a.txt", "a").unwrap();
+        assert!(fs.create_dir("/work/out").is_err());
+        fs.delete_dir("/work/out").unwrap();
+        assert!(!fs.path_exists("/work/out/a.txt").unwrap());
+        assert_eq!(fs.clone().tree("/work").len(), 2);
+    }
+}
@@ -0,0 +1,151 @@
+//! Fuzzy anchors for `edit-file`: `old_text` found where the file has
+//! nearly the same text, for anchors quoted with a typo or a word that has
+//! since changed.
+//!
+//! Each run of whitespace counts as one space, in the anchor and in the
+//! file, and the stretch of the file closest to the anchor is found by edit
+//! distance. The confidence of a match is the share of the anchor's
+//! characters that needed no edit, so a match differing only in whitespace
+//! has confidence 1.
+
+/// Least confidence accepted when the command gives no `min_confidence`
+pub const DEFAULT_MIN_CONFIDENCE: f64 = 0.9;
+
+/// Most anchor characters times file characters compared in one search
+pub const MAX_CELLS: usize = 50_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    /// Byte range in the file, from its first to its last visible character
+    pub start: usize,
+    pub end: usize,
+    /// Characters inserted, deleted or changed to turn the match into the anchor
+    pub distance: usize,
+}
+
+/// Text with each run of whitespace turned into one space, and the byte
+/// range each character came from.
+struct Normalized {
+    chars: Vec<char>,
+    ranges: Vec<(usize, usize)>,
+}
+
+fn normalize(text: &str) -> Normalized {
+    let mut normalized = Normalized { chars: Vec::new(), ranges: Vec::new() };
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        if c.is_whitespace() {
+            if normalized.chars.last() == Some(&' ') {
+                normalized.ranges.last_mut().unwrap().1 = end;
+                continue;
+            }
+            normalized.chars.push(' ');
+        } else {
+            normalized.chars.push(c);
+        }
+        normalized.ranges.push((i, end));
+    }
+    normalized
+}
+
+/// The anchor as it is compared: whitespace runs as single spaces, none at
+/// either end.
+fn anchor(old: &str) -> Vec<char> {
+    old.split_whitespace().collect::<Vec<_>>().join(" ").chars().collect()
+}
+
+/// Characters of `old` a distance is measured against.
+pub fn anchor_len(old: &str) -> usize {
+    anchor(old).len()
+}
+
+/// The work a search for `old` in `text` takes, to hold against [`MAX_CELLS`].
+pub fn cells(text: &str, old: &str) -> usize {
+    text.len().saturating_mul(anchor_len(old))
+}
+
+/// How much of `old` a match `distance` edits away still agrees with it.
+pub fn confidence(old: &str, distance: usize) -> f64 {
+    match anchor_len(old) {
+        0 => 0.0,
+        len => 1.0 - distance.min(len) as f64 / len as f64,
+    }
+}
+
+/// The stretches of `text` closest to `old`, all at the least distance
+/// found, left to right and not overlapping. Empty when `old` has no
+/// visible characters.
+pub fn closest(text: &str, old: &str) -> Vec<Match> {
+    let pattern = anchor(old);
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    let text_norm = normalize(text);
+    let m = pattern.len();
+
+    // One column of the table per file character: the cost of matching the
+    // first i anchor characters so as to end here, and where that match
+    // starts. A match may start anywhere, so row 0 costs nothing.
+    let mut column: Vec<(usize, usize)> = (0..=m).map(|i| (i, 0)).collect();
+    let mut ends: Vec<(usize, usize, usize)> = Vec::new();
+    for (j, c) in text_norm.chars.iter().enumerate() {
+        let mut next = Vec::with_capacity(m + 1);
+        next.push((0, j + 1));
+        for i in 1..=m {
+            let (diagonal, from) = column[i - 1];
+            let mut best = (diagonal + usize::from(pattern[i - 1] != *c), from);
+            if column[i].0 + 1 < best.0 {
+                best = (column[i].0 + 1, column[i].1);
+            }
+            if next[i - 1].0 + 1 < best.0 {
+                best = (next[i - 1].0 + 1, next[i - 1].1);
+            }
+            next.push(best);
+        }
+        column = next;
+        ends.push((column[m].1, j + 1, column[m].0));
+    }
+
+    let Some(least) = ends.iter().map(|(_, _, distance)| *distance).min() else {
+        return Vec::new();
+    };
+    let mut matches: Vec<Match> = Vec::new();
+    let mut taken = 0;
+    for (mut start, mut end, distance) in ends.into_iter().filter(|(_, _, distance)| *distance == least) {
+        if start < taken || start >= end {
+            continue;
+        }
+        if text_norm.chars[start] == ' ' {
+            start += 1;
+        }
+        if end > start && text_norm.chars[end - 1] == ' ' {
+            end -= 1;
+        }
+        if start >= end {
+            continue;
+        }
+        taken = end;
+        matches.push(Match { start: text_norm.ranges[start].0, end: text_norm.ranges[end - 1].1, distance });
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_closest_stretch() {
+        let file = "fn main() {\n\tif ready {\n\t\trun();\n\t}\n}\n";
+        let found = closest(file, "if  ready {\n    go();");
+        assert_eq!(found.len(), 1);
+        assert_eq!(&file[found[0].start..found[0].end], "if ready {\n\t\trun();");
+        assert_eq!(found[0].distance, 3);
+        assert_eq!(confidence("if  ready {\n    go();", 3), 0.8125);
+
+        let exact = closest(file, "if ready {");
+        assert_eq!((exact[0].distance, exact[0].start), (0, 13));
+        assert_eq!(closest("a x b x c", "x").len(), 2);
+        assert!(closest(file, " \n").is_empty());
+    }
+}
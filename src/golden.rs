@@ -0,0 +1,1042 @@
+//! Golden tests: each case runs a message of commands against an in-memory
+//! tree and store, and compares a transcript of the results and the tree
+//! left behind with `tests/golden/<case>.txt`.
+//!
+//! After an intended change in behavior, regenerate the transcripts with
+//! `UPDATE_GOLDEN=1 cargo test golden` and review the diff.
+
+use crate::config::Config;
+use crate::delegate::{Delegate, Supervisor};
+use crate::error::FsChildError;
+use crate::fs::{Filesystem, MemoryFilesystem};
+use crate::operations::OPERATIONS;
+use crate::store::MemoryStore;
+use crate::commands::CommandResult;
+use crate::state::State;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const ROOT: &str = "/work";
+
+struct Case {
+    name: &'static str,
+    permissions: &'static [&'static str],
+    /// Files present before the commands run, relative to the root
+    files: &'static [(&'static str, &'static [u8])],
+    /// Adjusts the configuration before the commands run
+    setup: fn(&mut State),
+    /// The message holding the commands
+    message: &'static str,
+}
+
+const READ_WRITE: &[&str] = &["read", "write"];
+
+const FILES: &[(&str, &[u8])] = &[
+    ("notes.txt", b"alpha\nbeta\nalpha\n"),
+    ("src/lib.rs", b"fn main() {\n\tif ready {\n\t\tgo();\n\t}\n}\n"),
+    ("data/people.csv", b"name,role\nAda,engineer\nGrace,admiral\n"),
+    ("data/log.ndjson", b"{\"event\":\"start\"}\n"),
+    ("docs/guide.md", b"---\ntitle: Guide\n---\n# Guide\n\n## Install\n\n## Usage\n"),
+    ("Cargo.toml", b"[package]\nname = \"demo\"\nversion = \"0.1.0\"\n\n[dependencies]\nlog = \"0.4\"\n"),
+    ("logo.png", b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"),
+    ("schema.json", b"{\"type\": \"object\", \"required\": [\"name\"]}"),
+];
+
+fn no_setup(_: &mut State) {}
+
+const LICENSE: &[u8] = b"Copyright (c) The fs-child authors\n\nPermission is hereby granted, free of charge, to any person obtaining a copy\nof this software and associated documentation files (the \"Software\"), to deal\nin the Software without restriction, subject to the conditions of the MIT license.\n";
+
+fn with_stores(state: &mut State) {
+    state.store_id = Some("chat-store".to_string());
+    state.stores.insert("artifact".to_string(), "artifact-store".to_string());
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "files",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path><offset>6</offset><length>4</length></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>missing.txt</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>missing.txt</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>src</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>out.txt</path><content>hello</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>out.txt</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>nested/none/out.txt</path><content>x</content></fs-command>
+<fs-command name="default"><operation>list-files</operation><path>.</path></fs-command>
+<fs-command name="default"><operation>list-files</operation><path>src</path><recursive>true</recursive></fs-command>
+<fs-command name="default"><operation>list-files</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>create-dir</operation><path>build</path></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>out.txt</path></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>out.txt</path></fs-command>
+<fs-command name="default"><operation>read-base64</operation><path>logo.png</path></fs-command>
+<fs-command name="default"><operation>read-base64</operation><path>logo.png</path></fs-command>
+<fs-command name="default"><operation>append-ndjson</operation><path>data/log.ndjson</path><content>{"event": "tick"}</content></fs-command>
+<fs-command name="default"><operation>append-ndjson</operation><path>data/log.ndjson</path><content>{"event": "tick"}</content></fs-command>
+<fs-command name="default"><operation>file-type</operation><path>logo.png</path></fs-command>
+<fs-command name="default"><operation>file-type</operation><path>src/lib.rs</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>../outside.txt</path></fs-command>
+<fs-command name="default"><operation>rename-file</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>read-fiel</operation><path>notes.txt</path></fs-command>
+"#,
+    },
+    Case {
+        name: "edits",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>beta</old_text><new_text>gamma</new_text></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>alpha</old_text><new_text>one</new_text></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>alpha</old_text><new_text>two</new_text><occurrence>2</occurrence></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>delta</old_text><new_text>x</new_text></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text></old_text><new_text>x</new_text></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>(al)pha</old_text><new_text>${1}ways</new_text><regex>true</regex></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>(</old_text><new_text>x</new_text><regex>true</regex></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>    if ready {
+        go();
+    }</old_text><new_text>    if ready {
+        go();
+        done();
+    }</new_text><ignore_whitespace>true</ignore_whitespace></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>go();</old_text><new_text>  go();</new_text><ignore_whitespace>true</ignore_whitespace></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>logo.png</path><old_text>PNG</old_text><new_text>JPG</new_text></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>gamma</old_text></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>config.json</path><content>{"name": "demo", "tags": ["a"]}</content><format>true</format><schema>schema.json</schema></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>config.json</path><content>{"title": "demo"}</content><schema>schema.json</schema></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>config.json</path><content>{"name": </content><format>true</format></fs-command>
+"#,
+    },
+    Case {
+        name: "fuzzy",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>if redy {
+    go();</old_text><new_text>if ready {
+    go();
+    done();</new_text><fuzzy>true</fuzzy></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>if  ready {</old_text><new_text>if set {</new_text><fuzzy>true</fuzzy></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>fn mian() {</old_text><new_text>fn start() {</new_text><fuzzy>true</fuzzy></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>fn mian() {</old_text><new_text>fn start() {</new_text><fuzzy>true</fuzzy><min_confidence>0.8</min_confidence></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>x</old_text><new_text>y</new_text><fuzzy>true</fuzzy><min_confidence>high</min_confidence></fs-command>
+"#,
+    },
+    Case {
+        name: "structured",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>frontmatter-get</operation><path>docs/guide.md</path></fs-command>
+<fs-command name="default"><operation>frontmatter-set</operation><path>docs/guide.md</path><key>draft</key><value>false</value></fs-command>
+<fs-command name="default"><operation>generate-toc</operation><path>docs/guide.md</path></fs-command>
+<fs-command name="default"><operation>cargo-add-dep</operation><path>Cargo.toml</path><dependency>serde</dependency><version>1</version><features>derive</features></fs-command>
+<fs-command name="default"><operation>cargo-set-version</operation><path>Cargo.toml</path><version>0.2.0</version></fs-command>
+<fs-command name="default"><operation>cargo-add-dep</operation><path>Cargo.toml</path><dependency>bad name</dependency><version>1</version></fs-command>
+<fs-command name="default"><operation>csv-to-json</operation><path>data/people.csv</path></fs-command>
+<fs-command name="default"><operation>json-to-csv</operation><path>data/people.json</path><destination>data/copy.csv</destination></fs-command>
+<fs-command name="default"><operation>append-ndjson</operation><path>data/log.ndjson</path><content>{"event": "stop"}</content></fs-command>
+<fs-command name="default"><operation>append-ndjson</operation><path>data/log.ndjson</path><content>not json</content></fs-command>
+<fs-command name="default"><operation>manifest</operation><path>data</path></fs-command>
+<fs-command name="default"><operation>manifest</operation><path>notes.txt</path></fs-command>
+"#,
+    },
+    Case {
+        name: "store",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            with_stores(state);
+            state.backup_before_write = true;
+        },
+        message: r#"
+<fs-command name="default"><operation>push-to-store</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>fetch-from-store</operation><path>copy.txt</path><id>missing-id</id></fs-command>
+<fs-command name="default"><operation>fetch-from-store</operation><path>copy.txt</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>replaced</content></fs-command>
+<fs-command name="default"><operation>restore-from-store</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>restore-from-store</operation><path>src/lib.rs</path></fs-command>
+<fs-command name="default"><operation>export-dir</operation><path>data</path></fs-command>
+<fs-command name="default"><operation>import-dir</operation><path>data</path><id>missing-id</id></fs-command>
+<fs-command name="default"><operation>import-dir</operation><path>unpacked</path><id>bcae9717e3969972</id></fs-command>
+"#,
+    },
+    Case {
+        name: "gc_backups",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            with_stores(state);
+            state.backup_before_write = true;
+        },
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>first</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>second</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>src/lib.rs</path><content>pub fn lib() {}</content></fs-command>
+<fs-command name="default"><operation>gc-backups</operation><path>notes.txt</path><keep>2</keep></fs-command>
+<fs-command name="default"><operation>gc-backups</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>restore-from-store</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>gc-backups</operation><keep>none</keep></fs-command>
+<fs-command name="default"><operation>gc-backups</operation><keep>0</keep></fs-command>
+<fs-command name="default"><operation>restore-from-store</operation><path>src/lib.rs</path></fs-command>
+"#,
+    },
+    Case {
+        name: "session",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>subscribe</operation><path>src/**/*.rs</path></fs-command>
+<fs-command name="default"><operation>unsubscribe</operation><path>src/**/*.rs</path></fs-command>
+<fs-command name="default"><operation>unsubscribe</operation><path>docs</path></fs-command>
+<fs-command name="default"><operation>export-history</operation></fs-command>
+<fs-command name="default"><operation>help</operation><topic>edit-file</topic></fs-command>
+<fs-command name="default"><operation>help</operation><topic>rename-file</topic></fs-command>
+<fs-command name="default"><operation>help</operation><topic>subscibe</topic></fs-command>
+"#,
+    },
+    Case {
+        name: "read_only",
+        permissions: &["read"],
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>x</content></fs-command>
+<fs-command name="default"><operation>manifest</operation><path>src</path><destination>manifest.json</destination></fs-command>
+<fs-command name="default"><operation>help</operation></fs-command>
+"#,
+    },
+    Case {
+        name: "write_only",
+        permissions: &["write"],
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>x</content></fs-command>
+"#,
+    },
+    Case {
+        name: "no_permissions",
+        permissions: &[],
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>help</operation><topic>read-file</topic></fs-command>
+"#,
+    },
+    Case {
+        name: "transactional",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>changed</content><mode>transactional</mode></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>new.txt</path><content>new</content></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>absent</old_text><new_text>x</new_text></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>after.txt</path><content>never</content></fs-command>
+"#,
+    },
+    Case {
+        name: "transactional_format",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>changed</content><mode>transactional</mode></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>config.json</path><content>{"name": "demo"}</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>config.json</path><content>{"name": </content><format>true</format></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>after.txt</path><content>never</content></fs-command>
+"#,
+    },
+    Case {
+        name: "transactional_schema",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>changed</content><mode>transactional</mode></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>config.json</path><content>{"name": "demo"}</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>config.json</path><content>{"name": "demo"}</content><schema>missing.json</schema></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>after.txt</path><content>never</content></fs-command>
+"#,
+    },
+    Case {
+        name: "transactional_history",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>changed</content><mode>transactional</mode></fs-command>
+<fs-command name="default"><operation>commit</operation><message>Checkpoint</message></fs-command>
+<fs-command name="default"><operation>checkout</operation><id>0aece1</id></fs-command>
+<fs-command name="default"><operation>commit-staged</operation><message>Staged</message></fs-command>
+<fs-command name="default"><operation>compact</operation></fs-command>
+"#,
+    },
+    Case {
+        name: "macros",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>define-macro</operation><macro>new-module</macro><content>[{"operation": "create-dir", "path": "src/{{module}}"}, {"operation": "write-file", "path": "src/{{module}}/mod.rs", "content": "//! {{doc}}\n"}]</content></fs-command>
+<fs-command name="default"><operation>run-macro</operation><macro>new-module</macro><args>module=parser doc="Parses input."</args></fs-command>
+<fs-command name="default"><operation>run-macro</operation><macro>new-module</macro><args>module=lexer</args></fs-command>
+<fs-command name="default"><operation>run-macro</operation><macro>release</macro></fs-command>
+<fs-command name="default"><operation>define-macro</operation><macro>bad name</macro><content>[]</content></fs-command>
+"#,
+    },
+    Case {
+        name: "mounts",
+        permissions: &["read"],
+        files: FILES,
+        setup: |state| {
+            let mount = |path: &str, permissions: &[&str]| crate::mounts::Mount {
+                path: path.to_string(),
+                permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            };
+            state.mounts.insert("docs".to_string(), mount("/work/docs", &["read"]));
+            state.mounts.insert("scratch".to_string(), mount("/work/data", &["read", "write"]));
+        },
+        message: r#"
+<fs-command name="default"><operation>list-files</operation><path>docs:</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>docs:/guide.md</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>docs:/../notes.txt</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>docs:/guide.md</path><content>x</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>scratch:/new.txt</path><content>new</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>new.txt</path><content>x</content></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>nope:/x</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>/work/data/abs.txt</path><content>abs</content></fs-command>
+"#,
+    },
+    Case {
+        name: "mounts_resolved",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            state.base_path = "/work/src".to_string();
+            let docs = crate::mounts::Mount { path: "/work/docs".to_string(), permissions: vec!["read".to_string()] };
+            state.mounts.insert("docs".to_string(), docs);
+        },
+        message: r#"
+<fs-command name="default"><operation>read-file</operation><path>../docs/guide.md</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>../docs/guide.md</path><content>x</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>/work/docs/guide.md</path><content>x</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>../notes.txt</path><content>x</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>new.rs</path><content>x</content></fs-command>
+"#,
+    },
+    Case {
+        name: "vcs",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>commit</operation><message>Initial</message></fs-command>
+<fs-command name="default"><operation>commit</operation><message>Again</message></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>rewritten</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>src/new.rs</path><content>new</content></fs-command>
+<fs-command name="default"><operation>commit</operation><message>Rewrite notes</message></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>src/lib.rs</path></fs-command>
+<fs-command name="default"><operation>checkout</operation><id>0aece1</id></fs-command>
+<fs-command name="default"><operation>checkout</operation><id>0aece1</id><force>true</force></fs-command>
+<fs-command name="default"><operation>checkout</operation><id>ffff</id></fs-command>
+"#,
+    },
+    Case {
+        name: "policy",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            state.policy = serde_json::from_str(
+                r#"[
+                    {"match": "src/**/*.rs", "allow": ["read", "edit"], "max_bytes": 16},
+                    {"match": "**/*.toml", "deny": ["write"]}
+                ]"#,
+            )
+            .unwrap();
+        },
+        message: r#"
+<fs-command name="default"><operation>read-file</operation><path>src/lib.rs</path></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>go();</old_text><new_text>run();</new_text></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>src/lib.rs</path><content>x</content></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>go();</old_text><new_text>run_everything_now();</new_text></fs-command>
+<fs-command name="default"><operation>cargo-set-version</operation><path>Cargo.toml</path><version>0.2.0</version></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>free</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>/work/src/lib.rs</path><content>x</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>src/x/../lib.rs</path><content>x</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>/etc/passwd</path><content>x</content></fs-command>
+<fs-command name="default"><operation>why-denied</operation><topic>write-file</topic><path>src/lib.rs</path></fs-command>
+<fs-command name="default"><operation>why-denied</operation><topic>write-file</topic><path>Cargo.toml</path></fs-command>
+<fs-command name="default"><operation>why-denied</operation><topic>read-file</topic><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>why-denied</operation><topic>remove</topic><path>notes.txt</path></fs-command>
+"#,
+    },
+    Case {
+        name: "policy_history",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            // A commit from before the locked file was added
+            let commit = r#"<fs-command name="default"><operation>commit</operation><message>Before</message></fs-command>"#;
+            state.process_fs_commands("head-0", crate::parser::commands(commit, "default"));
+            state.fs.create_dir(&format!("{}/locked", ROOT)).unwrap();
+            state.fs.write_file(&format!("{}/locked/keep.txt", ROOT), "keep").unwrap();
+            state.policy = serde_json::from_str(r#"[{"match": "locked/**", "deny": ["write"]}]"#).unwrap();
+        },
+        message: r#"
+<fs-command name="default"><operation>delete-file</operation><path>locked/keep.txt</path></fs-command>
+<fs-command name="default"><operation>checkout</operation><id>0392b9</id><force>true</force></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>locked/keep.txt</path></fs-command>
+"#,
+    },
+    Case {
+        name: "quotas",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            state.quotas = serde_json::from_str(
+                r#"{
+                    "src": {"max_bytes": 80},
+                    "docs": {"max_files": 1, "limit": "soft"}
+                }"#,
+            )
+            .unwrap();
+        },
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>src/util.rs</path><content>pub fn util() {}
+</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>src/big.rs</path><content>// far more than the forty or so bytes the quota has left
+</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>/work/src/big.rs</path><content>// far more than the forty or so bytes the quota has left
+</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>docs/../src/big.rs</path><content>// far more than the forty or so bytes the quota has left
+</content></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>go();</old_text><new_text>go(); go(); go(); go(); go(); go(); go(); go();</new_text></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>src/util.rs</path></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>go();</old_text><new_text>go(); go(); go(); go(); go(); go(); go(); go();</new_text></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>docs/extra.md</path><content>more</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>no quota here</content></fs-command>
+"#,
+    },
+    Case {
+        name: "quotas_unmeasured",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            state.quotas = serde_json::from_str(r#"{"data": {"max_files": 50}}"#).unwrap();
+            // Too few to reach every file under the quota
+            state.max_walk_entries = 1;
+        },
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>data/new.csv</path><content>a,b</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>no quota here</content></fs-command>
+"#,
+    },
+    Case {
+        name: "quotas_checkout",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            let commit = r#"<fs-command name="default"><operation>commit</operation><message>Before</message></fs-command>"#;
+            state.process_fs_commands("head-0", crate::parser::commands(commit, "default"));
+            state.fs.delete_file(&format!("{}/src/lib.rs", ROOT)).unwrap();
+            state.quotas = serde_json::from_str(r#"{"src": {"max_bytes": 10}}"#).unwrap();
+        },
+        message: r#"
+<fs-command name="default"><operation>checkout</operation><id>0392b9</id><force>true</force></fs-command>
+<fs-command name="default"><operation>list-files</operation><path>src</path></fs-command>
+"#,
+    },
+    Case {
+        name: "staging",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>stage</operation></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>src/lib.rs</path><old_text>go();</old_text><new_text>run();</new_text></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>data/log.ndjson</path></fs-command>
+<fs-command name="default"><operation>create-dir</operation><path>out</path></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>out/new.txt</path><content>hello</content></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>src/lib.rs</path></fs-command>
+<fs-command name="default"><operation>list-files</operation><path>data</path></fs-command>
+<fs-command name="default"><operation>preview-staged</operation></fs-command>
+<fs-command name="default"><operation>commit-staged</operation></fs-command>
+<fs-command name="default"><operation>preview-staged</operation></fs-command>
+<fs-command name="default"><operation>stage</operation></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>draft</content></fs-command>
+<fs-command name="default"><operation>discard-staged</operation></fs-command>
+<fs-command name="default"><operation>commit-staged</operation></fs-command>
+"#,
+    },
+    Case {
+        name: "compact",
+        permissions: READ_WRITE,
+        files: &[
+            ("build/a/LICENSE", LICENSE),
+            ("build/b/LICENSE", LICENSE),
+            ("build/c/LICENSE", LICENSE),
+            ("build/a/mod.rs", b"pub mod a;\n"),
+            ("build/b/mod.rs", b"pub mod a;\n"),
+        ],
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>compact</operation><path>build</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>build/b/LICENSE</path></fs-command>
+<fs-command name="default"><operation>manifest</operation><path>build</path></fs-command>
+<fs-command name="default"><operation>compact</operation><path>build/a</path></fs-command>
+<fs-command name="default"><operation>compact</operation><path>build/a/mod.rs</path></fs-command>
+"#,
+    },
+    Case {
+        name: "around",
+        permissions: &["read"],
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>read-around</operation><path>notes.txt</path><pattern>alpha</pattern><lines>0</lines></fs-command>
+<fs-command name="default"><operation>read-around</operation><path>src/lib.rs</path><pattern>go();</pattern><lines>1</lines></fs-command>
+<fs-command name="default"><operation>read-around</operation><path>docs/guide.md</path><pattern>^## \w+</pattern><regex>true</regex></fs-command>
+<fs-command name="default"><operation>read-around</operation><path>notes.txt</path><pattern>gamma</pattern></fs-command>
+<fs-command name="default"><operation>read-around</operation><path>notes.txt</path><pattern>alpha</pattern><lines>many</lines></fs-command>
+<fs-command name="default"><operation>read-around</operation><path>src</path><pattern>fn</pattern></fs-command>
+"#,
+    },
+    Case {
+        name: "overwrite",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>replaced</content><overwrite>false</overwrite></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>new.txt</path><content>fresh</content><overwrite>false</overwrite></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>missing.txt</path><content>nope</content><create>false</create></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>new.txt</path><content>updated</content><create>false</create></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>other.txt</path><content>x</content><create>false</create><overwrite>false</overwrite></fs-command>
+"#,
+    },
+    Case {
+        name: "truncation",
+        permissions: READ_WRITE,
+        files: &[("LICENSE", LICENSE), ("notes.txt", b"alpha\nbeta\nalpha\n")],
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>LICENSE</path><content>MIT
+</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>x</content></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>LICENSE</path><content>MIT
+</content><confirm_truncation>true</confirm_truncation></fs-command>
+"#,
+    },
+    Case {
+        name: "dry_run",
+        permissions: READ_WRITE,
+        files: &[("LICENSE", LICENSE), ("notes.txt", b"alpha\nbeta\nalpha\n")],
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>alpha
+gamma
+alpha
+omega
+</content><dry_run>true</dry_run></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>new.txt</path><content>fresh
+</content><dry_run>true</dry_run></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>alpha
+beta
+alpha
+</content><dry_run>true</dry_run></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>LICENSE</path><content>MIT
+</content><dry_run>true</dry_run></fs-command>
+"#,
+    },
+    Case {
+        name: "unchanged",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path><if_changed_since>e95e9bf120a98ef0</if_changed_since></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path><if_changed_since>E95E9BF1</if_changed_since><length>5</length></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path><if_changed_since>e95e</if_changed_since></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>beta</old_text><new_text>gamma</new_text></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path><if_changed_since>e95e9bf120a98ef0</if_changed_since></fs-command>
+"#,
+    },
+    Case {
+        name: "continuation",
+        permissions: &["read"],
+        files: FILES,
+        setup: |state| state.max_walk_entries = 4,
+        message: r#"
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path><length>6</length></fs-command>
+<fs-command name="default"><operation>continue</operation><token>c1</token></fs-command>
+<fs-command name="default"><operation>continue</operation><token>c2</token></fs-command>
+<fs-command name="default"><operation>continue</operation><token>c1</token></fs-command>
+<fs-command name="default"><operation>list-files</operation><path>.</path><recursive>true</recursive></fs-command>
+<fs-command name="default"><operation>continue</operation><token>c3</token></fs-command>
+"#,
+    },
+    Case {
+        name: "queue",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>write-file</operation><path>scratch.txt</path><content>draft</content></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>scratch.txt</path><defer>true</defer></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>beta</old_text><new_text>gamma</new_text><defer>3</defer></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>notes.txt</path><defer>soon</defer></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path><defer>false</defer></fs-command>
+<fs-command name="default"><operation>queue</operation></fs-command>
+"#,
+    },
+    Case {
+        name: "templates",
+        permissions: READ_WRITE,
+        files: &[
+            ("src/lib.rs", b"mod lexer;\n"),
+            ("templates/header.j2", b"// Generated for {{ name }}\n"),
+            (
+                "templates/module.rs.j2",
+                b"{% include 'templates/header.j2' %}\n{% for item in items %}\npub struct {{ item }};\n{% endfor %}\n{% if tests %}\n\n#[cfg(test)]\nmod tests {}\n{% endif %}\n",
+            ),
+            ("templates/broken.j2", b"{% for item in items %}\n{{ itme }}\n{% endfor %}\n"),
+            ("templates/lost.j2", b"{% include 'templates/nope.j2' %}\n"),
+        ],
+        setup: no_setup,
+        message: r#"
+<fs-command name="default"><operation>render</operation><path>templates/module.rs.j2</path><context>{"name": "parser", "items": ["Token", "Lexer"]}</context></fs-command>
+<fs-command name="default"><operation>render</operation><path>templates/module.rs.j2</path><context>{"name": "lexer", "items": [], "tests": true}</context><destination>src/lexer.rs</destination></fs-command>
+<fs-command name="default"><operation>render</operation><path>templates/broken.j2</path><context>{"items": [1]}</context></fs-command>
+<fs-command name="default"><operation>render</operation><path>templates/lost.j2</path></fs-command>
+<fs-command name="default"><operation>render</operation><path>templates/module.rs.j2</path><context>["not", "an", "object"]</context></fs-command>
+"#,
+    },
+    Case {
+        name: "limits",
+        permissions: READ_WRITE,
+        files: FILES,
+        setup: |state| {
+            with_stores(state);
+            state.max_inline_bytes = 8;
+            state.host = crate::host::Host::with_missing(["store"]).0;
+        },
+        message: r#"
+<fs-command name="default"><operation>read-base64</operation><path>logo.png</path></fs-command>
+<fs-command name="default"><operation>push-to-store</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>export-dir</operation><path>data</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path></fs-command>
+"#,
+    },
+];
+
+fn state_for(case: &Case, fs: &MemoryFilesystem) -> State {
+    let mut state = State {
+        base_path: ROOT.to_string(),
+        permissions: case.permissions.iter().map(|p| p.to_string()).collect(),
+        render_html: false,
+        fs: Rc::new(fs.clone()),
+        store_client: Box::new(MemoryStore::default()),
+        ..State::default()
+    };
+    (case.setup)(&mut state);
+    state
+}
+
+/// The transcript a case is compared by: every result, then every entry
+/// left in the tree.
+fn run(case: &Case) -> String {
+    let (fs, mut state) = fixture(case);
+    let mut out = run_message(&mut state, "head-1", case.message);
+    out.push_str(&tree(&fs));
+    out
+}
+
+/// A case for a test that drives the state itself: only its permissions
+/// and the files it starts from.
+fn bare_case(permissions: &'static [&'static str], files: &'static [(&'static str, &'static [u8])]) -> Case {
+    Case { name: "", permissions, files, setup: no_setup, message: "" }
+}
+
+/// A tree holding the case's files, and a state set up for the case over it.
+fn fixture(case: &Case) -> (MemoryFilesystem, State) {
+    let mut fs = MemoryFilesystem::new(ROOT);
+    for (path, content) in case.files {
+        fs = fs.with_file(&format!("{}/{}", ROOT, path), content);
+    }
+    let state = state_for(case, &fs);
+    (fs, state)
+}
+
+/// Runs the commands in `message` as those of head-update `head`, and
+/// describes every result.
+fn run_message(state: &mut State, head: &str, message: &str) -> String {
+    state
+        .process_fs_commands(head, crate::parser::commands(message, "default"))
+        .iter()
+        .map(describe)
+        .collect()
+}
+
+fn tree(fs: &MemoryFilesystem) -> String {
+    let mut out = "== tree ==\n".to_string();
+    for (path, content) in fs.tree(ROOT) {
+        match content {
+            None => out.push_str(&format!("{}\n", path)),
+            Some(bytes) => match String::from_utf8(bytes) {
+                Ok(text) => out.push_str(&format!("{} {:?}\n", path, text)),
+                Err(e) => out.push_str(&format!("{} <{} bytes>\n", path, e.as_bytes().len())),
+            },
+        }
+    }
+    out
+}
+
+fn describe(result: &CommandResult) -> String {
+    let status = match (&result.error, result.skipped) {
+        (Some(code), _) => format!("{:?}", code),
+        (None, Some(reason)) => format!("skipped ({})", reason),
+        (None, None) => "ok".to_string(),
+    };
+    let mut out = format!("[{}] {} {} -> {}\n", result.index, result.operation, result.path, status);
+    for line in result.message.lines() {
+        if !line.is_empty() {
+            out.push_str("    ");
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    if let Some(data) = &result.data {
+        out.push_str(&format!("    data: {}\n", data));
+    }
+    out
+}
+
+/// Runs delegates as instances in this process, on the same tree.
+#[derive(Debug)]
+struct LocalSupervisor {
+    fs: MemoryFilesystem,
+    children: RefCell<Vec<State>>,
+}
+
+impl Supervisor for LocalSupervisor {
+    fn spawn(&self, _manifest: &str, init: &[u8]) -> Result<String, FsChildError> {
+        let config = Config::parse(Some(init)).map_err(FsChildError::Delegate)?;
+        let mut child = State::new(config).map_err(FsChildError::Delegate)?;
+        child.fs = Rc::new(self.fs.clone());
+        child.render_html = false;
+        let mut children = self.children.borrow_mut();
+        children.push(child);
+        Ok(format!("child-{}", children.len()))
+    }
+
+    fn request(&self, actor_id: &str, msg: &[u8]) -> Result<Vec<u8>, FsChildError> {
+        let index = actor_id
+            .strip_prefix("child-")
+            .and_then(|n| n.parse::<usize>().ok())
+            .and_then(|n| n.checked_sub(1))
+            .filter(|index| *index < self.children.borrow().len())
+            .ok_or_else(|| FsChildError::Delegate(format!("Request to delegate '{}' failed: no such actor", actor_id)))?;
+        let request: Value = serde_json::from_slice(msg)?;
+        let reply = self.children.borrow_mut()[index].dispatch(&request);
+        Ok(serde_json::to_vec(&reply)?)
+    }
+}
+
+/// Commands for delegates go to a child rooted at the delegate's directory,
+/// spawned the first time, and only when they stay inside it.
+#[test]
+#[cfg_attr(not(feature = "supervisor"), ignore = "delegates are spawned through the supervisor interface")]
+fn delegates_run_their_commands() {
+    let (fs, mut state) = fixture(&bare_case(&["read"], FILES));
+    state.supervisor = Box::new(LocalSupervisor { fs: fs.clone(), children: RefCell::new(Vec::new()) });
+    let delegate = |path: &str, manifest: Option<&str>, actor_id: Option<&str>| Delegate {
+        path: path.to_string(),
+        permissions: vec!["read".to_string(), "write".to_string()],
+        manifest: manifest.map(str::to_string),
+        actor_id: actor_id.map(str::to_string),
+    };
+    state.delegates.insert("src-writer".to_string(), delegate("src", Some("fs-child.toml"), None));
+    state.delegates.insert("data-writer".to_string(), delegate("data", Some("fs-child.toml"), None));
+    state.delegates.insert("docs-writer".to_string(), delegate("docs", None, Some("child-9")));
+
+    let message = r#"
+<fs-command name="default"><operation>write-file</operation><path>src/x.rs</path><content>no</content></fs-command>
+<fs-command name="src-writer"><operation>write-file</operation><path>parser.rs</path><content>//! Parser</content></fs-command>
+<fs-command name="src-writer"><operation>read-file</operation><path>lib.rs</path></fs-command>
+<fs-command name="data-writer"><operation>write-file</operation><path>../notes.txt</path><content>escaped</content></fs-command>
+<fs-command name="docs-writer"><operation>read-file</operation><path>guide.md</path></fs-command>
+"#;
+    let delegated = state.delegated_commands(message);
+    let mut out = String::new();
+    for (name, reply) in state.run_delegated("head-1", delegated) {
+        out.push_str(&format!("== {} ==\n", name));
+        match reply {
+            Ok(reply) => out.push_str(&format!("{}\n", reply.text)),
+            Err(e) => out.push_str(&format!("{:?}: {}\n", e.code(), e)),
+        }
+    }
+    out.push_str(&format!("spawned: {:?}\n", state.delegates["src-writer"].actor_id));
+    out.push_str(&tree(&fs));
+    assert!(matches("delegates.txt", &out));
+}
+
+/// Deferred commands run on the head-update they come due, after that
+/// message's own commands, and not before.
+#[test]
+fn deferred_commands_run_when_due() {
+    let (fs, mut state) = fixture(&bare_case(READ_WRITE, &[("notes.txt", b"alpha\n")]));
+    let message = r#"
+<fs-command name="default"><operation>write-file</operation><path>scratch.txt</path><content>draft</content></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>scratch.txt</path><defer>1</defer></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>alpha</old_text><new_text>beta</new_text><defer>2</defer></fs-command>
+"#;
+    state.turn = 1;
+    let mut out = run_message(&mut state, "head-1", message);
+    for turn in 2..=4 {
+        state.turn = turn;
+        let due = crate::queue::take_due(&mut state.deferred, turn);
+        let mut response = crate::state::ChildMessage {
+            child_id: "child".to_string(),
+            text: String::new(),
+            html: None,
+            parent_id: None,
+            data: serde_json::json!({}),
+        };
+        state.run_deferred(&format!("head-{}", turn), due, &mut response);
+        out.push_str(&format!("== turn {} ==\n{}\ntasks: {}\n", turn, response.text, response.data["deferred"]["tasks"]));
+    }
+    out.push_str(&tree(&fs));
+    assert!(matches("deferred.txt", &out));
+}
+
+/// Two actors sharing a tree: a write over a file the other changed since
+/// this one last saw it is refused until it is read again or forced.
+#[test]
+fn edits_by_others_conflict() {
+    let case = bare_case(READ_WRITE, &[("notes.txt", b"alpha\n"), ("todo.txt", b"one\n")]);
+    let (fs, first) = fixture(&case);
+    let mut actors = [first, state_for(&case, &fs)];
+    let turns = [
+        (0, r#"
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>todo.txt</path></fs-command>
+"#),
+        (1, r#"
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>alpha</old_text><new_text>beta</new_text></fs-command>
+<fs-command name="default"><operation>delete-file</operation><path>todo.txt</path></fs-command>
+"#),
+        (0, r#"
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>beta</old_text><new_text>gamma</new_text></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>todo.txt</path><content>two</content></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>beta</old_text><new_text>gamma</new_text></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>todo.txt</path><content>two</content><force>true</force></fs-command>
+"#),
+        (1, r#"
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>mine</content></fs-command>
+"#),
+    ];
+    let mut out = String::new();
+    for (turn, (actor, message)) in turns.into_iter().enumerate() {
+        out.push_str(&format!("== turn {}, actor {} ==\n", turn + 1, actor + 1));
+        out.push_str(&run_message(&mut actors[actor], "head", message));
+    }
+    out.push_str(&tree(&fs));
+    assert!(matches("conflicts.txt", &out));
+}
+
+/// With `batch_reports`, the response is a summary and the report holds
+/// every result in full.
+#[test]
+fn batch_reports() {
+    let (fs, mut state) = fixture(&bare_case(READ_WRITE, FILES));
+    state.batch_reports = true;
+    state.child_id = Some("child".to_string());
+    let message = r#"
+<fs-command name="default"><operation>read-file</operation><path>src/lib.rs</path></fs-command>
+<fs-command name="default"><operation>edit-file</operation><path>notes.txt</path><old_text>gamma</old_text><new_text>delta</new_text></fs-command>
+<fs-command name="default"><operation>list-files</operation><path>data</path></fs-command>
+"#;
+    let batch = crate::state::AsyncBatch { head: "head/7".to_string(), commands: crate::parser::commands(message, "default") };
+    let response = state.run_batch(batch);
+    let report = fs.read_file(&format!("{}/.fs-child/reports/head_7.md", ROOT)).unwrap();
+    let out = format!(
+        "{}\nreport: {}\n== report ==\n{}",
+        response.text,
+        response.data["report"],
+        String::from_utf8(report).unwrap()
+    );
+    assert!(matches("reports.txt", &out));
+}
+
+/// With `response_budget_bytes`, a long read is cut to its share while the
+/// short results around it stay whole.
+#[test]
+fn response_budget() {
+    let long: String = (1..=80).map(|i| format!("line {}\n", i)).collect();
+    let (fs, mut state) = fixture(&bare_case(READ_WRITE, FILES));
+    fs.with_file(&format!("{}/long.txt", ROOT), &long);
+    state.response_budget_bytes = Some(600);
+    state.child_id = Some("child".to_string());
+    let message = r#"
+<fs-command name="default"><operation>read-file</operation><path>long.txt</path></fs-command>
+<fs-command name="default"><operation>list-files</operation><path>data</path></fs-command>
+<fs-command name="default"><operation>read-file</operation><path>notes.txt</path></fs-command>
+"#;
+    let batch = crate::state::AsyncBatch { head: "head/8".to_string(), commands: crate::parser::commands(message, "default") };
+    let response = state.run_batch(batch);
+    assert!(response.text.len() < 800);
+    let out = format!("{}\nbudget: {}", response.text, response.data["budget"]);
+    assert!(matches("budget.txt", &out));
+}
+
+/// The overview sent with the introduction, over a tree with a nested
+/// project and build output that is not counted, and the project whose
+/// paths the examples use.
+#[test]
+fn workspace_overview() {
+    let (fs, state) = fixture(&bare_case(&["read"], FILES));
+    // The state shares the tree, so it sees files added after it was made
+    fs.with_file(&format!("{}/web/package.json", ROOT), br#"{"name": "demo-web", "private": true}"#)
+        .with_file(&format!("{}/web/src/index.js", ROOT), b"export {};\n")
+        .with_file(&format!("{}/target/debug/demo", ROOT), b"\x7fELF");
+    let overview = state.workspace_overview();
+    let mut out = format!("{}\n{}\n", overview.text(), serde_json::to_string_pretty(&overview).unwrap());
+    let (project, paths) = state.detected_project().unwrap();
+    out.push_str(&format!("== detected project ==\n{}\n{}\n", json!(project), json!(paths)));
+    assert!(matches("overview.txt", &out));
+}
+
+/// Whether `actual` matches the golden file `tests/golden/<file>`, printing
+/// both when it does not. With `UPDATE_GOLDEN` set the file is rewritten
+/// instead.
+pub fn matches(file: &str, actual: &str) -> bool {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(file);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return true;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_default();
+    if actual != expected {
+        eprintln!("--- {} (expected)\n{}\n+++ {} (actual)\n{}", path.display(), expected, file, actual);
+        return false;
+    }
+    true
+}
+
+#[test]
+#[cfg_attr(
+    not(all(feature = "store", feature = "supervisor", feature = "archive", feature = "regex", feature = "structured", feature = "templates")),
+    ignore = "the transcripts are of a build with every feature"
+)]
+fn transcripts_match() {
+    let mismatched: Vec<&str> = CASES
+        .iter()
+        .filter(|case| !matches(&format!("{}.txt", case.name), &run(case)))
+        .map(|case| case.name)
+        .collect();
+    assert!(mismatched.is_empty(), "golden transcripts differ: {:?}; rerun with UPDATE_GOLDEN=1 if intended", mismatched);
+}
+
+#[test]
+fn every_operation_has_a_case() {
+    let messages: Vec<&str> = CASES.iter().map(|case| case.message).collect();
+    for spec in OPERATIONS {
+        let tag = format!("<operation>{}</operation>", spec.name);
+        assert!(messages.iter().any(|m| m.contains(&tag)), "no golden case runs '{}'", spec.name);
+    }
+}
+
+/// Every operation under each combination of permissions: allowed, or the
+/// error `check_command` gives.
+#[test]
+fn permission_matrix() {
+    let combinations: [&[&str]; 4] = [&[], &["read"], &["write"], READ_WRITE];
+    let mut out = String::new();
+    for spec in OPERATIONS {
+        let mut row = format!("{:<20}", spec.name);
+        for permissions in combinations {
+            let (_, state) = fixture(&bare_case(permissions, &[]));
+            let message = format!(
+                "<fs-command name=\"default\"><operation>{}</operation><path>x</path></fs-command>",
+                spec.name
+            );
+            let commands = crate::parser::commands(&message, "default");
+            let cell = match state.check_command(&commands[0]) {
+                Err(e) if e.code() == crate::error::ErrorCode::PermissionDenied => "denied",
+                _ => "allowed",
+            };
+            row.push_str(&format!(" {:<8}", cell));
+        }
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+
+    let actual = format!("{:<20} {:<8} {:<8} {:<8} read+write\n{}", "operation", "none", "read", "write", out);
+    assert!(matches("permissions.txt", &actual));
+}
+
+#[test]
+#[cfg_attr(not(feature = "store"), ignore = "backups are taken and released through the store")]
+fn shutdown_releases_backups() {
+    let case = CASES.iter().find(|case| case.name == "store").unwrap();
+    let (_, mut state) = fixture(case);
+    let message = r#"<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>replaced</content></fs-command>"#;
+    run_message(&mut state, "head-1", message);
+    assert_eq!(state.backups.len(), 1);
+
+    let response = state.dispatch(&json!({"msg_type": "shutdown", "data": {"head": "head-1"}}));
+    assert!(state.backups.is_empty());
+    assert_eq!(response.data["backups"]["released"][0]["path"], "notes.txt");
+    assert!(response.text.starts_with("Shutting down; released 1 backup(s)"));
+
+    let response = state.dispatch(&json!({"msg_type": "shutdown"}));
+    assert_eq!(response.text, "Shutting down; no backups to release");
+}
+
+#[test]
+#[cfg_attr(not(feature = "store"), ignore = "backups are taken and released through the store")]
+fn shutdown_releases_cold_backups() {
+    let case = CASES.iter().find(|case| case.name == "store").unwrap();
+    let (_, mut state) = fixture(case);
+    state.cold_state_dir = Some(".fs-child".to_string());
+    let message = r#"<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>replaced</content></fs-command>"#;
+    run_message(&mut state, "head-1", message);
+    // As at the end of every message: the backups move out of the state blob
+    state.save_cold_state();
+    assert!(state.backups.is_empty());
+
+    let response = state.dispatch(&json!({"msg_type": "shutdown", "data": {"head": "head-1"}}));
+    assert_eq!(response.data["backups"]["released"][0]["path"], "notes.txt");
+}
+
+#[test]
+fn cancel_discards_staged_changes() {
+    let case = CASES.iter().find(|case| case.name == "staging").unwrap();
+    let (fs, mut state) = fixture(case);
+    let message = r#"<fs-command name="default"><operation>stage</operation></fs-command>
+<fs-command name="default"><operation>write-file</operation><path>notes.txt</path><content>staged</content></fs-command>"#;
+    run_message(&mut state, "head-1", message);
+    assert!(state.staging.is_some());
+
+    let response = state.dispatch(&json!({"msg_type": "cancel", "data": {"head": "head-1"}}));
+    assert!(state.staging.is_none());
+    assert_eq!(response.data["cancelled"][0]["kind"], "staged-changes");
+    assert_eq!(response.data["cancelled"][0]["paths"]["written"][0], "notes.txt");
+    assert_eq!(fs.read_file(&format!("{}/notes.txt", ROOT)).unwrap(), b"alpha\nbeta\nalpha\n");
+}
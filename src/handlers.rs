@@ -0,0 +1,426 @@
+//! The handler of every operation, by name.
+//!
+//! An operation is declared by its [`OperationSpec`](crate::operations::OperationSpec),
+//! which gives its permission and fields, and run by the handler registered
+//! here under the same name. `process_fs_commands` checks a command against
+//! the spec, resolves its paths and calls the handler; adding an operation
+//! means adding both, and a test checks that they pair up.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::error::FsChildError;
+use crate::history::{self, HistoryEntry};
+use crate::operations::operation_spec;
+use crate::{backup, edit, filetype, macros, path, watch};
+use crate::commands::{flag, CommandResult, FsCommand};
+use crate::state::State;
+
+/// A command ready to run: its paths resolved, and the schema its content
+/// must match.
+#[derive(Clone, Copy)]
+pub struct Exec<'a> {
+    pub cmd: &'a FsCommand,
+    /// `path` on the host
+    pub path: &'a str,
+    /// The file the command writes, relative and on the host; see
+    /// [`FsCommand::target`]
+    pub target: &'a str,
+    pub target_path: &'a str,
+    pub schema: Option<&'a Value>,
+}
+
+pub type Handler = fn(&mut State, &Exec) -> CommandResult;
+
+/// Every operation's handler. Operations left out of the build have none.
+pub const HANDLERS: &[(&str, Handler)] = &[
+    ("read-file", read_file),
+    ("read-around", |state, x| crate::commands::read_around(&*state.fs, x.cmd, x.path)),
+    ("write-file", write_file),
+    ("edit-file", edit_file),
+    ("list-files", list_files),
+    ("create-dir", create_dir),
+    ("delete-file", delete_file),
+    ("fetch-from-store", fetch_from_store),
+    ("push-to-store", push_to_store),
+    ("read-base64", read_base64),
+    #[cfg(feature = "structured")]
+    ("frontmatter-get", |state, x| crate::commands::frontmatter_get(&*state.fs, x.cmd, x.path)),
+    #[cfg(feature = "structured")]
+    ("frontmatter-set", |state, x| crate::commands::frontmatter_set(&*state.fs, x.cmd, x.path)),
+    #[cfg(feature = "structured")]
+    ("cargo-add-dep", |state, x| crate::commands::cargo_add_dep(&*state.fs, x.cmd, x.path)),
+    #[cfg(feature = "structured")]
+    ("cargo-set-version", |state, x| crate::commands::cargo_set_version(&*state.fs, x.cmd, x.path)),
+    ("generate-toc", |state, x| crate::commands::generate_toc(&*state.fs, x.cmd, x.path)),
+    ("csv-to-json", convert),
+    ("json-to-csv", convert),
+    ("append-ndjson", |state, x| crate::commands::append_ndjson(&*state.fs, x.cmd, x.path)),
+    ("manifest", |state, x| state.manifest(x.cmd, x.target, x.target_path)),
+    ("compact", |state, x| state.compact(x.cmd)),
+    #[cfg(feature = "templates")]
+    ("render", |state, x| state.render_template(x.cmd, x.path, x.target, x.target_path)),
+    #[cfg(feature = "archive")]
+    ("export-dir", |state, x| state.export_dir(x.cmd)),
+    #[cfg(feature = "archive")]
+    ("import-dir", |state, x| state.import_dir(x.cmd, x.path)),
+    ("file-type", file_type),
+    ("restore-from-store", restore_from_store),
+    ("gc-backups", gc_backups),
+    ("subscribe", subscribe),
+    ("unsubscribe", unsubscribe),
+    ("export-history", export_history),
+    ("commit", |state, x| state.commit(x.cmd)),
+    ("checkout", |state, x| state.checkout(x.cmd)),
+    ("stage", |state, x| state.stage(x.cmd)),
+    ("preview-staged", |state, x| state.preview_staged(x.cmd)),
+    ("commit-staged", |state, x| state.commit_staged(x.cmd)),
+    ("discard-staged", |state, x| state.discard_staged(x.cmd)),
+    ("define-macro", define_macro),
+    // Expanded before the batch runs, so only a macro that failed to expand
+    // gets here
+    ("run-macro", |state, x| CommandResult::error(x.cmd, state.macro_error(x.cmd))),
+    ("why-denied", |state, x| state.why_denied(x.cmd)),
+    ("queue", |state, x| state.list_queue(x.cmd)),
+    ("continue", |state, x| state.continue_unknown(x.cmd)),
+    ("help", help),
+];
+
+pub fn handler(name: &str) -> Option<Handler> {
+    HANDLERS.iter().find(|(registered, _)| *registered == name).map(|(_, handler)| *handler)
+}
+
+fn read_file(state: &mut State, x: &Exec) -> CommandResult {
+    match state.fs.read_file(x.path) {
+        Ok(content) => state.read_window(x.cmd, content),
+        Err(e) => CommandResult::host_err(x.cmd, &e, format!("Failed to read file '{}': {}", x.cmd.path, e)),
+    }
+}
+
+fn write_file(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    let Some(content) = &cmd.content else {
+        return CommandResult::error(cmd, FsChildError::MissingField("No content provided for write operation".to_string()));
+    };
+    // A preview reports a write that would shrink the file too far
+    let ratio = if cmd.previews() { 0.0 } else { state.truncation_ratio };
+    if let Err(e) = crate::commands::write_precondition(&*state.fs, cmd, x.path, ratio) {
+        return CommandResult::error(cmd, e);
+    }
+    match crate::commands::schema_failure(cmd, x.schema, content) {
+        Some(failure) => failure,
+        None if cmd.previews() => crate::commands::write_preview(&*state.fs, cmd, x.path, content, state.truncation_ratio),
+        None => match state.fs.write_file(x.path, content) {
+            Ok(_) => CommandResult::ok(cmd, format!("Successfully wrote to file '{}'", cmd.path)),
+            Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", cmd.path, e)),
+        },
+    }
+}
+
+fn edit_file(state: &mut State, x: &Exec) -> CommandResult {
+    let (cmd, path, schema) = (x.cmd, x.path, x.schema);
+    match (&cmd.old_text, &cmd.new_text) {
+        (Some(old_text), Some(new_text)) if flag(cmd.ignore_whitespace.as_deref()) || flag(cmd.fuzzy.as_deref()) => {
+            crate::commands::edit_ignoring_whitespace(&*state.fs, cmd, path, old_text, new_text, schema)
+        }
+        (Some(old_text), Some(new_text)) if flag(cmd.regex.as_deref()) => {
+            crate::commands::edit_with_regex(&*state.fs, cmd, path, old_text, new_text, schema)
+        }
+        (Some(old_text), Some(new_text)) => match state.fs.read_file(path) {
+            Ok(mut content) => {
+                // Search the raw bytes first; the file is only
+                // validated as UTF-8 once, after a match is found
+                let positions = edit::find_all(&content, old_text.as_bytes());
+                match crate::commands::refuse_utf16(cmd, &content)
+                    .and_then(|_| crate::commands::edit_target(cmd))
+                    .and_then(|target| edit::select(&cmd.path, &content, &positions, target))
+                {
+                    Err(e @ FsChildError::AmbiguousAnchor(_)) => CommandResult::error(cmd, e)
+                        .with_data(json!({"lines": edit::line_numbers(&content, &positions)})),
+                    Err(e) => CommandResult::error(cmd, e),
+                    Ok(selected) => {
+                        edit::replace_in_place(&mut content, &selected, old_text.as_bytes(), new_text.as_bytes());
+                        // Validates without copying
+                        match String::from_utf8(content) {
+                            Ok(content_str) => match crate::commands::schema_failure(cmd, schema, &content_str) {
+                                Some(failure) => failure,
+                                None => match state.fs.write_file(path, &content_str) {
+                                    Ok(_) => CommandResult::ok(cmd, format!("Successfully edited file '{}'", cmd.path))
+                                        .with_data(json!({"matches": selected.len()})),
+                                    Err(e) => CommandResult::host_err(cmd, &e, format!(
+                                        "Failed to write edited content to '{}': {}",
+                                        cmd.path, e
+                                    )),
+                                },
+                            },
+                            Err(_) => CommandResult::error(cmd, FsChildError::NotUtf8(format!("Failed to decode file content of '{}'", cmd.path))),
+                        }
+                    }
+                }
+            }
+            Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", cmd.path, e)),
+        },
+        _ => CommandResult::error(cmd, FsChildError::MissingField("Both old_text and new_text must be provided for edit operation".to_string())),
+    }
+}
+
+fn list_files(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    if flag(cmd.recursive.as_deref()) {
+        return state.list_recursive(cmd);
+    }
+    match state.list_files_cached(x.path) {
+        Ok(files) => {
+            let formatted_files = files
+                .iter()
+                .map(|f| format!(" {}", path::display(f)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            CommandResult::ok(cmd, format!("Contents of '{}': {}", cmd.path, formatted_files))
+        }
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to list files in '{}': {}", cmd.path, e)),
+    }
+}
+
+fn create_dir(state: &mut State, x: &Exec) -> CommandResult {
+    match state.fs.create_dir(x.path) {
+        Ok(_) => CommandResult::ok(x.cmd, format!("Created directory '{}'", x.cmd.path)),
+        Err(e) => CommandResult::host_err(x.cmd, &e, format!("Failed to create directory '{}': {}", x.cmd.path, e)),
+    }
+}
+
+fn delete_file(state: &mut State, x: &Exec) -> CommandResult {
+    match state.fs.delete_file(x.path) {
+        Ok(_) => CommandResult::ok(x.cmd, format!("Deleted file '{}'", x.cmd.path)),
+        Err(e) => CommandResult::host_err(x.cmd, &e, format!("Failed to delete file '{}': {}", x.cmd.path, e)),
+    }
+}
+
+fn fetch_from_store(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    let Some(id) = &cmd.id else {
+        return CommandResult::error(cmd, FsChildError::MissingField("No id provided for fetch-from-store operation".to_string()));
+    };
+    let role = cmd.store.as_deref().unwrap_or("artifact");
+    match state.store_get(role, id, state.max_transfer_bytes) {
+        // The host filesystem interface only writes text
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(text) => match state.fs.write_file(x.path, &text) {
+                Ok(_) => CommandResult::ok(cmd, format!("Wrote store value '{}' ({} bytes) to '{}'", id, text.len(), cmd.path)),
+                Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", cmd.path, e)),
+            },
+            Err(_) => CommandResult::error(cmd, FsChildError::NotUtf8(format!("Store value '{}' is not valid UTF-8 and cannot be written", id))),
+        },
+        Err(e) => CommandResult::failed(cmd, e, format!("Failed to fetch '{}' from the {} store", id, role)),
+    }
+}
+
+fn push_to_store(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    match state.fs.read_file(x.path) {
+        Ok(content) if content.len() > state.max_transfer_bytes => CommandResult::error(cmd, FsChildError::TooLarge(format!(
+            "File '{}' is {} bytes, over the {} byte transfer limit",
+            cmd.path, content.len(), state.max_transfer_bytes
+        ))),
+        Ok(content) => {
+            let size = content.len();
+            let role = cmd.store.as_deref().unwrap_or("artifact");
+            match state.store_put(role, content) {
+                Ok(id) => CommandResult::ok(cmd, format!("Stored '{}' ({} bytes) in the {} store as '{}'", cmd.path, size, role, id))
+                    .with_data(json!({"id": id, "store": role, "bytes": size})),
+                Err(e) => CommandResult::failed(cmd, e, format!("Failed to store '{}' in the {} store", cmd.path, role)),
+            }
+        }
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", cmd.path, e)),
+    }
+}
+
+fn read_base64(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    match state.fs.read_file(x.path) {
+        Ok(content) if content.len() > state.max_inline_bytes => CommandResult::error(cmd, FsChildError::TooLarge(format!(
+            "File '{}' is {} bytes, over the {} byte inline limit; use push-to-store instead",
+            cmd.path, content.len(), state.max_inline_bytes
+        ))),
+        // Only a summary goes in the text; the encoded bytes are
+        // for the parent, not the conversation
+        Ok(content) => CommandResult::ok(cmd, format!("Read '{}' ({} bytes) as base64 into the result data", cmd.path, content.len()))
+            .with_data(json!({"bytes": content.len(), "base64": BASE64.encode(&content)})),
+        Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", cmd.path, e)),
+    }
+}
+
+fn convert(state: &mut State, x: &Exec) -> CommandResult {
+    crate::commands::convert(&*state.fs, x.cmd, x.path, x.target, x.target_path)
+}
+
+fn file_type(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    let content = match state.fs.read_file(x.path) {
+        Ok(content) => content,
+        Err(e) => return CommandResult::host_err(cmd, &e, format!("Failed to read file '{}': {}", cmd.path, e)),
+    };
+    let detected = filetype::detect(&cmd.path, &content);
+    // How to look at it without flooding the conversation
+    let suggested = match (detected.text, content.len() <= state.max_inline_bytes) {
+        (true, _) => "read-file",
+        (false, true) => "read-base64",
+        (false, false) => "push-to-store",
+    };
+    CommandResult::ok(cmd, format!(
+        "'{}' looks like {} ({}), {}, {} bytes; suggested: {}",
+        cmd.path,
+        detected.name,
+        detected.mime,
+        if detected.text { "text" } else { "binary" },
+        content.len(),
+        suggested
+    ))
+    .with_data(json!({
+        "type": detected.name,
+        "mime": detected.mime,
+        "text": detected.text,
+        "detected_by": detected.detected_by,
+        "bytes": content.len(),
+        "suggested_operation": suggested,
+    }))
+}
+
+fn restore_from_store(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    let current = state.current_hash(x.path);
+    let Some(record) = state.backups.get(&cmd.path).and_then(|records| backup::find(records, cmd.id.as_deref(), current.as_deref())).cloned() else {
+        return CommandResult::error(cmd, FsChildError::NotFound(format!("No backup of '{}' matches", cmd.path)));
+    };
+    match state.store_get("backup", &record.id, state.max_transfer_bytes) {
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(text) => match state.fs.write_file(x.path, &text) {
+                Ok(_) => CommandResult::ok(cmd, format!("Restored '{}' from backup {} (turn {})", cmd.path, record.hash, record.turn))
+                    .with_data(json!({"hash": record.hash, "id": record.id})),
+                Err(e) => CommandResult::host_err(cmd, &e, format!("Failed to write to file '{}': {}", cmd.path, e)),
+            },
+            Err(_) => CommandResult::error(cmd, FsChildError::NotUtf8(format!("Backup {} of '{}' is not valid UTF-8 and cannot be written", record.hash, cmd.path))),
+        },
+        Err(e) => CommandResult::failed(cmd, e, format!("Failed to fetch backup {} of '{}'", record.hash, cmd.path)),
+    }
+}
+
+fn gc_backups(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    let keep = match cmd.keep.as_deref().map(str::trim) {
+        None => 1,
+        Some(keep) => match keep.parse::<usize>() {
+            Ok(keep) => keep,
+            Err(_) => return CommandResult::error(cmd, FsChildError::Parse(format!("`keep` must be a number of backups, not '{}'", keep))),
+        },
+    };
+    let scope = match cmd.path.is_empty() {
+        true => "any file".to_string(),
+        false => format!("'{}'", cmd.path),
+    };
+    match state.release_backups(&cmd.path, keep) {
+        Ok((released, None)) => CommandResult::ok(cmd, format!("No backups of {} to release beyond the {} kept per file", scope, keep))
+            .with_data(json!({"released": released, "keep": keep})),
+        Ok((released, Some(id))) => {
+            let files: std::collections::BTreeSet<&str> = released.iter().map(|gone| gone.path.as_str()).collect();
+            let bytes: usize = released.iter().map(|gone| gone.bytes).sum();
+            CommandResult::ok(
+                cmd,
+                format!(
+                    "Released {} backup(s) of {} file(s), {} bytes, keeping {} per file; store entry '{}' lists them for collection",
+                    released.len(), files.len(), bytes, keep, id
+                ),
+            )
+            .with_data(json!({"released": released, "keep": keep, "bytes": bytes, "marker": id}))
+        }
+        Err(e) => CommandResult::failed(cmd, e, format!("Backups of {} kept: recording their release in the store failed", scope)),
+    }
+}
+
+fn subscribe(state: &mut State, x: &Exec) -> CommandResult {
+    let pattern = x.cmd.path.trim_start_matches("./").to_string();
+    let mut budget = state.budget();
+    let files = state.matching_files(&pattern, &mut budget);
+    for file in &files {
+        if budget.exhausted() {
+            break;
+        }
+        if let Ok(content) = state.fs.read_file(&state.resolve_path(file)) {
+            budget.spend_bytes(content.len());
+            state.watch_manifest.insert(file.clone(), watch::content_hash(&content));
+        }
+    }
+    if !state.subscriptions.contains(&pattern) {
+        state.subscriptions.push(pattern);
+    }
+    CommandResult::ok(x.cmd, format!("Subscribed to '{}' ({} files watched)", x.cmd.path, files.len()))
+}
+
+fn unsubscribe(state: &mut State, x: &Exec) -> CommandResult {
+    let pattern = x.cmd.path.trim_start_matches("./");
+    let Some(index) = state.subscriptions.iter().position(|p| p == pattern) else {
+        return CommandResult::error(x.cmd, FsChildError::NotFound(format!("No subscription for '{}'", x.cmd.path)));
+    };
+    state.subscriptions.remove(index);
+    let remaining = state.subscriptions.clone();
+    state.watch_manifest.retain(|path, _| remaining.iter().any(|p| crate::state::watches(p, path)));
+    CommandResult::ok(x.cmd, format!("Unsubscribed from '{}'", x.cmd.path))
+}
+
+fn export_history(state: &mut State, x: &Exec) -> CommandResult {
+    let since = x.cmd.since.as_deref().and_then(|t| t.trim().parse::<u64>().ok());
+    let until = x.cmd.until.as_deref().and_then(|t| t.trim().parse::<u64>().ok());
+    let entries: Vec<HistoryEntry> = state
+        .history
+        .iter()
+        .filter(|e| since.is_none_or(|since| e.turn >= since))
+        .filter(|e| until.is_none_or(|until| e.turn <= until))
+        .cloned()
+        .collect();
+    CommandResult::ok(x.cmd, history::summarize(&entries)).with_data(json!({"since": since, "until": until, "entries": entries}))
+}
+
+fn define_macro(state: &mut State, x: &Exec) -> CommandResult {
+    let cmd = x.cmd;
+    match crate::commands::define_macro(cmd) {
+        Ok((name, _)) if !state.macros.contains_key(&name) && state.macros.len() >= macros::MAX_MACROS => {
+            CommandResult::error(cmd, FsChildError::TooLarge(format!("Cannot define '{}': {} macros are already defined", name, macros::MAX_MACROS)))
+        }
+        Ok((name, m)) => {
+            let params = if m.params.is_empty() { "none".to_string() } else { m.params.join(", ") };
+            let message = format!("Defined macro '{}': {} steps, parameters: {}", name, m.steps.len(), params);
+            let data = json!({"macro": name, "steps": m.steps.len(), "params": m.params, "replaced": state.macros.contains_key(&name)});
+            state.macros.insert(name, m);
+            CommandResult::ok(cmd, message).with_data(data)
+        }
+        Err(e) => CommandResult::error(cmd, e),
+    }
+}
+
+fn help(state: &mut State, x: &Exec) -> CommandResult {
+    match x.cmd.topic.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        Some(topic) => match operation_spec(topic) {
+            Some(spec) => CommandResult::ok(x.cmd, spec.usage(&state.name)),
+            None => CommandResult::error(x.cmd, crate::commands::unknown_operation(topic, format!("No help for unknown operation '{}'", topic))),
+        },
+        None => CommandResult::ok(x.cmd, state.help_index()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::OPERATIONS;
+
+    #[test]
+    fn every_built_operation_has_one_handler() {
+        for spec in OPERATIONS {
+            let registered = HANDLERS.iter().filter(|(name, _)| *name == spec.name).count();
+            assert_eq!(registered, usize::from(spec.is_built()), "'{}' has {} handlers", spec.name, registered);
+        }
+        for (name, _) in HANDLERS {
+            assert!(operation_spec(name).is_some(), "handler for undeclared operation '{}'", name);
+        }
+    }
+}
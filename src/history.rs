@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ErrorCode;
+
+/// Oldest entries are dropped once the history grows past this
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// One executed (or refused) command, as remembered for `export-history`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Position in the session, counting every recorded command
+    pub seq: u64,
+    /// Head-update count when the command ran; the actor's only clock
+    pub turn: u64,
+    pub head: String,
+    pub operation: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+/// Readable overview of a slice of history: totals and per-operation counts.
+pub fn summarize(entries: &[HistoryEntry]) -> String {
+    if entries.is_empty() {
+        return "No operations recorded in this range".to_string();
+    }
+
+    let failed = entries.iter().filter(|e| e.error.is_some()).count();
+    let first_turn = entries.first().map(|e| e.turn).unwrap_or_default();
+    let last_turn = entries.last().map(|e| e.turn).unwrap_or_default();
+
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for entry in entries {
+        match counts.iter_mut().find(|(op, _)| *op == entry.operation) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((&entry.operation, 1)),
+        }
+    }
+    let per_operation = counts
+        .iter()
+        .map(|(op, count)| format!("  {}: {}", op, count))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        "{} operations ({} failed) between turns {} and {}:\n{}",
+        entries.len(),
+        failed,
+        first_turn,
+        last_turn,
+        per_operation
+    )
+}
@@ -0,0 +1,139 @@
+//! Host capabilities the actor can run without.
+//!
+//! A component only instantiates if the runtime provides every interface it
+//! imports. The full `fs-child` world imports `runtime`, `filesystem`,
+//! `message-server-host` and `supervisor`; builds without the `filesystem`,
+//! `store` or `supervisor` feature leave the matching import out, for the
+//! smaller worlds in `wit/world.wit`.
+//! Anything not always imported is a [`Capability`]: known to be absent from
+//! this build, declared missing in the configuration, or found missing when
+//! the host first refuses a call. Operations needing an absent capability
+//! fail with `Unsupported` instead of the whole actor failing to start.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Requests to store actors through the message server
+    Store,
+    /// File sizes and modification times without reading the file
+    Metadata,
+    /// Wall-clock time
+    Clock,
+    /// Spawning delegates as child actors
+    Supervisor,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 4] = [Capability::Store, Capability::Metadata, Capability::Clock, Capability::Supervisor];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Store => "store",
+            Capability::Metadata => "metadata",
+            Capability::Clock => "clock",
+            Capability::Supervisor => "supervisor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        Capability::ALL.into_iter().find(|capability| capability.name() == name.trim())
+    }
+
+    /// Whether this build imports an interface providing it. The Theater
+    /// interfaces have no metadata or clock calls yet.
+    pub fn imported(self) -> bool {
+        match self {
+            Capability::Store => cfg!(feature = "store"),
+            Capability::Supervisor => cfg!(feature = "supervisor"),
+            Capability::Metadata | Capability::Clock => false,
+        }
+    }
+
+    /// The capability an operation cannot run without, beyond the basic
+    /// filesystem calls.
+    pub fn required_by(operation: &str) -> Option<Capability> {
+        match operation {
+            "fetch-from-store" | "push-to-store" | "restore-from-store" | "export-dir" | "import-dir" | "gc-backups" => {
+                Some(Capability::Store)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Whether a host error says the call is not available at all, rather than
+/// that it failed this time.
+pub fn is_unsupported(error: &str) -> bool {
+    let lower = error.to_lowercase();
+    ["not supported", "unsupported", "not implemented", "unimplemented", "unknown function", "no handler"]
+        .iter()
+        .any(|phrase| lower.contains(phrase))
+}
+
+/// What this runtime is known to lack.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Host {
+    /// Declared in the configuration or seen refused by the host
+    missing: BTreeSet<Capability>,
+}
+
+impl Host {
+    /// A host lacking the named capabilities. Unknown names are returned so
+    /// they can be reported.
+    pub fn with_missing<'a>(names: impl IntoIterator<Item = &'a str>) -> (Host, Vec<String>) {
+        let mut host = Host::default();
+        let mut unknown = Vec::new();
+        for name in names {
+            match Capability::from_name(name) {
+                Some(capability) => {
+                    host.missing.insert(capability);
+                }
+                None => unknown.push(name.to_string()),
+            }
+        }
+        (host, unknown)
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        capability.imported() && !self.missing.contains(&capability)
+    }
+
+    /// Remember that the host refused a call needing `capability`.
+    pub fn mark_missing(&mut self, capability: Capability) -> bool {
+        self.missing.insert(capability)
+    }
+
+    /// The absent capability `operation` needs, if any.
+    pub fn lacks_for(&self, operation: &str) -> Option<Capability> {
+        Capability::required_by(operation).filter(|capability| !self.supports(*capability))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_missing_capabilities() {
+        let (mut host, unknown) = Host::with_missing(["metadata", " gpu "]);
+        assert_eq!(unknown, [" gpu "]);
+        assert_eq!(host.supports(Capability::Store), cfg!(feature = "store"));
+        assert!(!host.supports(Capability::Clock));
+        assert_eq!(host.lacks_for("push-to-store").is_none(), cfg!(feature = "store"));
+
+        assert!(host.mark_missing(Capability::Store));
+        assert!(!host.mark_missing(Capability::Store));
+        assert_eq!(host.lacks_for("import-dir"), Some(Capability::Store));
+        assert_eq!(host.lacks_for("read-file"), None);
+    }
+
+    #[test]
+    fn recognizes_unsupported_errors() {
+        assert!(is_unsupported("Operation not supported by this runtime"));
+        assert!(is_unsupported("No handler registered for message-server-host"));
+        assert!(!is_unsupported("No such file or directory"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Leveled, structured log lines on top of the runtime's log.
+//!
+//! A line reads `LEVEL [component] message key=value ...`. Values holding
+//! spaces or quotes are quoted. Lines above the configured level are
+//! dropped before they are formatted. Nothing logged carries file or
+//! message content, only names, ids and sizes.
+
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Only failures
+    Error,
+    /// Also requests and commands that could not be fully honored
+    Warn,
+    /// Also one line per request and batch
+    #[default]
+    Info,
+    /// Also each step of handling a request
+    Debug,
+    /// Also cache hits and sizes of every blob passed in and out
+    Trace,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        })
+    }
+}
+
+thread_local! {
+    static LEVEL: Cell<Level> = const { Cell::new(Level::Info) };
+}
+
+/// Drop lines above `level` from now on.
+pub fn set_level(level: Level) {
+    LEVEL.with(|current| current.set(level));
+}
+
+pub fn enabled(level: Level) -> bool {
+    LEVEL.with(|current| level <= current.get())
+}
+
+/// Log `message` from `component` with `fields`, if `level` is enabled.
+pub fn log(level: Level, component: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    if enabled(level) {
+        crate::log(&line(level, component, message, fields));
+    }
+}
+
+pub fn error(component: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Error, component, message, fields);
+}
+
+pub fn warn(component: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Warn, component, message, fields);
+}
+
+pub fn info(component: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Info, component, message, fields);
+}
+
+pub fn debug(component: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Debug, component, message, fields);
+}
+
+pub fn trace(component: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Trace, component, message, fields);
+}
+
+fn line(level: Level, component: &str, message: &str, fields: &[(&str, &dyn Display)]) -> String {
+    let mut line = format!("{} [{}] {}", level, component, message);
+    for (key, value) in fields {
+        let value = value.to_string();
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+            line.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_fields_and_filters_levels() {
+        let line = line(Level::Warn, "store", "Request failed", &[("id", &"abc"), ("error", &"timed out"), ("bytes", &12)]);
+        assert_eq!(line, r#"WARN [store] Request failed id=abc error="timed out" bytes=12"#);
+
+        set_level(Level::Warn);
+        assert!(enabled(Level::Error) && !enabled(Level::Info));
+        set_level(Level::Info);
+        assert!(enabled(Level::Info) && !enabled(Level::Debug));
+    }
+}
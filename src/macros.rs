@@ -0,0 +1,208 @@
+//! Named sequences of commands, defined once with `define-macro` and run
+//! with `run-macro`.
+//!
+//! A macro body is a JSON array of commands, each an object with the same
+//! fields as the XML format. Any string field may hold `{{param}}`
+//! placeholders, which `run-macro` fills from its `args`, written as
+//! `param=value` pairs separated by whitespace or newlines; a value with
+//! whitespace is written in double quotes.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::commands::FsCommand;
+
+/// Most macros one instance keeps
+pub const MAX_MACROS: usize = 64;
+
+/// Most commands in one macro
+pub const MAX_STEPS: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Macro {
+    /// Placeholder names, in order of first use
+    pub params: Vec<String>,
+    pub steps: Vec<FsCommand>,
+}
+
+/// Check a macro name: letters, digits, `-` and `_`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("'{}' is not a macro name; use letters, digits, '-' and '_'", name));
+    }
+    Ok(())
+}
+
+/// The macro a `define-macro` body describes.
+pub fn define(body: &str) -> Result<Macro, String> {
+    let steps: Vec<FsCommand> =
+        serde_json::from_str(body).map_err(|e| format!("the body is not a JSON array of commands: {}", e))?;
+    if steps.is_empty() {
+        return Err("the body has no commands".to_string());
+    }
+    if steps.len() > MAX_STEPS {
+        return Err(format!("the body has {} commands, more than the {} allowed", steps.len(), MAX_STEPS));
+    }
+    if let Some(nested) = steps.iter().find(|step| step.operation.ends_with("-macro")) {
+        return Err(format!("a macro cannot run '{}'", nested.operation));
+    }
+
+    let mut params = Vec::new();
+    for step in &steps {
+        for text in strings(&serde_json::to_value(step).map_err(|e| e.to_string())?) {
+            for param in placeholders(&text) {
+                if !params.contains(&param) {
+                    params.push(param);
+                }
+            }
+        }
+    }
+    Ok(Macro { params, steps })
+}
+
+/// `param=value` pairs from a `run-macro` command.
+pub fn parse_args(text: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut args = BTreeMap::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('=').ok_or_else(|| format!("expected name=value at '{}'", rest))?;
+        if name.contains(char::is_whitespace) {
+            return Err(format!("expected name=value at '{}'", rest));
+        }
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').ok_or_else(|| format!("unterminated quote in the value of '{}'", name))?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if args.insert(name.to_string(), value.to_string()).is_some() {
+            return Err(format!("'{}' is given twice", name));
+        }
+        rest = after.trim_start();
+    }
+    Ok(args)
+}
+
+/// The steps of `m` with every placeholder filled from `args`. Every
+/// parameter needs a value and every argument must be a parameter.
+pub fn expand(name: &str, m: &Macro, args: &BTreeMap<String, String>) -> Result<Vec<FsCommand>, String> {
+    let missing: Vec<&str> = m.params.iter().filter(|p| !args.contains_key(*p)).map(String::as_str).collect();
+    if !missing.is_empty() {
+        return Err(format!("macro '{}' needs a value for: {}", name, missing.join(", ")));
+    }
+    if let Some(unknown) = args.keys().find(|arg| !m.params.contains(arg)) {
+        let params = if m.params.is_empty() { "none".to_string() } else { m.params.join(", ") };
+        return Err(format!("macro '{}' has no parameter '{}'; its parameters: {}", name, unknown, params));
+    }
+
+    m.steps
+        .iter()
+        .map(|step| {
+            let mut value = serde_json::to_value(step).map_err(|e| e.to_string())?;
+            fill(&mut value, args);
+            serde_json::from_value(value).map_err(|e| e.to_string())
+        })
+        .collect()
+}
+
+/// Every string in a command's fields.
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Object(fields) => fields.values().filter_map(Value::as_str).map(str::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Names of the `{{name}}` placeholders in `text`.
+fn placeholders(text: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let name = &after[..end];
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            names.push(name.to_string());
+        }
+        rest = &after[end + 2..];
+    }
+    names
+}
+
+fn fill(value: &mut Value, args: &BTreeMap<String, String>) {
+    if let Value::Object(fields) = value {
+        for field in fields.values_mut() {
+            if let Value::String(text) = field {
+                *text = substitute(text, args);
+            }
+        }
+    }
+}
+
+/// `text` with each `{{name}}` of an argument replaced in one pass, so a
+/// value holding a placeholder is left as written.
+fn substitute(text: &str, args: &BTreeMap<String, String>) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}").and_then(|end| Some((args.get(&after[..end])?, end))) {
+            Some((arg, end)) => {
+                out.push_str(arg);
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCAFFOLD: &str = r#"[
+        {"operation": "create-dir", "path": "src/{{module}}"},
+        {"operation": "write-file", "path": "src/{{module}}/mod.rs", "content": "//! {{doc}}\n"}
+    ]"#;
+
+    #[test]
+    fn expands_placeholders() {
+        let scaffold = define(SCAFFOLD).unwrap();
+        assert_eq!(scaffold.params, ["module", "doc"]);
+
+        let args = parse_args("module=parser\n doc=\"Parses {{module}}.\"").unwrap();
+        let steps = expand("scaffold", &scaffold, &args).unwrap();
+        assert_eq!(steps[0].path, "src/parser");
+        assert_eq!(steps[1].content.as_deref(), Some("//! Parses {{module}}.\n"));
+    }
+
+    #[test]
+    fn rejects_bad_definitions_and_arguments() {
+        assert!(define("[]").is_err());
+        assert!(define(r#"[{"path": "a"}]"#).is_err());
+        assert!(define(r#"[{"operation": "run-macro", "macro": "x"}]"#).unwrap_err().contains("cannot run"));
+        assert!(check_name("new module").is_err());
+
+        let scaffold = define(SCAFFOLD).unwrap();
+        let args = parse_args("module=a").unwrap();
+        assert!(expand("scaffold", &scaffold, &args).unwrap_err().contains("doc"));
+        let args = parse_args("module=a doc=b extra=c").unwrap();
+        assert!(expand("scaffold", &scaffold, &args).unwrap_err().contains("'extra'"));
+        assert!(parse_args("module").is_err());
+        assert!(parse_args("a=1 a=2").is_err());
+        assert!(parse_args("a=\"open").is_err());
+    }
+}
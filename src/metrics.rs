@@ -0,0 +1,134 @@
+//! Counters for monitoring an instance: how often each operation ran, how
+//! often it failed and why, and how many bytes went in and out. Reported by
+//! a `stats` request and, with `metrics_interval` set, pushed to the store.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::ErrorCode;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OperationMetrics {
+    pub count: u64,
+    pub failed: u64,
+    /// Failures by error code
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<String, u64>,
+    /// Bytes of content the commands carried in
+    pub request_bytes: u64,
+    /// Bytes of result text sent back
+    pub response_bytes: u64,
+    /// Milliseconds spent, for commands timed against a host clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<Latency>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    pub samples: u64,
+    pub total_ms: u64,
+    pub max_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metrics {
+    /// By operation name; names outside the registry count as `unknown`
+    pub operations: BTreeMap<String, OperationMetrics>,
+    /// Turn of the last push to the store
+    #[serde(default)]
+    pub pushed_turn: u64,
+}
+
+impl Metrics {
+    pub fn record(
+        &mut self,
+        operation: &str,
+        error: Option<ErrorCode>,
+        request_bytes: usize,
+        response_bytes: usize,
+        elapsed_ms: Option<u64>,
+    ) {
+        let name = if crate::operations::operation_spec(operation).is_some() { operation } else { "unknown" };
+        let entry = self.operations.entry(name.to_string()).or_default();
+        entry.count += 1;
+        if let Some(code) = error {
+            entry.failed += 1;
+            *entry.errors.entry(format!("{:?}", code)).or_default() += 1;
+        }
+        entry.request_bytes += request_bytes as u64;
+        entry.response_bytes += response_bytes as u64;
+        if let Some(ms) = elapsed_ms {
+            let latency = entry.latency.get_or_insert_with(Latency::default);
+            latency.samples += 1;
+            latency.total_ms += ms;
+            latency.max_ms = latency.max_ms.max(ms);
+        }
+    }
+
+    /// Every operation added together.
+    pub fn total(&self) -> OperationMetrics {
+        let mut total = OperationMetrics::default();
+        for entry in self.operations.values() {
+            total.count += entry.count;
+            total.failed += entry.failed;
+            for (code, count) in &entry.errors {
+                *total.errors.entry(code.clone()).or_default() += count;
+            }
+            total.request_bytes += entry.request_bytes;
+            total.response_bytes += entry.response_bytes;
+            if let Some(latency) = &entry.latency {
+                let sum = total.latency.get_or_insert_with(Latency::default);
+                sum.samples += latency.samples;
+                sum.total_ms += latency.total_ms;
+                sum.max_ms = sum.max_ms.max(latency.max_ms);
+            }
+        }
+        total
+    }
+
+    /// One line per operation, busiest first.
+    pub fn summary(&self) -> String {
+        if self.operations.is_empty() {
+            return "No operations run yet".to_string();
+        }
+        let mut operations: Vec<_> = self.operations.iter().collect();
+        operations.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        let total = self.total();
+        let mut lines = vec![format!("{} operations ({} failed):", total.count, total.failed)];
+        for (name, entry) in operations {
+            let mut line = format!(
+                "  {}: {} ({} failed), {} bytes in, {} bytes out",
+                name, entry.count, entry.failed, entry.request_bytes, entry.response_bytes
+            );
+            if let Some(latency) = entry.latency.as_ref().filter(|l| l.samples > 0) {
+                line.push_str(&format!(", {} ms mean, {} ms max", latency.total_ms / latency.samples, latency.max_ms));
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_operation_and_in_total() {
+        let mut metrics = Metrics::default();
+        metrics.record("read-file", None, 0, 120, None);
+        metrics.record("write-file", None, 40, 30, Some(5));
+        metrics.record("write-file", Some(ErrorCode::PermissionDenied), 10, 20, Some(9));
+        metrics.record("launch-rockets", Some(ErrorCode::UnknownOperation), 0, 25, None);
+
+        let writes = &metrics.operations["write-file"];
+        assert_eq!((writes.count, writes.failed, writes.request_bytes), (2, 1, 50));
+        assert_eq!(writes.errors["PermissionDenied"], 1);
+        assert_eq!(writes.latency, Some(Latency { samples: 2, total_ms: 14, max_ms: 9 }));
+        assert_eq!(metrics.operations["unknown"].count, 1);
+
+        let total = metrics.total();
+        assert_eq!((total.count, total.failed, total.response_bytes), (4, 2, 195));
+        assert!(metrics.summary().starts_with("4 operations (2 failed):\n  write-file: 2 (1 failed)"));
+    }
+}
@@ -0,0 +1,51 @@
+//! Named roots besides `base_path`, each with its own permissions.
+//!
+//! A command addresses a mount by prefixing its path with the mount's name,
+//! as in `docs:/guide.md`. The path after the prefix is taken from the
+//! mount's root, so `..` cannot climb out of it. Paths without a prefix
+//! resolve against `base_path` as before.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mount {
+    /// Host directory the mount's paths resolve against
+    pub path: String,
+    pub permissions: Vec<String>,
+}
+
+impl Mount {
+    pub fn allows(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+}
+
+/// Check a mount name: letters, digits, `-` and `_`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("'{}' is not a mount name; use letters, digits, '-' and '_'", name));
+    }
+    Ok(())
+}
+
+/// The name and the rest of a path written `name:rest`, for any name that
+/// could be a mount's.
+pub fn split(path: &str) -> Option<(&str, &str)> {
+    let (name, rest) = path.split_once(':')?;
+    check_name(name).ok()?;
+    Some((name, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_prefixed_paths() {
+        assert_eq!(split("docs:/guide.md"), Some(("docs", "/guide.md")));
+        assert_eq!(split("docs:guide.md"), Some(("docs", "guide.md")));
+        assert_eq!(split("notes/12:30.txt"), None);
+        assert_eq!(split("src/lib.rs"), None);
+        assert!(check_name("my docs").is_err());
+    }
+}
@@ -0,0 +1,550 @@
+/// Static description of a supported operation, used for permission checks,
+/// field validation, and for advertising capabilities to the parent.
+pub struct OperationSpec {
+    pub name: &'static str,
+    /// Permission the instance needs, or `None` for informational operations
+    pub permission: Option<&'static str>,
+    pub description: &'static str,
+    pub required: &'static [&'static str],
+    pub optional: &'static [&'static str],
+    /// Field values used when rendering a usage example
+    pub example: &'static [(&'static str, &'static str)],
+    /// Whether the operation may replace or remove the file at `path`
+    pub overwrites: bool,
+}
+
+pub const OPERATIONS: &[OperationSpec] = &[
+    OperationSpec {
+        name: "read-file",
+        permission: Some("read"),
+        description: "Read file contents, in windows for large files, or only whether they changed",
+        required: &["path"],
+        optional: &["offset", "length", "if_changed_since"],
+        example: &[("path", "src/file.rs")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "read-around",
+        permission: Some("read"),
+        description: "Show numbered lines around each match of a text or regular expression in a file",
+        required: &["path", "pattern"],
+        optional: &["regex", "lines"],
+        example: &[("path", "src/file.rs"), ("pattern", "fn main"), ("lines", "5")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "write-file",
+        permission: Some("write"),
+        description: "Write to a file",
+        required: &["path", "content"],
+        optional: &["format", "sort_keys", "schema", "overwrite", "create", "confirm_truncation", "dry_run", "force"],
+        example: &[("path", "src/file.rs"), ("content", "file contents here")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "edit-file",
+        permission: Some("write"),
+        description: "Edit file contents by replacing text",
+        required: &["path", "old_text", "new_text"],
+        optional: &["regex", "occurrence", "replace_all", "ignore_whitespace", "fuzzy", "min_confidence", "schema", "force"],
+        example: &[
+            ("path", "src/file.rs"),
+            ("old_text", "text to find"),
+            ("new_text", "replacement text"),
+        ],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "list-files",
+        permission: Some("read"),
+        description: "List directory contents, optionally recursively",
+        required: &["path"],
+        optional: &["recursive", "depth", "cursor"],
+        example: &[("path", ".")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "create-dir",
+        permission: Some("write"),
+        description: "Create a new directory",
+        required: &["path"],
+        optional: &[],
+        example: &[("path", "new_directory")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "delete-file",
+        permission: Some("write"),
+        description: "Delete a file",
+        required: &["path"],
+        optional: &["force"],
+        example: &[("path", "file_to_delete.txt")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "fetch-from-store",
+        permission: Some("write"),
+        description: "Write a value from the store to a file",
+        required: &["path", "id"],
+        optional: &["store", "force"],
+        example: &[
+            ("path", "out/report.md"),
+            ("id", "store value id"),
+            ("store", "artifact"),
+        ],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "push-to-store",
+        permission: Some("read"),
+        description: "Store a file's contents and return the assigned id",
+        required: &["path"],
+        optional: &["store"],
+        example: &[("path", "out/report.md"), ("store", "artifact")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "read-base64",
+        permission: Some("read"),
+        description: "Return a small file's bytes base64-encoded in the result data",
+        required: &["path"],
+        optional: &[],
+        example: &[("path", "assets/logo.png")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "frontmatter-get",
+        permission: Some("read"),
+        description: "Read the YAML front matter of a Markdown file, or one key of it",
+        required: &["path"],
+        optional: &["key"],
+        example: &[("path", "posts/hello.md"), ("key", "title")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "frontmatter-set",
+        permission: Some("write"),
+        description: "Set one key of a Markdown file's YAML front matter",
+        required: &["path", "key", "value"],
+        optional: &["force"],
+        example: &[("path", "posts/hello.md"), ("key", "draft"), ("value", "false")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "cargo-add-dep",
+        permission: Some("write"),
+        description: "Add a dependency to a Cargo.toml, or update the one already listed",
+        required: &["path", "dependency", "version"],
+        optional: &["features", "table", "optional", "force"],
+        example: &[("path", "Cargo.toml"), ("dependency", "serde"), ("version", "1.0"), ("features", "derive")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "cargo-set-version",
+        permission: Some("write"),
+        description: "Set the package version of a Cargo.toml, or the version of one dependency",
+        required: &["path", "version"],
+        optional: &["dependency", "table", "force"],
+        example: &[("path", "Cargo.toml"), ("version", "0.2.0")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "generate-toc",
+        permission: Some("write"),
+        description: "Insert or update a Markdown file's table of contents",
+        required: &["path"],
+        optional: &["depth", "force"],
+        example: &[("path", "README.md"), ("depth", "3")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "csv-to-json",
+        permission: Some("write"),
+        description: "Convert a CSV file to a JSON array in another file",
+        required: &["path"],
+        optional: &["destination", "header", "delimiter", "force"],
+        example: &[("path", "data/items.csv"), ("destination", "data/items.json")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "json-to-csv",
+        permission: Some("write"),
+        description: "Convert a JSON array of records to a CSV file",
+        required: &["path"],
+        optional: &["destination", "header", "delimiter", "force"],
+        example: &[("path", "data/items.json"), ("destination", "data/items.csv")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "append-ndjson",
+        permission: Some("write"),
+        description: "Append one JSON record as a line of an NDJSON file",
+        required: &["path", "content"],
+        optional: &["force"],
+        example: &[("path", "logs/events.ndjson"), ("content", "{\"event\": \"deploy\", \"ok\": true}")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "manifest",
+        permission: Some("read"),
+        description: "List every file under a directory with its size and SHA-256, or save that list as JSON",
+        required: &["path"],
+        optional: &["destination", "cursor"],
+        example: &[("path", "src"), ("destination", "build/src-manifest.json")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "compact",
+        permission: Some("write"),
+        description: "Replace duplicate files under a directory with stubs sharing one stored copy, read back transparently",
+        required: &["path"],
+        optional: &["cursor"],
+        example: &[("path", "target/generated")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "render",
+        permission: Some("read"),
+        description: "Fill a template from the workspace with a JSON context, with loops, conditionals and includes",
+        required: &["path"],
+        optional: &["context", "destination"],
+        example: &[
+            ("path", "templates/module.rs.j2"),
+            ("context", r#"{"name": "parser", "items": ["Token", "Lexer"]}"#),
+            ("destination", "src/parser.rs"),
+        ],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "export-dir",
+        permission: Some("read"),
+        description: "Pack a directory into a tar archive and push it to the store",
+        required: &["path"],
+        optional: &["store"],
+        example: &[("path", "out/site"), ("store", "artifact")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "import-dir",
+        permission: Some("write"),
+        description: "Fetch a tar archive from the store and unpack it into a new directory",
+        required: &["path", "id"],
+        optional: &["store"],
+        example: &[
+            ("path", "vendor/site"),
+            ("id", "store value id"),
+            ("store", "artifact"),
+        ],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "file-type",
+        permission: Some("read"),
+        description: "Identify a file's type from its content and name, and how best to read it",
+        required: &["path"],
+        optional: &[],
+        example: &[("path", "assets/logo.png")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "restore-from-store",
+        permission: Some("write"),
+        description: "Restore a file from a backup archived in the store",
+        required: &["path"],
+        optional: &["id", "force"],
+        example: &[("path", "src/file.rs")],
+        overwrites: true,
+    },
+    OperationSpec {
+        name: "gc-backups",
+        permission: Some("write"),
+        description: "Release store backups this instance took, beyond the most recent per file, for the store to collect",
+        required: &[],
+        optional: &["path", "keep"],
+        example: &[("path", "src"), ("keep", "1")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "subscribe",
+        permission: Some("read"),
+        description: "Get notified when files matching a glob change",
+        required: &["path"],
+        optional: &[],
+        example: &[("path", "src/**/*.rs")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "unsubscribe",
+        permission: Some("read"),
+        description: "Stop change notifications for a glob",
+        required: &["path"],
+        optional: &[],
+        example: &[("path", "src/**/*.rs")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "export-history",
+        permission: None,
+        description: "Summarize the operations run this session",
+        required: &[],
+        optional: &["since", "until"],
+        example: &[("since", "1"), ("until", "10")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "commit",
+        permission: Some("write"),
+        description: "Record the whole workspace as a checkpoint that checkout can return to",
+        required: &["message"],
+        optional: &[],
+        example: &[("message", "Before refactoring the parser")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "checkout",
+        permission: Some("write"),
+        description: "Return the workspace to a commit, by id or id prefix",
+        required: &["id"],
+        optional: &["force"],
+        example: &[("id", "3f2a9c")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "stage",
+        permission: Some("write"),
+        description: "Send later writes, edits and deletes to an overlay for review instead of the workspace",
+        required: &[],
+        optional: &[],
+        example: &[],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "preview-staged",
+        permission: Some("read"),
+        description: "Show a diff of every staged change",
+        required: &[],
+        optional: &[],
+        example: &[],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "commit-staged",
+        permission: Some("write"),
+        description: "Apply every staged change to the workspace at once, and stop staging",
+        required: &[],
+        optional: &[],
+        example: &[],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "discard-staged",
+        permission: Some("write"),
+        description: "Drop every staged change, and stop staging",
+        required: &[],
+        optional: &[],
+        example: &[],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "define-macro",
+        permission: None,
+        description: "Save a sequence of commands, with {{param}} placeholders, to run by name",
+        required: &["macro", "content"],
+        optional: &[],
+        example: &[
+            ("macro", "new-module"),
+            (
+                "content",
+                r#"[{"operation": "create-dir", "path": "src/{{module}}"}, {"operation": "write-file", "path": "src/{{module}}/mod.rs", "content": "//! {{module}}\n"}]"#,
+            ),
+        ],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "run-macro",
+        permission: None,
+        description: "Run a defined macro's commands with its parameters filled in",
+        required: &["macro"],
+        optional: &["args"],
+        example: &[("macro", "new-module"), ("args", "module=parser")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "why-denied",
+        permission: None,
+        description: "Explain whether an operation on a path is allowed, and which permission or policy rule decides",
+        required: &["topic", "path"],
+        optional: &["content", "destination"],
+        example: &[("topic", "write-file"), ("path", "src/lib.rs")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "queue",
+        permission: None,
+        description: "List the commands held back with `defer` and when each will run",
+        required: &[],
+        optional: &[],
+        example: &[],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "continue",
+        permission: None,
+        description: "Pick up a read, listing or manifest that stopped early, from the token its result gave",
+        required: &["token"],
+        optional: &[],
+        example: &[("token", "c1")],
+        overwrites: false,
+    },
+    OperationSpec {
+        name: "help",
+        permission: None,
+        description: "Show usage for one operation, or list all operations",
+        required: &[],
+        optional: &["topic"],
+        example: &[("topic", "edit-file")],
+        overwrites: false,
+    },
+];
+
+/// Operations the introduction shows an example command for, in order.
+pub const INTRO_EXAMPLES: &[&str] = &[
+    "list-files",
+    "read-file",
+    "write-file",
+    "edit-file",
+    "create-dir",
+    "delete-file",
+    "fetch-from-store",
+    "push-to-store",
+    "subscribe",
+    "help",
+];
+
+/// The optional parts of the component, as Cargo features, and whether this
+/// build includes each.
+pub const FEATURES: [(&str, bool); 8] = [
+    ("filesystem", cfg!(feature = "filesystem")),
+    ("store", cfg!(feature = "store")),
+    ("supervisor", cfg!(feature = "supervisor")),
+    ("html", cfg!(feature = "html")),
+    ("archive", cfg!(feature = "archive")),
+    ("regex", cfg!(feature = "regex")),
+    ("structured", cfg!(feature = "structured")),
+    ("templates", cfg!(feature = "templates")),
+];
+
+/// Whether this build includes the named feature.
+pub fn built(feature: &str) -> bool {
+    FEATURES.iter().any(|(name, included)| *name == feature && *included)
+}
+
+/// Optional fields accepted by every operation
+pub const COMMON_FIELDS: &[&str] = &["idempotency_key", "mode", "defer"];
+
+pub fn operation_spec(name: &str) -> Option<&'static OperationSpec> {
+    OPERATIONS.iter().find(|spec| spec.name == name)
+}
+
+/// The operation a mistyped `name` most likely meant: the closest by edit
+/// distance within two, or failing that one whose name contains it.
+pub fn suggest(name: &str) -> Option<&'static str> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() {
+        return None;
+    }
+    let built = || OPERATIONS.iter().filter(|spec| spec.is_built()).map(|spec| spec.name);
+    built()
+        .map(|op| (distance(&name, op), op))
+        .filter(|(d, _)| *d <= 2)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, op)| op)
+        .or_else(|| built().find(|op| op.contains(name.as_str())))
+}
+
+/// Levenshtein distance between two names.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1).min(row[j] + 1).min(diagonal + usize::from(ca != *cb));
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+impl OperationSpec {
+    /// The example command for this operation, addressed to `instance_name`.
+    pub fn example_command(&self, instance_name: &str) -> String {
+        self.example_command_at(instance_name, None)
+    }
+
+    /// The example command, with `path` in place of the example's own.
+    pub fn example_command_at(&self, instance_name: &str, path: Option<&str>) -> String {
+        let mut fields = vec![("operation", self.name)];
+        fields.extend(self.example.iter().map(|(name, value)| match (*name, path) {
+            ("path", Some(path)) => (*name, path),
+            _ => (*name, *value),
+        }));
+        crate::parser::render(instance_name, &fields)
+    }
+
+    /// The feature the operation is compiled under, if it is optional.
+    pub fn feature(&self) -> Option<&'static str> {
+        match self.name {
+            "export-dir" | "import-dir" => Some("archive"),
+            "frontmatter-get" | "frontmatter-set" | "cargo-add-dep" | "cargo-set-version" => Some("structured"),
+            "render" => Some("templates"),
+            _ => None,
+        }
+    }
+
+    /// Whether this build can run the operation at all.
+    pub fn is_built(&self) -> bool {
+        self.feature().is_none_or(built)
+    }
+
+    /// Full usage block: description, permission, fields, and an example.
+    pub fn usage(&self, instance_name: &str) -> String {
+        let permission = match self.permission {
+            Some(permission) => format!("requires '{}'", permission),
+            None => "no permission required".to_string(),
+        };
+        let fields = |fields: &[&str]| {
+            if fields.is_empty() {
+                "none".to_string()
+            } else {
+                fields.join(", ")
+            }
+        };
+
+        format!(
+            "{} ({}): {}\nRequired fields: {}\nOptional fields: {}\n\nExample:\n{}",
+            self.name,
+            permission,
+            self.description,
+            fields(self.required),
+            fields(self.optional),
+            self.example_command(instance_name)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_close_operations() {
+        assert_eq!(suggest("read-fiel"), Some("read-file"));
+        assert_eq!(suggest("Write-File"), Some("write-file"));
+        assert_eq!(suggest("macro"), Some("define-macro"));
+        assert_eq!(suggest("launch-rockets"), None);
+        assert_eq!(distance("kitten", "sitting"), 3);
+    }
+}
@@ -0,0 +1,213 @@
+//! The workspace overview sent with the introduction, so a session starts
+//! out knowing the layout instead of spending its first turns listing it.
+//!
+//! The overview names each top-level entry with the number of files under
+//! it, and the projects found from their manifests at the top level or one
+//! directory down. Directories that hold dependencies or build output are
+//! named but not counted.
+//!
+//! With `detect_project`, the introduction's examples also use the paths of
+//! the project found at the top level rather than made-up ones.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Top-level directories named but not walked
+pub const NOT_COUNTED: [&str; 5] = [".git", ".fs-child", "node_modules", "target", "__pycache__"];
+
+/// Most top-level entries named; the rest are summed up in a count
+pub const MAX_ENTRIES: usize = 40;
+
+/// Manifests that mark a project, and the kind of project each marks
+pub const MARKERS: [(&str, &str); 4] = [
+    ("Cargo.toml", "rust"),
+    ("package.json", "node"),
+    ("pyproject.toml", "python"),
+    ("go.mod", "go"),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub dir: bool,
+    /// Files beneath a directory, or `None` when it was not walked
+    pub files: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Project {
+    pub kind: &'static str,
+    /// Path of the manifest, relative to `base_path`
+    pub manifest: String,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Overview {
+    pub entries: Vec<Entry>,
+    /// Top-level entries past [`MAX_ENTRIES`]
+    pub more: usize,
+    pub files: usize,
+    pub projects: Vec<Project>,
+    /// Whether every counted directory was walked to the end
+    pub complete: bool,
+}
+
+impl Overview {
+    pub fn text(&self) -> String {
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| match (entry.dir, entry.files) {
+                (false, _) => format!("- {}", entry.name),
+                (true, None) => format!("- {}/ (not counted)", entry.name),
+                (true, Some(files)) => format!("- {}/ ({} {})", entry.name, files, plural(files, "file")),
+            })
+            .collect();
+        if self.more > 0 {
+            lines.push(format!("- … and {} more", self.more));
+        }
+        let at_least = if self.complete { "" } else { "at least " };
+        lines.push(format!("{}{} {} in all", at_least, self.files, plural(self.files, "file")));
+        if !self.projects.is_empty() {
+            let projects: Vec<String> = self
+                .projects
+                .iter()
+                .map(|project| match &project.name {
+                    Some(name) => format!("{} '{}' ({})", project.kind, name, project.manifest),
+                    None => format!("{} ({})", project.kind, project.manifest),
+                })
+                .collect();
+            lines.push(format!("Projects: {}", projects.join(", ")));
+        }
+        format!("Workspace overview:\n{}", lines.join("\n"))
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        noun.to_string()
+    } else {
+        format!("{}s", noun)
+    }
+}
+
+/// The project a manifest marks, with its name when the manifest gives one.
+pub fn project(manifest: &str, kind: &'static str, content: &str) -> Project {
+    let name = match kind {
+        "rust" => toml_name(content, "package"),
+        "python" => toml_name(content, "project").or_else(|| toml_name(content, "tool.poetry")),
+        "node" => serde_json::from_str::<Value>(content).ok().and_then(|json| json["name"].as_str().map(String::from)),
+        "go" => content.lines().find_map(|line| line.trim().strip_prefix("module ").map(|module| module.trim().to_string())),
+        _ => None,
+    };
+    Project { kind, manifest: manifest.to_string(), name }
+}
+
+/// The `name` key of `[section]` in a TOML file, read line by line so the
+/// overview needs no TOML parser.
+fn toml_name(content: &str, section: &str) -> Option<String> {
+    let header = format!("[{}]", section);
+    let mut inside = false;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            inside = line == header;
+        } else if let Some(value) = line.strip_prefix("name").and_then(|rest| rest.trim_start().strip_prefix('=')) {
+            if inside {
+                return Some(value.trim().trim_matches(|c| c == '"' || c == '\'').to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Paths to show in the introduction's examples, by operation
+pub type ExamplePaths = BTreeMap<&'static str, String>;
+
+/// Example paths for `project`, given the files under its source directory
+/// as paths relative to `base_path`. An operation with nothing fitting to
+/// show keeps its usual example.
+pub fn example_paths(project: &Project, files: &[String]) -> ExamplePaths {
+    let root = match project.manifest.rsplit_once('/') {
+        Some((dir, _)) => format!("{}/", dir),
+        None => String::new(),
+    };
+    let (preferred, extensions, new_file): (&[&str], &[&str], &str) = match project.kind {
+        "rust" => (&["src/lib.rs", "src/main.rs"], &["rs"], "new_module.rs"),
+        "node" => (&["src/index.ts", "src/index.js", "index.js"], &["ts", "js", "tsx", "jsx", "mjs"], "new-module"),
+        "python" => (&[], &["py"], "new_module.py"),
+        "go" => (&["main.go"], &["go"], "new_file.go"),
+        _ => return ExamplePaths::new(),
+    };
+    let source = preferred
+        .iter()
+        .map(|file| format!("{}{}", root, file))
+        .find(|file| files.contains(file))
+        .or_else(|| {
+            files
+                .iter()
+                .filter(|file| file.starts_with(&root))
+                .find(|file| file.rsplit_once('.').is_some_and(|(_, extension)| extensions.contains(&extension)))
+                .cloned()
+        });
+
+    let mut paths = ExamplePaths::new();
+    // Manifests say the most about a project, except Cargo.toml, which
+    // has operations of its own
+    let read = match project.kind {
+        "rust" => source.clone(),
+        _ => Some(project.manifest.clone()),
+    };
+    if let Some(read) = read {
+        paths.insert("read-file", read);
+    }
+    if let Some(source) = source {
+        let dir = match source.rsplit_once('/') {
+            Some((dir, _)) => dir.to_string(),
+            None => ".".to_string(),
+        };
+        let new_file = match (project.kind, source.rsplit_once('.')) {
+            ("node", Some((_, extension))) => format!("{}.{}", new_file, extension),
+            _ => new_file.to_string(),
+        };
+        let prefix = if dir == "." { String::new() } else { format!("{}/", dir) };
+        paths.insert("write-file", format!("{}{}", prefix, new_file));
+        paths.insert("edit-file", source);
+        paths.insert("list-files", dir);
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_projects_from_their_manifests() {
+        let cargo = "[workspace]\nname = \"no\"\n\n[package]\nname = \"demo\"\nversion = \"0.1.0\"\n";
+        assert_eq!(project("Cargo.toml", "rust", cargo).name.as_deref(), Some("demo"));
+        assert_eq!(project("web/package.json", "node", r#"{"name": "web"}"#).name.as_deref(), Some("web"));
+        assert_eq!(project("go.mod", "go", "module example.com/tool\n\ngo 1.22\n").name.as_deref(), Some("example.com/tool"));
+        assert_eq!(project("Cargo.toml", "rust", "[workspace]\nmembers = []\n").name, None);
+    }
+
+    #[test]
+    fn picks_example_paths_from_the_project() {
+        let files = |list: &[&str]| list.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let rust = project("Cargo.toml", "rust", "");
+        let paths = example_paths(&rust, &files(&["src/cli.rs", "src/main.rs"]));
+        assert_eq!(paths["read-file"], "src/main.rs");
+        assert_eq!(paths["write-file"], "src/new_module.rs");
+        assert_eq!(paths["list-files"], "src");
+
+        let node = project("web/package.json", "node", "");
+        let paths = example_paths(&node, &files(&["web/lib/app.ts"]));
+        assert_eq!(paths["read-file"], "web/package.json");
+        assert_eq!(paths["edit-file"], "web/lib/app.ts");
+        assert_eq!(paths["write-file"], "web/lib/new-module.ts");
+
+        let python = project("pyproject.toml", "python", "");
+        assert_eq!(example_paths(&python, &[]).into_keys().collect::<Vec<_>>(), ["read-file"]);
+    }
+}
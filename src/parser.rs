@@ -0,0 +1,205 @@
+//! Commands as they are written in chat messages.
+//!
+//! The grammar, over the text of a message:
+//!
+//! ```text
+//! message  = { text | command }
+//! command  = marker { field | text } "</fs-command>"
+//! marker   = '<fs-command name="' instance '">'
+//! field    = "<" name ">" value "</" name ">"
+//! name     = ( letter | "_" ) { letter | digit | "_" | "-" }
+//! value    = any text not containing "</" name ">"
+//! ```
+//!
+//! Text between fields is ignored, as is a command for another instance. A
+//! field's value runs to the first closing tag of the same name, so markup
+//! inside it, even another marker or `</fs-command>`, is part of the value.
+//! When a name repeats, the first field counts. A command without an
+//! `operation` field, or without its closing tag, is dropped. Scanning
+//! itself lives in [`tags`], which the fuzz target builds on its own.
+
+use crate::tags;
+use crate::commands::FsCommand;
+use crate::logger;
+
+/// The opening tag of commands addressed to `instance_name`.
+pub fn marker(instance_name: &str) -> String {
+    format!("<fs-command name=\"{}\">", instance_name)
+}
+
+/// The commands for `instance_name` in a message, in order.
+pub fn commands(content: &str, instance_name: &str) -> Vec<FsCommand> {
+    let blocks = tags::blocks(content, &marker(instance_name));
+    if blocks.len() == tags::MAX_COMMANDS {
+        logger::warn("parser", "Too many commands in one message; the rest are ignored", &[("max", &tags::MAX_COMMANDS)]);
+    }
+
+    blocks
+        .iter()
+        .filter_map(|block| {
+            let field = |name| block.get(name).map(str::to_string);
+            // Operations that need a path report it missing when run
+            Some(FsCommand {
+                operation: field("operation")?,
+                // Line breaks around a path come from formatting the
+                // markup, never from the name
+                path: field("path")
+                    .map(|path| path.trim_matches(|c: char| c.is_control()).to_string())
+                    .unwrap_or_default(),
+                content: field("content"),
+                old_text: field("old_text"),
+                new_text: field("new_text"),
+                idempotency_key: field("idempotency_key"),
+                id: field("id"),
+                store: field("store"),
+                keep: field("keep"),
+                topic: field("topic"),
+                since: field("since"),
+                until: field("until"),
+                offset: field("offset"),
+                length: field("length"),
+                if_changed_since: field("if_changed_since"),
+                regex: field("regex"),
+                occurrence: field("occurrence"),
+                replace_all: field("replace_all"),
+                pattern: field("pattern"),
+                lines: field("lines"),
+                ignore_whitespace: field("ignore_whitespace"),
+                fuzzy: field("fuzzy"),
+                min_confidence: field("min_confidence"),
+                recursive: field("recursive"),
+                depth: field("depth"),
+                cursor: field("cursor"),
+                mode: field("mode"),
+                defer: field("defer"),
+                format: field("format"),
+                sort_keys: field("sort_keys"),
+                overwrite: field("overwrite"),
+                create: field("create"),
+                confirm_truncation: field("confirm_truncation"),
+                dry_run: field("dry_run"),
+                schema: field("schema"),
+                key: field("key"),
+                value: field("value"),
+                dependency: field("dependency"),
+                version: field("version"),
+                features: field("features"),
+                table: field("table"),
+                optional: field("optional"),
+                destination: field("destination"),
+                header: field("header"),
+                delimiter: field("delimiter"),
+                macro_name: field("macro"),
+                args: field("args"),
+                message: field("message"),
+                force: field("force"),
+                context: field("context"),
+                token: field("token"),
+            })
+        })
+        .collect()
+}
+
+/// A command for `instance_name` with `fields` in order, one per line.
+/// Values are written as they are; [`commands`] reads them back unless one
+/// contains its own closing tag.
+pub fn render(instance_name: &str, fields: &[(&str, &str)]) -> String {
+    let mut lines = vec![marker(instance_name)];
+    for (name, value) in fields {
+        lines.push(format!("  <{name}>{value}</{name}>", name = name, value = value));
+    }
+    lines.push("</fs-command>".to_string());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde_json::{json, Map, Value};
+
+    /// Every field of a command, as serde names them.
+    fn field_names() -> Vec<String> {
+        let cmd: FsCommand = serde_json::from_value(json!({"operation": ""})).unwrap();
+        match serde_json::to_value(cmd).unwrap() {
+            Value::Object(fields) => fields.keys().cloned().collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Commands with any subset of fields set. Values mix markup, line
+    /// breaks and multi-byte text; a path has no surrounding control
+    /// characters, which parsing trims.
+    fn any_command() -> impl Strategy<Value = FsCommand> {
+        let value = "[a-z<>/\"= \n\té→]{0,24}";
+        let path = "([a-z/._é<>-]([a-z/._ é<>\n-]{0,16}[a-z/._é<>-])?)?";
+        let names = field_names();
+        let optional: Vec<String> = names.into_iter().filter(|name| name != "operation" && name != "path").collect();
+        let count = optional.len();
+        (value, path, proptest::collection::vec(proptest::option::of(value), count)).prop_map(
+            move |(operation, path, values)| {
+                let mut fields = Map::new();
+                fields.insert("operation".to_string(), json!(operation));
+                fields.insert("path".to_string(), json!(path));
+                for (name, value) in optional.iter().zip(values) {
+                    fields.insert(name.clone(), json!(value));
+                }
+                serde_json::from_value(Value::Object(fields)).unwrap()
+            },
+        )
+    }
+
+    fn render_command(instance_name: &str, cmd: &FsCommand) -> String {
+        let Value::Object(fields) = serde_json::to_value(cmd).unwrap() else {
+            unreachable!()
+        };
+        let fields: Vec<(&str, &str)> = fields
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.as_str()?)))
+            .filter(|(name, value)| *name != "path" || !value.is_empty())
+            .collect();
+        render(instance_name, &fields)
+    }
+
+    fn holds_own_closing_tag(cmd: &FsCommand) -> bool {
+        let Value::Object(fields) = serde_json::to_value(cmd).unwrap() else {
+            unreachable!()
+        };
+        fields
+            .iter()
+            .any(|(name, value)| value.as_str().is_some_and(|value| value.contains(&format!("</{}>", name))))
+    }
+
+    proptest! {
+        #[test]
+        fn rendered_commands_parse_back(
+            cmds in proptest::collection::vec(any_command(), 1..4),
+            filler in "[a-z<>/ \n]{0,12}",
+        ) {
+            prop_assume!(!cmds.iter().any(holds_own_closing_tag));
+            let mut message = filler.clone();
+            for cmd in &cmds {
+                message.push_str(&render_command("default", cmd));
+                message.push_str(&render_command("other", cmd));
+                message.push_str(&filler);
+            }
+            prop_assert_eq!(commands(&message, "default"), cmds);
+        }
+
+        #[test]
+        fn any_text_parses_without_panicking(message in "(<fs-command name=\"default\">|</?[a-z_]{1,8}>|[a-z< >/\n é])*") {
+            let _ = commands(&message, "default");
+        }
+    }
+
+    #[test]
+    fn commands_need_an_operation() {
+        let message = render("default", &[("path", "a.txt")]) + &render("default", &[("operation", "help")]);
+        let parsed = commands(&message, "default");
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].operation, "help");
+
+        let path = render("default", &[("operation", "read-file"), ("path", "\n  a.txt\n")]);
+        assert_eq!(commands(&path, "default")[0].path, "  a.txt");
+    }
+}
@@ -0,0 +1,171 @@
+use crate::error::FsChildError;
+
+/// Canonical form of a path as written in a command: `.` segments and
+/// repeated or trailing slashes are dropped, and each `..` removes the
+/// segment before it. A `..` with nothing left to remove is kept on a
+/// relative path and dropped at the root of an absolute one. Paths holding
+/// NUL or other control characters are refused, since the host interfaces
+/// cannot represent them faithfully.
+pub fn normalize(path: &str) -> Result<String, FsChildError> {
+    if let Some(c) = path.chars().find(|c| c.is_control()) {
+        return Err(FsChildError::InvalidPath(format!(
+            "Path '{}' contains the control character {:?}",
+            path.escape_debug(),
+            c
+        )));
+    }
+    let absolute = path.starts_with('/');
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => match segments.last() {
+                Some(&last) if last != ".." => {
+                    segments.pop();
+                }
+                _ if absolute => {}
+                _ => segments.push(".."),
+            },
+            segment => segments.push(segment),
+        }
+    }
+    let joined = segments.join("/");
+    Ok(match (absolute, joined.is_empty()) {
+        (true, _) => format!("/{}", joined),
+        (false, true) => ".".to_string(),
+        (false, false) => joined,
+    })
+}
+
+/// `relative` resolved against `base` and normalized. An absolute
+/// `relative` ignores `base`.
+pub fn join(base: &str, relative: &str) -> Result<String, FsChildError> {
+    if relative.starts_with('/') {
+        normalize(relative)
+    } else {
+        normalize(&format!("{}/{}", base, relative))
+    }
+}
+
+/// The part of `path` inside the directory `root`, both normalized: `.`
+/// for `root` itself, or `None` if `path` is outside it. A relative `root`
+/// holds the relative paths that do not climb out of it.
+pub fn within(root: &str, path: &str) -> Option<String> {
+    let (root, path) = (normalize(root).ok()?, normalize(path).ok()?);
+    let rest = match root.as_str() {
+        _ if path == root => return Some(".".to_string()),
+        "." => Some(path.as_str()).filter(|p| !p.starts_with('/') && *p != ".." && !p.starts_with("../")),
+        "/" => path.strip_prefix('/'),
+        root => path.strip_prefix(root).and_then(|rest| rest.strip_prefix('/')),
+    };
+    rest.map(str::to_string)
+}
+
+/// How many directories deep `root` is, so the innermost of several
+/// roots holding a path can be told apart.
+pub fn depth(root: &str) -> usize {
+    normalize(root).map_or(0, |root| root.split('/').filter(|s| !s.is_empty() && *s != ".").count())
+}
+
+/// Where `entry` falls relative to `mark` in a walk that visits each
+/// directory's entries in sorted order, parents before their contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// Visited before `mark`, subtree included
+    Before,
+    /// `mark` itself
+    Same,
+    /// A directory holding `mark`
+    Ancestor,
+    /// Visited after `mark`
+    After,
+}
+
+pub fn walk_order(entry: &str, mark: &str) -> WalkOrder {
+    if entry == mark {
+        return WalkOrder::Same;
+    }
+    if mark.strip_prefix(entry).is_some_and(|rest| rest.starts_with('/')) {
+        return WalkOrder::Ancestor;
+    }
+    if entry.split('/').lt(mark.split('/')) {
+        WalkOrder::Before
+    } else {
+        WalkOrder::After
+    }
+}
+
+/// A path as shown in a line-per-path listing: as it is, unless it holds
+/// a control character such as a newline (possible in names the host
+/// reports), in which case it is quoted with escapes so it stays on one
+/// line.
+pub fn display(path: &str) -> String {
+    if path.chars().any(char::is_control) {
+        format!("\"{}\"", path.escape_debug())
+    } else {
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_dots_and_slashes() {
+        for (path, expected) in [
+            ("./a//b/../c", "a/c"),
+            ("a/b/", "a/b"),
+            ("", "."),
+            ("./", "."),
+            ("a/..", "."),
+            ("../x/./y", "../x/y"),
+            ("a/../../b", "../b"),
+            ("/a/../../b//", "/b"),
+            ("/", "/"),
+            ("dir with spaces/ü.txt", "dir with spaces/ü.txt"),
+        ] {
+            assert_eq!(normalize(path).unwrap(), expected, "{path}");
+        }
+        assert_eq!(join(".", "src//lib.rs").unwrap(), "src/lib.rs");
+        assert_eq!(join("/work", "../etc").unwrap(), "/etc");
+        assert_eq!(join("/work", "/tmp/x").unwrap(), "/tmp/x");
+    }
+
+    #[test]
+    fn finds_paths_within_roots() {
+        assert_eq!(within("/work", "/work/src/../lib.rs").as_deref(), Some("lib.rs"));
+        assert_eq!(within("/work/", "/work").as_deref(), Some("."));
+        assert_eq!(within("/work", "/workshop/x"), None);
+        assert_eq!(within("/", "/etc").as_deref(), Some("etc"));
+        assert_eq!(within(".", "src/lib.rs").as_deref(), Some("src/lib.rs"));
+        assert_eq!(within(".", "../x"), None);
+        assert_eq!(within(".", "/x"), None);
+        assert_eq!(depth("/work/docs"), 2);
+        assert_eq!(depth("."), 0);
+    }
+
+    #[test]
+    fn orders_entries_by_segment() {
+        assert_eq!(walk_order("a", "a/b"), WalkOrder::Ancestor);
+        assert_eq!(walk_order("a/b", "a/b"), WalkOrder::Same);
+        // "a-z" sorts before "a/" as a string but after "a" as a segment
+        assert_eq!(walk_order("a-z", "a/b"), WalkOrder::After);
+        assert_eq!(walk_order("a/a", "a/b"), WalkOrder::Before);
+        assert_eq!(walk_order("ab", "a/b"), WalkOrder::After);
+        assert_eq!(walk_order("a/c/d", "a/b"), WalkOrder::After);
+    }
+
+    #[test]
+    fn displays_names_on_one_line() {
+        assert_eq!(display("my file's \"name\".txt"), "my file's \"name\".txt");
+        assert_eq!(display("two\nlines"), "\"two\\nlines\"");
+    }
+
+    #[test]
+    fn refuses_control_characters() {
+        for path in ["a\0b", "line\nbreak", "tab\there"] {
+            assert!(matches!(normalize(path), Err(FsChildError::InvalidPath(_))), "{path:?}");
+        }
+    }
+}
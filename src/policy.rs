@@ -0,0 +1,196 @@
+//! Path rules from the configuration that narrow what commands may do
+//! beyond the instance's permissions.
+//!
+//! Each rule has a glob `match` and may `allow` or `deny` actions and cap
+//! the bytes a command writes. An action is a permission (`read`, `write`),
+//! an operation name, or an operation name without its `-file` suffix
+//! (`edit` for `edit-file`). The first rule matching a path decides for it:
+//! an action it denies, or one missing from its `allow` list, is refused.
+//! Paths no rule matches are governed by the permissions alone.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PERMISSIONS;
+use crate::operations::OPERATIONS;
+use crate::watch;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Actions allowed on matching paths; any not denied when left out
+    pub allow: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// Most bytes a command may write to a matching path
+    pub max_bytes: Option<usize>,
+}
+
+/// What a command is, as rules see it.
+pub struct Action<'a> {
+    pub operation: &'a str,
+    /// The permission the operation needs
+    pub permission: Option<&'a str>,
+    /// Bytes the command writes, for writing commands
+    pub writes: Option<usize>,
+}
+
+impl Action<'_> {
+    fn named(&self, action: &str) -> bool {
+        self.permission == Some(action)
+            || self.operation == action
+            || self.operation.strip_suffix("-file") == Some(action)
+    }
+}
+
+/// The rule that decided for a path, and why it refused, if it did.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Decision {
+    /// Position of the rule in the configuration, from 1
+    pub rule: Option<usize>,
+    pub denied: Option<Denial>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Denial {
+    pub reason: String,
+    /// Refused for size rather than for the action itself
+    pub too_large: bool,
+    /// The change to the rule that would let the command through, as in
+    /// "removing 'write' from `deny`"
+    pub grant: String,
+}
+
+impl Rule {
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("match '{}'", self.pattern)];
+        if let Some(allow) = &self.allow {
+            parts.push(format!("allow [{}]", allow.join(", ")));
+        }
+        if !self.deny.is_empty() {
+            parts.push(format!("deny [{}]", self.deny.join(", ")));
+        }
+        if let Some(max) = self.max_bytes {
+            parts.push(format!("max_bytes {}", max));
+        }
+        parts.join(", ")
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        watch::glob_match(&self.pattern, path)
+    }
+
+    fn refusal(&self, action: &Action) -> Option<Denial> {
+        let refused = |reason: String, grant: String| Some(Denial { reason, too_large: false, grant });
+        if let Some(denied) = self.deny.iter().find(|a| action.named(a)) {
+            return refused(format!("'{}' is denied", denied), format!("removing '{}' from `deny`", denied));
+        }
+        if let Some(allow) = &self.allow {
+            if !allow.iter().any(|a| action.named(a)) {
+                return refused(
+                    format!("'{}' is not among the allowed actions", action.operation),
+                    format!("adding '{}' to `allow`", action.operation),
+                );
+            }
+        }
+        match (self.max_bytes, action.writes) {
+            (Some(max), Some(bytes)) if bytes > max => Some(Denial {
+                reason: format!("{} bytes is over the {} byte limit", bytes, max),
+                too_large: true,
+                grant: format!("raising `max_bytes` to at least {}", bytes),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Check a rule when the configuration is read.
+pub fn check(rule: &Rule) -> Result<(), String> {
+    if rule.pattern.trim().is_empty() {
+        return Err("`match` must not be empty".to_string());
+    }
+    let known = |action: &str| {
+        PERMISSIONS.contains(&action)
+            || OPERATIONS
+                .iter()
+                .any(|spec| spec.name == action || spec.name.strip_suffix("-file") == Some(action))
+    };
+    let actions = rule.allow.iter().flatten().chain(&rule.deny);
+    if let Some(unknown) = actions.into_iter().find(|a| !known(a)) {
+        return Err(format!("unknown action '{}'; use a permission or an operation name", unknown));
+    }
+    if rule.max_bytes == Some(0) {
+        return Err("`max_bytes` must be greater than 0".to_string());
+    }
+    Ok(())
+}
+
+/// The decision of the first rule matching `path`.
+pub fn evaluate(rules: &[Rule], action: &Action, path: &str) -> Decision {
+    match rules.iter().position(|rule| rule.matches(path)) {
+        Some(index) => Decision { rule: Some(index + 1), denied: rules[index].refusal(action) },
+        None => Decision { rule: None, denied: None },
+    }
+}
+
+/// One line per rule up to the one that decided for `path`, saying how
+/// each was weighed.
+pub fn explain(rules: &[Rule], action: &Action, path: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        if !rule.matches(path) {
+            lines.push(format!("rule {} ({}): does not match", i + 1, rule.describe()));
+            continue;
+        }
+        lines.push(match rule.refusal(action) {
+            Some(denial) => format!("rule {} ({}): matches and refuses: {}", i + 1, rule.describe(), denial.reason),
+            None => format!("rule {} ({}): matches and allows", i + 1, rule.describe()),
+        });
+        return lines;
+    }
+    lines.push("no rule matches, so the permissions alone decide".to_string());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, allow: Option<&[&str]>, deny: &[&str], max_bytes: Option<usize>) -> Rule {
+        Rule {
+            pattern: pattern.to_string(),
+            allow: allow.map(|a| a.iter().map(|s| s.to_string()).collect()),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+            max_bytes,
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rules = [
+            rule("src/**/*.rs", Some(&["read", "edit"]), &[], Some(100)),
+            rule("**/*.lock", None, &["write"], None),
+        ];
+        let edit = Action { operation: "edit-file", permission: Some("write"), writes: Some(10) };
+        let write = Action { operation: "write-file", permission: Some("write"), writes: Some(10) };
+        let big_edit = Action { operation: "edit-file", permission: Some("write"), writes: Some(500) };
+
+        assert_eq!(evaluate(&rules, &edit, "src/lib.rs"), Decision { rule: Some(1), denied: None });
+        assert!(evaluate(&rules, &write, "src/lib.rs").denied.unwrap().reason.contains("not among"));
+        assert!(evaluate(&rules, &big_edit, "src/a/b.rs").denied.unwrap().too_large);
+        assert!(evaluate(&rules, &write, "Cargo.lock").denied.is_some());
+        assert_eq!(evaluate(&rules, &write, "README.md"), Decision { rule: None, denied: None });
+
+        let lines = explain(&rules, &write, "Cargo.lock");
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("rule 2 (match '**/*.lock', deny [write]): matches and refuses"));
+    }
+
+    #[test]
+    fn rejects_unknown_actions() {
+        assert!(check(&rule("src/**", Some(&["read", "edit", "delete-file"]), &[], None)).is_ok());
+        assert!(check(&rule("src/**", Some(&["execute"]), &[], None)).unwrap_err().contains("'execute'"));
+        assert!(check(&rule("", None, &[], None)).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! Pretty-printing of structured files before they are written, so files an
+//! agent rewrites keep a layout that diffs well, and the one-line JSON that
+//! NDJSON logs need.
+
+use serde_json::Value;
+#[cfg(feature = "structured")]
+use toml_edit::{DocumentMut, Item, Table};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Kind {
+    /// The format a file's extension names, if it is one we format.
+    pub fn from_path(path: &str) -> Option<Kind> {
+        let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(Kind::Json),
+            "toml" => Some(Kind::Toml),
+            "yaml" | "yml" => Some(Kind::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Json => "json",
+            Kind::Toml => "toml",
+            Kind::Yaml => "yaml",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Formatted(String),
+    /// Left as it was, for the reason given
+    Skipped(&'static str),
+}
+
+/// `text` laid out in the usual style for `kind`. Content that does not
+/// parse is an error, so a broken file is never written as if formatted.
+///
+/// JSON is indented by two spaces, keeping key order unless `sort_keys`.
+/// TOML keeps its comments and order; only the spacing around `=` and
+/// before trailing comments is made uniform. YAML is re-emitted by the
+/// YAML serializer, which would drop comments and expand aliases, so YAML
+/// using either is skipped.
+pub fn format(kind: Kind, text: &str, sort_keys: bool) -> Result<Outcome, String> {
+    match kind {
+        Kind::Json if sort_keys => {
+            // Without serde_json's `preserve_order`, object keys are kept sorted
+            let value: Value = serde_json::from_str(text).map_err(|e| format!("not valid JSON: {}", e))?;
+            let mut out = serde_json::to_string_pretty(&value).map_err(|e| e.to_string())?;
+            out.push('\n');
+            Ok(Outcome::Formatted(out))
+        }
+        Kind::Json => {
+            serde_json::from_str::<serde::de::IgnoredAny>(text).map_err(|e| format!("not valid JSON: {}", e))?;
+            Ok(Outcome::Formatted(indent_json(text)))
+        }
+        #[cfg(feature = "structured")]
+        Kind::Toml => {
+            let mut doc = text
+                .parse::<DocumentMut>()
+                .map_err(|e| format!("not valid TOML: {}", e.to_string().trim_end()))?;
+            tidy_table(doc.as_table_mut());
+            let mut out = doc.to_string();
+            if !out.ends_with('\n') {
+                out.push('\n');
+            }
+            Ok(Outcome::Formatted(out))
+        }
+        #[cfg(feature = "structured")]
+        Kind::Yaml => {
+            let value: serde_yaml::Value = serde_yaml::from_str(text).map_err(|e| format!("not valid YAML: {}", e))?;
+            if has_yaml_comments(text) {
+                return Ok(Outcome::Skipped("it has comments"));
+            }
+            if has_yaml_aliases(text) {
+                return Ok(Outcome::Skipped("it uses anchors or aliases"));
+            }
+            serde_yaml::to_string(&value)
+                .map(Outcome::Formatted)
+                .map_err(|e| format!("cannot write it back as YAML: {}", e))
+        }
+        #[cfg(not(feature = "structured"))]
+        Kind::Toml | Kind::Yaml => Ok(Outcome::Skipped("this build formats JSON only")),
+    }
+}
+
+/// Reindent valid JSON without parsing it into a map, so key order and the
+/// exact spelling of numbers and strings are kept.
+fn indent_json(text: &str) -> String {
+    fn newline(out: &mut String, depth: usize) {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+
+    let mut out = String::with_capacity(text.len() + text.len() / 4);
+    let mut depth = 0usize;
+    let mut chars = text.chars().peekable();
+    let (mut in_string, mut escaped) = (false, false);
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            '{' | '[' => {
+                out.push(c);
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                match chars.next_if(|c| matches!(c, '}' | ']')) {
+                    Some(close) => out.push(close),
+                    None => {
+                        depth += 1;
+                        newline(&mut out, depth);
+                    }
+                }
+            }
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut out, depth);
+                out.push(c);
+            }
+            ',' => {
+                out.push(c);
+                newline(&mut out, depth);
+            }
+            ':' => out.push_str(": "),
+            c if c.is_whitespace() => {}
+            c => out.push(c),
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// Valid JSON on one line, with the whitespace between tokens removed and
+/// key order kept.
+pub fn compact_json(text: &str) -> Result<String, String> {
+    serde_json::from_str::<serde::de::IgnoredAny>(text).map_err(|e| format!("not valid JSON: {}", e))?;
+    let mut out = String::with_capacity(text.len());
+    let (mut in_string, mut escaped) = (false, false);
+    for c in text.chars() {
+        match c {
+            _ if in_string => {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+                out.push(c);
+            }
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            c if c.is_whitespace() => {}
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// Put `key = value` spacing on every plain key-value pair, keeping the
+/// comments above and after it. Dotted keys are left as written.
+#[cfg(feature = "structured")]
+fn tidy_table(table: &mut Table) {
+    for (mut key, item) in table.iter_mut() {
+        match item {
+            Item::Value(value) => {
+                let decor = key.leaf_decor_mut();
+                let prefix = decor.prefix().and_then(|p| p.as_str()).unwrap_or_default();
+                let prefix = prefix.trim_end_matches([' ', '\t']).to_string();
+                decor.set_prefix(prefix);
+                decor.set_suffix(" ");
+
+                let decor = value.decor_mut();
+                let comment = decor.suffix().and_then(|s| s.as_str()).unwrap_or_default().trim().to_string();
+                decor.set_prefix(" ");
+                decor.set_suffix(if comment.is_empty() { comment } else { format!(" {}", comment) });
+            }
+            Item::Table(table) if !table.is_dotted() => tidy_table(table),
+            Item::ArrayOfTables(tables) => tables.iter_mut().for_each(tidy_table),
+            _ => {}
+        }
+    }
+}
+
+/// Whether a `#` could start a comment: at the start of a line or after
+/// whitespace. A `#` inside a quoted string also counts, which only costs
+/// a skipped format.
+#[cfg(feature = "structured")]
+fn has_yaml_comments(text: &str) -> bool {
+    text.lines().any(|line| line.trim_start().starts_with('#') || line.contains(" #") || line.contains("\t#"))
+}
+
+/// Whether an `&anchor` or `*alias` appears where YAML would read one: at
+/// the start of a line, or after whitespace or a flow `,`, `[` or `{`.
+#[cfg(feature = "structured")]
+fn has_yaml_aliases(text: &str) -> bool {
+    let mut previous = None;
+    for (i, c) in text.char_indices() {
+        if matches!(c, '&' | '*')
+            && previous.is_none_or(|p: char| p.is_whitespace() || matches!(p, ',' | '[' | '{'))
+            && text[i + 1..].chars().next().is_some_and(|n| !n.is_whitespace() && !matches!(n, ',' | '[' | ']' | '{' | '}'))
+        {
+            return true;
+        }
+        previous = Some(c);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_json_keeping_or_sorting_keys() {
+        let text = r#"{"b":1,"a":{"s":"x, \"y\": {z}","e":[],"n":1.50},"l":[1,{}]}"#;
+        let Outcome::Formatted(kept) = format(Kind::Json, text, false).unwrap() else { panic!() };
+        assert_eq!(
+            kept,
+            "{\n  \"b\": 1,\n  \"a\": {\n    \"s\": \"x, \\\"y\\\": {z}\",\n    \"e\": [],\n    \"n\": 1.50\n  },\n  \"l\": [\n    1,\n    {}\n  ]\n}\n"
+        );
+        let Outcome::Formatted(sorted) = format(Kind::Json, text, true).unwrap() else { panic!() };
+        assert!(sorted.starts_with("{\n  \"a\": {\n    \"e\": [],\n    \"n\": 1.5,\n"));
+        assert!(format(Kind::Json, "{\"a\":", false).is_err());
+        assert_eq!(compact_json(" {\"b\": [1, 2],\n \"a\": \"x y\\\" z\"}\n").unwrap(), r#"{"b":[1,2],"a":"x y\" z"}"#);
+    }
+
+    #[test]
+    #[cfg(feature = "structured")]
+    fn tidies_toml_and_yaml() {
+        let toml = "# top\n[package]\nname=\"a\"   # the name\n  version   =  \"1\"\n\n[dependencies]\nserde.workspace=true\n";
+        assert_eq!(
+            format(Kind::Toml, toml, false).unwrap(),
+            Outcome::Formatted("# top\n[package]\nname = \"a\" # the name\nversion = \"1\"\n\n[dependencies]\nserde.workspace=true\n".to_string())
+        );
+        assert_eq!(
+            format(Kind::Yaml, "a:   1\nb: [x,  y]\n", false).unwrap(),
+            Outcome::Formatted("a: 1\nb:\n- x\n- y\n".to_string())
+        );
+        assert_eq!(format(Kind::Yaml, "a: 1 # one\n", false).unwrap(), Outcome::Skipped("it has comments"));
+        assert_eq!(format(Kind::Yaml, "a: &x 1\nb: *x\n", false).unwrap(), Outcome::Skipped("it uses anchors or aliases"));
+        assert!(!has_yaml_aliases("a: 2 * 3\nb: x&y\n"));
+        assert_eq!(Kind::from_path("config/App.YML"), Some(Kind::Yaml));
+    }
+}
@@ -0,0 +1,97 @@
+//! Commands held back with `defer` to run on a later head-update, such as
+//! cleanup that should wait until a change has been reviewed.
+//!
+//! A deferred command is checked against the permissions when it arrives and
+//! run, with its result reported, on the head-update it comes due, after
+//! that message's own commands.
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::FsCommand;
+
+/// Most commands waiting at once
+pub const MAX_TASKS: usize = 64;
+
+/// Most head-updates a command can be held back
+pub const MAX_DELAY: u64 = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    pub id: u64,
+    /// The command to run, without its `defer`
+    pub command: FsCommand,
+    /// Head the command arrived in
+    pub head: String,
+    /// Turn it was queued on, and the turn it runs on
+    pub queued: u64,
+    pub due: u64,
+}
+
+impl Task {
+    pub fn describe(&self, turn: u64) -> String {
+        let path = if self.command.path.is_empty() { String::new() } else { format!(" on '{}'", self.command.path) };
+        format!(
+            "#{} '{}'{}: runs on turn {} (in {} head-updates), queued on turn {}",
+            self.id,
+            self.command.operation,
+            path,
+            self.due,
+            self.due.saturating_sub(turn),
+            self.queued
+        )
+    }
+}
+
+/// How many head-updates a `defer` value holds a command back: `true` for
+/// the next one, or a count. `None` runs the command now.
+pub fn delay(value: &str) -> Result<Option<u64>, String> {
+    match value.trim() {
+        "true" | "yes" => Ok(Some(1)),
+        "" | "false" | "no" | "0" => Ok(None),
+        count => match count.parse::<u64>() {
+            Ok(count) if count <= MAX_DELAY => Ok(Some(count)),
+            Ok(_) => Err(format!("`defer` can hold a command back at most {} head-updates, not {}", MAX_DELAY, count)),
+            Err(_) => Err(format!("`defer` must be true or a number of head-updates, not '{}'", count)),
+        },
+    }
+}
+
+/// Remove and return the tasks due by `turn`, in the order they were queued.
+pub fn take_due(tasks: &mut Vec<Task>, turn: u64) -> Vec<Task> {
+    let (due, waiting) = std::mem::take(tasks).into_iter().partition(|task| task.due <= turn);
+    *tasks = waiting;
+    due
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_delays() {
+        assert_eq!(delay("true").unwrap(), Some(1));
+        assert_eq!(delay(" 3 ").unwrap(), Some(3));
+        assert_eq!(delay("false").unwrap(), None);
+        assert!(delay("later").unwrap_err().contains("'later'"));
+        assert_eq!(delay("10000").unwrap(), Some(MAX_DELAY));
+        assert!(delay("10001").is_err());
+        assert!(delay("18446744073709551615").is_err());
+    }
+
+    #[test]
+    fn takes_due_tasks_in_order() {
+        let task = |id: u64, due: u64| Task {
+            id,
+            command: serde_json::from_value(json!({"operation": "delete-file", "path": "tmp.txt"})).unwrap(),
+            head: "h".to_string(),
+            queued: 1,
+            due,
+        };
+        let mut tasks = vec![task(1, 3), task(2, 2), task(3, 5)];
+        let due: Vec<u64> = take_due(&mut tasks, 3).iter().map(|t| t.id).collect();
+        assert_eq!(due, vec![1, 2]);
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].describe(3), "#3 'delete-file' on 'tmp.txt': runs on turn 5 (in 2 head-updates), queued on turn 1");
+    }
+}
@@ -0,0 +1,159 @@
+//! Limits on how much a part of the workspace may hold, for handing an
+//! instance to automation that should not fill the disk.
+//!
+//! A quota caps the bytes and the number of files under a path prefix,
+//! `.` being the whole workspace. Usage is measured by walking the prefix
+//! and then kept up to date from the sizes of the files each command writes.
+//! A command that takes a hard quota over its limit is undone and fails; one
+//! that goes over a soft quota succeeds with a warning.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Limit {
+    /// Commands going over are undone and fail
+    #[default]
+    Hard,
+    /// Commands going over succeed with a warning
+    Soft,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+    #[serde(default)]
+    pub limit: Limit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    /// Account for a file going from `before` bytes to `after`, `None`
+    /// meaning no file.
+    pub fn apply(&mut self, before: Option<usize>, after: Option<usize>) {
+        self.bytes = (self.bytes + after.unwrap_or(0) as u64).saturating_sub(before.unwrap_or(0) as u64);
+        match (before, after) {
+            (None, Some(_)) => self.files += 1,
+            (Some(_), None) => self.files = self.files.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    /// Whether this holds more than `other` in bytes or files.
+    pub fn grew_from(&self, other: &Usage) -> bool {
+        self.bytes > other.bytes || self.files > other.files
+    }
+}
+
+impl Quota {
+    /// How `usage` is over this quota, if it is.
+    pub fn excess(&self, usage: &Usage) -> Option<String> {
+        let mut over = Vec::new();
+        if let Some(max) = self.max_bytes.filter(|max| usage.bytes > *max) {
+            over.push(format!("{} bytes of {} allowed", usage.bytes, max));
+        }
+        if let Some(max) = self.max_files.filter(|max| usage.files > *max) {
+            over.push(format!("{} files of {} allowed", usage.files, max));
+        }
+        (!over.is_empty()).then(|| over.join(", "))
+    }
+
+    /// One line of a usage report.
+    pub fn describe(&self, prefix: &str, usage: &Usage) -> String {
+        let of = |used: u64, max: Option<u64>| match max {
+            Some(max) => format!("{}/{}", used, max),
+            None => used.to_string(),
+        };
+        let limit = match self.limit {
+            Limit::Hard => "hard",
+            Limit::Soft => "soft",
+        };
+        format!(
+            "{}: {} bytes, {} files ({})",
+            prefix,
+            of(usage.bytes, self.max_bytes),
+            of(usage.files, self.max_files),
+            limit
+        )
+    }
+}
+
+/// Check a quota when the configuration is read, returning its prefix
+/// normalized.
+pub fn check(prefix: &str, quota: &Quota) -> Result<String, String> {
+    let normalized = path::normalize(prefix).map_err(|e| e.to_string())?;
+    if normalized.starts_with('/') || normalized.starts_with("..") {
+        return Err(format!("'{}' is not under `base_path`", prefix));
+    }
+    if quota.max_bytes.is_none() && quota.max_files.is_none() {
+        return Err("needs `max_bytes` or `max_files`".to_string());
+    }
+    if quota.max_bytes == Some(0) || quota.max_files == Some(0) {
+        return Err("limits must be greater than 0".to_string());
+    }
+    Ok(normalized)
+}
+
+/// Whether `path`, relative to `base_path`, falls under `prefix`.
+pub fn covers(prefix: &str, path: &str) -> bool {
+    prefix == "."
+        || path == prefix
+        || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The usage report: one line per quota, noting those `usage` lacks as
+/// too large to measure.
+pub fn report(quotas: &BTreeMap<String, Quota>, usage: &BTreeMap<String, Usage>) -> Vec<String> {
+    quotas
+        .iter()
+        .map(|(prefix, quota)| match usage.get(prefix) {
+            Some(usage) => quota.describe(prefix, usage),
+            None => format!("{}: not measured; it holds more than the walk limits reach", prefix),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_for_writes() {
+        let quota = Quota { max_bytes: Some(100), max_files: Some(2), limit: Limit::Hard };
+        let mut usage = Usage { bytes: 90, files: 2 };
+        let before = usage;
+        usage.apply(Some(10), Some(30));
+        assert_eq!(usage, Usage { bytes: 110, files: 2 });
+        assert!(usage.grew_from(&before));
+        assert_eq!(quota.excess(&usage).unwrap(), "110 bytes of 100 allowed");
+
+        usage.apply(None, Some(0));
+        assert_eq!(quota.excess(&usage).unwrap(), "110 bytes of 100 allowed, 3 files of 2 allowed");
+        usage.apply(Some(110), None);
+        assert_eq!(usage, Usage { bytes: 0, files: 2 });
+        assert_eq!(quota.excess(&usage), None);
+        assert_eq!(quota.describe("src", &usage), "src: 0/100 bytes, 2/2 files (hard)");
+    }
+
+    #[test]
+    fn checks_prefixes() {
+        let quota = Quota { max_bytes: Some(1), max_files: None, limit: Limit::Soft };
+        assert_eq!(check("./src/", &quota).unwrap(), "src");
+        assert_eq!(check(".", &quota).unwrap(), ".");
+        assert!(check("../up", &quota).is_err());
+        assert!(check("src", &Quota { max_bytes: None, ..quota.clone() }).is_err());
+
+        assert!(covers("src", "src/lib.rs") && covers(".", "README.md"));
+        assert!(!covers("src", "srcs/lib.rs"));
+    }
+}
@@ -0,0 +1,336 @@
+//! How responses look to the parent: the plain text every chat shows, and
+//! the HTML shown when the parent renders it. Everything interpolated into
+//! HTML goes through [`escape_html`].
+//!
+//! The output of each function is pinned by a snapshot in
+//! `tests/golden/render/`, so a change in styling or escaping shows up as a
+//! diff there.
+//!
+//! The HTML functions are only compiled with the `html` feature. Callers
+//! wrap them in [`html!`], which leaves the call out of other builds.
+
+use crate::operations::{operation_spec, OperationSpec, INTRO_EXAMPLES, OPERATIONS};
+use crate::overview::ExamplePaths;
+use crate::watch::{ChangeKind, PathChange};
+use crate::commands::CommandResult;
+
+/// `Some` HTML from the rendering expression, or `None` in a build without
+/// the `html` feature, where the expression is not compiled at all.
+macro_rules! html {
+    ($render:expr) => {{
+        #[cfg(feature = "html")]
+        let html = Some($render);
+        #[cfg(not(feature = "html"))]
+        let html: Option<String> = None;
+        html
+    }};
+}
+pub(crate) use html;
+
+/// Escape text for interpolation into HTML, in element content or a quoted
+/// attribute.
+#[cfg(feature = "html")]
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Result messages, one paragraph each.
+pub fn results_text(results: &[CommandResult]) -> String {
+    results
+        .iter()
+        .map(|result| result.message.clone())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(feature = "html")]
+pub fn results_html(results: &[CommandResult]) -> String {
+    // Create HTML version with nice formatting based on operation type
+    let mut html_parts = Vec::new();
+
+    for result in results {
+        let op_type = &result.operation;
+        let (icon, color) = match op_type.as_str() {
+            "read-file" => ("📄", "#3B82F6"), // Blue for read
+            "read-around" => ("🔎", "#3B82F6"),
+            "read-base64" => ("📦", "#3B82F6"),
+            "frontmatter-get" => ("🏷️", "#3B82F6"),
+            "frontmatter-set" => ("🏷️", "#8B5CF6"),
+            "cargo-add-dep" | "cargo-set-version" => ("🦀", "#8B5CF6"),
+            "generate-toc" => ("📑", "#8B5CF6"),
+            "csv-to-json" | "json-to-csv" => ("🔀", "#10B981"),
+            "append-ndjson" => ("➕", "#10B981"),
+            "manifest" => ("🧮", "#F59E0B"),
+            "compact" => ("🗜️", "#64748B"),
+            "render" => ("🧱", "#10B981"),
+            "export-dir" | "import-dir" => ("🗜️", "#0EA5E9"),
+            "file-type" => ("🔎", "#3B82F6"),
+            "write-file" => ("✏️", "#10B981"), // Green for write
+            "edit-file" => ("🔄", "#8B5CF6"),   // Purple for edit
+            "list-files" => ("📁", "#F59E0B"), // Yellow for list
+            "create-dir" => ("📂", "#10B981"), // Green for create
+            "delete-file" => ("🗑️", "#EF4444"), // Red for delete
+            "fetch-from-store" => ("📥", "#0EA5E9"), // Sky for store transfers
+            "push-to-store" => ("📤", "#0EA5E9"),
+            "restore-from-store" => ("⏪", "#0EA5E9"),
+            "gc-backups" => ("🧹", "#0EA5E9"),
+            "subscribe" | "unsubscribe" => ("🔔", "#6366F1"),
+            "continue" => ("⏭️", "#6366F1"),
+            "help" => ("💡", "#6366F1"),
+            "export-history" => ("🧾", "#6366F1"),
+            "define-macro" | "run-macro" => ("🧩", "#6366F1"),
+            "commit" | "checkout" => ("🔖", "#8B5CF6"),
+            "stage" | "preview-staged" | "commit-staged" | "discard-staged" => ("🗂️", "#8B5CF6"),
+            "why-denied" => ("🛡️", "#6366F1"),
+            "queue" => ("⏳", "#6366F1"),
+            _ => ("❓", "#6B7280"),            // Gray for unknown
+        };
+
+        html_parts.push(format!(r#"<div style="margin-bottom: 1rem;">
+            <div style="display: flex; align-items: center; margin-bottom: 0.5rem;">
+                <span style="margin-right: 0.5rem;">{icon}</span>
+                <span style="color: {color}; font-weight: bold;">{op_type}</span>
+            </div>
+            <div style="background: var(--bg-tertiary); padding: 0.75rem; border-radius: var(--radius-sm);">
+                <pre style="margin: 0; white-space: pre-wrap;"><code>{result}</code></pre>
+            </div>
+        </div>"#, icon = icon, color = color, op_type = escape_html(op_type), result = escape_html(&result.message)));
+    }
+
+    format!(r#"<div style="background: var(--bg-secondary); border: 1px solid var(--border-color); border-radius: var(--radius-md); padding: 1rem;">
+        <h3 style="color: var(--accent-primary); margin-bottom: 0.75rem;">Filesystem Operation Results</h3>
+        {results_html}
+    </div>
+    "#, results_html = html_parts.join(""))
+}
+
+/// One part of a response split to fit `max_message_bytes`.
+#[cfg(feature = "html")]
+pub fn part_html(part: usize, total: usize, text: &str) -> String {
+    format!(r#"<div style="background: var(--bg-secondary); border: 1px solid var(--border-color); border-radius: var(--radius-md); padding: 1rem;">
+                    <h3 style="color: var(--accent-primary); margin-bottom: 0.75rem;">Filesystem Operation Results (part {part}/{total})</h3>
+                    <pre style="margin: 0; white-space: pre-wrap;"><code>{text}</code></pre>
+                </div>"#, part = part, total = total, text = escape_html(text))
+}
+
+/// One line per change, as `  <kind>: <path>`.
+pub fn changes_text(changes: &[PathChange]) -> String {
+    changes
+        .iter()
+        .map(|change| {
+            let kind = match change.kind {
+                ChangeKind::Added => "added",
+                ChangeKind::Modified => "modified",
+                ChangeKind::Removed => "removed",
+            };
+            format!("  {}: {}", kind, change.path)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(feature = "html")]
+pub fn changes_html(changes: &[PathChange]) -> String {
+    format!(r#"<div style="background: var(--bg-secondary); border: 1px solid var(--border-color); border-radius: var(--radius-md); padding: 1rem;">
+        <h3 style="color: var(--accent-primary); margin-bottom: 0.75rem;">Subscribed Paths Changed</h3>
+        <pre style="margin: 0; white-space: pre-wrap;"><code>{}</code></pre>
+    </div>"#, escape_html(&changes_text(changes)))
+}
+
+/// A message that could not be handled at all.
+#[cfg(feature = "html")]
+pub fn error_html(text: &str) -> String {
+    format!(r#"<div style="background: var(--bg-secondary); border: 1px solid var(--border-color); border-radius: var(--radius-md); padding: 1rem;">
+                        <h3 style="color: #EF4444; margin-bottom: 0.75rem;">Error</h3>
+                        <div style="background: var(--bg-tertiary); padding: 0.75rem; border-radius: var(--radius-sm);">
+                            <p style="margin: 0;">{}</p>
+                        </div>
+                    </div>
+                    "#, escape_html(text))
+}
+
+/// A short plain message, for protocol-level problems.
+#[cfg(feature = "html")]
+pub fn notice_html(text: &str) -> String {
+    format!(
+        "<div style=\"color: var(--text-primary); padding: 0.5rem;\"><p>{}</p></div>",
+        escape_html(text)
+    )
+}
+
+/// The reply to an introduction: what the instance can do. Examples use
+/// the workspace's own paths where `paths` has one for the operation.
+pub fn intro_text(name: &str, permissions: &[String], paths: &ExamplePaths) -> String {
+    let operations = intro_operations()
+        .map(|spec| match spec.permission {
+            Some(permission) => format!("- {} (requires '{}'): {}", spec.name, permission, spec.description),
+            None => format!("- {}: {}", spec.name, spec.description),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let examples = intro_examples()
+        .enumerate()
+        .map(|(i, spec)| format!("{}. {}:\n{}", i + 1, spec.name, example(spec, name, paths)))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    format!(
+        "Filesystem operations for '{}' initialized.\n\nAvailable commands (with required permissions):\n{}\n\nCommand formats:\n\n{}\n\nCurrent permissions: {}",
+        name,
+        operations,
+        examples,
+        permissions.join(", ")
+    )
+}
+
+#[cfg(feature = "html")]
+pub fn intro_html(name: &str, permissions: &[String], paths: &ExamplePaths) -> String {
+    let operations: String = intro_operations()
+        .map(|spec| {
+            let permission = spec.permission.map(|p| format!(" (requires '{}')", p)).unwrap_or_default();
+            format!(
+                "\n                    <li><code>{}</code> - {}{}</li>",
+                spec.name,
+                escape_html(spec.description),
+                escape_html(&permission)
+            )
+        })
+        .collect();
+    let examples: String = intro_examples()
+        .map(|spec| {
+            format!(
+                r#"
+                <div style="background: var(--bg-tertiary); padding: 0.75rem; border-radius: var(--radius-sm); margin-bottom: 0.75rem;">
+                    <pre style="margin: 0;"><code>{}</code></pre>
+                </div>"#,
+                escape_html(&example(spec, name, paths))
+            )
+        })
+        .collect();
+    format!(r#"<div style="background: var(--bg-secondary); border: 1px solid var(--border-color); border-radius: var(--radius-md); padding: 1rem;">
+            <h3 style="color: var(--accent-primary); margin-bottom: 0.75rem;">Filesystem Operations</h3>
+            <p>Operations for <strong>{name}</strong> initialized with permissions: <code>{permissions}</code></p>
+            
+            <div style="margin-top: 1rem;">
+                <h4 style="color: var(--text-primary);">Available Commands:</h4>
+                <ul>{operations}
+                </ul>
+            </div>
+
+            <div style="margin-top: 1rem;">
+                <h4 style="color: var(--text-primary);">Command Examples:</h4>{examples}
+            </div>
+        </div>
+        "#, name = escape_html(name), permissions = escape_html(&permissions.join(", ")), operations = operations, examples = examples)
+}
+
+/// The operations the introduction advertises: every one this build runs.
+fn intro_operations() -> impl Iterator<Item = &'static OperationSpec> {
+    OPERATIONS.iter().filter(|spec| spec.is_built())
+}
+
+fn example(spec: &OperationSpec, name: &str, paths: &ExamplePaths) -> String {
+    spec.example_command_at(name, paths.get(spec.name).map(String::as_str))
+}
+
+/// The operations the introduction shows an example of, in order.
+fn intro_examples() -> impl Iterator<Item = &'static OperationSpec> {
+    INTRO_EXAMPLES.iter().filter_map(|name| operation_spec(name)).filter(|spec| spec.is_built())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FsChildError;
+    use crate::golden;
+
+    fn command(operation: &str, path: &str) -> crate::commands::FsCommand {
+        let message = crate::parser::render("default", &[("operation", operation), ("path", path)]);
+        crate::parser::commands(&message, "default").remove(0)
+    }
+
+    /// A success, a failure and a skip, with markup to escape, then one
+    /// result per operation for its icon and color.
+    fn results() -> Vec<CommandResult> {
+        let mut results = vec![
+            CommandResult::ok(&command("read-file", "a<b>.txt"), "Contents of 'a<b>.txt': \"x\" & 'y'".to_string()),
+            CommandResult::error(&command("write-file", "out.txt"), FsChildError::NotFound("No such file: <out>".to_string())),
+            CommandResult::skipped(&command("edit-file", "x.rs"), "batch-invalid", "Not executed".to_string()),
+        ];
+        for operation in OPERATIONS.iter().map(|spec| spec.name).chain(["<unknown>"]) {
+            results.push(CommandResult::ok(&command(operation, "p"), format!("ran {}", operation)));
+        }
+        results
+    }
+
+    fn changes() -> Vec<PathChange> {
+        vec![
+            PathChange { path: "src/<a>.rs".to_string(), kind: ChangeKind::Added },
+            PathChange { path: "b.rs".to_string(), kind: ChangeKind::Modified },
+            PathChange { path: "c.rs".to_string(), kind: ChangeKind::Removed },
+        ]
+    }
+
+    const PERMISSIONS: [&str; 2] = ["read", "<write>"];
+
+    #[test]
+    fn text_matches_snapshots() {
+        let permissions = PERMISSIONS.map(str::to_string);
+        assert!(golden::matches("render/results.txt", &results_text(&results()[..3])));
+        assert!(golden::matches("render/changes.txt", &changes_text(&changes())));
+        // The introduction leaves out operations the build does not include
+        if cfg!(all(feature = "archive", feature = "structured")) {
+            assert!(golden::matches("render/intro.txt", &intro_text("docs\"&", &permissions, &ExamplePaths::new())));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn html_matches_snapshots() {
+        let permissions = PERMISSIONS.map(str::to_string);
+        assert!(golden::matches("render/results.html", &results_html(&results())));
+        assert!(golden::matches("render/part.html", &part_html(2, 3, "<partial> & more")));
+        assert!(golden::matches("render/changes.html", &changes_html(&changes())));
+        assert!(golden::matches("render/error.html", &error_html("Failed to load message: <bad> & \"worse\"")));
+        assert!(golden::matches("render/notice.html", &notice_html("Unknown message type 'x<y>'")));
+        if cfg!(all(feature = "archive", feature = "structured")) {
+            assert!(golden::matches("render/intro.html", &intro_html("docs\"&", &permissions, &ExamplePaths::new())));
+        }
+    }
+
+    #[test]
+    fn intro_lists_every_operation_built() {
+        let permissions = PERMISSIONS.map(str::to_string);
+        let text = intro_text("default", &permissions, &ExamplePaths::new());
+        #[cfg(feature = "html")]
+        let html = intro_html("default", &permissions, &ExamplePaths::new());
+        for spec in OPERATIONS {
+            let listed = text.lines().any(|line| line.starts_with(&format!("- {} ", spec.name)) || line.starts_with(&format!("- {}:", spec.name)));
+            assert_eq!(listed, spec.is_built(), "{}", spec.name);
+            #[cfg(feature = "html")]
+            assert_eq!(html.contains(&format!("<code>{}</code>", spec.name)), spec.is_built(), "{}", spec.name);
+        }
+        for name in INTRO_EXAMPLES {
+            assert!(operation_spec(name).is_some(), "{}", name);
+        }
+        let examples = crate::parser::commands(&text, "default");
+        let shown: Vec<&str> = examples.iter().map(|cmd| cmd.operation.as_str()).collect();
+        assert_eq!(shown, intro_examples().map(|spec| spec.name).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn escapes_markup_and_quotes() {
+        assert_eq!(escape_html("<a href=\"x\">'&'</a>"), "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;");
+    }
+}
@@ -0,0 +1,84 @@
+//! Batch reports: every result of a batch written in full to a Markdown
+//! file in the workspace, while the response carries a short summary.
+//!
+//! The parent's chat keeps one line per command, and a later `read-file`
+//! of the report brings back any result in full, with its data.
+
+use crate::commands::CommandResult;
+
+/// Directory of the reports, relative to `base_path`
+pub const DIR: &str = ".fs-child/reports";
+
+/// Reports kept; the oldest are deleted first
+pub const MAX_REPORTS: usize = 50;
+
+/// Longest first line of a result shown in the summary
+const SUMMARY_CHARS: usize = 100;
+
+/// Where the report of the batch in `head` is written, relative to
+/// `base_path`. Characters a file name should not hold are replaced.
+pub fn path(head: &str) -> String {
+    let name: String = head
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}/{}.md", DIR, name)
+}
+
+/// The report itself: each result's text in full, then its data.
+pub fn markdown(head: &str, turn: u64, results: &[CommandResult]) -> String {
+    let mut out = format!("# Batch report\n\n- Head: `{}`\n- Turn: {}\n- Commands: {}\n", head, turn, results.len());
+    for (index, result) in results.iter().enumerate() {
+        out.push_str(&format!("\n## {}. {} `{}`: {}\n\n", index + 1, result.operation, result.path, outcome(result)));
+        // A fence longer than any run of backticks in the text
+        let fence = "`".repeat(longest_backtick_run(&result.message).max(2) + 1);
+        out.push_str(&format!("{}\n{}\n{}\n", fence, result.message, fence));
+        if let Some(data) = &result.data {
+            let data = serde_json::to_string_pretty(data).unwrap_or_default();
+            let fence = "`".repeat(longest_backtick_run(&data).max(2) + 1);
+            out.push_str(&format!("\n{}json\n{}\n{}\n", fence, data, fence));
+        }
+    }
+    out
+}
+
+/// One line per result, for the response text.
+pub fn summary(results: &[CommandResult]) -> String {
+    results
+        .iter()
+        .enumerate()
+        .map(|(index, result)| {
+            let first = result.message.lines().next().unwrap_or_default();
+            let first = match first.char_indices().nth(SUMMARY_CHARS) {
+                Some((cut, _)) => format!("{}…", &first[..cut]),
+                None => first.to_string(),
+            };
+            format!("{}. {} {}: {} - {}", index + 1, result.operation, result.path, outcome(result), first)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn outcome(result: &CommandResult) -> String {
+    match (result.error, result.skipped) {
+        (Some(code), _) => format!("{:?}", code),
+        (None, Some(reason)) => format!("skipped ({})", reason),
+        (None, None) => "ok".to_string(),
+    }
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_reports_after_the_head() {
+        assert_eq!(path("head-1"), ".fs-child/reports/head-1.md");
+        assert_eq!(path("../a/b"), ".fs-child/reports/___a_b.md");
+        assert_eq!(longest_backtick_run("a ```` b ` c"), 4);
+    }
+}
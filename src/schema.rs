@@ -0,0 +1,309 @@
+//! Validation of JSON documents against a JSON Schema.
+//!
+//! Covers the keywords config files use in practice: `type`, `enum`,
+//! `const`, object keywords (`properties`, `required`,
+//! `additionalProperties`, `patternProperties`), array keywords (`items`,
+//! `minItems`, `maxItems`, `uniqueItems`), string and number bounds,
+//! `pattern`, the `allOf`/`anyOf`/`oneOf`/`not` combinators, and `$ref` to
+//! a location in the same schema. Unknown keywords are ignored, as the
+//! specification requires. A build without the `regex` feature reports the
+//! pattern keywords as unchecked rather than passing them.
+
+#[cfg(feature = "regex")]
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Refs followed in a row before giving up, so a schema that refers to
+/// itself without consuming input cannot loop.
+const MAX_REF_DEPTH: usize = 32;
+
+/// Stand-in for builds without the `regex` feature, in which no pattern
+/// compiles.
+#[cfg(not(feature = "regex"))]
+enum Regex {}
+
+#[cfg(not(feature = "regex"))]
+impl Regex {
+    fn new(_: &str) -> Result<Regex, ()> {
+        Err(())
+    }
+
+    fn is_match(&self, _: &str) -> bool {
+        match *self {}
+    }
+}
+
+/// Message for a pattern keyword the build cannot check.
+const PATTERNS_UNCHECKED: &str = "cannot be checked: this build has no regular expressions";
+
+/// One way the document breaks the schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    /// JSON pointer to the offending value, `""` for the whole document
+    pub path: String,
+    pub message: String,
+}
+
+/// Every violation of `schema` by `document`; empty when it is valid.
+pub fn validate(schema: &Value, document: &Value) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    Validator { root: schema }.check(schema, document, "", 0, &mut violations);
+    violations
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+}
+
+impl<'a> Validator<'a> {
+    fn check(&self, schema: &'a Value, value: &Value, path: &str, refs: usize, out: &mut Vec<Violation>) {
+        let fail = |out: &mut Vec<Violation>, message: String| {
+            out.push(Violation {
+                path: path.to_string(),
+                message,
+            })
+        };
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => return fail(out, "no value is allowed here".to_string()),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match self.resolve(reference) {
+                Some(_) if refs >= MAX_REF_DEPTH => fail(out, format!("$ref '{}' nests too deeply", reference)),
+                Some(target) => self.check(target, value, path, refs + 1, out),
+                None => fail(out, format!("$ref '{}' does not point into this schema", reference)),
+            }
+        }
+
+        if let Some(types) = schema.get("type") {
+            let allowed: Vec<&str> = match types {
+                Value::String(t) => vec![t.as_str()],
+                Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !allowed.is_empty() && !allowed.iter().any(|t| has_type(value, t)) {
+                fail(out, format!("expected {}, found {}", allowed.join(" or "), type_name(value)));
+            }
+        }
+        if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+            if !options.contains(value) {
+                fail(out, format!("must be one of {}", Value::Array(options.clone())));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                fail(out, format!("must be {}", expected));
+            }
+        }
+
+        match value {
+            Value::Object(object) => {
+                if let Some(required) = schema.get("required").and_then(Value::as_array) {
+                    for key in required.iter().filter_map(Value::as_str) {
+                        if !object.contains_key(key) {
+                            fail(out, format!("missing required property '{}'", key));
+                        }
+                    }
+                }
+                if !cfg!(feature = "regex") && schema.get("patternProperties").is_some() {
+                    fail(out, format!("patternProperties {}", PATTERNS_UNCHECKED));
+                }
+                let properties = schema.get("properties").and_then(Value::as_object);
+                let patterns: Vec<(Regex, &Value)> = schema
+                    .get("patternProperties")
+                    .and_then(Value::as_object)
+                    .map(|patterns| {
+                        patterns
+                            .iter()
+                            .filter_map(|(pattern, schema)| Some((Regex::new(pattern).ok()?, schema)))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                for (key, item) in object {
+                    let item_path = format!("{}/{}", path, escape_pointer(key));
+                    let mut matched = false;
+                    if let Some(property) = properties.and_then(|properties| properties.get(key)) {
+                        matched = true;
+                        self.check(property, item, &item_path, refs, out);
+                    }
+                    for (_, property) in patterns.iter().filter(|(pattern, _)| pattern.is_match(key)) {
+                        matched = true;
+                        self.check(property, item, &item_path, refs, out);
+                    }
+                    match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) if !matched => out.push(Violation {
+                            path: item_path,
+                            message: format!("property '{}' is not allowed", key),
+                        }),
+                        Some(additional) if !matched => self.check(additional, item, &item_path, refs, out),
+                        _ => {}
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter().enumerate() {
+                        self.check(item_schema, item, &format!("{}/{}", path, i), refs, out);
+                    }
+                }
+                if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                    if (items.len() as u64) < min {
+                        fail(out, format!("must have at least {} items", min));
+                    }
+                }
+                if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                    if items.len() as u64 > max {
+                        fail(out, format!("must have at most {} items", max));
+                    }
+                }
+                if schema.get("uniqueItems") == Some(&Value::Bool(true))
+                    && items.iter().enumerate().any(|(i, item)| items[..i].contains(item))
+                {
+                    fail(out, "items must be unique".to_string());
+                }
+            }
+            Value::String(text) => {
+                let length = text.chars().count() as u64;
+                if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                    if length < min {
+                        fail(out, format!("must be at least {} characters", min));
+                    }
+                }
+                if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                    if length > max {
+                        fail(out, format!("must be at most {} characters", max));
+                    }
+                }
+                if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+                    match Regex::new(pattern) {
+                        Ok(regex) if !regex.is_match(text) => fail(out, format!("must match the pattern {}", pattern)),
+                        Ok(_) => {}
+                        Err(_) if !cfg!(feature = "regex") => fail(out, format!("schema pattern {} {}", pattern, PATTERNS_UNCHECKED)),
+                        Err(_) => fail(out, format!("schema pattern {} is not a valid regular expression", pattern)),
+                    }
+                }
+            }
+            Value::Number(number) => {
+                let n = number.as_f64().unwrap_or_default();
+                let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+                if let Some(min) = bound("minimum").filter(|min| n < *min) {
+                    fail(out, format!("must be at least {}", min));
+                }
+                if let Some(max) = bound("maximum").filter(|max| n > *max) {
+                    fail(out, format!("must be at most {}", max));
+                }
+                if let Some(min) = bound("exclusiveMinimum").filter(|min| n <= *min) {
+                    fail(out, format!("must be greater than {}", min));
+                }
+                if let Some(max) = bound("exclusiveMaximum").filter(|max| n >= *max) {
+                    fail(out, format!("must be less than {}", max));
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+            for sub in all {
+                self.check(sub, value, path, refs, out);
+            }
+        }
+        if let Some(any) = schema.get("anyOf").and_then(Value::as_array) {
+            if !any.iter().any(|sub| self.passes(sub, value, path, refs)) {
+                fail(out, "does not match any of the allowed schemas".to_string());
+            }
+        }
+        if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+            let matches = one.iter().filter(|sub| self.passes(sub, value, path, refs)).count();
+            if matches != 1 {
+                fail(out, format!("must match exactly one of the allowed schemas, matches {}", matches));
+            }
+        }
+        if let Some(not) = schema.get("not") {
+            if self.passes(not, value, path, refs) {
+                fail(out, "matches a schema it must not match".to_string());
+            }
+        }
+    }
+
+    fn passes(&self, schema: &'a Value, value: &Value, path: &str, refs: usize) -> bool {
+        let mut out = Vec::new();
+        self.check(schema, value, path, refs, &mut out);
+        out.is_empty()
+    }
+
+    /// The part of the schema a local `$ref` such as `#/$defs/port` names.
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        let pointer = reference.strip_prefix('#')?;
+        if pointer.is_empty() {
+            return Some(self.root);
+        }
+        self.root.pointer(pointer)
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reports_each_violation_with_its_location() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name", "port"],
+            "additionalProperties": false,
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "port": {"$ref": "#/$defs/port"},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}, "uniqueItems": true}
+            },
+            "$defs": {"port": {"type": "integer", "minimum": 1, "maximum": 65535}}
+        });
+        assert!(validate(&schema, &json!({"name": "x", "port": 80, "tags": ["a"]})).is_empty());
+
+        let paths: Vec<String> = validate(&schema, &json!({"name": "", "port": 70000, "tags": ["a", "c", "a"], "extra": 1}))
+            .into_iter()
+            .map(|v| v.path)
+            .collect();
+        assert_eq!(paths, ["/extra", "/name", "/port", "/tags/1", "/tags"]);
+
+        let missing = validate(&schema, &json!({"name": "x"}));
+        assert_eq!(missing[0].message, "missing required property 'port'");
+    }
+
+    #[test]
+    fn combinators() {
+        let schema = json!({"oneOf": [{"type": "integer"}, {"type": "number"}]});
+        assert!(validate(&schema, &json!(1.5)).is_empty());
+        assert_eq!(validate(&schema, &json!(2)).len(), 1);
+        let looping = json!({"$ref": "#"});
+        assert_eq!(validate(&looping, &json!(1)).len(), 1);
+    }
+}
@@ -0,0 +1,344 @@
+//! Review-before-apply for whole batches of changes.
+//!
+//! While staging is on, every write, edit and delete lands in an overlay
+//! instead of the workspace: written content is kept under [`DIR`], and the
+//! [`Stage`] saved with the actor's state records what became of each path.
+//! Reads see the workspace with the overlay on top. `commit-staged` applies
+//! the whole overlay at once, undoing it if any change fails, and
+//! `discard-staged` drops it.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::{Ref, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::fs::Filesystem;
+use crate::transaction::Journal;
+
+/// Directory of the staged content, relative to `base_path`
+pub const DIR: &str = ".fs-child/staging";
+
+/// What became of a staged path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Change {
+    /// A file was written; its content is kept under [`DIR`]
+    Written,
+    Deleted,
+    /// A directory was created
+    Created,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stage {
+    /// Turn staging began on
+    pub started: u64,
+    /// Every changed path, as the host names it
+    pub changes: BTreeMap<String, Change>,
+}
+
+/// What a path is, as the overlay shows it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    File,
+    Dir,
+    Missing,
+}
+
+/// The workspace with staged changes on top. Changes go to the overlay and
+/// never reach `base` until [`commit`] applies them.
+#[derive(Debug)]
+pub struct Overlay {
+    base: Rc<dyn Filesystem>,
+    /// Host path of `base_path`, which [`DIR`] is under
+    root: String,
+    stage: RefCell<Stage>,
+}
+
+impl Overlay {
+    pub fn new(base: Rc<dyn Filesystem>, root: &str, stage: Stage) -> Overlay {
+        Overlay { base, root: root.to_string(), stage: RefCell::new(stage) }
+    }
+
+    /// The filesystem without the overlay.
+    pub fn base(&self) -> Rc<dyn Filesystem> {
+        self.base.clone()
+    }
+
+    pub fn stage(&self) -> Ref<'_, Stage> {
+        self.stage.borrow()
+    }
+
+    pub fn take_stage(&self) -> Stage {
+        self.stage.take()
+    }
+
+    fn kind(&self, path: &str) -> Kind {
+        let stage = self.stage.borrow();
+        match stage.changes.get(path) {
+            Some(Change::Written) => return Kind::File,
+            Some(Change::Created) => return Kind::Dir,
+            Some(Change::Deleted) => return Kind::Missing,
+            None => {}
+        }
+        // Anything under a deleted path, or under what is now a file, is gone
+        if ancestors(path).any(|dir| matches!(stage.changes.get(dir), Some(Change::Deleted | Change::Written))) {
+            return Kind::Missing;
+        }
+        drop(stage);
+        base_kind(&*self.base, path)
+    }
+
+    fn check_parent(&self, path: &str) -> Result<(), String> {
+        match ancestors(path).next() {
+            Some(parent) if self.kind(parent) != Kind::Dir => Err(format!("No such file or directory: {}", path)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop what `path` had staged, removing its content.
+    fn unstage(&self, path: &str) {
+        if self.stage.borrow_mut().changes.remove(path) == Some(Change::Written) {
+            let _ = self.base.delete_file(&blob_path(&self.root, path));
+        }
+    }
+
+    /// Record that `path` is gone: as a deletion when the workspace has it,
+    /// or by forgetting it when it only ever existed in the overlay.
+    fn remove(&self, path: &str) {
+        self.unstage(path);
+        let hidden = ancestors(path).any(|dir| self.stage.borrow().changes.contains_key(dir));
+        if !hidden && self.base.path_exists(path).unwrap_or(false) {
+            self.stage.borrow_mut().changes.insert(path.to_string(), Change::Deleted);
+        }
+    }
+}
+
+impl Filesystem for Overlay {
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, String> {
+        match (self.stage.borrow().changes.get(path), self.kind(path)) {
+            (Some(Change::Written), _) => self.base.read_file(&blob_path(&self.root, path)),
+            (_, Kind::Missing) => Err(format!("No such file or directory: {}", path)),
+            (Some(Change::Created), _) => Err(format!("Is a directory: {}", path)),
+            _ => self.base.read_file(path),
+        }
+    }
+
+    fn write_file(&self, path: &str, content: &str) -> Result<(), String> {
+        if self.kind(path) == Kind::Dir {
+            return Err(format!("Is a directory: {}", path));
+        }
+        self.check_parent(path)?;
+        let dir = format!("{}/{}", self.root, DIR);
+        if !self.base.path_exists(&dir).unwrap_or(false) {
+            let mut current = self.root.clone();
+            for segment in DIR.split('/') {
+                current = format!("{}/{}", current, segment);
+                if !self.base.path_exists(&current).unwrap_or(false) {
+                    self.base.create_dir(&current)?;
+                }
+            }
+        }
+        self.base.write_file(&blob_path(&self.root, path), content)?;
+        self.stage.borrow_mut().changes.insert(path.to_string(), Change::Written);
+        Ok(())
+    }
+
+    fn list_files(&self, path: &str) -> Result<Vec<String>, String> {
+        match self.kind(path) {
+            Kind::Missing => return Err(format!("No such file or directory: {}", path)),
+            Kind::File => return Err(format!("Not a directory: {}", path)),
+            Kind::Dir => {}
+        }
+        let visible = |name: &String| self.kind(&format!("{}/{}", path, name)) != Kind::Missing;
+        let mut names: Vec<String> = self.base.list_files(path).unwrap_or_default().into_iter().filter(visible).collect();
+        let prefix = format!("{}/", path);
+        let staged: Vec<String> = self
+            .stage
+            .borrow()
+            .changes
+            .keys()
+            .filter_map(|changed| changed.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(str::to_string)
+            .collect();
+        for name in staged {
+            if !names.contains(&name) && visible(&name) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), String> {
+        match self.kind(path) {
+            Kind::File => {
+                self.remove(path);
+                Ok(())
+            }
+            Kind::Dir => Err(format!("Is a directory: {}", path)),
+            Kind::Missing => Err(format!("No such file or directory: {}", path)),
+        }
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), String> {
+        if self.kind(path) != Kind::Missing {
+            return Err(format!("File exists: {}", path));
+        }
+        self.check_parent(path)?;
+        self.unstage(path);
+        self.stage.borrow_mut().changes.insert(path.to_string(), Change::Created);
+        Ok(())
+    }
+
+    fn delete_dir(&self, path: &str) -> Result<(), String> {
+        match self.kind(path) {
+            Kind::Dir => {}
+            Kind::File => return Err(format!("Not a directory: {}", path)),
+            Kind::Missing => return Err(format!("No such file or directory: {}", path)),
+        }
+        let prefix = format!("{}/", path);
+        let inside: Vec<String> = self.stage.borrow().changes.keys().filter(|p| p.starts_with(&prefix)).cloned().collect();
+        for changed in inside {
+            self.unstage(&changed);
+        }
+        self.remove(path);
+        Ok(())
+    }
+
+    fn path_exists(&self, path: &str) -> Result<bool, String> {
+        Ok(self.kind(path) != Kind::Missing)
+    }
+}
+
+/// The directories above a host path, nearest first.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    let mut rest = path;
+    std::iter::from_fn(move || {
+        let (dir, _) = rest.rsplit_once('/').filter(|(dir, _)| !dir.is_empty())?;
+        rest = dir;
+        Some(dir)
+    })
+}
+
+fn base_kind(fs: &dyn Filesystem, path: &str) -> Kind {
+    match fs.path_exists(path) {
+        Ok(true) if fs.list_files(path).is_ok() => Kind::Dir,
+        Ok(true) => Kind::File,
+        _ => Kind::Missing,
+    }
+}
+
+/// Where the staged content of a host path is kept.
+pub fn blob_path(root: &str, path: &str) -> String {
+    format!("{}/{}/{}", root, DIR, &format!("{:x}", Sha256::digest(path))[..16])
+}
+
+/// Apply every staged change to `base`: directories first, then written
+/// files, then deletions, deepest first. If any change fails, the ones
+/// already made are undone. The staged content is left for [`clear`].
+pub fn commit(base: &dyn Filesystem, root: &str, stage: &Stage) -> Result<(), String> {
+    let mut journal = Journal::default();
+    let changes = |wanted: Change| stage.changes.iter().filter(move |(_, change)| **change == wanted).map(|(path, _)| path);
+    let mut outcome = Ok(());
+    for path in changes(Change::Created) {
+        if base_kind(base, path) == Kind::Dir {
+            continue;
+        }
+        journal.record(base, path);
+        outcome = base.create_dir(path).map_err(|e| format!("Cannot create '{}': {}", path, e));
+        if outcome.is_err() {
+            break;
+        }
+    }
+    if outcome.is_ok() {
+        for path in changes(Change::Written) {
+            journal.record(base, path);
+            outcome = base
+                .read_file(&blob_path(root, path))
+                .and_then(|content| String::from_utf8(content).map_err(|_| "staged content is not UTF-8".to_string()))
+                .and_then(|text| base.write_file(path, &text))
+                .map_err(|e| format!("Cannot write '{}': {}", path, e));
+            if outcome.is_err() {
+                break;
+            }
+        }
+    }
+    if outcome.is_ok() {
+        for path in changes(Change::Deleted).rev() {
+            let removed = match base_kind(base, path) {
+                Kind::Missing => Ok(()),
+                Kind::Dir => base.delete_dir(path),
+                Kind::File => {
+                    journal.record(base, path);
+                    base.delete_file(path)
+                }
+            };
+            outcome = removed.map_err(|e| format!("Cannot delete '{}': {}", path, e));
+            if outcome.is_err() {
+                break;
+            }
+        }
+    }
+    outcome.map_err(|e| {
+        let problems = journal.rollback(base);
+        match problems.is_empty() {
+            true => e,
+            false => format!("{}; undoing the changes already made also failed: {}", e, problems.join("; ")),
+        }
+    })
+}
+
+/// Remove the staged content kept for `stage`.
+pub fn clear(base: &dyn Filesystem, root: &str, stage: &Stage) {
+    for (path, _) in stage.changes.iter().filter(|(_, change)| **change == Change::Written) {
+        let _ = base.delete_file(&blob_path(root, path));
+    }
+    let dir = format!("{}/{}", root, DIR);
+    if base.list_files(&dir).is_ok_and(|names| names.is_empty()) {
+        let _ = base.delete_dir(&dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFilesystem;
+
+    #[test]
+    fn overlay_hides_changes_until_committed() {
+        let fs = MemoryFilesystem::new("/work").with_file("/work/a.txt", "a").with_file("/work/src/b.txt", "b");
+        let overlay = Overlay::new(Rc::new(fs.clone()), "/work", Stage::default());
+        overlay.write_file("/work/a.txt", "A").unwrap();
+        overlay.create_dir("/work/new").unwrap();
+        overlay.write_file("/work/new/c.txt", "c").unwrap();
+        overlay.delete_file("/work/src/b.txt").unwrap();
+        assert!(overlay.write_file("/work/gone/d.txt", "d").unwrap_err().starts_with("No such file"));
+
+        assert_eq!(overlay.read_file("/work/a.txt").unwrap(), b"A");
+        assert!(overlay.read_file("/work/src/b.txt").is_err());
+        assert_eq!(overlay.list_files("/work/src").unwrap(), Vec::<String>::new());
+        assert!(overlay.list_files("/work").unwrap().contains(&"new".to_string()));
+        assert_eq!(fs.read_file("/work/a.txt").unwrap(), b"a");
+        assert!(!fs.path_exists("/work/new").unwrap());
+
+        let stage = overlay.take_stage();
+        commit(&fs, "/work", &stage).unwrap();
+        clear(&fs, "/work", &stage);
+        assert_eq!(fs.read_file("/work/a.txt").unwrap(), b"A");
+        assert_eq!(fs.read_file("/work/new/c.txt").unwrap(), b"c");
+        assert!(!fs.path_exists("/work/src/b.txt").unwrap());
+        assert!(!fs.path_exists("/work/.fs-child/staging").unwrap());
+    }
+
+    #[test]
+    fn forgets_paths_created_and_removed_in_the_overlay() {
+        let fs = MemoryFilesystem::new("/work");
+        let overlay = Overlay::new(Rc::new(fs.clone()), "/work", Stage::default());
+        overlay.write_file("/work/tmp.txt", "x").unwrap();
+        overlay.delete_file("/work/tmp.txt").unwrap();
+        assert!(overlay.stage().changes.is_empty());
+        assert!(fs.list_files("/work/.fs-child/staging").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1828 @@
+//! The actor's state between messages, and the handlers for each kind of
+//! message it receives.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+use crate::backup::BackupRecord;
+use crate::cache::LruCache;
+use crate::codec::StateEncoding;
+use crate::commands::{invalidate_listings, BatchMode, CommandResult, FsCommand};
+use crate::config::Config;
+use crate::delegate::{Delegate, HostSupervisor, Supervisor};
+use crate::encoding::NonUtf8Mode;
+use crate::error::{ErrorCode, ErrorCounts, FsChildError};
+use crate::fs::{Filesystem, HostFilesystem};
+use crate::history::HistoryEntry;
+use crate::host::{Capability, Host};
+use crate::logger::Level;
+use crate::macros::Macro;
+use crate::metrics::Metrics;
+use crate::mounts::Mount;
+use crate::operations::{COMMON_FIELDS, OPERATIONS};
+use crate::quota::Quota;
+use crate::store::{JsonStoreClient, StoreClient};
+use crate::watch::{ChangeKind, PathChange};
+use crate::{allot, compact, cursors, conflict, delegate, logger, operations, parser, policy, queue, quota, report, render, staging, watch};
+
+/// Chain entries kept in memory between requests
+const MESSAGE_CACHE_CAPACITY: usize = 64;
+
+thread_local! {
+    /// Chain entries are immutable once stored, so lookups by id can be
+    /// reused for as long as the component instance lives.
+    static MESSAGE_CACHE: RefCell<LruCache<String, ChainEntry>> =
+        RefCell::new(LruCache::new(MESSAGE_CACHE_CAPACITY));
+}
+
+/// Highest protocol version this actor speaks. Version 1 is the original
+/// response format; version 2 adds structured result data and chunking.
+const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct State {
+    pub(crate) name: String,
+    pub(crate) child_id: Option<String>,
+    pub(crate) store_id: Option<String>,
+    pub(crate) base_path: String,
+    pub(crate) permissions: Vec<String>,
+    /// Responses with text larger than this are split into parts
+    #[serde(default)]
+    pub(crate) max_response_bytes: Option<usize>,
+    /// Bytes of result text the commands of a batch share; each result over
+    /// its share is trimmed
+    #[serde(default)]
+    pub(crate) response_budget_bytes: Option<usize>,
+    /// Remaining parts of the last chunked response, delivered on `next-chunk`
+    #[serde(default)]
+    pub(crate) pending_chunks: Vec<ChildMessage>,
+    /// Results of `handle_send` batches waiting to be delivered to the parent
+    #[serde(default)]
+    pub(crate) completed_async: Vec<ChildMessage>,
+    /// Protocol version agreed with the parent during introduction
+    #[serde(default = "legacy_protocol_version")]
+    pub(crate) protocol_version: u32,
+    /// Path globs the parent asked to be notified about
+    #[serde(default)]
+    pub(crate) subscriptions: Vec<String>,
+    /// Content hashes of subscribed files as of the last scan
+    #[serde(default)]
+    pub(crate) watch_manifest: BTreeMap<String, String>,
+    /// Additional store actors by role (e.g. `artifact`, `audit`). The
+    /// introduction's `store_id` always serves the `chat` role.
+    #[serde(default)]
+    pub(crate) stores: BTreeMap<String, String>,
+    /// Idempotency keys of recently succeeded commands, oldest first
+    #[serde(default)]
+    pub(crate) idempotency_keys: VecDeque<String>,
+    /// Heads whose message was already loaded and handled, oldest first
+    #[serde(default)]
+    pub(crate) processed_heads: VecDeque<String>,
+    /// Largest value moved between the store and the filesystem
+    #[serde(default = "default_max_transfer_bytes")]
+    pub(crate) max_transfer_bytes: usize,
+    /// Largest file returned inline by `read-base64`
+    #[serde(default = "default_max_inline_bytes")]
+    pub(crate) max_inline_bytes: usize,
+    /// Largest chat message loaded from the store
+    #[serde(default = "default_max_message_bytes")]
+    pub(crate) max_message_bytes: usize,
+    /// Number of head-updates seen, used to place history entries in time
+    #[serde(default)]
+    pub(crate) turn: u64,
+    /// Commands run this session, oldest first
+    #[serde(default)]
+    pub(crate) history: Vec<HistoryEntry>,
+    #[serde(default)]
+    pub(crate) history_seq: u64,
+    /// Send a status heartbeat after this many head-updates, when idle
+    #[serde(default)]
+    pub(crate) heartbeat_interval: Option<u64>,
+    #[serde(default)]
+    pub(crate) last_heartbeat_turn: u64,
+    /// Archive a file's prior content in the store before overwriting it
+    #[serde(default)]
+    pub(crate) backup_before_write: bool,
+    /// Store-archived backups by path, oldest first
+    #[serde(default)]
+    pub(crate) backups: BTreeMap<String, Vec<BackupRecord>>,
+    /// Failures seen this session, reported in the status
+    #[serde(default)]
+    pub(crate) errors: ErrorCounts,
+    /// Send an overview of the workspace with the introduction
+    #[serde(default)]
+    pub(crate) workspace_overview: bool,
+    /// Share of an existing file's size below which a `write-file` needs
+    /// `confirm_truncation`; 0 turns the guard off
+    #[serde(default = "default_truncation_ratio")]
+    pub(crate) truncation_ratio: f64,
+    /// Write every batch's results in full to a report file, and answer
+    /// with a summary that points to it
+    #[serde(default)]
+    pub(crate) batch_reports: bool,
+    /// Reports written, oldest first
+    #[serde(default)]
+    pub(crate) reports: Vec<String>,
+    /// Fill the introduction's examples with paths from the project at the
+    /// top of the workspace
+    #[serde(default)]
+    pub(crate) detect_project: bool,
+    /// Make written text follow the nearest `.editorconfig` files
+    #[serde(default)]
+    pub(crate) editorconfig: bool,
+    /// Append a record of every batch to the audit store
+    #[serde(default)]
+    pub(crate) audit_to_store: bool,
+    /// Deepest directory level entered by recursive operations
+    #[serde(default = "default_max_walk_depth")]
+    pub(crate) max_walk_depth: usize,
+    /// Most files returned by one recursive listing
+    #[serde(default = "default_max_walk_entries")]
+    pub(crate) max_walk_entries: usize,
+    /// Most directory entries one command may visit
+    #[serde(default = "default_max_work_entries")]
+    pub(crate) max_work_entries: usize,
+    /// Most file bytes one command or subscription scan may read
+    #[serde(default = "default_max_work_bytes")]
+    pub(crate) max_work_bytes: usize,
+    /// Head-updates a directory listing may be reused for; 0 disables caching
+    #[serde(default)]
+    pub(crate) list_cache_turns: u64,
+    /// Largest window of a file returned by one read-file
+    #[serde(default = "default_read_chunk_bytes")]
+    pub(crate) read_chunk_bytes: usize,
+    /// Where the next `<offset>next</offset>` read of each path resumes
+    #[serde(default)]
+    pub(crate) read_cursors: BTreeMap<String, usize>,
+    /// Head-updates a continuation token stays valid for; 0 hands none out
+    #[serde(default = "default_cursor_turns")]
+    pub(crate) cursor_turns: u64,
+    /// Continuation tokens handed out and not yet used, by token
+    #[serde(default)]
+    pub(crate) cursors: BTreeMap<String, cursors::Cursor>,
+    /// Number of the last token handed out
+    #[serde(default)]
+    pub(crate) cursor_seq: u64,
+    /// Build the styled HTML rendering of results; off when the parent said
+    /// it does not render HTML
+    #[serde(default = "default_render_html")]
+    pub(crate) render_html: bool,
+    /// How file content that is not valid UTF-8 is shown when read
+    #[serde(default)]
+    pub(crate) non_utf8: NonUtf8Mode,
+    /// How batches run when no command asks for a `mode`
+    #[serde(default)]
+    pub(crate) batch_mode: BatchMode,
+    /// Encoding of the state blob handed back to the runtime
+    #[serde(default)]
+    pub(crate) state_encoding: StateEncoding,
+    /// Directory under `base_path` that history, backups and the watch
+    /// manifest are kept in, instead of in the state blob
+    #[serde(default)]
+    pub(crate) cold_state_dir: Option<String>,
+    /// Content hashes of the cold sections as last loaded; `None` until they
+    /// are first needed in this message
+    #[serde(skip)]
+    pub(crate) cold_hashes: Option<BTreeMap<String, String>>,
+    /// Set when this message found no state and defaults were used instead
+    #[serde(skip)]
+    pub(crate) recovered: bool,
+    /// Optional host capabilities this runtime is known to lack
+    #[serde(default)]
+    pub(crate) host: Host,
+    /// Most detailed log lines written
+    #[serde(default)]
+    pub(crate) log_level: Level,
+    /// Operation counts, failures and bytes for monitoring
+    #[serde(default)]
+    pub(crate) metrics: Metrics,
+    /// Push the metrics to the store every this many head-updates
+    #[serde(default)]
+    pub(crate) metrics_interval: Option<u64>,
+    /// Command sequences defined with `define-macro`, by name
+    #[serde(default)]
+    pub(crate) macros: BTreeMap<String, Macro>,
+    /// Roots besides `base_path`, addressed as `name:/path`
+    #[serde(default)]
+    pub(crate) mounts: BTreeMap<String, Mount>,
+    /// Child instances for subtrees, by the name their commands address
+    #[serde(default)]
+    pub(crate) delegates: BTreeMap<String, Delegate>,
+    /// Path rules narrowing the permissions, the first match deciding
+    #[serde(default)]
+    pub(crate) policy: Vec<policy::Rule>,
+    /// Limits on the bytes and files under path prefixes
+    #[serde(default)]
+    pub(crate) quotas: BTreeMap<String, Quota>,
+    /// Bytes and files under each quota's prefix, measured when first
+    /// needed and kept up to date from the commands' writes
+    #[serde(default)]
+    pub(crate) usage: BTreeMap<String, quota::Usage>,
+    /// Add a usage report to the response every this many head-updates
+    #[serde(default)]
+    pub(crate) usage_report_interval: Option<u64>,
+    #[serde(default)]
+    pub(crate) last_usage_report_turn: u64,
+    /// Commands held back with `defer`, in the order they were queued
+    #[serde(default)]
+    pub(crate) deferred: Vec<queue::Task>,
+    #[serde(default)]
+    pub(crate) deferred_seq: u64,
+    /// Content hashes of the files this actor last read or wrote, by host
+    /// path; see the `conflict` module
+    #[serde(default)]
+    pub(crate) seen: BTreeMap<String, conflict::Seen>,
+    /// Changes held back for review since `stage`; see the `staging` module
+    #[serde(default)]
+    pub(crate) staging: Option<staging::Stage>,
+    /// The overlay reads and writes go through while a batch runs staged
+    #[serde(skip)]
+    pub(crate) overlay: Option<Rc<staging::Overlay>>,
+    /// How store actors are talked to; not part of the persisted state
+    #[serde(skip, default = "default_store_client")]
+    pub(crate) store_client: Box<dyn StoreClient>,
+    /// Where files are read and written; not part of the persisted state
+    #[serde(skip, default = "default_filesystem")]
+    pub(crate) fs: Rc<dyn Filesystem>,
+    /// How delegates are started and reached; not part of the persisted state
+    #[serde(skip, default = "default_supervisor")]
+    pub(crate) supervisor: Box<dyn Supervisor>,
+}
+
+fn default_store_client() -> Box<dyn StoreClient> {
+    Box::new(JsonStoreClient)
+}
+
+fn default_filesystem() -> Rc<dyn Filesystem> {
+    Rc::new(HostFilesystem)
+}
+
+fn default_supervisor() -> Box<dyn Supervisor> {
+    Box::new(HostSupervisor)
+}
+
+pub(crate) fn default_max_transfer_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+pub(crate) fn default_max_inline_bytes() -> usize {
+    1024 * 1024
+}
+
+pub(crate) fn default_max_message_bytes() -> usize {
+    4 * 1024 * 1024
+}
+
+pub(crate) fn default_read_chunk_bytes() -> usize {
+    256 * 1024
+}
+
+pub(crate) fn default_cursor_turns() -> u64 {
+    5
+}
+
+pub(crate) fn default_truncation_ratio() -> f64 {
+    0.2
+}
+
+pub(crate) fn default_max_walk_depth() -> usize {
+    16
+}
+
+pub(crate) fn default_max_walk_entries() -> usize {
+    2000
+}
+
+pub(crate) fn default_max_work_entries() -> usize {
+    20_000
+}
+
+pub(crate) fn default_max_work_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_render_html() -> bool {
+    true
+}
+
+fn legacy_protocol_version() -> u32 {
+    1
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            child_id: None,
+            store_id: None,
+            base_path: String::from("."),
+            permissions: vec!["read".to_string(), "write".to_string()],
+            max_response_bytes: None,
+            response_budget_bytes: None,
+            pending_chunks: Vec::new(),
+            completed_async: Vec::new(),
+            protocol_version: legacy_protocol_version(),
+            subscriptions: Vec::new(),
+            watch_manifest: BTreeMap::new(),
+            stores: BTreeMap::new(),
+            idempotency_keys: VecDeque::new(),
+            processed_heads: VecDeque::new(),
+            max_transfer_bytes: default_max_transfer_bytes(),
+            max_inline_bytes: default_max_inline_bytes(),
+            max_message_bytes: default_max_message_bytes(),
+            turn: 0,
+            history: Vec::new(),
+            history_seq: 0,
+            heartbeat_interval: None,
+            last_heartbeat_turn: 0,
+            backup_before_write: false,
+            backups: BTreeMap::new(),
+            errors: ErrorCounts::default(),
+            workspace_overview: false,
+            batch_reports: false,
+            truncation_ratio: default_truncation_ratio(),
+            reports: Vec::new(),
+            detect_project: false,
+            editorconfig: false,
+            audit_to_store: false,
+            max_walk_depth: default_max_walk_depth(),
+            max_walk_entries: default_max_walk_entries(),
+            max_work_entries: default_max_work_entries(),
+            max_work_bytes: default_max_work_bytes(),
+            list_cache_turns: 0,
+            read_chunk_bytes: default_read_chunk_bytes(),
+            read_cursors: BTreeMap::new(),
+            cursor_turns: default_cursor_turns(),
+            cursors: BTreeMap::new(),
+            cursor_seq: 0,
+            render_html: default_render_html(),
+            batch_mode: BatchMode::default(),
+            non_utf8: NonUtf8Mode::default(),
+            state_encoding: StateEncoding::default(),
+            cold_state_dir: None,
+            cold_hashes: None,
+            recovered: false,
+            host: Host::default(),
+            log_level: Level::default(),
+            metrics: Metrics::default(),
+            metrics_interval: None,
+            macros: BTreeMap::new(),
+            mounts: BTreeMap::new(),
+            delegates: BTreeMap::new(),
+            policy: Vec::new(),
+            quotas: BTreeMap::new(),
+            usage: BTreeMap::new(),
+            usage_report_interval: None,
+            last_usage_report_turn: 0,
+            deferred: Vec::new(),
+            deferred_seq: 0,
+            seen: BTreeMap::new(),
+            staging: None,
+            overlay: None,
+            store_client: default_store_client(),
+            fs: default_filesystem(),
+            supervisor: default_supervisor(),
+        }
+    }
+}
+
+/// Parts of the state that can grow without bound, and so may be kept in
+/// `cold_state_dir` rather than in the state blob
+const COLD_SECTIONS: [&str; 3] = ["history", "backups", "watch_manifest"];
+
+/// How many handled head ids are remembered to skip repeated head-updates
+const PROCESSED_HEAD_LIMIT: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ChainEntry {
+    parent: Option<String>,
+    id: Option<String>,
+    data: MessageData,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum MessageData {
+    Chat(Message),
+    ChildRollup(Vec<ChildMessage>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Message {
+    User {
+        content: String,
+    },
+    Assistant {
+        content: String,
+        id: String,
+        model: String,
+        stop_reason: String,
+        stop_sequence: Option<String>,
+        message_type: String,
+        usage: Usage,
+    },
+}
+
+impl Message {
+    pub fn content(&self) -> &str {
+        match self {
+            Self::User { content } => content,
+            Self::Assistant { content, .. } => content,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Usage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ChildMessage {
+    pub(crate) child_id: String,
+    pub(crate) text: String,
+    pub(crate) html: Option<String>,
+    pub(crate) parent_id: Option<String>,
+    pub(crate) data: Value,
+}
+
+/// Messages the parent sends through `handle_request`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "msg_type", rename_all = "kebab-case")]
+enum IncomingMessage {
+    Introduction {
+        #[serde(default)]
+        data: IntroductionData,
+    },
+    HeadUpdate {
+        #[serde(default)]
+        data: HeadData,
+    },
+    ConfigUpdate {
+        #[serde(default)]
+        data: ConfigUpdateData,
+    },
+    DescribeCapabilities {
+        #[serde(default)]
+        data: HeadData,
+    },
+    CollectAsync {
+        #[serde(default)]
+        data: HeadData,
+    },
+    NextChunk {
+        #[serde(default)]
+        data: HeadData,
+    },
+    Cancel {
+        #[serde(default)]
+        data: HeadData,
+    },
+    Stats {
+        #[serde(default)]
+        data: HeadData,
+    },
+    /// The parent is done with this instance
+    Shutdown {
+        #[serde(default)]
+        data: HeadData,
+    },
+    /// Commands from a supervising instance this one is a delegate of
+    RunCommands { data: AsyncBatch },
+}
+
+/// Messages the parent sends through `handle_send`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "msg_type", rename_all = "kebab-case")]
+pub(crate) enum SendMessage {
+    FsCommands { data: AsyncBatch },
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct IntroductionData {
+    child_id: Option<String>,
+    store_id: Option<String>,
+    head: Option<String>,
+    /// Highest protocol version the parent understands
+    protocol_version: Option<u32>,
+    /// Extra store actors by role
+    #[serde(default)]
+    stores: BTreeMap<String, String>,
+    /// Whether the parent displays the `html` of responses
+    renders_html: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ConfigUpdateData {
+    head: Option<String>,
+    #[serde(default)]
+    stores: BTreeMap<String, String>,
+    renders_html: Option<bool>,
+    log_level: Option<Level>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct HeadData {
+    head: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AsyncBatch {
+    #[serde(default)]
+    pub(crate) head: String,
+    #[serde(default)]
+    pub(crate) commands: Vec<FsCommand>,
+}
+
+impl State {
+    pub(crate) fn new(config: Config) -> Result<Self, String> {
+        Ok(Self {
+            host: config.host()?,
+            mounts: config.mounts(),
+            delegates: config.delegates(),
+            policy: config.policy.clone(),
+            quotas: config.quotas(),
+            usage_report_interval: config.usage_report_interval,
+            name: config.name,
+            base_path: config.base_path,
+            permissions: config.permissions,
+            max_response_bytes: config.max_response_bytes,
+            response_budget_bytes: config.response_budget_bytes,
+            max_transfer_bytes: config.max_transfer_bytes,
+            max_inline_bytes: config.max_inline_bytes,
+            max_message_bytes: config.max_message_bytes,
+            heartbeat_interval: config.heartbeat_interval,
+            backup_before_write: config.backup_before_write,
+            audit_to_store: config.audit_to_store,
+            workspace_overview: config.workspace_overview,
+            batch_reports: config.batch_reports,
+            truncation_ratio: config.truncation_ratio,
+            detect_project: config.detect_project,
+            editorconfig: config.editorconfig,
+            state_encoding: config.state_encoding,
+            batch_mode: config.batch_mode,
+            non_utf8: config.non_utf8,
+            cold_state_dir: config.cold_state_dir,
+            max_walk_depth: config.max_walk_depth,
+            max_walk_entries: config.max_walk_entries,
+            max_work_entries: config.max_work_entries,
+            max_work_bytes: config.max_work_bytes,
+            list_cache_turns: config.list_cache_turns,
+            read_chunk_bytes: config.read_chunk_bytes,
+            cursor_turns: config.cursor_turns,
+            log_level: config.log_level,
+            metrics_interval: config.metrics_interval,
+            ..Self::default()
+        })
+    }
+
+    /// Split an oversized response into parts chained via `parent_id`. The
+    /// first part is returned; the rest are queued for `next-chunk` requests.
+    fn chunk_response(&mut self, response: ChildMessage) -> ChildMessage {
+        let max = match self.max_response_bytes {
+            Some(max) if self.protocol_version >= 2 && max > 0 && response.text.len() > max => max,
+            _ => {
+                self.pending_chunks.clear();
+                return response;
+            }
+        };
+
+        let parts = split_text(&response.text, max);
+        let total = parts.len();
+        let base_id = response.parent_id.clone().unwrap_or_default();
+
+        let mut chunks: Vec<ChildMessage> = parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let part = i + 1;
+                let parent_id = if part == 1 {
+                    response.parent_id.clone()
+                } else {
+                    Some(format!("{}/part-{}", base_id, part - 1))
+                };
+
+                let mut data = response.data.clone();
+                data["chunk"] = json!({
+                    "id": format!("{}/part-{}", base_id, part),
+                    "part": part,
+                    "total": total,
+                });
+
+                ChildMessage {
+                    child_id: response.child_id.clone(),
+                    text: format!("[part {}/{}]\n{}", part, total, text),
+                    html: render::html!(render::part_html(part, total, &text)),
+                    parent_id,
+                    data,
+                }
+            })
+            .collect();
+
+        let first = chunks.remove(0);
+        self.pending_chunks = chunks;
+        first
+    }
+
+    /// Whether a usage report is due in the next response with results.
+    fn usage_report_due(&self) -> bool {
+        match self.usage_report_interval {
+            Some(interval) if interval > 0 => self.turn - self.last_usage_report_turn >= interval,
+            _ => false,
+        }
+    }
+
+    /// Usage against every quota, measured afresh, and the operations run.
+    fn usage_report(&mut self) -> (String, Value) {
+        // Files may have changed outside the actor since last measured
+        self.usage.clear();
+        let prefixes: Vec<String> = self.quotas.keys().cloned().collect();
+        let quotas: serde_json::Map<String, Value> = prefixes
+            .iter()
+            .filter_map(|prefix| {
+                // One too large to measure is left out, and noted in the text
+                let usage = self.usage_of(prefix).ok()?;
+                let quota = &self.quotas[prefix];
+                let entry = json!({
+                    "bytes": usage.bytes,
+                    "files": usage.files,
+                    "max_bytes": quota.max_bytes,
+                    "max_files": quota.max_files,
+                    "limit": quota.limit,
+                });
+                Some((prefix.clone(), entry))
+            })
+            .collect();
+        let total = self.metrics.total();
+        let mut lines: Vec<String> = quota::report(&self.quotas, &self.usage).into_iter().map(|line| format!("  {}", line)).collect();
+        lines.push(format!(
+            "  {} operations ({} failed), {} bytes in, {} bytes out",
+            total.count, total.failed, total.request_bytes, total.response_bytes
+        ));
+        let text = format!("Usage for '{}':\n{}", self.name, lines.join("\n"));
+        (text, json!({"quotas": quotas, "operations": total}))
+    }
+
+    /// Whether a heartbeat should be sent at the next idle head-update.
+    fn heartbeat_due(&self) -> bool {
+        match self.heartbeat_interval {
+            Some(interval) if interval > 0 => self.turn - self.last_heartbeat_turn >= interval,
+            _ => false,
+        }
+    }
+
+    /// Snapshot of the actor's health for monitoring parents.
+    fn status(&self) -> Value {
+        json!({
+            "turn": self.turn,
+            "protocol_version": self.protocol_version,
+            "watch": {
+                "subscriptions": self.subscriptions,
+                "watched_files": self.watch_manifest.len(),
+            },
+            "queues": {
+                "async_results": self.completed_async.len(),
+                "response_parts": self.pending_chunks.len(),
+                "deferred_commands": self.deferred.len(),
+            },
+            "staging": self.staging.as_ref().map(|stage| json!({"since": stage.started, "changes": stage.changes.len()})),
+            "history": {
+                "entries": self.history.len(),
+                "failed": self.history.iter().filter(|e| e.error.is_some()).count(),
+            },
+            "errors": self.errors,
+        })
+    }
+
+    /// Machine-readable description of every operation and this instance's
+    /// configuration, for parents that generate instructions automatically.
+    fn capabilities(&self) -> Value {
+        let operations = OPERATIONS
+            .iter()
+            .map(|spec| {
+                let mut properties = serde_json::Map::new();
+                properties.insert(
+                    "operation".to_string(),
+                    json!({"type": "string", "const": spec.name}),
+                );
+                for field in spec
+                    .required
+                    .iter()
+                    .chain(spec.optional.iter())
+                    .chain(COMMON_FIELDS.iter())
+                {
+                    properties.insert(field.to_string(), json!({"type": "string"}));
+                }
+                let mut required = vec!["operation"];
+                required.extend(spec.required.iter());
+
+                json!({
+                    "name": spec.name,
+                    "description": spec.description,
+                    "permission": spec.permission,
+                    "allowed": self.spec_allowed(spec),
+                    "supported": self.host.lacks_for(spec.name).is_none() && spec.is_built(),
+                    "input_schema": {
+                        "type": "object",
+                        "properties": properties,
+                        "required": required,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "instance": {
+                "name": self.name,
+                "base_path": self.base_path,
+                "permissions": self.permissions,
+                "max_response_bytes": self.max_response_bytes,
+                "response_budget_bytes": self.response_budget_bytes,
+                "max_transfer_bytes": self.max_transfer_bytes,
+                "max_inline_bytes": self.max_inline_bytes,
+                "max_message_bytes": self.max_message_bytes,
+                "read_chunk_bytes": self.read_chunk_bytes,
+                "list_cache_turns": self.list_cache_turns,
+                "cursor_turns": self.cursor_turns,
+                "max_walk_depth": self.max_walk_depth,
+                "max_walk_entries": self.max_walk_entries,
+                "max_work_entries": self.max_work_entries,
+                "max_work_bytes": self.max_work_bytes,
+                "heartbeat_interval": self.heartbeat_interval,
+                "backup_before_write": self.backup_before_write,
+                "truncation_ratio": self.truncation_ratio,
+                "audit_to_store": self.audit_to_store,
+                "workspace_overview": self.workspace_overview,
+                "batch_reports": self.batch_reports,
+                "detect_project": self.detect_project,
+                "editorconfig": self.editorconfig,
+                "state_encoding": self.state_encoding,
+                "batch_mode": self.batch_mode,
+                "non_utf8": self.non_utf8,
+                "cold_state_dir": self.cold_state_dir,
+                "log_level": self.log_level,
+                "metrics_interval": self.metrics_interval,
+                "mounts": self.mounts,
+                "delegates": self.delegates,
+                "policy": self.policy,
+                "quotas": self.quotas,
+                "usage_report_interval": self.usage_report_interval,
+                "stores": self.store_roles(),
+            },
+            "host": Capability::ALL
+                .iter()
+                .map(|capability| (capability.name().to_string(), json!(self.host.supports(*capability))))
+                .collect::<serde_json::Map<_, _>>(),
+            "protocol_version": self.protocol_version,
+            "max_protocol_version": PROTOCOL_VERSION,
+            "render_html": self.render_html,
+            "features": operations::FEATURES
+                .iter()
+                .map(|(name, included)| (name.to_string(), json!(included)))
+                .collect::<serde_json::Map<_, _>>(),
+            "command_format": "xml",
+            // Commands run one at a time in document order; the runtime
+            // exposes no clock, so results carry no timings
+            "execution_order": "sequential",
+            "timing": false,
+            "command_tag": parser::marker(&self.name),
+            "operations": operations,
+        })
+    }
+
+    /// Compact list of every operation, for `help` without a topic.
+    pub(crate) fn help_index(&self) -> String {
+        let lines = OPERATIONS
+            .iter()
+            .map(|spec| {
+                let marker = if !self.spec_allowed(spec) {
+                    " (not permitted)"
+                } else if !spec.is_built() || self.host.lacks_for(spec.name).is_some() {
+                    " (unsupported)"
+                } else {
+                    ""
+                };
+                format!("- {}: {}{}", spec.name, spec.description, marker)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            "Operations for '{}':\n{}\n\nUse help with <topic>operation-name</topic> for details.",
+            self.name, lines
+        )
+    }
+
+    fn register_stores(&mut self, stores: BTreeMap<String, String>) {
+        for (role, id) in stores {
+            if role == "chat" {
+                self.store_id = Some(id);
+            } else {
+                self.stores.insert(role, id);
+            }
+        }
+    }
+
+    /// Every registered store by role, including the chat store.
+    fn store_roles(&self) -> BTreeMap<String, String> {
+        let mut stores = self.stores.clone();
+        if let Some(chat) = &self.store_id {
+            stores.insert("chat".to_string(), chat.clone());
+        }
+        stores
+    }
+
+    fn describe_stores(&self) -> String {
+        self.store_roles()
+            .iter()
+            .map(|(role, id)| format!("{}={}", role, id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn load_message(&self, id: &str) -> Result<ChainEntry, FsChildError> {
+        // Ids are only unique within one store
+        let key = format!("{}/{}", self.store_for("chat").unwrap_or_default(), id);
+        if let Some(entry) = MESSAGE_CACHE.with(|cache| cache.borrow_mut().get(&key)) {
+            logger::trace("message", "Served from cache", &[("id", &id)]);
+            return Ok(entry);
+        }
+
+        let bytes = self.store_get("chat", id, self.max_message_bytes)?;
+        logger::debug("message", "Loaded", &[("id", &id), ("bytes", &bytes.len())]);
+
+        let entry = match serde_json::from_slice::<ChainEntry>(&bytes) {
+            Ok(entry) => entry,
+            Err(e) => {
+                logger::warn("message", "Full parse failed, salvaging", &[("id", &id), ("error", &e)]);
+                salvage_chain_entry(&bytes).ok_or(e)?
+            }
+        };
+        MESSAGE_CACHE.with(|cache| cache.borrow_mut().insert(key, entry.clone()));
+        Ok(entry)
+    }
+
+    fn cold_section_path(&self, dir: &str, section: &str) -> String {
+        self.resolve_path(&format!("{}/{}.json", dir, section))
+    }
+
+    /// Read the cold sections from `cold_state_dir`, once per message. A
+    /// section still present in the state blob (its last write failed) is
+    /// newer than its file and is kept.
+    pub(crate) fn load_cold_state(&mut self) {
+        let Some(dir) = self.cold_state_dir.clone() else {
+            return;
+        };
+        if self.cold_hashes.is_some() {
+            return;
+        }
+
+        let mut hashes = BTreeMap::new();
+        for section in COLD_SECTIONS {
+            let Ok(bytes) = self.fs.read_file(&self.cold_section_path(&dir, section)) else {
+                continue;
+            };
+            hashes.insert(section.to_string(), watch::content_hash(&bytes));
+            let loaded = match section {
+                "history" if self.history.is_empty() => {
+                    serde_json::from_slice(&bytes).map(|v| self.history = v)
+                }
+                "backups" if self.backups.is_empty() => {
+                    serde_json::from_slice(&bytes).map(|v| self.backups = v)
+                }
+                "watch_manifest" if self.watch_manifest.is_empty() => {
+                    serde_json::from_slice(&bytes).map(|v| self.watch_manifest = v)
+                }
+                _ => Ok(()),
+            };
+            if let Err(e) = loaded {
+                logger::warn("state", "Ignoring unreadable cold state", &[("section", &section), ("error", &e)]);
+            }
+        }
+        self.cold_hashes = Some(hashes);
+    }
+
+    /// Write back cold sections that changed since they were loaded and drop
+    /// them from the state blob. Sections that fail to write stay in the blob.
+    pub(crate) fn save_cold_state(&mut self) {
+        let Some(dir) = self.cold_state_dir.clone() else {
+            return;
+        };
+        let Some(hashes) = self.cold_hashes.take() else {
+            return;
+        };
+
+        let dir_path = self.resolve_path(&dir);
+        if !self.fs.path_exists(&dir_path).unwrap_or(false) {
+            if let Err(e) = self.fs.create_dir(&dir_path) {
+                logger::error("state", "Failed to create cold state directory", &[("path", &dir_path), ("error", &e)]);
+            }
+        }
+
+        for section in COLD_SECTIONS {
+            let encoded = match section {
+                "history" => serde_json::to_string(&self.history),
+                "backups" => serde_json::to_string(&self.backups),
+                _ => serde_json::to_string(&self.watch_manifest),
+            };
+            let Ok(encoded) = encoded else {
+                continue;
+            };
+            let unchanged = hashes.get(section) == Some(&watch::content_hash(encoded.as_bytes()));
+            if !unchanged {
+                if let Err(e) = self.fs.write_file(&self.cold_section_path(&dir, section), &encoded) {
+                    logger::warn("state", "Failed to write cold state, keeping it inline", &[("section", &section), ("error", &e)]);
+                    continue;
+                }
+            }
+            match section {
+                "history" => self.history = Vec::new(),
+                "backups" => self.backups = BTreeMap::new(),
+                _ => self.watch_manifest = BTreeMap::new(),
+            }
+        }
+    }
+
+    fn remember_processed_head(&mut self, head: &str) {
+        self.processed_heads.push_back(head.to_string());
+        while self.processed_heads.len() > PROCESSED_HEAD_LIMIT {
+            self.processed_heads.pop_front();
+        }
+    }
+
+    /// Re-hash every subscribed file and report what changed since the last
+    /// scan. The manifest is updated to the current state.
+    fn scan_subscriptions(&mut self) -> Vec<PathChange> {
+        if self.subscriptions.is_empty() {
+            return Vec::new();
+        }
+        self.load_cold_state();
+
+        let mut current = BTreeMap::new();
+        let mut budget = self.budget();
+        for pattern in &self.subscriptions {
+            for path in self.matching_files(pattern, &mut budget) {
+                if budget.exhausted() {
+                    break;
+                }
+                if let Ok(content) = self.fs.read_file(&self.resolve_path(&path)) {
+                    let content = compact::resolve(&*self.fs, &self.resolve_path("."), content);
+                    budget.spend_bytes(content.len());
+                    current.insert(path, watch::content_hash(&content));
+                }
+            }
+        }
+        if budget.exhausted() {
+            // Files not reached this time keep their last known hash rather
+            // than being reported as removed
+            logger::warn("watch", "Scan stopped at the work budget; unscanned files are unchanged", &[]);
+            for (path, hash) in &self.watch_manifest {
+                current.entry(path.clone()).or_insert_with(|| hash.clone());
+            }
+        }
+
+        let mut changes = Vec::new();
+        for (path, hash) in &current {
+            let kind = match self.watch_manifest.get(path) {
+                None => ChangeKind::Added,
+                Some(previous) if previous != hash => ChangeKind::Modified,
+                Some(_) => continue,
+            };
+            changes.push(PathChange {
+                path: path.clone(),
+                kind,
+            });
+        }
+        for path in self.watch_manifest.keys() {
+            if !current.contains_key(path) {
+                changes.push(PathChange {
+                    path: path.clone(),
+                    kind: ChangeKind::Removed,
+                });
+            }
+        }
+
+        self.watch_manifest = current;
+        changes
+    }
+
+    /// Collect structured requests that sibling children embedded in their
+    /// output. Siblings address us by listing commands under `fs_commands`
+    /// in their message data, each tagged with the target instance `name`.
+    fn extract_sibling_commands(
+        children: &[ChildMessage],
+        own_child_id: &str,
+        instance_name: &str,
+    ) -> Vec<FsCommand> {
+        let mut commands = Vec::new();
+
+        for child in children {
+            // Never act on our own previous output
+            if child.child_id == own_child_id {
+                continue;
+            }
+
+            let Some(requests) = child.data.get("fs_commands").and_then(|v| v.as_array()) else {
+                continue;
+            };
+
+            for req in requests {
+                if req.get("name").and_then(|n| n.as_str()) != Some(instance_name) {
+                    continue;
+                }
+                match serde_json::from_value::<FsCommand>(req.clone()) {
+                    Ok(cmd) => commands.push(cmd),
+                    Err(e) => logger::warn(
+                        "rollup",
+                        "Ignoring malformed sibling request",
+                        &[("child", &child.child_id), ("error", &e)],
+                    ),
+                }
+            }
+        }
+
+        commands
+    }
+
+    /// Route a parsed request to its handler.
+    pub(crate) fn dispatch(&mut self, request: &Value) -> ChildMessage {
+        match IncomingMessage::deserialize(request) {
+            Ok(IncomingMessage::Introduction { data }) => self.handle_introduction(data),
+            Ok(IncomingMessage::HeadUpdate { data }) => self.handle_head_update(data),
+            Ok(IncomingMessage::ConfigUpdate { data }) => self.handle_config_update(data),
+            Ok(IncomingMessage::DescribeCapabilities { data }) => {
+                self.handle_describe_capabilities(data)
+            }
+            Ok(IncomingMessage::CollectAsync { data }) => self.handle_collect_async(data),
+            Ok(IncomingMessage::NextChunk { data }) => self.handle_next_chunk(data),
+            Ok(IncomingMessage::Cancel { data }) => self.handle_cancel(data),
+            Ok(IncomingMessage::Stats { data }) => self.handle_stats(data),
+            Ok(IncomingMessage::Shutdown { data }) => self.handle_shutdown(data),
+            Ok(IncomingMessage::RunCommands { data }) => self.handle_run_commands(data),
+            Err(e) => {
+                logger::warn("request", "Invalid message", &[("error", &e)]);
+                self.errors.count(ErrorCode::ParseError);
+                let mut response = self.notice(
+                    format!("Invalid message: {}", e),
+                    request["data"]["head"].as_str().map(String::from),
+                );
+                response.data = json!({"error": ErrorCode::ParseError});
+                response
+            }
+        }
+    }
+
+    fn handle_introduction(&mut self, data: IntroductionData) -> ChildMessage {
+        logger::debug("request", "Processing introduction", &[]);
+        let (Some(child_id), Some(store_id)) = (data.child_id, data.store_id) else {
+            logger::warn("request", "Introduction lacks child_id or store_id", &[]);
+            return self.notice(
+                "Failed to get child_id or store_id from introduction".to_string(),
+                None,
+            );
+        };
+
+        let reintroduced = self.child_id.is_some();
+        if reintroduced {
+            logger::info(
+                "request",
+                "Re-introduced",
+                &[
+                    ("previous_child_id", &self.child_id.as_deref().unwrap_or_default()),
+                    ("child_id", &child_id),
+                ],
+            );
+            // Undelivered parts belong to a conversation the parent may no
+            // longer track; finished async work is still worth delivering.
+            self.pending_chunks.clear();
+            for message in &mut self.completed_async {
+                message.child_id = child_id.clone();
+            }
+            // A new introduction describes the complete set of stores
+            self.stores.clear();
+            // Head ids are only unique within one chat store
+            if self.store_id.as_deref() != Some(store_id.as_str()) {
+                self.processed_heads.clear();
+            }
+        }
+
+        self.child_id = Some(child_id.clone());
+        self.store_id = Some(store_id);
+        self.register_stores(data.stores);
+        self.render_html = data.renders_html.unwrap_or(true);
+        self.protocol_version = data
+            .protocol_version
+            .unwrap_or_else(legacy_protocol_version)
+            .clamp(1, PROTOCOL_VERSION);
+        logger::info(
+            "request",
+            "Introduced",
+            &[
+                ("child_id", &self.child_id.as_deref().unwrap_or_default()),
+                ("stores", &self.describe_stores()),
+                ("protocol_version", &self.protocol_version),
+            ],
+        );
+
+        let can_read = self.permissions.iter().any(|p| p == "read");
+        let project = match self.detect_project && can_read {
+            true => self.detected_project(),
+            false => None,
+        };
+        let paths = project.as_ref().map(|(_, paths)| paths.clone()).unwrap_or_default();
+        let mut text = render::intro_text(&self.name, &self.permissions, &paths);
+        if let Some((project, paths)) = &project {
+            if !paths.is_empty() {
+                let name = project.name.as_ref().map(|name| format!(" '{}'", name)).unwrap_or_default();
+                text.push_str(&format!(
+                    "\n\nThe examples use paths from the {} project{} ({}).",
+                    project.kind, name, project.manifest
+                ));
+            }
+        }
+        if !self.mounts.is_empty() {
+            let mounts = self
+                .mounts
+                .iter()
+                .map(|(name, mount)| format!("- {}:/ ({})", name, mount.permissions.join(", ")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            text.push_str(&format!("\n\nMounts, addressed by prefixing a path with the name:\n{}", mounts));
+        }
+        if !self.delegates.is_empty() {
+            let delegates = self
+                .delegates
+                .iter()
+                .map(|(name, delegate)| format!("- {}: {} ({})", name, delegate.path, delegate.permissions.join(", ")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            text.push_str(&format!(
+                "\n\nDelegates, addressed by their name in the command tag, with paths inside their directory:\n{}",
+                delegates
+            ));
+        }
+
+        let mut response_data = json!({
+            "protocol_version": self.protocol_version,
+            "reintroduced": reintroduced,
+            "capabilities": self.capabilities(),
+        });
+        if let Some((project, _)) = &project {
+            response_data["project"] = json!(project);
+        }
+        if self.workspace_overview && can_read {
+            let overview = self.workspace_overview();
+            text.push_str(&format!("\n\n{}", overview.text()));
+            response_data["workspace"] = json!(overview);
+        }
+
+        ChildMessage {
+            child_id,
+            text,
+            html: render::html!(render::intro_html(&self.name, &self.permissions, &paths)),
+            parent_id: data.head,
+            data: response_data,
+        }
+    }
+
+    fn handle_head_update(&mut self, data: HeadData) -> ChildMessage {
+        if let (Some(child_id), Some(head)) = (self.child_id.clone(), data.head.as_deref()) {
+            self.turn += 1;
+            self.push_metrics_if_due();
+            if self.processed_heads.iter().any(|seen| seen == head) {
+                // Redelivered or repeated head: already handled, nothing new to load
+                logger::debug("request", "Head already processed, skipping", &[("head", &head)]);
+                return self.idle_response(data.head);
+            }
+            logger::debug("request", "Processing head update", &[("head", &head)]);
+
+            match self.load_message(head) {
+                Ok(entry) => {
+                    self.remember_processed_head(head);
+                    let (commands, delegated) = match entry.data {
+                        MessageData::Chat(msg) => {
+                            logger::debug("request", "Scanning chat message", &[("bytes", &msg.content().len())]);
+                            (parser::commands(msg.content(), &self.name), self.delegated_commands(msg.content()))
+                        }
+                        MessageData::ChildRollup(children) => {
+                            logger::debug("request", "Scanning child rollup", &[("messages", &children.len())]);
+                            (State::extract_sibling_commands(&children, &child_id, &self.name), Vec::new())
+                        }
+                    };
+                    // Changes made by others since the last head
+                    let changes = self.scan_subscriptions();
+                    let due = queue::take_due(&mut self.deferred, self.turn);
+
+                    if !commands.is_empty() || !delegated.is_empty() || !due.is_empty() {
+                        let mut response = if commands.is_empty() {
+                            ChildMessage {
+                                child_id: child_id.clone(),
+                                text: String::new(),
+                                html: None,
+                                parent_id: Some(head.to_string()),
+                                data: json!({"head": head}),
+                            }
+                        } else {
+                            logger::info("batch", "Running commands", &[("head", &head), ("commands", &commands.len())]);
+                            let results = self.process_fs_commands(head, commands);
+                            self.batch_response(&child_id, head, &results)
+                        };
+                        let replies = self.run_delegated(head, delegated);
+                        add_delegated(&mut response, replies);
+                        self.run_deferred(head, due, &mut response);
+                        if self.usage_report_due() {
+                            self.last_usage_report_turn = self.turn;
+                            let (text, usage) = self.usage_report();
+                            response.text = format!("{}\n\n{}", response.text, text);
+                            response.data["usage"] = usage;
+                        }
+                        // Absorb our own and the delegates' writes so they
+                        // are not reported next time
+                        self.scan_subscriptions();
+
+                        if !changes.is_empty() {
+                            response.text = format!(
+                                "{}\n\nChanges detected in subscribed paths:\n{}",
+                                response.text,
+                                render::changes_text(&changes)
+                            );
+                            response.data["changes"] = json!(changes);
+                        }
+                        let response = self.negotiate(response);
+                        return self.chunk_response(response);
+                    }
+
+                    if !changes.is_empty() {
+                        logger::info("watch", "Subscribed paths changed", &[("changes", &changes.len())]);
+                        return self.negotiate(changes_notification(&child_id, head, &changes));
+                    }
+                }
+                Err(e) => {
+                    logger::error("message", "Failed to load", &[("head", &head), ("error", &e)]);
+                    self.errors.count(e.code());
+                    let error_text = format!("Failed to load message: {}", e);
+
+                    return self.negotiate(ChildMessage {
+                        child_id,
+                        html: render::html!(render::error_html(&error_text)),
+                        text: error_text,
+                        parent_id: Some(head.to_string()),
+                        data: json!({"head": head, "error": e.code()}),
+                    });
+                }
+            }
+        }
+
+        self.idle_response(data.head)
+    }
+
+    /// Response to a head-update with nothing of its own to report: a
+    /// finished async batch, a due heartbeat, or an empty message.
+    fn idle_response(&mut self, head: Option<String>) -> ChildMessage {
+        // Nothing to do for this head, so deliver a finished async batch if any
+        if !self.completed_async.is_empty() {
+            return self.completed_async.remove(0);
+        }
+
+        if self.heartbeat_due() {
+            self.last_heartbeat_turn = self.turn;
+            self.load_cold_state();
+            let status = self.status();
+            let text = format!(
+                "Heartbeat: turn {}, {} subscriptions watching {} files, {} queued messages, {} errors",
+                self.turn,
+                self.subscriptions.len(),
+                self.watch_manifest.len(),
+                self.completed_async.len() + self.pending_chunks.len(),
+                self.errors.total()
+            );
+            return self.negotiate(ChildMessage {
+                child_id: self.child_id.clone().unwrap_or_default(),
+                text,
+                html: None,
+                parent_id: head,
+                data: json!({"notification": "heartbeat", "status": status}),
+            });
+        }
+
+        ChildMessage {
+            child_id: self.child_id.clone().unwrap_or_default(),
+            text: String::new(),
+            html: None,
+            parent_id: head,
+            data: json!({}),
+        }
+    }
+
+    fn handle_config_update(&mut self, data: ConfigUpdateData) -> ChildMessage {
+        logger::debug("request", "Processing config-update", &[]);
+        self.register_stores(data.stores);
+        if let Some(renders_html) = data.renders_html {
+            self.render_html = renders_html;
+        }
+        if let Some(log_level) = data.log_level {
+            self.log_level = log_level;
+            logger::set_level(log_level);
+        }
+        let text = format!("Configuration updated. Stores: {}", self.describe_stores());
+        logger::info("request", "Configuration updated", &[("stores", &self.describe_stores())]);
+        self.notice(text, data.head)
+    }
+
+    fn handle_describe_capabilities(&self, data: HeadData) -> ChildMessage {
+        logger::debug("request", "Processing describe-capabilities", &[]);
+        let mut capabilities = self.capabilities();
+        capabilities["status"] = self.status();
+        let text = format!(
+            "Filesystem capabilities for '{}': {}",
+            self.name,
+            OPERATIONS
+                .iter()
+                .map(|spec| spec.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        ChildMessage {
+            child_id: self.child_id.clone().unwrap_or_default(),
+            text,
+            html: None,
+            parent_id: data.head,
+            data: capabilities,
+        }
+    }
+
+    fn handle_stats(&mut self, data: HeadData) -> ChildMessage {
+        logger::debug("request", "Processing stats", &[]);
+        let mut text = format!("Metrics for '{}': {}", self.name, self.metrics.summary());
+        let usage = match self.quotas.is_empty() {
+            true => Value::Null,
+            false => {
+                let (report, usage) = self.usage_report();
+                text = format!("{}\n\n{}", text, report);
+                usage
+            }
+        };
+        let mut response = self.notice(text, data.head);
+        response.data = json!({"metrics": self.metrics_snapshot(), "status": self.status(), "usage": usage});
+        response
+    }
+
+    /// The metrics as reported and pushed: per operation and in total.
+    fn metrics_snapshot(&self) -> Value {
+        json!({
+            "instance": self.name,
+            "child_id": self.child_id,
+            "turn": self.turn,
+            "operations": self.metrics.operations,
+            "total": self.metrics.total(),
+        })
+    }
+
+    /// Push the metrics to the store serving `metrics` once
+    /// `metrics_interval` head-updates have passed since the last push.
+    fn push_metrics_if_due(&mut self) {
+        let Some(interval) = self.metrics_interval.filter(|interval| *interval > 0) else {
+            return;
+        };
+        if self.turn - self.metrics.pushed_turn < interval || !self.host.supports(Capability::Store) {
+            return;
+        }
+        self.metrics.pushed_turn = self.turn;
+        let Ok(bytes) = serde_json::to_vec(&self.metrics_snapshot()) else {
+            return;
+        };
+        match self.store_put("metrics", bytes) {
+            Ok(id) => logger::debug("metrics", "Pushed", &[("turn", &self.turn), ("id", &id)]),
+            Err(e) => logger::error("metrics", "Failed to push", &[("turn", &self.turn), ("error", &e)]),
+        }
+    }
+
+    fn handle_collect_async(&mut self, data: HeadData) -> ChildMessage {
+        logger::debug("request", "Processing collect-async", &[]);
+        if self.completed_async.is_empty() {
+            return ChildMessage {
+                child_id: self.child_id.clone().unwrap_or_default(),
+                text: "No completed async operations".to_string(),
+                html: None,
+                parent_id: data.head,
+                data: json!({}),
+            };
+        }
+        self.completed_async.remove(0)
+    }
+
+    fn handle_next_chunk(&mut self, data: HeadData) -> ChildMessage {
+        logger::debug("request", "Processing next-chunk", &[]);
+        if self.pending_chunks.is_empty() {
+            return ChildMessage {
+                child_id: self.child_id.clone().unwrap_or_default(),
+                text: "No pending response parts".to_string(),
+                html: None,
+                parent_id: data.head,
+                data: json!({}),
+            };
+        }
+        self.pending_chunks.remove(0)
+    }
+
+    /// Drop everything queued for later delivery, and any staged changes,
+    /// and report what was dropped.
+    fn handle_cancel(&mut self, data: HeadData) -> ChildMessage {
+        logger::debug("request", "Processing cancel", &[]);
+        let mut cancelled = Vec::new();
+
+        for message in self.completed_async.drain(..) {
+            let head = message.parent_id.unwrap_or_default();
+            cancelled.push(json!({"kind": "async-result", "head": head}));
+        }
+        for task in self.deferred.drain(..) {
+            cancelled.push(json!({"kind": "deferred-command", "head": task.head, "task": task.id, "operation": task.command.operation}));
+        }
+        if !self.pending_chunks.is_empty() {
+            let head = self.pending_chunks[0].data["head"].clone();
+            cancelled.push(json!({
+                "kind": "response-parts",
+                "head": head,
+                "parts": self.pending_chunks.len(),
+            }));
+            self.pending_chunks.clear();
+        }
+        // Staged changes wait on the parent's approval too
+        if let Some(stage) = self.staging.take() {
+            staging::clear(&*self.fs, &self.resolve_path("."), &stage);
+            for path in stage.changes.keys() {
+                invalidate_listings(path);
+            }
+            self.usage.clear();
+            cancelled.push(json!({"kind": "staged-changes", "changes": stage.changes.len(), "paths": self.staged_paths(&stage)}));
+        }
+
+        let text = if cancelled.is_empty() {
+            "Nothing to cancel".to_string()
+        } else {
+            let lines = cancelled
+                .iter()
+                .map(|item| match item["kind"].as_str() {
+                    Some("response-parts") => format!(
+                        "  {} undelivered response parts for head {}",
+                        item["parts"],
+                        item["head"].as_str().unwrap_or_default()
+                    ),
+                    Some("deferred-command") => format!(
+                        "  deferred command #{} ('{}')",
+                        item["task"],
+                        item["operation"].as_str().unwrap_or_default()
+                    ),
+                    Some("staged-changes") => format!("  {} staged changes; staging is off", item["changes"]),
+                    _ => format!(
+                        "  undelivered async results for head {}",
+                        item["head"].as_str().unwrap_or_default()
+                    ),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("Cancelled {} queued items:\n{}", cancelled.len(), lines)
+        };
+
+        let mut response = self.notice(text, data.head);
+        response.data = json!({"cancelled": cancelled});
+        response
+    }
+
+    /// Release every backup this instance took, since nothing will restore
+    /// them once it is gone.
+    fn handle_shutdown(&mut self, data: HeadData) -> ChildMessage {
+        logger::debug("request", "Processing shutdown", &[]);
+        self.load_cold_state();
+        let (text, released) = match self.release_backups("", 0) {
+            Ok((released, None)) => ("Shutting down; no backups to release".to_string(), json!({"released": released})),
+            Ok((released, Some(id))) => (
+                format!("Shutting down; released {} backup(s), listed in store entry '{}' for collection", released.len(), id),
+                json!({"released": released, "marker": id}),
+            ),
+            Err(e) => {
+                self.errors.count(e.code());
+                (format!("Shutting down; backups kept, recording their release failed: {}", e), json!({"error": e.code()}))
+            }
+        };
+        let mut response = self.notice(text, data.head);
+        response.data = json!({"backups": released});
+        response
+    }
+
+    pub(crate) fn handle_async_batch(&mut self, batch: AsyncBatch) {
+        logger::info("batch", "Running async commands", &[("head", &batch.head), ("commands", &batch.commands.len())]);
+        let mut response = self.run_batch(batch);
+        response.data["async"] = json!(true);
+        self.completed_async.push(self.negotiate(response));
+    }
+
+    /// Run a batch a supervising instance forwarded, replying with every
+    /// result. The supervisor is this actor too, so the reply is never
+    /// cut down to an older protocol version.
+    fn handle_run_commands(&mut self, batch: AsyncBatch) -> ChildMessage {
+        logger::info("batch", "Running delegated commands", &[("head", &batch.head), ("commands", &batch.commands.len())]);
+        self.run_batch(batch)
+    }
+
+    pub(crate) fn run_batch(&mut self, batch: AsyncBatch) -> ChildMessage {
+        let results = self.process_fs_commands(&batch.head, batch.commands);
+        let child_id = self.child_id.clone().unwrap_or_default();
+        self.batch_response(&child_id, &batch.head, &results)
+    }
+
+    /// The response to a batch's results: audited, fitted to the response
+    /// budget, and with `batch_reports` summed up with a report.
+    fn batch_response(&mut self, child_id: &str, head: &str, results: &[CommandResult]) -> ChildMessage {
+        let audit_id = self.mirror_audit(head, results);
+        let (shown, trimmed) = self.fit_budget(results);
+        let mut response = results_response(child_id, head, shown.as_deref().unwrap_or(results), self.render_html);
+        if let Some(audit_id) = audit_id {
+            response.data["audit_id"] = json!(audit_id);
+        }
+        if !trimmed.is_empty() {
+            let indexes: Vec<String> = trimmed.iter().map(|t| (t.index + 1).to_string()).collect();
+            let which = match indexes.len() {
+                1 => format!("Result {} was", indexes[0]),
+                _ => format!("Results {} were", indexes.join(", ")),
+            };
+            response.text = format!(
+                "{}\n\n[{} trimmed to fit the {} byte response budget; data.budget says by how much]",
+                response.text,
+                which,
+                self.response_budget_bytes.unwrap_or_default()
+            );
+            response.data["budget"] = json!({"limit": self.response_budget_bytes, "trimmed": trimmed});
+        }
+        // The report keeps every result whole
+        self.attach_report(head, results, &mut response);
+        response
+    }
+
+    /// With `response_budget_bytes`, the results with any text over its
+    /// share trimmed, and what was trimmed. `None` when all fit.
+    fn fit_budget(&self, results: &[CommandResult]) -> (Option<Vec<CommandResult>>, Vec<allot::Trimmed>) {
+        let Some(budget) = self.response_budget_bytes else {
+            return (None, Vec::new());
+        };
+        // Results are separated by blank lines
+        let budget = budget.saturating_sub(2 * results.len().saturating_sub(1));
+        let sizes: Vec<usize> = results.iter().map(|result| result.message.len()).collect();
+        if sizes.iter().sum::<usize>() <= budget {
+            return (None, Vec::new());
+        }
+        let mut shown = results.to_vec();
+        let mut trimmed = Vec::new();
+        for ((index, result), share) in shown.iter_mut().enumerate().zip(allot::shares(&sizes, budget)) {
+            if result.message.len() <= share {
+                continue;
+            }
+            let (text, lines) = allot::trim(&result.operation, &result.message, share);
+            trimmed.push(allot::Trimmed {
+                index,
+                operation: result.operation.clone(),
+                path: result.path.clone(),
+                kept: text.len(),
+                bytes: result.message.len(),
+                lines,
+            });
+            result.message = text;
+        }
+        (Some(shown), trimmed)
+    }
+
+    /// With `batch_reports`, write the results to the batch's report and
+    /// cut the response text down to a summary naming it. A report that
+    /// cannot be written leaves the response in full.
+    fn attach_report(&mut self, head: &str, results: &[CommandResult], response: &mut ChildMessage) {
+        if !self.batch_reports || results.is_empty() {
+            return;
+        }
+        let path = report::path(head);
+        let written = self
+            .create_parents(&path)
+            .and_then(|_| self.fs.write_file(&self.resolve_path(&path), &report::markdown(head, self.turn, results)));
+        if let Err(e) = written {
+            logger::warn("report", "Failed to write batch report", &[("path", &path), ("error", &e)]);
+            return;
+        }
+        invalidate_listings(&self.resolve_path(&path));
+        self.reports.retain(|report| *report != path);
+        self.reports.push(path.clone());
+        while self.reports.len() > report::MAX_REPORTS {
+            let oldest = self.reports.remove(0);
+            let _ = self.fs.delete_file(&self.resolve_path(&oldest));
+        }
+        response.text = format!("{}\n\nFull results: {}", report::summary(results), path);
+        response.data["report"] = json!(path);
+    }
+
+    /// Commands in a message addressed to each delegate, for the delegates
+    /// that have any.
+    pub(crate) fn delegated_commands(&self, message: &str) -> Vec<(String, Vec<FsCommand>)> {
+        self.delegates
+            .keys()
+            .map(|name| (name.clone(), parser::commands(message, name)))
+            .filter(|(_, commands)| !commands.is_empty())
+            .collect()
+    }
+
+    /// Hand each delegate its commands and collect the replies.
+    pub(crate) fn run_delegated(
+        &mut self,
+        head: &str,
+        delegated: Vec<(String, Vec<FsCommand>)>,
+    ) -> Vec<(String, Result<ChildMessage, FsChildError>)> {
+        let mut replies = Vec::new();
+        for (name, commands) in delegated {
+            logger::info("delegate", "Forwarding commands", &[("delegate", &name), ("commands", &commands.len())]);
+            let reply = self.forward(&name, head, commands);
+            if let Err(e) = &reply {
+                logger::warn("delegate", "Delegation failed", &[("delegate", &name), ("error", e)]);
+                self.errors.count(e.code());
+            }
+            replies.push((name, reply));
+        }
+        replies
+    }
+
+    fn forward(&mut self, name: &str, head: &str, commands: Vec<FsCommand>) -> Result<ChildMessage, FsChildError> {
+        if let Some(outside) = commands.iter().find_map(delegate::escapes) {
+            return Err(FsChildError::InvalidPath(format!(
+                "Not delegating to '{}': '{}' is outside its directory",
+                name, outside
+            )));
+        }
+        let actor_id = self.delegate_actor(name)?;
+        let request = json!({"msg_type": "run-commands", "data": {"head": head, "commands": commands}});
+        let reply = self.supervisor.request(&actor_id, &serde_json::to_vec(&request)?)?;
+        serde_json::from_slice(&reply)
+            .map_err(|e| FsChildError::Delegate(format!("Delegate '{}' sent an unreadable reply: {}", name, e)))
+    }
+
+    /// The actor running a delegate, spawned from its manifest the first
+    /// time it is needed.
+    fn delegate_actor(&mut self, name: &str) -> Result<String, FsChildError> {
+        let delegate = &self.delegates[name];
+        if let Some(actor_id) = &delegate.actor_id {
+            return Ok(actor_id.clone());
+        }
+        if !self.host.supports(Capability::Supervisor) {
+            return Err(FsChildError::Unsupported(format!(
+                "Cannot spawn delegate '{}': this runtime provides no supervisor interface; give its `actor_id` instead",
+                name
+            )));
+        }
+        let init = delegate.init(name, &self.resolve_path(&delegate.path));
+        let spawned = self.supervisor.spawn(delegate.manifest.as_deref().unwrap_or_default(), &init);
+        // Later delegates fail fast once the host has refused
+        if let Err(FsChildError::Unsupported(_)) = &spawned {
+            self.host.mark_missing(Capability::Supervisor);
+        }
+        let actor_id = spawned?;
+        logger::info("delegate", "Spawned", &[("delegate", &name), ("actor_id", &actor_id)]);
+        if let Some(delegate) = self.delegates.get_mut(name) {
+            delegate.actor_id = Some(actor_id.clone());
+        }
+        Ok(actor_id)
+    }
+
+    /// Strip response features the parent did not declare support for.
+    /// Version 1 parents only ever received the head in `data`.
+    fn negotiate(&self, mut response: ChildMessage) -> ChildMessage {
+        if !self.render_html {
+            response.html = None;
+        }
+        if self.protocol_version < 2 {
+            let head = response.data.get("head").cloned();
+            response.data = match head {
+                Some(head) => json!({"head": head}),
+                None => json!({}),
+            };
+        }
+        response
+    }
+
+    /// A short plain message, used for protocol-level problems.
+    pub(crate) fn notice(&self, text: String, parent_id: Option<String>) -> ChildMessage {
+        ChildMessage {
+            child_id: self.child_id.clone().unwrap_or_default(),
+            html: render::html!(render::notice_html(&text)),
+            text,
+            parent_id,
+            data: json!({}),
+        }
+    }
+}
+
+fn results_response(
+    child_id: &str,
+    head: &str,
+    results: &[CommandResult],
+    render_html: bool,
+) -> ChildMessage {
+    ChildMessage {
+        child_id: child_id.to_string(),
+        text: render::results_text(results),
+        html: if render_html { render::html!(render::results_html(results)) } else { None },
+        parent_id: Some(head.to_string()),
+        data: json!({"head": head, "results": results}),
+    }
+}
+
+/// Fold the delegates' replies into `response`: a section of text for
+/// each, and their data under `data.delegated`.
+fn add_delegated(response: &mut ChildMessage, replies: Vec<(String, Result<ChildMessage, FsChildError>)>) {
+    if replies.is_empty() {
+        return;
+    }
+    let mut delegated = serde_json::Map::new();
+    for (name, reply) in replies {
+        let (text, data) = match reply {
+            Ok(reply) => (reply.text, reply.data),
+            Err(e) => (format!("Error: {}", e), json!({"error": e.code(), "message": e.to_string()})),
+        };
+        let section = format!("Delegated to '{}':\n{}", name, text);
+        if let Some(html) = render::html!(render::notice_html(&section)) {
+            response.html = Some(response.html.take().unwrap_or_default() + &html);
+        }
+        response.text = if response.text.is_empty() { section } else { format!("{}\n\n{}", response.text, section) };
+        delegated.insert(name, data);
+    }
+    response.data["delegated"] = Value::Object(delegated);
+}
+
+/// Recover the chat text from an entry whose shape does not fully match
+/// `ChainEntry` (new message variants, extra required fields), so commands in
+/// it can still be found.
+fn salvage_chain_entry(bytes: &[u8]) -> Option<ChainEntry> {
+    let value: Value = serde_json::from_slice(bytes).ok()?;
+    let chat = value.get("data")?.get("Chat")?;
+    let content = chat
+        .as_object()?
+        .values()
+        .find_map(|variant| variant.get("content")?.as_str())?
+        .to_string();
+    logger::debug("message", "Salvaged chat content from a partially understood message", &[]);
+
+    Some(ChainEntry {
+        parent: value["parent"].as_str().map(String::from),
+        id: value["id"].as_str().map(String::from),
+        data: MessageData::Chat(Message::User { content }),
+    })
+}
+
+/// Whether a subscription pattern covers a path, treating a literal pattern
+/// as the file itself or a directory prefix.
+pub(crate) fn watches(pattern: &str, path: &str) -> bool {
+    if watch::is_glob(pattern) {
+        watch::glob_match(pattern, path)
+    } else {
+        path == pattern || path.starts_with(&format!("{}/", pattern))
+    }
+}
+
+fn changes_notification(child_id: &str, head: &str, changes: &[PathChange]) -> ChildMessage {
+    let text = format!(
+        "Changes detected in subscribed paths:\n{}",
+        render::changes_text(changes)
+    );
+
+    ChildMessage {
+        child_id: child_id.to_string(),
+        text,
+        html: render::html!(render::changes_html(changes)),
+        parent_id: Some(head.to_string()),
+        data: json!({"head": head, "notification": "path-changes", "changes": changes}),
+    }
+}
+
+/// Split text into pieces of at most `max` bytes, preferring to break after a
+/// newline and never splitting a UTF-8 character.
+fn split_text(text: &str, max: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+
+    while rest.len() > max {
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Some(newline) = rest[..end].rfind('\n') {
+            if newline > 0 {
+                end = newline + 1;
+            }
+        }
+        if end == 0 {
+            // A single character wider than the limit
+            end = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(rest.len());
+        }
+        parts.push(rest[..end].to_string());
+        rest = &rest[end..];
+    }
+    parts.push(rest.to_string());
+
+    parts
+}
@@ -0,0 +1,260 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[cfg(feature = "store")]
+use crate::bindings::ntwk::theater::message_server_host::request;
+use crate::error::FsChildError;
+use crate::host;
+use crate::logger;
+
+/// Access to store actors. Message handling only talks to stores through
+/// this trait, so other protocols or an in-memory double can stand in for
+/// the JSON request protocol.
+pub trait StoreClient: std::fmt::Debug {
+    /// Fetch the value stored under `id` in the store actor `store_id`,
+    /// refusing values larger than `max_bytes` with [`FsChildError::TooLarge`].
+    fn get(&self, store_id: &str, id: &str, max_bytes: usize) -> Result<Vec<u8>, FsChildError>;
+
+    /// Store `content` in the store actor `store_id`, returning its id.
+    fn put(&self, store_id: &str, content: Vec<u8>) -> Result<String, FsChildError>;
+}
+
+/// Worst-case size of one byte in a JSON byte array (`255,`)
+const ENCODED_BYTE_WIDTH: usize = 4;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    _type: String,
+    data: Action,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Action {
+    Get(String),
+    Put(Vec<u8>),
+}
+
+/// The store actor's JSON request protocol over the message server.
+#[derive(Debug)]
+pub struct JsonStoreClient;
+
+/// Typed view of a Get response, so byte arrays decode straight into a
+/// `Vec<u8>` instead of one `Value` per byte.
+#[derive(Debug, Deserialize)]
+struct GetResponse {
+    status: String,
+    #[serde(default)]
+    data: Option<GetData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetData {
+    #[serde(rename = "Get")]
+    get: Option<GetValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetValue {
+    value: StoreValue,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StoreValue {
+    Bytes(Vec<u8>),
+    Other(Value),
+}
+
+impl JsonStoreClient {
+    fn send(&self, store_id: &str, action: Action) -> Result<Vec<u8>, FsChildError> {
+        let req = Request {
+            _type: "request".to_string(),
+            data: action,
+        };
+
+        let request_bytes = serde_json::to_vec(&req)?;
+        let response_bytes = host_request(store_id, &request_bytes)
+            .map_err(|e| match host::is_unsupported(&e) {
+                true => FsChildError::Unsupported(format!("Store requests are unsupported by host: {}", e)),
+                false => FsChildError::Store(format!("Store request failed: {}", e)),
+            })?;
+
+        logger::trace("store", "Response", &[("bytes", &response_bytes.len())]);
+        Ok(response_bytes)
+    }
+}
+
+/// Send `bytes` to the actor `actor_id` through the message server.
+#[cfg(feature = "store")]
+pub fn host_request(actor_id: &str, bytes: &Vec<u8>) -> Result<Vec<u8>, String> {
+    request(&actor_id.to_string(), bytes)
+}
+
+/// Without the `store` feature the component does not import the interface.
+#[cfg(not(feature = "store"))]
+pub fn host_request(_actor_id: &str, _bytes: &Vec<u8>) -> Result<Vec<u8>, String> {
+    Err("not supported: this build does not import the message-server-host interface".to_string())
+}
+
+impl StoreClient for JsonStoreClient {
+    fn get(&self, store_id: &str, id: &str, max_bytes: usize) -> Result<Vec<u8>, FsChildError> {
+        let response_bytes = self.send(store_id, Action::Get(id.to_string()))?;
+
+        // Reject before parsing when even the densest encoding is too big
+        let encoded_limit = max_bytes.saturating_mul(ENCODED_BYTE_WIDTH).saturating_add(1024);
+        if response_bytes.len() > encoded_limit {
+            return Err(FsChildError::TooLarge(format!(
+                "store value is {} bytes, over the {} byte limit",
+                response_bytes.len() / ENCODED_BYTE_WIDTH,
+                max_bytes
+            )));
+        }
+
+        let response: GetResponse = serde_json::from_slice(&response_bytes).map_err(malformed)?;
+        drop(response_bytes);
+        if response.status == "ok" {
+            if let Some(value) = response.data.and_then(|d| d.get) {
+                let bytes = match value.value {
+                    StoreValue::Bytes(bytes) => bytes,
+                    StoreValue::Other(value) => {
+                        decode_store_value(&value).map_err(FsChildError::Store)?
+                    }
+                };
+                if bytes.len() > max_bytes {
+                    return Err(FsChildError::TooLarge(format!(
+                "store value is {} bytes, over the {} byte limit",
+                bytes.len(),
+                max_bytes
+            )));
+                }
+                return Ok(bytes);
+            }
+        }
+        Err(FsChildError::Store(format!("Failed to load '{}' from store", id)))
+    }
+
+    fn put(&self, store_id: &str, content: Vec<u8>) -> Result<String, FsChildError> {
+        let response: Value =
+            serde_json::from_slice(&self.send(store_id, Action::Put(content))?).map_err(malformed)?;
+        if response["status"].as_str() == Some("ok") {
+            let put = &response["data"]["Put"];
+            if let Some(id) = put["id"].as_str().or_else(|| put.as_str()) {
+                return Ok(id.to_string());
+            }
+        }
+        Err(FsChildError::Store("Store did not return an id".to_string()))
+    }
+}
+
+/// A reply that is not the JSON the protocol promises is the store's fault,
+/// not a parse error in anything the parent sent.
+fn malformed(error: serde_json::Error) -> FsChildError {
+    FsChildError::Store(format!("Malformed store response: {}", error))
+}
+
+/// Decode a value returned by the store. Stores may send a JSON array of
+/// bytes, a base64 string, or an object tagging its `encoding`; anything
+/// malformed is an error rather than silently corrupted data.
+pub fn decode_store_value(value: &Value) -> Result<Vec<u8>, String> {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                item.as_u64()
+                    .filter(|byte| *byte <= u8::MAX as u64)
+                    .map(|byte| byte as u8)
+                    .ok_or_else(|| format!("Invalid byte at index {} in store value: {}", i, item))
+            })
+            .collect(),
+        Value::String(encoded) => BASE64
+            .decode(encoded.trim())
+            .map_err(|e| format!("Invalid base64 store value: {}", e)),
+        Value::Object(object) => {
+            let data = object.get("data").ok_or("Store value object has no data")?;
+            match object.get("encoding").and_then(|e| e.as_str()) {
+                Some("base64") | Some("bytes") | None => decode_store_value(data),
+                Some("utf8") | Some("utf-8") => data
+                    .as_str()
+                    .map(|text| text.as_bytes().to_vec())
+                    .ok_or_else(|| "utf8 store value data is not a string".to_string()),
+                Some(other) => Err(format!("Unsupported store value encoding '{}'", other)),
+            }
+        }
+        other => Err(format!("Unexpected store value: {}", other)),
+    }
+}
+
+/// In-memory store for exercising store-backed behavior without a host.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    values: std::cell::RefCell<std::collections::BTreeMap<(String, String), Vec<u8>>>,
+}
+
+#[cfg(test)]
+impl StoreClient for MemoryStore {
+    fn get(&self, store_id: &str, id: &str, max_bytes: usize) -> Result<Vec<u8>, FsChildError> {
+        let values = self.values.borrow();
+        let value = values
+            .get(&(store_id.to_string(), id.to_string()))
+            .ok_or_else(|| FsChildError::Store(format!("Failed to load '{}' from store", id)))?;
+        if value.len() > max_bytes {
+            return Err(FsChildError::TooLarge(format!(
+                "store value is {} bytes, over the {} byte limit",
+                value.len(),
+                max_bytes
+            )));
+        }
+        Ok(value.clone())
+    }
+
+    fn put(&self, store_id: &str, content: Vec<u8>) -> Result<String, FsChildError> {
+        let id = crate::watch::content_hash(&content);
+        self.values
+            .borrow_mut()
+            .insert((store_id.to_string(), id.clone()), content);
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn memory_store_round_trips_values() {
+        let store = MemoryStore::default();
+        let id = store.put("store-1", b"hello".to_vec()).unwrap();
+        assert_eq!(store.get("store-1", &id, 1024).unwrap(), b"hello");
+        assert!(store.get("store-2", &id, 1024).is_err());
+    }
+
+    #[test]
+    fn memory_store_enforces_size_limit() {
+        let store = MemoryStore::default();
+        let id = store.put("store-1", b"hello".to_vec()).unwrap();
+        let err = store.get("store-1", &id, 4).unwrap_err();
+        assert!(matches!(err, FsChildError::TooLarge(_)));
+    }
+
+    #[test]
+    fn decodes_byte_arrays_and_base64() {
+        assert_eq!(decode_store_value(&json!([104, 105])).unwrap(), b"hi");
+        assert_eq!(decode_store_value(&json!("aGk=")).unwrap(), b"hi");
+        assert_eq!(
+            decode_store_value(&json!({"encoding": "utf8", "data": "hi"})).unwrap(),
+            b"hi"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_byte_arrays() {
+        assert!(decode_store_value(&json!([104, 256])).is_err());
+        assert!(decode_store_value(&json!([104, "x"])).is_err());
+        assert!(decode_store_value(&json!("not base64!")).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Scanning of the `<fs-command>` markup in chat messages. Every offset
+//! is produced by `find` on the slice being indexed or by stepping over an
+//! ASCII byte, so no slice can land inside a multi-byte character whatever
+//! the input looks like.
+
+use std::collections::BTreeSet;
+
+/// Most commands taken from one message; the rest are ignored.
+pub const MAX_COMMANDS: usize = 256;
+
+/// Most fields read from one command block.
+pub const MAX_FIELDS: usize = 64;
+
+const BLOCK_END: &str = "</fs-command>";
+
+/// One `<fs-command>` block: its fields in the order they appear.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Block<'a> {
+    pub fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Block<'a> {
+    /// The first field with this name.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.fields.iter().find(|(field, _)| *field == name).map(|(_, value)| *value)
+    }
+}
+
+/// Every complete block opened by `marker`, up to [`MAX_COMMANDS`].
+///
+/// Field values are skipped as a whole, so markup inside a file's
+/// `content` (another `<path>`, `</fs-command>`, or a second marker) is
+/// treated as text rather than ending or starting a command. A block
+/// without a closing tag is dropped.
+pub fn blocks<'a>(content: &'a str, marker: &str) -> Vec<Block<'a>> {
+    let mut blocks = Vec::new();
+    let mut rest = content;
+    while blocks.len() < MAX_COMMANDS {
+        let Some(start) = rest.find(marker) else {
+            break;
+        };
+        rest = &rest[start + marker.len()..];
+        match scan_block(rest) {
+            Some((block, consumed)) => {
+                blocks.push(block);
+                rest = &rest[consumed..];
+            }
+            None => break,
+        }
+    }
+    blocks
+}
+
+/// Read fields until the closing tag, returning the block and how many
+/// bytes of `body` it used.
+fn scan_block(body: &str) -> Option<(Block<'_>, usize)> {
+    let mut block = Block::default();
+    // Names whose closing tag does not occur again; looking for them again
+    // further on would only repeat a failed search
+    let mut unclosed: BTreeSet<&str> = BTreeSet::new();
+    let mut pos = 0;
+    loop {
+        let lt = pos + body[pos..].find('<')?;
+        let tail = &body[lt..];
+        if tail.starts_with(BLOCK_END) {
+            return Some((block, lt + BLOCK_END.len()));
+        }
+        pos = lt + 1;
+        let Some(name) = tag_name(&tail[1..]) else {
+            continue;
+        };
+        if unclosed.contains(name) {
+            continue;
+        }
+        let value_start = lt + name.len() + 2;
+        let close = format!("</{}>", name);
+        match body[value_start..].find(&close) {
+            Some(len) => {
+                if block.fields.len() < MAX_FIELDS {
+                    block.fields.push((name, &body[value_start..value_start + len]));
+                }
+                pos = value_start + len + close.len();
+            }
+            None => {
+                unclosed.insert(name);
+            }
+        }
+    }
+}
+
+/// The name of an opening tag at the start of `s` (just past its `<`), if
+/// it is a plain `name>` with no attributes.
+fn tag_name(s: &str) -> Option<&str> {
+    let end = s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))?;
+    let name = &s[..end];
+    let starts_well = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    (starts_well && s[end..].starts_with('>')).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKER: &str = "<fs-command name=\"default\">";
+
+    #[test]
+    fn markup_inside_values_is_text() {
+        let message = format!(
+            "{MARKER}<operation>write-file</operation><content>{MARKER}<path>evil</path></fs-command></content><path>a.txt</path></fs-command>"
+        );
+        let blocks = blocks(&message, MARKER);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].get("path"), Some("a.txt"));
+        assert_eq!(blocks[0].get("content"), Some(format!("{MARKER}<path>evil</path></fs-command>").as_str()));
+    }
+
+    #[test]
+    fn malformed_markup_does_not_panic() {
+        for message in [
+            format!("{MARKER}</operation><operation>"),
+            format!("{MARKER}<operation>é</fs-command>"),
+            format!("{MARKER}<<<<é<a><b></a></fs-command>{MARKER}"),
+            format!("{MARKER}<operation"),
+            "é".repeat(10) + MARKER,
+        ] {
+            let _ = blocks(&message, MARKER);
+        }
+        let message = format!("{MARKER}<operation>read-file</fs-command>");
+        assert_eq!(blocks(&message, MARKER), vec![Block::default()]);
+    }
+
+    #[test]
+    fn caps_commands_per_message() {
+        let message = format!("{MARKER}<operation>help</operation></fs-command>").repeat(MAX_COMMANDS + 5);
+        assert_eq!(blocks(&message, MARKER).len(), MAX_COMMANDS);
+    }
+}
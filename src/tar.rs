@@ -0,0 +1,219 @@
+//! Reading and writing tar archives in the POSIX ustar layout, enough to
+//! move a directory of files through the store as a single value.
+//!
+//! Archives are written with regular file entries only, mode 0644 and a
+//! zero timestamp, since the host reports neither. Reading also accepts
+//! directory entries and the long names GNU tar and pax write; links and
+//! devices are listed but carry no content.
+
+const BLOCK: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Directory,
+    /// Links, devices and anything else, by type flag
+    Other(char),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub kind: Kind,
+    pub content: Vec<u8>,
+}
+
+/// An archive holding `files`, each a path and its bytes.
+pub fn write(files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    for (path, content) in files {
+        let (prefix, name) = split_name(path)?;
+        let mut header = [0u8; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        put_octal(&mut header[100..108], 0o644);
+        put_octal(&mut header[108..116], 0);
+        put_octal(&mut header[116..124], 0);
+        put_octal(&mut header[124..136], content.len() as u64);
+        put_octal(&mut header[136..148], 0);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        let checksum = checksum(&header);
+        header[148..154].copy_from_slice(format!("{:06o}", checksum).as_bytes());
+        header[154] = 0;
+        header[155] = b' ';
+
+        out.extend_from_slice(&header);
+        out.extend_from_slice(content);
+        out.resize(out.len().next_multiple_of(BLOCK), 0);
+    }
+    // Two empty blocks mark the end
+    out.resize(out.len() + 2 * BLOCK, 0);
+    Ok(out)
+}
+
+/// The entries of an archive, in order.
+pub fn read(bytes: &[u8]) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    // Name for the next entry, from a pax or GNU long-name record
+    let mut long_name: Option<String> = None;
+    while offset + BLOCK <= bytes.len() {
+        let header = &bytes[offset..offset + BLOCK];
+        if header.iter().all(|b| *b == 0) {
+            return Ok(entries);
+        }
+        let stored = parse_octal(&header[148..156]).ok_or_else(|| format!("bad header checksum at byte {}", offset))?;
+        if stored != checksum(header) {
+            return Err(format!("header checksum mismatch at byte {}; the archive is damaged or not a tar file", offset));
+        }
+        let size = parse_octal(&header[124..136]).ok_or_else(|| format!("bad entry size at byte {}", offset))? as usize;
+        let start = offset + BLOCK;
+        let content = bytes
+            .get(start..start.saturating_add(size))
+            .ok_or_else(|| format!("the archive ends inside the entry at byte {}", offset))?;
+        offset = start + size.next_multiple_of(BLOCK);
+
+        let flag = header[156];
+        match flag {
+            b'x' => {
+                long_name = pax_path(content).or(long_name);
+                continue;
+            }
+            b'L' => {
+                long_name = Some(text(content));
+                continue;
+            }
+            b'g' => continue,
+            _ => {}
+        }
+        let path = match long_name.take() {
+            Some(path) => path,
+            None => {
+                let name = text(&header[..100]);
+                let prefix = if &header[257..262] == b"ustar" { text(&header[345..500]) } else { String::new() };
+                if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+            }
+        };
+        let kind = match flag {
+            b'0' | 0 | b'7' if !path.ends_with('/') => Kind::File,
+            b'5' | b'0' | 0 => Kind::Directory,
+            other => Kind::Other(other as char),
+        };
+        let content = if kind == Kind::File { content.to_vec() } else { Vec::new() };
+        entries.push(Entry { path, kind, content });
+    }
+    if entries.is_empty() {
+        return Err("not a tar archive".to_string());
+    }
+    // Some writers leave out the end marker
+    Ok(entries)
+}
+
+/// An entry's path relative to where the archive is unpacked, without `./`
+/// or a trailing `/`. Absolute paths and `..` would reach outside it.
+pub fn relative_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') || path.contains('\\') {
+        return Err(format!("'{}' is not a relative path", path));
+    }
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return Err(format!("'{}' reaches outside the directory", path)),
+            part => parts.push(part),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+/// The ustar prefix and name fields for `path`: up to 155 and 100 bytes,
+/// split at a `/`.
+fn split_name(path: &str) -> Result<(&str, &str), String> {
+    if path.len() <= 100 {
+        return Ok(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+        .ok_or_else(|| format!("'{}' is too long for a tar header", path))
+}
+
+/// Sum of the header bytes, counting the checksum field as spaces.
+fn checksum(header: &[u8]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' as u64 } else { *b as u64 })
+        .sum()
+}
+
+/// Write `value` as zero-padded octal, leaving the last byte NUL.
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let digits = text(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// A NUL-terminated header field.
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// The `path` record of a pax extended header, whose records read
+/// `<length> path=<value>\n`.
+fn pax_path(records: &[u8]) -> Option<String> {
+    let records = String::from_utf8_lossy(records);
+    records.lines().find_map(|record| {
+        let (_, field) = record.split_once(' ')?;
+        field.strip_prefix("path=").map(str::to_string)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_files() {
+        let long = format!("{}/{}.txt", "nested/dir".repeat(12), "n".repeat(90));
+        let files = vec![
+            ("a.txt".to_string(), b"hello\n".to_vec()),
+            ("bin/data".to_string(), vec![0, 159, 146, 150]),
+            (long.clone(), Vec::new()),
+        ];
+        let archive = write(&files).unwrap();
+        assert_eq!(archive.len() % BLOCK, 0);
+        assert_eq!(&archive[257..262], b"ustar");
+
+        let entries = read(&archive).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1], Entry { path: "bin/data".to_string(), kind: Kind::File, content: vec![0, 159, 146, 150] });
+        assert_eq!(entries[2].path, long);
+        assert!(write(&[("x".repeat(101), Vec::new())]).is_err());
+
+        let mut damaged = archive.clone();
+        damaged[0] = b'b';
+        assert!(read(&damaged).is_err());
+        assert!(read(b"plain text").is_err());
+    }
+
+    #[test]
+    fn keeps_paths_inside_the_directory() {
+        assert_eq!(relative_path("./src//lib.rs").unwrap(), "src/lib.rs");
+        assert_eq!(relative_path("docs/").unwrap(), "docs");
+        assert!(relative_path("/etc/passwd").is_err());
+        assert!(relative_path("a/../../b").is_err());
+        assert_eq!(pax_path(b"30 mtime=1.0\n25 path=very/long/name\n").as_deref(), Some("very/long/name"));
+    }
+}
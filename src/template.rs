@@ -0,0 +1,143 @@
+//! Jinja-style templates kept in the workspace, filled from a JSON context
+//! by `render`.
+//!
+//! Templates support the usual `{{ value }}`, `{% for %}`, `{% if %}`,
+//! filters, macros and `{% include %}`/`{% extends %}` of other templates,
+//! named by their workspace path. Block tags take their own line without
+//! leaving a blank one behind, and a template's final newline is kept, so
+//! generated files come out as they are laid out. Using a value the context
+//! does not have is an error rather than an empty string, except as an `if`
+//! condition.
+
+use minijinja::{Environment, ErrorKind, UndefinedBehavior};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+/// Most templates one render loads, counting the one rendered
+pub const MAX_TEMPLATES: usize = 32;
+
+/// Evaluation steps one render may take, so a runaway loop stops
+const FUEL: u64 = 1_000_000;
+
+/// The context in a `render` command: a JSON object, empty when not given.
+pub fn context(text: Option<&str>) -> Result<Value, String> {
+    let Some(text) = text.map(str::trim).filter(|text| !text.is_empty()) else {
+        return Ok(Value::Object(Default::default()));
+    };
+    match serde_json::from_str(text) {
+        Ok(Value::Object(fields)) => Ok(Value::Object(fields)),
+        Ok(_) => Err("the context must be a JSON object".to_string()),
+        Err(e) => Err(format!("the context is not valid JSON: {}", e)),
+    }
+}
+
+/// Why a render failed.
+#[derive(Debug)]
+pub struct Failure {
+    pub message: String,
+    /// A template included or extended does not exist
+    pub missing: bool,
+}
+
+/// The template `name` rendered with `context`. Templates it includes or
+/// extends are fetched with `load`, which gives `None` for one that does
+/// not exist.
+pub fn render(
+    name: &str,
+    source: String,
+    context: &Value,
+    mut load: impl FnMut(&str) -> Result<Option<String>, String>,
+) -> Result<String, Failure> {
+    let fail = |message: String| Failure { message, missing: false };
+    let sources = Arc::new(Mutex::new(BTreeMap::from([(name.to_string(), source)])));
+    let mut absent = BTreeSet::new();
+    loop {
+        let missed = Arc::new(Mutex::new(BTreeSet::new()));
+        let mut env = Environment::new();
+        env.set_keep_trailing_newline(true);
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+        env.set_fuel(Some(FUEL));
+        let (known, misses) = (Arc::clone(&sources), Arc::clone(&missed));
+        env.set_loader(move |name| {
+            let found = known.lock().unwrap().get(name).cloned();
+            if found.is_none() {
+                misses.lock().unwrap().insert(name.to_string());
+            }
+            Ok(found)
+        });
+
+        let result = env.get_template(name).and_then(|template| template.render(context));
+        // Load what the render asked for and did not find, then try again
+        let new: Vec<String> = missed.lock().unwrap().iter().filter(|m| !absent.contains(*m)).cloned().collect();
+        if new.is_empty() {
+            return result.map_err(|e| Failure {
+                message: describe(&e),
+                missing: e.kind() == ErrorKind::TemplateNotFound,
+            });
+        }
+        for miss in new {
+            if sources.lock().unwrap().len() >= MAX_TEMPLATES {
+                return Err(fail(format!("'{}' uses more than {} templates", name, MAX_TEMPLATES)));
+            }
+            match load(&miss).map_err(fail)? {
+                Some(source) => {
+                    sources.lock().unwrap().insert(miss, source);
+                }
+                None => {
+                    absent.insert(miss);
+                }
+            }
+        }
+    }
+}
+
+/// An error with the template and line it happened at.
+fn describe(e: &minijinja::Error) -> String {
+    match (e.name(), e.line()) {
+        (Some(name), Some(line)) => format!("{} at {}:{}", e.detail().unwrap_or(&e.kind().to_string()), name, line),
+        _ => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn templates(name: &str) -> Result<Option<String>, String> {
+        Ok(match name {
+            "header.j2" => Some("// {{ crate }}\n".to_string()),
+            "base.j2" => Some("{% block body %}{% endblock %}\n".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn renders_loops_conditionals_and_includes() {
+        let source = "{% include 'header.j2' %}\n{% for m in modules %}\nmod {{ m }};\n{% endfor %}\n{% if tests %}\n#[cfg(test)]\n{% endif %}\n";
+        let context = json!({"crate": "demo", "modules": ["a", "b"]});
+        let out = render("lib.rs.j2", source.to_string(), &context, templates).unwrap();
+        assert_eq!(out, "// demo\nmod a;\nmod b;\n");
+
+        let child = "{% extends 'base.j2' %}{% block body %}{{ crate | upper }}{% endblock %}";
+        assert_eq!(render("c.j2", child.to_string(), &context, templates).unwrap(), "DEMO");
+    }
+
+    #[test]
+    fn reports_what_went_wrong() {
+        let context = json!({});
+        let undefined = render("t.j2", "a\n{{ nmae }}\n".to_string(), &context, templates).unwrap_err();
+        assert!(undefined.message.contains("t.j2:2") && !undefined.missing, "{}", undefined.message);
+        let missing = render("t.j2", "{% include 'nope.j2' %}".to_string(), &context, templates).unwrap_err();
+        assert!(missing.message.contains("nope.j2") && missing.missing, "{}", missing.message);
+        assert!(context_of("[1]").is_err() && context_of("{").is_err());
+        assert_eq!(context_of("").unwrap(), json!({}));
+    }
+
+    fn context_of(text: &str) -> Result<Value, String> {
+        context(Some(text))
+    }
+}
@@ -0,0 +1,263 @@
+//! Tables of contents for Markdown files, kept between a pair of marker
+//! comments so regenerating replaces the old list instead of adding one.
+
+use crate::frontmatter;
+
+pub const START: &str = "<!-- toc -->";
+pub const END: &str = "<!-- tocstop -->";
+
+/// Deepest heading level listed unless asked otherwise.
+pub const DEFAULT_DEPTH: usize = 3;
+
+/// A heading found in the document.
+#[derive(Debug, PartialEq, Eq)]
+struct Heading {
+    level: usize,
+    text: String,
+}
+
+/// Outcome of [`generate`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Toc {
+    pub text: String,
+    /// Headings listed
+    pub entries: usize,
+    /// Whether the markers were added rather than found
+    pub inserted: bool,
+}
+
+/// `text` with its table of contents brought up to date, listing headings
+/// down to `depth`. A lone level-1 heading is taken as the title and left
+/// out. Without markers, the list goes after the title, or at the top of
+/// the body.
+pub fn generate(text: &str, depth: usize) -> Result<Toc, String> {
+    let eol = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let body_start = frontmatter::block(text).map_or(0, |block| line_end(text, block.end));
+    let markers = find_markers(text, body_start)?;
+
+    let mut headings = headings(&text[body_start..], markers.as_ref().map(|(s, e)| (s - body_start, e - body_start)));
+    if headings.iter().filter(|h| h.level == 1).count() == 1 && headings.first().is_some_and(|h| h.level == 1) {
+        headings.remove(0);
+    }
+    headings.retain(|h| h.level <= depth);
+    let list = render(&headings, eol);
+    let block = format!("{START}{eol}{eol}{list}{eol}{END}");
+
+    let (text, inserted) = match markers {
+        Some((start, end)) => (format!("{}{}{}", &text[..start], block, &text[end..]), false),
+        None => {
+            let at = title_end(text, body_start);
+            let (before, after) = text.split_at(at);
+            let lead = if before.is_empty() || before.ends_with('\n') { "" } else { eol };
+            (format!("{}{}{}{}{}{}", before, lead, eol, block, eol, after), true)
+        }
+    };
+    Ok(Toc { text, entries: headings.len(), inserted })
+}
+
+/// Byte range from the start of the opening marker to the end of the
+/// closing one.
+fn find_markers(text: &str, from: usize) -> Result<Option<(usize, usize)>, String> {
+    let Some(start) = text[from..].find(START).map(|i| from + i) else {
+        return match text[from..].contains(END) {
+            true => Err(format!("found {} without {} before it", END, START)),
+            false => Ok(None),
+        };
+    };
+    match text[start..].find(END) {
+        Some(i) => Ok(Some((start, start + i + END.len()))),
+        None => Err(format!("found {} without a closing {}", START, END)),
+    }
+}
+
+/// Where a new table of contents goes: after a level-1 heading opening the
+/// body, else at the start of the body.
+fn title_end(text: &str, body_start: usize) -> usize {
+    let body = &text[body_start..];
+    let skipped = body.len() - body.trim_start_matches(['\n', '\r']).len();
+    let first = body_start + skipped;
+    let line = &text[first..line_end(text, first)];
+    if atx(line.trim_end()).is_some_and(|h| h.level == 1) {
+        line_end(text, first)
+    } else {
+        body_start
+    }
+}
+
+/// Offset just past the line containing `pos`, including its newline.
+fn line_end(text: &str, pos: usize) -> usize {
+    text[pos..].find('\n').map_or(text.len(), |i| pos + i + 1)
+}
+
+/// ATX (`## Title`) and setext (underlined) headings outside code fences
+/// and outside the `skip` range.
+fn headings(body: &str, skip: Option<(usize, usize)>) -> Vec<Heading> {
+    let mut found = Vec::new();
+    let mut fence: Option<&str> = None;
+    let mut previous: Option<&str> = None;
+    let mut pos = 0;
+    for line in body.split_inclusive('\n') {
+        let start = pos;
+        pos += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+        if skip.is_some_and(|(s, e)| start >= s && start < e) {
+            previous = None;
+            continue;
+        }
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if let Some(open) = fence {
+            if trimmed.starts_with(open) {
+                fence = None;
+            }
+            continue;
+        }
+        if indent < 4 && (trimmed.starts_with("```") || trimmed.starts_with("~~~")) {
+            fence = Some(&trimmed[..3]);
+            previous = None;
+            continue;
+        }
+
+        if indent < 4 {
+            if let Some(heading) = atx(trimmed) {
+                found.push(heading);
+                previous = None;
+                continue;
+            }
+            let underline = trimmed.trim_end();
+            let level = match underline.chars().next() {
+                Some('=') if underline.chars().all(|c| c == '=') => Some(1),
+                Some('-') if underline.chars().all(|c| c == '-') => Some(2),
+                _ => None,
+            };
+            if let (Some(level), Some(text)) = (level, previous) {
+                found.push(Heading { level, text: text.trim().to_string() });
+                previous = None;
+                continue;
+            }
+        }
+        previous = (!line.trim().is_empty() && indent < 4).then_some(line);
+    }
+    found
+}
+
+/// An ATX heading such as `## Usage ##`.
+fn atx(line: &str) -> Option<Heading> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    // A closing run of #s is not part of the text
+    let text = rest.trim();
+    let text = match text.trim_end_matches('#') {
+        stripped if stripped.is_empty() || stripped.ends_with([' ', '\t']) => stripped.trim_end(),
+        _ => text,
+    };
+    Some(Heading { level, text: text.to_string() })
+}
+
+fn render(headings: &[Heading], eol: &str) -> String {
+    let top = headings.iter().map(|h| h.level).min().unwrap_or(1);
+    let mut used: Vec<String> = Vec::new();
+    let mut list = String::new();
+    for heading in headings {
+        let label = plain_text(&heading.text);
+        let base = slug(&label);
+        let mut anchor = base.clone();
+        let mut n = 0;
+        while used.contains(&anchor) {
+            n += 1;
+            anchor = format!("{}-{}", base, n);
+        }
+        used.push(anchor.clone());
+        let label = label.replace('[', "\\[").replace(']', "\\]");
+        list.push_str(&format!("{}- [{}](#{}){}", "  ".repeat(heading.level - top), label, anchor, eol));
+    }
+    list
+}
+
+/// Heading text without link targets, emphasis or code markers.
+fn plain_text(text: &str) -> String {
+    strip_links(text)
+        .chars()
+        .filter(|c| !matches!(c, '`' | '*'))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// `text` with each `[label](target)` link, or `![alt](target)` image,
+/// replaced by its label.
+fn strip_links(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('[') {
+        let link = rest[open + 1..].find(']').map(|i| open + 1 + i).and_then(|close| {
+            let target = rest[close + 1..].strip_prefix('(')?;
+            Some((close, close + 2 + target.find(')')? + 1))
+        });
+        match link {
+            Some((close, end)) => {
+                out.push_str(rest[..open].strip_suffix('!').unwrap_or(&rest[..open]));
+                out.push_str(&rest[open + 1..close]);
+                rest = &rest[end..];
+            }
+            None => {
+                out.push_str(&rest[..open + 1]);
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The anchor GitHub gives a heading: lowercase, punctuation dropped,
+/// spaces as hyphens.
+fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "# Title\n\nIntro.\n\n## Getting Started\n\n```sh\n# not a heading\n```\n\n### Install `fs-child`\n\nSetup\n-----\n\n## Getting Started\n\n#### Too deep\n";
+
+    #[test]
+    fn inserts_after_the_title() {
+        let toc = generate(DOC, DEFAULT_DEPTH).unwrap();
+        assert!(toc.inserted);
+        assert_eq!(toc.entries, 4);
+        assert!(toc.text.starts_with(
+            "# Title\n\n<!-- toc -->\n\n- [Getting Started](#getting-started)\n  - [Install fs-child](#install-fs-child)\n- [Setup](#setup)\n- [Getting Started](#getting-started-1)\n\n<!-- tocstop -->\n\nIntro.\n"
+        ));
+    }
+
+    #[test]
+    fn regenerates_between_markers() {
+        let first = generate(DOC, DEFAULT_DEPTH).unwrap().text;
+        let edited = first.replace("## Getting Started\n\n####", "## Usage\n\n####");
+        let second = generate(&edited, 2).unwrap();
+        assert!(!second.inserted);
+        assert_eq!(second.text.matches(START).count(), 1);
+        assert!(second.text.contains("- [Setup](#setup)\n- [Usage](#usage)\n\n<!-- tocstop -->"));
+        assert!(generate("<!-- toc -->\n# A\n", 3).is_err());
+    }
+
+    #[test]
+    fn labels_keep_link_text() {
+        assert_eq!(plain_text("See [a [b](c) and ![logo](x.png) [x] (y) `[z](`"), "See a [b and logo [x] (y) [z](");
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeSet;
+
+use crate::fs::Filesystem;
+
+/// What a path held before a transactional batch first touched it.
+enum Snapshot {
+    File { path: String, content: Vec<u8> },
+    /// Nothing was there; anything found on rollback was created by the batch
+    Absent { path: String },
+}
+
+/// Prior contents of every path a transactional batch changes, so the batch
+/// can be undone if a later command fails.
+#[derive(Default)]
+pub struct Journal {
+    snapshots: Vec<Snapshot>,
+    seen: BTreeSet<String>,
+}
+
+impl Journal {
+    /// Remember what `path` holds now, unless it was already recorded.
+    /// Existing directories need no snapshot: no operation replaces one.
+    pub fn record(&mut self, fs: &dyn Filesystem, path: &str) {
+        if !self.seen.insert(path.to_string()) {
+            return;
+        }
+        match fs.read_file(path) {
+            Ok(content) => self.snapshots.push(Snapshot::File {
+                path: path.to_string(),
+                content,
+            }),
+            Err(_) if fs.path_exists(path).unwrap_or(false) => {}
+            Err(_) => self.snapshots.push(Snapshot::Absent {
+                path: path.to_string(),
+            }),
+        }
+    }
+
+    /// Put every recorded path back as it was, newest change first.
+    /// Returns a description of each path that could not be restored.
+    pub fn rollback(self, fs: &dyn Filesystem) -> Vec<String> {
+        let mut problems = Vec::new();
+        for snapshot in self.snapshots.into_iter().rev() {
+            let outcome = match snapshot {
+                Snapshot::File { path, content } => match String::from_utf8(content) {
+                    Ok(text) => fs.write_file(&path, &text).map_err(|e| format!("{}: {}", path, e)),
+                    Err(_) => Err(format!("{}: original content is not UTF-8", path)),
+                },
+                Snapshot::Absent { path } => remove_created(fs, &path),
+            };
+            if let Err(problem) = outcome {
+                problems.push(problem);
+            }
+        }
+        problems
+    }
+}
+
+fn remove_created(fs: &dyn Filesystem, path: &str) -> Result<(), String> {
+    if !fs.path_exists(path).unwrap_or(false) {
+        return Ok(());
+    }
+    let removed = if fs.list_files(path).is_ok() {
+        fs.delete_dir(path)
+    } else {
+        fs.delete_file(path)
+    };
+    removed.map_err(|e| format!("{}: {}", path, e))
+}
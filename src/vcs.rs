@@ -0,0 +1,188 @@
+//! A small content-addressed history of the workspace, for `commit` and
+//! `checkout` in trees that are not git repositories.
+//!
+//! Everything lives under [`DIR`] in the workspace: `blobs/<sha256>` holds
+//! each distinct file content once, `commits/<id>.json` the manifest of each
+//! commit, and `HEAD` the id of the commit last made or checked out. Binary
+//! content is kept base64-encoded, since the host only writes text.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Directory of the history, relative to `base_path`
+pub const DIR: &str = ".fs-child/vcs";
+
+/// Hex digits in a commit id
+const ID_LENGTH: usize = 12;
+
+/// Shortest prefix `checkout` accepts for an id
+pub const MIN_PREFIX: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// SHA-256 of the content, naming its blob
+    pub hash: String,
+    pub size: usize,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub binary: bool,
+}
+
+impl Entry {
+    pub fn of(content: &[u8]) -> Entry {
+        Entry {
+            hash: format!("{:x}", Sha256::digest(content)),
+            size: content.len(),
+            binary: std::str::from_utf8(content).is_err(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    pub id: String,
+    pub parent: Option<String>,
+    pub message: String,
+    /// Head-update count when the commit was made
+    pub turn: u64,
+    /// Every file by path relative to `base_path`
+    pub files: BTreeMap<String, Entry>,
+}
+
+impl Commit {
+    /// A commit of `files`, with an id derived from its parent, message and
+    /// content.
+    pub fn new(parent: Option<String>, message: &str, turn: u64, files: BTreeMap<String, Entry>) -> Commit {
+        let digest = Sha256::digest(json!({"parent": parent, "message": message, "files": files}).to_string());
+        let id = format!("{:x}", digest)[..ID_LENGTH].to_string();
+        Commit { id, parent, message: message.to_string(), turn, files }
+    }
+}
+
+pub fn head_path() -> String {
+    format!("{}/HEAD", DIR)
+}
+
+pub fn commits_dir() -> String {
+    format!("{}/commits", DIR)
+}
+
+pub fn commit_path(id: &str) -> String {
+    format!("{}/commits/{}.json", DIR, id)
+}
+
+pub fn blob_path(hash: &str) -> String {
+    format!("{}/blobs/{}", DIR, hash)
+}
+
+/// Whether a workspace path is the actor's own data, left out of commits.
+pub fn is_internal(path: &str) -> bool {
+    let top = DIR.split('/').next().unwrap_or(DIR);
+    path == top || path.strip_prefix(top).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// How file content is written as a blob.
+pub fn encode_blob(content: &[u8]) -> String {
+    match std::str::from_utf8(content) {
+        Ok(text) => text.to_string(),
+        Err(_) => BASE64.encode(content),
+    }
+}
+
+pub fn decode_blob(blob: &[u8], entry: &Entry) -> Result<Vec<u8>, String> {
+    if entry.binary {
+        BASE64.decode(blob).map_err(|e| format!("blob {} is not base64: {}", entry.hash, e))
+    } else {
+        Ok(blob.to_vec())
+    }
+}
+
+/// The commit `selector` names among `ids`: an exact id, or a unique prefix
+/// of at least [`MIN_PREFIX`] digits.
+pub fn find<'a>(ids: &'a [String], selector: &str) -> Result<&'a str, String> {
+    let selector = selector.trim();
+    if let Some(id) = ids.iter().find(|id| *id == selector) {
+        return Ok(id);
+    }
+    if selector.len() < MIN_PREFIX {
+        return Err(format!("'{}' is too short; give at least {} digits of a commit id", selector, MIN_PREFIX));
+    }
+    let matching: Vec<&String> = ids.iter().filter(|id| id.starts_with(selector)).collect();
+    match matching.as_slice() {
+        [id] => Ok(id),
+        [] => Err(format!("No commit '{}'", selector)),
+        _ => Err(format!(
+            "'{}' matches {} commits: {}",
+            selector,
+            matching.len(),
+            matching.iter().map(|id| id.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+/// Paths added, changed and removed going from `from` to `to`.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{} added, {} changed, {} removed", self.added.len(), self.changed.len(), self.removed.len())
+    }
+}
+
+pub fn diff(from: &BTreeMap<String, Entry>, to: &BTreeMap<String, Entry>) -> Diff {
+    let mut diff = Diff::default();
+    for (path, entry) in to {
+        match from.get(path) {
+            None => diff.added.push(path.clone()),
+            Some(old) if old.hash != entry.hash => diff.changed.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = from.keys().filter(|path| !to.contains_key(*path)).cloned().collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_and_names_commits() {
+        let a = Entry::of(b"alpha");
+        let b = Entry::of(b"beta");
+        assert!(!a.binary && Entry::of(&[0xff, 0xfe]).binary);
+        let from = BTreeMap::from([("a".to_string(), a.clone()), ("b".to_string(), a.clone())]);
+        let to = BTreeMap::from([("a".to_string(), b), ("c".to_string(), a)]);
+        let diff = diff(&from, &to);
+        assert_eq!((diff.added, diff.changed, diff.removed), (vec!["c".to_string()], vec!["a".to_string()], vec!["b".to_string()]));
+
+        let first = Commit::new(None, "start", 1, from.clone());
+        assert_eq!(first.id.len(), ID_LENGTH);
+        assert_ne!(first.id, Commit::new(Some(first.id.clone()), "start", 1, from).id);
+
+        let ids = vec!["ab12cd34ef56".to_string(), "ab12ff000000".to_string()];
+        assert_eq!(find(&ids, "ab12c").unwrap(), "ab12cd34ef56");
+        assert!(find(&ids, "ab12").unwrap_err().contains("matches 2"));
+        assert!(find(&ids, "ab").unwrap_err().contains("too short"));
+        assert!(is_internal(".fs-child/vcs/HEAD") && !is_internal(".fs-childish"));
+    }
+
+    #[test]
+    fn keeps_binary_blobs_as_base64() {
+        let content = [0x89, b'P', b'N', b'G', 0xff];
+        let entry = Entry::of(&content);
+        assert_eq!(decode_blob(encode_blob(&content).as_bytes(), &entry).unwrap(), content);
+    }
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on files visited while scanning subscriptions, so a broad glob
+/// over a large tree cannot stall head-update processing.
+pub const WATCH_SCAN_LIMIT: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathChange {
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+/// Match a path against a glob pattern. `*` and `?` stay within one path
+/// segment, `**` spans any number of segments (including none).
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    // `matches[i][j]`: whether `pattern[i..]` matches `path[j..]`, filled
+    // from the ends so each is decided once, whatever the wildcards
+    let mut matches = vec![vec![false; path.len() + 1]; pattern.len() + 1];
+    matches[pattern.len()][path.len()] = true;
+    for i in (0..pattern.len()).rev() {
+        let double = pattern[i] == '*' && pattern.get(i + 1) == Some(&'*');
+        // Whether the rest after `**` matches from here or any later point
+        let mut later = false;
+        for j in (0..=path.len()).rev() {
+            let next = path.get(j);
+            matches[i][j] = match pattern[i] {
+                '*' if double => {
+                    later |= matches[i + 2][j];
+                    // `**/` may also match zero directories
+                    later || (pattern.get(i + 2) == Some(&'/') && matches[i + 3][j])
+                }
+                '*' => matches[i + 1][j] || (next.is_some_and(|&c| c != '/') && matches[i][j + 1]),
+                '?' => next.is_some_and(|&c| c != '/') && matches[i + 1][j + 1],
+                c => next == Some(&c) && matches[i + 1][j + 1],
+            };
+        }
+    }
+    matches[0][0]
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// The directory a glob has to be scanned from: every leading segment that
+/// contains no wildcard.
+pub fn glob_base(pattern: &str) -> String {
+    let literal: Vec<&str> = pattern
+        .split('/')
+        .take_while(|segment| !is_glob(segment))
+        .collect();
+    if literal.is_empty() {
+        ".".to_string()
+    } else {
+        literal.join("/")
+    }
+}
+
+/// Stable 64-bit FNV-1a content hash, rendered as hex.
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        for (pattern, path, expected) in [
+            ("src/*.rs", "src/lib.rs", true),
+            ("src/*.rs", "src/a/lib.rs", false),
+            ("src/?.rs", "src/a.rs", true),
+            ("src/**/*.rs", "src/lib.rs", true),
+            ("src/**/*.rs", "src/a/b/lib.rs", true),
+            ("**/*.toml", "Cargo.toml", true),
+            ("a/**/b", "a/xb", false),
+            ("a/**", "a/x/y", true),
+            ("*", "a/b", false),
+        ] {
+            assert_eq!(glob_match(pattern, path), expected, "{pattern} on {path}");
+        }
+    }
+
+    #[test]
+    fn many_stars_do_not_backtrack_exponentially() {
+        let path = "a".repeat(40);
+        assert!(!glob_match("*a*a*a*a*a*a*a*a*a*b", &path));
+        assert!(!glob_match("**a**a**a**a**a**a**a**a**a**b", &path));
+    }
+}